@@ -0,0 +1,163 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::complex::Complex;
+use crate::scene::{Face, Scene};
+use crate::shell::AIR_DENSITY;
+use crate::vec3::Vec3;
+
+/// How far from a face the center of an absorber may be, in meters.
+const MAX_FACE_DISTANCE: f32 = 0.05;
+
+/// How a resonant absorber is built.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AbsorberKind {
+    /// A panel or membrane on a sealed air space, such as plywood on a frame.
+    /// The mass of the panel resonates on the air behind it.
+    Membrane,
+
+    /// A perforated or slotted panel on an air space. The air in the holes
+    /// resonates on the air behind them.
+    Helmholtz,
+}
+
+impl AbsorberKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AbsorberKind::Membrane => "Membrane",
+            AbsorberKind::Helmholtz => "Helmholtz",
+        }
+    }
+}
+
+/// A resonant bass trap, a rectangular patch on a face that absorbs around one frequency.
+///
+/// Porous absorbers need to be a sizeable part of a wavelength thick to work,
+/// which at bass frequencies is impractical. Resonant absorbers are thin, but
+/// they only absorb near their resonance, over a band of about `frequency / q`.
+/// Away from the resonance, the patch reflects like the face behind it.
+#[derive(Clone, Debug)]
+pub struct Absorber {
+    pub kind: AbsorberKind,
+
+    /// The index of the face that the absorber is mounted on.
+    pub face: usize,
+
+    /// The center of the patch, on the face.
+    pub center: Vec3,
+
+    /// The size of the patch along the tangent of the face, and perpendicular
+    /// to it. For a wall, that is its width along the wall, and its height.
+    pub width: f32,
+    pub height: f32,
+
+    /// The resonance frequency, in Hz.
+    pub frequency: f32,
+
+    /// The quality factor, the resonance frequency over the bandwidth in
+    /// which the absorption is at least half of its peak.
+    pub q: f32,
+
+    /// The absorption coefficient at the resonance frequency.
+    pub peak_absorption: f32,
+}
+
+/// Return the resonance frequency of a membrane absorber, in Hz.
+///
+/// The panel has a mass of `mass` kg/m², on an air space `depth` meters deep.
+/// At room temperature this is the well-known 60 / √(m d).
+pub fn membrane_frequency(speed_of_sound: f32, mass: f32, depth: f32) -> f32 {
+    let c = speed_of_sound;
+    (AIR_DENSITY * c * c / (mass * depth)).sqrt() / (2.0 * PI)
+}
+
+/// Return the resonance frequency of a perforated panel absorber, in Hz.
+///
+/// The panel is `thickness` meters thick, with round holes of `hole_radius`
+/// meters that make up `open_area` of its surface, on an air space `depth`
+/// meters deep. The air in the holes moves as if the holes were longer by
+/// an end correction of 1.7 times their radius.
+pub fn helmholtz_frequency(speed_of_sound: f32, open_area: f32, thickness: f32, hole_radius: f32, depth: f32) -> f32 {
+    let neck = thickness + 1.7 * hole_radius;
+    speed_of_sound / (2.0 * PI) * (open_area / (depth * neck)).sqrt()
+}
+
+impl Absorber {
+    pub fn area(&self) -> f32 {
+        self.width * self.height
+    }
+
+    /// Return the reflection coefficient of the absorber on a rigid backing at `frequency`.
+    ///
+    /// The absorber is a damped mass-spring system. At normal incidence, its
+    /// surface impedance, normalized to that of air, is r - iX, with a
+    /// resistance r that sets the peak absorption 4r / (r + 1)², and a
+    /// reactance X = Q (r + 1) (f/f0 - f0/f) that grows from the resonance as
+    /// fast as the quality factor requires. Like `Material::from_impedance()`,
+    /// a hard wall reflects with -1 in this convention, which is what the
+    /// absorber tends to away from its resonance.
+    pub fn reflectivity_at(&self, frequency: f32) -> Complex {
+        // Of the two resistances with the peak absorption, take the one of at
+        // least 1, which is how real traps are damped: with more resistance
+        // than air, rather than less.
+        let a = self.peak_absorption.max(1e-3).min(1.0);
+        let r = (2.0 - a + 2.0 * (1.0 - a).sqrt()) / a;
+        let detuning = frequency / self.frequency - self.frequency / frequency;
+        let z = Complex::new(r, -self.q * (r + 1.0) * detuning);
+        let one = Complex::new(1.0, 0.0);
+        (one - z) / (z + one)
+    }
+
+    /// Return the part of the incident energy that the absorber absorbs at `frequency`.
+    pub fn absorption_at(&self, frequency: f32) -> f32 {
+        1.0 - self.reflectivity_at(frequency).norm_squared()
+    }
+
+    /// Return whether the point `p` on the face `face` lies on the patch.
+    pub fn contains(&self, face: &Face, p: Vec3) -> bool {
+        let d = p - self.center;
+        let tangent = face.tangent();
+        let up = tangent.cross(face.normal());
+        d.dot(tangent).abs() <= 0.5 * self.width && d.dot(up).abs() <= 0.5 * self.height
+    }
+}
+
+/// Return the index of the face that `p` lies on, for mounting an absorber there.
+///
+/// Of the faces that reflect, and contain the point projected onto their
+/// plane, this is the one whose plane is closest, if it is closer than
+/// `MAX_FACE_DISTANCE`.
+pub fn face_at(scene: &Scene, p: Vec3) -> Option<usize> {
+    let mut best = None;
+    let mut best_distance = MAX_FACE_DISTANCE;
+    for (i, face) in scene.faces.iter().enumerate() {
+        if face.opening {
+            continue
+        }
+        let distance = face.normal().dot(p - face.start());
+        let projected = p - face.normal() * distance;
+        if distance.abs() <= best_distance && face.bounds_contain(projected) {
+            best = Some(i);
+            best_distance = distance.abs();
+        }
+    }
+    best
+}
+
+/// Describe the absorbers of the scene, one per line.
+pub fn describe(scene: &Scene) -> String {
+    let lines: Vec<String> = scene.absorbers
+        .iter()
+        .map(|a| format!(
+            "{} trap on {}: {:.0} Hz, Q {:.1}, {:.2} m²",
+            a.kind.name(), scene.face_name(a.face).to_lowercase(), a.frequency, a.q, a.area(),
+        ))
+        .collect();
+    lines.join("\n")
+}
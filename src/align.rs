@@ -0,0 +1,164 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::complex::Complex;
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The largest delay that we consider, in seconds.
+const MAX_DELAY_S: f32 = 0.020;
+
+/// The delay search step, in seconds.
+const DELAY_STEP_S: f32 = 0.000_05;
+
+/// Delay and polarity to apply to a source.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Alignment {
+    pub delay_s: f32,
+    pub invert: bool,
+}
+
+impl Alignment {
+    /// The alignment that leaves the source unchanged.
+    pub fn identity() -> Alignment {
+        Alignment {
+            delay_s: 0.0,
+            invert: false,
+        }
+    }
+
+    /// Return the complex factor that applying the alignment at `frequency` amounts to.
+    ///
+    /// This uses the same phase convention as `Source::drive()`, so the
+    /// alignment can be added to the delay and polarity of the source.
+    pub fn factor(&self, frequency: f32) -> Complex {
+        let polarity = if self.invert { -1.0 } else { 1.0 };
+        Complex::exp_i(2.0 * PI * frequency * self.delay_s) * polarity
+    }
+}
+
+/// The per-source responses at the listener, over a band.
+pub struct BandResponse {
+    pub frequencies: Vec<f32>,
+
+    /// Response of the source to align.
+    pub source: Vec<Complex>,
+
+    /// Summed response of all other sources.
+    pub others: Vec<Complex>,
+}
+
+impl BandResponse {
+    pub fn sample(
+        scene: &Scene,
+        rng: &mut Rng,
+        si: usize,
+        listener: Vec3,
+        f_min: f32,
+        f_max: f32,
+    ) -> BandResponse {
+        let frequencies = response::log_frequencies(f_min, f_max, 24);
+        let n_samples = 4096;
+
+        let source = response::sample_source_response(
+            scene, rng, si, listener, &frequencies[..], n_samples,
+        );
+        let mut others = vec![Complex::zero(); frequencies.len()];
+        for oi in (0..scene.sources.len()).filter(|&oi| oi != si) {
+            let r = response::sample_source_response(
+                scene, rng, oi, listener, &frequencies[..], n_samples,
+            );
+            for (acc, z) in others.iter_mut().zip(r) {
+                *acc = *acc + z;
+            }
+        }
+
+        BandResponse {
+            frequencies: frequencies,
+            source: source,
+            others: others,
+        }
+    }
+
+    /// Return the summed response with `alignment` applied to the source.
+    pub fn sum(&self, alignment: Alignment) -> Vec<Complex> {
+        self.frequencies
+            .iter()
+            .zip(self.source.iter().zip(self.others.iter()))
+            .map(|(&f, (&s, &o))| o + s * alignment.factor(f))
+            .collect()
+    }
+
+    /// Return the total power of the summed response, our figure of merit.
+    pub fn power(&self, alignment: Alignment) -> f32 {
+        self.sum(alignment).iter().map(|z| z.norm_squared()).sum()
+    }
+
+    /// Find the delay and polarity that maximize summation over the band.
+    ///
+    /// This mirrors what people do with measurements when integrating a
+    /// subwoofer: with all other sources fixed, sweep the delay and polarity of
+    /// one source, and keep the setting with the highest level over the band.
+    pub fn best_alignment(&self) -> Alignment {
+        let mut best = Alignment::identity();
+        let mut best_power = self.power(best);
+
+        let n_steps = (MAX_DELAY_S / DELAY_STEP_S) as u32;
+        for &invert in &[false, true] {
+            for i in 0..n_steps + 1 {
+                let candidate = Alignment {
+                    delay_s: i as f32 * DELAY_STEP_S,
+                    invert: invert,
+                };
+                let power = self.power(candidate);
+                if power > best_power {
+                    best = candidate;
+                    best_power = power;
+                }
+            }
+        }
+
+        best
+    }
+}
+
+/// Align source `si` at the listener, and print the before and after response.
+pub fn print_alignment(scene: &Scene, si: usize, listener: Vec3, f_min: f32, f_max: f32, seed: u64) {
+    let mut rng = Rng::new(seed);
+    let band = BandResponse::sample(scene, &mut rng, si, listener, f_min, f_max);
+    let alignment = band.best_alignment();
+
+    println!("Seed: {}", seed);
+    let source = &scene.sources[si];
+    println!("Source {}: delay {:.2} ms, polarity {}.",
+        si,
+        source.delay_ms + alignment.delay_s * 1000.0,
+        if source.invert != alignment.invert { "inverted" } else { "normal" },
+    );
+    println!();
+
+    let before = band.sum(Alignment::identity());
+    let after = band.sum(alignment);
+
+    // The scale of the sampled field is arbitrary, so report levels relative to
+    // the peak of the unaligned response.
+    let reference = before.iter().map(|&z| response::to_db(z)).fold(std::f32::NEG_INFINITY, f32::max);
+
+    println!("{:>9}  {:>10}  {:>10}", "Hz", "before dB", "after dB");
+    for (f, (b, a)) in band.frequencies.iter().zip(before.iter().zip(after.iter())) {
+        println!(
+            "{:>9.1}  {:>10.1}  {:>10.1}",
+            f,
+            response::to_db(*b) - reference,
+            response::to_db(*a) - reference,
+        );
+    }
+}
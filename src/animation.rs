@@ -0,0 +1,274 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use toml::Value;
+
+use crate::complex::Complex;
+use crate::pool;
+use crate::rand::Rng;
+use crate::renderer::{self, Viewport, SLICE_HEIGHT};
+use crate::scene::Scene;
+use crate::scene_file::{self, Result, invalid};
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// Width and height of the frames, in pixels.
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 360;
+
+/// A part of the timeline during which a fixed set of sources plays.
+pub struct Step {
+    /// The caption of the step, e.g. "Sub 1 only".
+    pub label: String,
+
+    /// The indices of the sources that play, the others are muted.
+    pub sources: Vec<usize>,
+
+    pub seconds: f32,
+}
+
+/// A sequence of steps, to compare combinations of sources in one clip.
+pub struct Schedule {
+    pub frames_per_second: u32,
+    pub steps: Vec<Step>,
+}
+
+impl Schedule {
+    pub fn n_frames(&self) -> u32 {
+        self.steps.iter().map(|s| self.frames_in(s)).sum()
+    }
+
+    fn frames_in(&self, step: &Step) -> u32 {
+        (step.seconds * self.frames_per_second as f32).round().max(1.0) as u32
+    }
+}
+
+/// Load a schedule from a TOML file.
+///
+/// `scene` is the scene that the schedule applies to, it is used to validate
+/// the source indices. A schedule file looks like this:
+///
+/// ```toml
+/// # Optional, defaults to 25.
+/// frames_per_second = 25
+///
+/// [[step]]
+/// label = "Sub 1 only"
+/// # Zero-based indices of the sources that play, all others are muted.
+/// sources = [0]
+/// # The duration of the step, defaults to 3 seconds.
+/// seconds = 3
+///
+/// [[step]]
+/// label = "Sub 2 only"
+/// sources = [1]
+///
+/// [[step]]
+/// label = "Both"
+/// sources = [0, 1]
+/// ```
+pub fn load_schedule<P: AsRef<Path>>(path: P, scene: &Scene) -> Result<Schedule> {
+    let contents = fs::read_to_string(path)?;
+    let root: Value = contents.parse()?;
+
+    let frames_per_second = match root.get("frames_per_second") {
+        Some(v) => scene_file::as_usize(v, "frames_per_second")? as u32,
+        None => 25,
+    };
+    if frames_per_second == 0 {
+        return invalid("'frames_per_second' must be positive.".to_string())
+    }
+
+    let mut steps = Vec::new();
+    let step_values = scene_file::as_array(scene_file::get(&root, "", "step")?, "step")?;
+    for (i, s) in step_values.iter().enumerate() {
+        let path = format!("step[{}]", i);
+        let label = scene_file::as_str(scene_file::get(s, &path, "label")?, &format!("{}.label", path))?;
+
+        let sources_path = format!("{}.sources", path);
+        let mut sources = Vec::new();
+        for v in scene_file::as_array(scene_file::get(s, &path, "sources")?, &sources_path)? {
+            let si = scene_file::as_usize(v, &sources_path)?;
+            if si >= scene.sources.len() {
+                return invalid(format!(
+                    "'{}' refers to source {}, but the scene has {} sources.",
+                    sources_path, si, scene.sources.len(),
+                ))
+            }
+            sources.push(si);
+        }
+
+        let seconds = match s.get("seconds") {
+            Some(v) => scene_file::as_f32(v, &format!("{}.seconds", path))?,
+            None => 3.0,
+        };
+        if !(seconds > 0.0) {
+            return invalid(format!("'{}.seconds' must be positive.", path))
+        }
+
+        steps.push(Step {
+            label: label.to_string(),
+            sources: sources,
+            seconds: seconds,
+        });
+    }
+
+    if steps.is_empty() {
+        return invalid("The schedule needs at least one [[step]].".to_string())
+    }
+
+    Ok(Schedule {
+        frames_per_second: frames_per_second,
+        steps: steps,
+    })
+}
+
+/// Return a copy of `scene` where only the sources in `step` play.
+fn apply_step(scene: &Scene, step: &Step) -> Scene {
+    let mut scene = scene.clone();
+    for (i, source) in scene.sources.iter_mut().enumerate() {
+        source.muted = !step.sources.contains(&i);
+        source.solo = false;
+    }
+    scene
+}
+
+/// Sample row `y` of the frame, return log10 of the magnitude of every pixel.
+///
+/// Every row has its own random stream, so the result does not depend on
+/// which thread samples it.
+fn sample_row(scene: &Scene, viewport: &Viewport, frequency: f32, n_samples: u32, seed: u64, y: u32) -> Vec<f32> {
+    let mut rng = Rng::new((seed << 32).wrapping_add(y as u64));
+    let weight = (n_samples as f32).recip();
+    (0..WIDTH)
+        .map(|x| {
+            let p = viewport.to_world(x, y);
+            let position = Vec3::new(p.x, p.y, scene.floor_height_at(p) + SLICE_HEIGHT);
+            let mut z = Complex::zero();
+            for _ in 0..n_samples {
+                z = z + scene.sample_at(&mut rng, frequency, position);
+            }
+            (z * weight).norm_squared().log10() * 0.5
+        })
+        .collect()
+}
+
+/// Sample the magnitude of every pixel, with the rows spread over threads.
+fn sample_frame(scene: &Arc<Scene>, frequency: f32, n_samples: u32, seed: u64) -> Vec<f32> {
+    let viewport = Viewport::fit(scene, WIDTH, HEIGHT);
+    let n_threads = pool::cpu_count();
+    let threads: Vec<_> = (0..n_threads)
+        .map(|t| {
+            let scene = scene.clone();
+            thread::spawn(move || {
+                (t as u32..HEIGHT)
+                    .step_by(n_threads)
+                    .map(|y| (y, sample_row(&scene, &viewport, frequency, n_samples, seed, y)))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let mut frame = vec![0.0; (WIDTH * HEIGHT) as usize];
+    for handle in threads {
+        for (y, row) in handle.join().unwrap() {
+            let start = (y * WIDTH) as usize;
+            frame[start..start + WIDTH as usize].copy_from_slice(&row[..]);
+        }
+    }
+    frame
+}
+
+/// Tone-map the magnitudes like the viewer does, and mark the listener.
+fn paint_frame(scene: &Scene, magnitudes: &[f32], exposure: f32) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(magnitudes.len() * 3);
+    for &m in magnitudes {
+        let r = renderer::gray_level(m - exposure);
+        rgb.extend_from_slice(&[r, r, r]);
+    }
+    if let Some(ref listener) = scene.listener {
+        let p = listener.position;
+        let (x, y) = Viewport::fit(scene, WIDTH, HEIGHT).to_pixel(Vec2::new(p.x, p.y));
+        renderer::draw_marker(&mut rgb[..], WIDTH, HEIGHT, x, y, renderer::MARKER_COLOR);
+    }
+    rgb
+}
+
+/// Return the magnitude at the listener, in log10 units.
+///
+/// Without a listener, or if the listener is silent, returns 0.
+fn listener_exposure(scene: &Scene, magnitudes: &[f32]) -> f32 {
+    let listener = match scene.listener {
+        Some(ref listener) => listener.position,
+        None => return 0.0,
+    };
+    let (x, y) = Viewport::fit(scene, WIDTH, HEIGHT).to_pixel(Vec2::new(listener.x, listener.y));
+    if x < 0 || y < 0 || x >= WIDTH as i32 || y >= HEIGHT as i32 {
+        return 0.0
+    }
+    let m = magnitudes[(y as u32 * WIDTH + x as u32) as usize];
+    if m.is_finite() { m } else { 0.0 }
+}
+
+/// Format a time in seconds as an SRT timestamp.
+fn srt_time(seconds: f32) -> String {
+    let ms = (seconds * 1000.0).round() as u32;
+    format!("{:02}:{:02}:{:02},{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// Render the schedule at `frequency` into numbered PPM frames in `out_dir`.
+///
+/// Every step is sampled once, with `n_samples` samples per pixel, and its
+/// frame is repeated for the duration of the step. All steps share the
+/// exposure of the first step, so a step that is louder at the listener is
+/// also brighter. Next to the frames, `captions.srt` holds the labels of the
+/// steps as subtitles, which video encoders can burn into the clip. Progress
+/// is reported on stderr.
+pub fn write_frames(
+    scene: &Scene,
+    schedule: &Schedule,
+    frequency: f32,
+    n_samples: u32,
+    seed: u64,
+    out_dir: &Path,
+) -> io::Result<()> {
+    fs::create_dir_all(out_dir)?;
+    let mut captions = io::BufWriter::new(fs::File::create(out_dir.join("captions.srt"))?);
+
+    let mut exposure = None;
+    let mut frame_index = 0;
+    for (i, step) in schedule.steps.iter().enumerate() {
+        eprint!("\rSampling step {} of {} ...", i + 1, schedule.steps.len());
+        let step_scene = Arc::new(apply_step(scene, step));
+        let magnitudes = sample_frame(&step_scene, frequency, n_samples, seed);
+        let exposure = *exposure.get_or_insert_with(|| listener_exposure(scene, &magnitudes[..]));
+        let rgb = paint_frame(scene, &magnitudes[..], exposure);
+
+        let start = frame_index as f32 / schedule.frames_per_second as f32;
+        for _ in 0..schedule.frames_in(step) {
+            let path = out_dir.join(format!("frame_{:05}.ppm", frame_index));
+            let mut out = io::BufWriter::new(fs::File::create(path)?);
+            writeln!(out, "P6\n{} {}\n255", WIDTH, HEIGHT)?;
+            out.write_all(&rgb[..])?;
+            out.flush()?;
+            frame_index += 1;
+        }
+        let end = frame_index as f32 / schedule.frames_per_second as f32;
+
+        write!(captions, "{}\n{} --> {}\n{}\n\n", i + 1, srt_time(start), srt_time(end), step.label)?;
+    }
+    eprintln!();
+
+    captions.flush()
+}
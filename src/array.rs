@@ -0,0 +1,100 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::scene::Scene;
+
+/// A directional arrangement of subwoofers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ArrayKind {
+    /// A gradient array: a second source behind the front one, delayed by the
+    /// time sound takes to travel from the front to the back, and inverted.
+    /// At the back, the two cancel, in front, they sum.
+    Cardioid,
+
+    /// `count` sources in a line along the aim direction, delayed such that
+    /// their wavefronts coincide in front. The rejection at the back is
+    /// narrower in frequency than for a cardioid, but it needs no inversion.
+    EndFire { count: usize },
+}
+
+/// Sources added behind a source to make it directional.
+#[derive(Clone, Debug)]
+pub struct SourceArray {
+    pub kind: ArrayKind,
+
+    /// The index of the front source, the one that the array was built for.
+    pub front: usize,
+
+    /// The indices of the sources that were added behind the front source.
+    pub members: Vec<usize>,
+
+    /// The delay of the front source without and with the array, in ms.
+    ///
+    /// In an end-fire array, the front source waits for the sound of the
+    /// sources behind it.
+    front_delay_ms: (f32, f32),
+
+    pub enabled: bool,
+}
+
+/// Add sources behind source `front` to form an array, and return its index.
+///
+/// The sources are `spacing` meters apart, along the aim direction of the
+/// front source, and they are copies of it, apart from their position, delay,
+/// and polarity. The array is added to `scene.arrays`, and it is enabled.
+pub fn add_array(scene: &mut Scene, front: usize, kind: ArrayKind, spacing: f32) -> usize {
+    let source = scene.sources[front].clone();
+    let step_ms = spacing / scene.atmosphere.speed_of_sound() * 1000.0;
+    let n_behind = match kind {
+        ArrayKind::Cardioid => 1,
+        ArrayKind::EndFire { count } => count - 1,
+    };
+
+    let mut members = Vec::with_capacity(n_behind);
+    for k in 1..n_behind + 1 {
+        let mut member = source.clone();
+        member.position = source.position - source.direction * (spacing * k as f32);
+        match kind {
+            ArrayKind::Cardioid => {
+                member.delay_ms += step_ms;
+                member.invert = !source.invert;
+            }
+            ArrayKind::EndFire { .. } => {
+                // The source furthest back plays first.
+                member.delay_ms += step_ms * (n_behind - k) as f32;
+            }
+        }
+        members.push(scene.sources.len());
+        scene.sources.push(member);
+    }
+
+    let front_delay_ms = match kind {
+        ArrayKind::Cardioid => source.delay_ms,
+        ArrayKind::EndFire { .. } => source.delay_ms + step_ms * n_behind as f32,
+    };
+    scene.sources[front].delay_ms = front_delay_ms;
+
+    scene.arrays.push(SourceArray {
+        kind: kind,
+        front: front,
+        members: members,
+        front_delay_ms: (source.delay_ms, front_delay_ms),
+        enabled: true,
+    });
+    scene.arrays.len() - 1
+}
+
+/// Switch array `ai` on or off, to compare the front source alone with the array.
+pub fn set_enabled(scene: &mut Scene, ai: usize, enabled: bool) {
+    let array = &mut scene.arrays[ai];
+    array.enabled = enabled;
+    for &si in &array.members {
+        scene.sources[si].enabled = enabled;
+    }
+    let (alone, in_array) = array.front_delay_ms;
+    scene.sources[array.front].delay_ms = if enabled { in_array } else { alone };
+}
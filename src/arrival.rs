@@ -0,0 +1,93 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::plot::{self, Canvas};
+use crate::renderer::{Viewport, SLICE_HEIGHT};
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// Differences in arrival time of this many ms or more get the most saturated color.
+const RANGE_MS: f32 = 5.0;
+
+/// The interval between lines of equal arrival time, in ms.
+const CONTOUR_MS: f32 = 1.0;
+
+/// A map of when the direct sound arrives, seen from above.
+pub struct ArrivalMap {
+    pub canvas: Canvas,
+
+    /// The earliest and latest time on the map, in ms.
+    pub min_ms: f32,
+    pub max_ms: f32,
+}
+
+/// Map the arrival time of the direct sound of source `si` at ear height.
+///
+/// If `reference` is a source, the map shows the arrival time of `si` minus
+/// that of the reference instead: red where `si` arrives later, blue where it
+/// arrives earlier, white where they arrive together. Otherwise, white is the
+/// earliest arrival on the map, and blue the latest. Gray lines connect points
+/// of equal time every millisecond. Points that the direct sound does not
+/// reach, because a wall is in the way, are gray.
+pub fn paint_map(scene: &Scene, si: usize, reference: Option<usize>, width: u32, height: u32) -> ArrivalMap {
+    let viewport = Viewport::fit(scene, width, height);
+    let times: Vec<Option<f32>> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let p = viewport.to_world(x, y);
+            let position = Vec3::new(p.x, p.y, scene.floor_height_at(p) + SLICE_HEIGHT);
+            if !scene.contains(position) {
+                return None
+            }
+            let t = scene.arrival_ms(si, position)?;
+            match reference {
+                Some(ri) => scene.arrival_ms(ri, position).map(|t_ref| t - t_ref),
+                None => Some(t),
+            }
+        })
+        .collect();
+
+    let min_ms = times.iter().filter_map(|&t| t).fold(std::f32::INFINITY, f32::min);
+    let max_ms = times.iter().filter_map(|&t| t).fold(std::f32::NEG_INFINITY, f32::max);
+
+    let mut canvas = Canvas::new(width, height, plot::GRID);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            let t = match times[i] {
+                Some(t) => t,
+                None => continue,
+            };
+            let color = match reference {
+                Some(_) => {
+                    let s = (t / RANGE_MS).max(-1.0).min(1.0);
+                    let target = if s > 0.0 { plot::RED } else { plot::BLUE };
+                    plot::lerp_color(plot::WHITE, target, s.abs())
+                }
+                None => plot::lerp_color(plot::WHITE, plot::BLUE, (t - min_ms) / (max_ms - min_ms).max(1e-3)),
+            };
+
+            // Draw a contour where the time crosses a multiple of the interval
+            // between this pixel and its right or bottom neighbor.
+            let band = (t / CONTOUR_MS).floor() as i32;
+            let is_contour = [(x + 1, y), (x, y + 1)].iter().any(|&(nx, ny)| {
+                nx < width && ny < height && match times[(ny * width + nx) as usize] {
+                    Some(tn) => (tn / CONTOUR_MS).floor() as i32 != band,
+                    None => false,
+                }
+            });
+            let color = if is_contour { plot::lerp_color(color, plot::BLACK, 0.3) } else { color };
+            canvas.set_pixel(x as i32, y as i32, color);
+        }
+    }
+
+    ArrivalMap {
+        canvas: canvas,
+        min_ms: min_ms,
+        max_ms: max_ms,
+    }
+}
@@ -0,0 +1,93 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+/// The reference pressure of ISO 9613-1, one standard atmosphere, in kPa.
+const REFERENCE_PRESSURE_KPA: f32 = 101.325;
+
+/// The reference temperature of ISO 9613-1, 20 degrees Celsius, in Kelvin.
+const REFERENCE_TEMPERATURE_K: f32 = 293.15;
+
+/// The triple-point isotherm temperature of water, in Kelvin.
+const TRIPLE_POINT_K: f32 = 273.16;
+
+/// The molar masses of dry air and water, in g/mol.
+const MOLAR_MASS_AIR: f32 = 28.966;
+const MOLAR_MASS_WATER: f32 = 18.016;
+
+/// The air that sound travels through.
+///
+/// The speed of sound and the absorption coefficients only depend on the
+/// temperature, pressure, and humidity, so we compute them once, and
+/// `absorption_db_per_m()` is cheap enough to call for every sample.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Atmosphere {
+    speed_of_sound: f32,
+
+    /// The relaxation frequencies of oxygen and nitrogen, in Hz.
+    relaxation_oxygen: f32,
+    relaxation_nitrogen: f32,
+
+    /// The factors of the classical, oxygen, and nitrogen absorption terms.
+    classical: f32,
+    oxygen: f32,
+    nitrogen: f32,
+}
+
+impl Atmosphere {
+    /// Describe the air at `temperature_c` degrees Celsius, a pressure of
+    /// `pressure_kpa` kPa, and a relative humidity of `humidity` percent.
+    pub fn new(temperature_c: f32, pressure_kpa: f32, humidity: f32) -> Atmosphere {
+        let temperature_k = temperature_c + 273.15;
+        let t = temperature_k / REFERENCE_TEMPERATURE_K;
+        let p = pressure_kpa / REFERENCE_PRESSURE_KPA;
+
+        // The molar concentration of water vapour in percent, as in ISO 9613-1 annex B.
+        let c = -6.8346 * (TRIPLE_POINT_K / temperature_k).powf(1.261) + 4.6151;
+        let h = humidity * 10.0_f32.powf(c) / p;
+
+        // For an ideal gas, the speed of sound depends on the temperature and
+        // on the molar mass, not on the pressure. Water vapour is lighter than
+        // air, so humid air carries sound slightly faster.
+        let molar_mass = MOLAR_MASS_AIR * (1.0 - 0.01 * h) + MOLAR_MASS_WATER * 0.01 * h;
+        let speed_of_sound = 331.3 * (temperature_k / 273.15).sqrt() * (MOLAR_MASS_AIR / molar_mass).sqrt();
+
+        Atmosphere {
+            speed_of_sound: speed_of_sound,
+            relaxation_oxygen: p * (24.0 + 4.04e4 * h * (0.02 + h) / (0.391 + h)),
+            relaxation_nitrogen: p * t.powf(-0.5) * (9.0 + 280.0 * h * (-4.170 * (t.powf(-1.0 / 3.0) - 1.0)).exp()),
+            classical: 1.84e-11 * p.recip() * t.sqrt(),
+            oxygen: t.powf(-2.5) * 0.01275 * (-2239.1 / temperature_k).exp(),
+            nitrogen: t.powf(-2.5) * 0.1068 * (-3352.0 / temperature_k).exp(),
+        }
+    }
+
+    /// Room temperature at sea level: 25 degrees Celsius, 1 atm, 50% humidity.
+    pub fn standard() -> Atmosphere {
+        Atmosphere::new(25.0, REFERENCE_PRESSURE_KPA, 50.0)
+    }
+
+    /// Return the speed of sound in m/s.
+    pub fn speed_of_sound(&self) -> f32 {
+        self.speed_of_sound
+    }
+
+    /// Return the attenuation by absorption in the air at `frequency`, in dB per meter.
+    ///
+    /// This is the pure-tone attenuation coefficient of ISO 9613-1. In the
+    /// bass range it is tiny, less than 0.001 dB/m at 100 Hz, but over the
+    /// long paths of late reflections it adds up.
+    pub fn absorption_db_per_m(&self, frequency: f32) -> f32 {
+        let f2 = frequency * frequency;
+        let (fr_o, fr_n) = (self.relaxation_oxygen, self.relaxation_nitrogen);
+        8.686 * f2 * (self.classical + self.oxygen / (fr_o + f2 / fr_o) + self.nitrogen / (fr_n + f2 / fr_n))
+    }
+
+    /// Return the amplitude factor of absorption over `distance` meters at `frequency`.
+    pub fn attenuation(&self, frequency: f32, distance: f32) -> f32 {
+        10.0_f32.powf(-self.absorption_db_per_m(frequency) * distance / 20.0)
+    }
+}
@@ -0,0 +1,158 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::rand::Rng;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The number of rays to trace per source.
+const N_RAYS: u32 = 20_000;
+
+/// The number of reflections to follow a ray for.
+const N_PASSES: usize = 30;
+
+/// Where the energy went in one pass, the reflections of one order.
+#[derive(Copy, Clone, Default)]
+struct Pass {
+    /// Energy absorbed by the faces that the rays hit.
+    absorbed: f32,
+
+    /// Energy gained at faces that reflect more than they receive.
+    gained: f32,
+
+    /// Energy of rays that left the scene without hitting a face.
+    escaped: f32,
+}
+
+/// Return a direction, uniformly distributed over the unit sphere.
+fn random_direction(rng: &mut Rng) -> Vec3 {
+    let z = 2.0 * rng.uniform() - 1.0;
+    let t = 2.0 * PI * rng.uniform();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(r * t.cos(), r * t.sin(), z)
+}
+
+/// Return the first face that the ray from `p` in direction `d` hits, and where.
+///
+/// Openings are skipped, sound passes through them.
+fn trace(scene: &Scene, p: Vec3, d: Vec3) -> Option<(usize, Vec3)> {
+    // Start slightly away from `p`, so we do not hit the face that we just
+    // reflected off again.
+    let start = p + d * 1e-4;
+    let end = p + d * 1e4;
+    let mut nearest = None;
+    let mut nearest_distance = std::f32::INFINITY;
+    for (i, face) in scene.faces.iter().enumerate() {
+        if face.opening {
+            continue
+        }
+        if let Some(q) = face.intersect(start, end) {
+            let distance = (q - p).norm();
+            if distance < nearest_distance && face.bounds_contain(q) {
+                nearest = Some((i, q));
+                nearest_distance = distance;
+            }
+        }
+    }
+    nearest
+}
+
+/// Trace rays from every source through the scene, and print where the energy goes.
+///
+/// Every ray carries the energy that the source emits in its direction, and
+/// at every face that it hits, a fraction `1 - |r|²` is absorbed, where `r` is
+/// the reflection coefficient of the face at `frequency`. What is left after
+/// the last pass is still in flight. The emitted energy must equal the sum of
+/// the absorbed, escaped, and in-flight energy; if it does not, or if a face
+/// reflects more energy than it receives, there is a bug in the model.
+pub fn print_audit(scene: &Scene, frequency: f32, seed: u64) {
+    let mut rng = Rng::new(seed);
+    let mut passes = [Pass::default(); N_PASSES];
+    let mut emitted = 0.0;
+    let mut in_flight = 0.0;
+    let mut inverted = 0.0;
+
+    for source in scene.sources.iter().filter(|s| s.enabled) {
+        // The rays sample the sphere uniformly, so the energy of a ray is the
+        // power that the source radiates in its direction, per ray.
+        let on_axis = source.directivity(&scene.atmosphere, frequency, source.direction);
+        for _ in 0..N_RAYS {
+            let mut d = random_direction(&mut rng);
+            let gain = source.directivity(&scene.atmosphere, frequency, d);
+            if (gain * on_axis.conj()).real() < 0.0 {
+                inverted += gain.norm_squared();
+            }
+            let mut energy = gain.norm_squared();
+            emitted += energy;
+            let mut p = source.position;
+
+            for pass in passes.iter_mut() {
+                match trace(scene, p, d) {
+                    None => {
+                        pass.escaped += energy;
+                        energy = 0.0;
+                        break
+                    }
+                    Some((fi, q)) => {
+                        let face = &scene.faces[fi];
+                        let reflected = energy * face.reflectivity_at(frequency).norm_squared();
+                        if reflected > energy {
+                            pass.gained += reflected - energy;
+                        } else {
+                            pass.absorbed += energy - reflected;
+                        }
+                        energy = reflected;
+                        let n = face.normal();
+                        d = d - n * (2.0 * n.dot(d));
+                        p = q;
+                    }
+                }
+            }
+            in_flight += energy;
+        }
+    }
+
+    println!("Frequency: {} Hz", frequency);
+    println!("Seed: {}", seed);
+    println!("Rays: {} per source", N_RAYS);
+    println!();
+
+    // Report energies relative to the emitted energy, in percent.
+    let pct = |e: f32| 100.0 * e / emitted;
+    println!("{:>4}  {:>9}  {:>9}  {:>9}", "pass", "absorbed", "escaped", "gained");
+    for (i, pass) in passes.iter().enumerate() {
+        println!(
+            "{:>4}  {:>8.3}%  {:>8.3}%  {:>8.3}%",
+            i + 1, pct(pass.absorbed), pct(pass.escaped), pct(pass.gained),
+        );
+    }
+    println!();
+
+    let absorbed: f32 = passes.iter().map(|p| p.absorbed).sum();
+    let escaped: f32 = passes.iter().map(|p| p.escaped).sum();
+    let gained: f32 = passes.iter().map(|p| p.gained).sum();
+    let balance = emitted + gained - absorbed - escaped - in_flight;
+
+    println!("Absorbed:   {:>8.3}%", pct(absorbed));
+    println!("Escaped:    {:>8.3}%", pct(escaped));
+    println!("In flight:  {:>8.3}%", pct(in_flight));
+    println!("Gained:     {:>8.3}%", pct(gained));
+    println!("Imbalance:  {:>8.3}%", pct(balance));
+    println!("Emitted with inverted phase: {:.1}%", pct(inverted));
+
+    if escaped > 0.0 {
+        println!("\nWarning: energy escaped, the scene is not closed.");
+    }
+    if gained > 0.0 {
+        println!("\nWarning: some faces reflect more energy than they receive.");
+    }
+    if pct(balance).abs() > 0.1 {
+        println!("\nWarning: energy is not conserved.");
+    }
+}
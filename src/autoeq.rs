@@ -0,0 +1,206 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::eq::{Biquad, FilterKind};
+use crate::peaks;
+use crate::rand::Rng;
+use crate::response::{self, Smoothing};
+use crate::scene::Scene;
+use crate::target::Target;
+
+/// The number of Monte Carlo samples per frequency, per seat.
+const N_SAMPLES: u32 = 2048;
+
+/// The resolution of the response.
+const STEPS_PER_OCTAVE: u32 = 24;
+
+/// The response is smoothed over this fraction of an octave before fitting,
+/// so the filters do not chase the noise of the simulation.
+const SMOOTHING: u32 = 12;
+
+/// The largest cut and boost of a filter, in dB.
+///
+/// Boosting a dip that is caused by cancellation only costs headroom, the
+/// cancellation deepens along, so boosts are kept small.
+const MAX_CUT_DB: f32 = 15.0;
+const MAX_BOOST_DB: f32 = 3.0;
+
+/// The range of the quality factor of the filters.
+const MIN_Q: f32 = 0.5;
+const MAX_Q: f32 = 20.0;
+
+/// Filters that would change the level by less than this, in dB, are not added.
+const MIN_GAIN_DB: f32 = 1.0;
+
+/// The most passes of the refinement over all filters.
+const MAX_PASSES: u32 = 200;
+
+/// The filters that fit the response to the target, and how well they do.
+pub struct Fit {
+    pub filters: Vec<Biquad>,
+
+    /// The RMS deviation from the target before and after the EQ, in dB.
+    pub before_db: f32,
+    pub after_db: f32,
+
+    /// The number of seats that the response is averaged over, 1 for the listener alone.
+    pub n_seats: usize,
+}
+
+/// Return the gain of all filters together at `frequency`, in dB.
+fn eq_db(filters: &[Biquad], frequency: f32) -> f32 {
+    filters.iter().map(|f| response::to_db(f.response(frequency))).sum()
+}
+
+/// Return the response with the filters applied.
+fn equalized(filters: &[Biquad], frequencies: &[f32], levels_db: &[f32]) -> Vec<f32> {
+    frequencies.iter().zip(levels_db).map(|(&f, db)| db + eq_db(filters, f)).collect()
+}
+
+/// Return the filter with its parameters moved into their allowed ranges.
+fn clamped(mut filter: Biquad, f_min: f32, f_max: f32) -> Biquad {
+    filter.frequency = filter.frequency.max(f_min).min(f_max);
+    filter.gain_db = filter.gain_db.max(-MAX_CUT_DB).min(MAX_BOOST_DB);
+    filter.q = filter.q.max(MIN_Q).min(MAX_Q);
+    filter
+}
+
+/// Fit `n_filters` peaking filters between `f_min` and `f_max` to the response.
+///
+/// The response is that of all sources together, averaged over the listening
+/// area if the listener has one, because EQ applies to every seat. Filters
+/// are placed one at a time, on the largest deviation from the target that
+/// remains, with the Q of its bandwidth at half the deviation. Then all
+/// filters are refined together, by nudging their frequency, gain, and Q, as
+/// long as that brings the response closer to the target. The filters go on
+/// the input of the DSP, so every source gets them.
+pub fn fit(scene: &Scene, target: &Target, n_filters: usize, f_min: f32, f_max: f32, seed: u64) -> Fit {
+    let listener = scene.listener.as_ref().expect("Auto-EQ needs a listener.");
+    let frequencies = &response::log_frequencies(f_min, f_max, STEPS_PER_OCTAVE)[..];
+    let seats = match listener.area {
+        Some(..) => listener.seats(),
+        None => vec![listener.position],
+    };
+    let mut power = vec![0.0_f32; frequencies.len()];
+    for &position in &seats {
+        let mut rng = Rng::new(seed);
+        let r = response::sample_response(scene, &mut rng, position, frequencies, N_SAMPLES);
+        for (p, h) in power.iter_mut().zip(r) {
+            *p += h.norm_squared() / seats.len() as f32;
+        }
+    }
+    let levels: Vec<f32> = power.into_iter().map(|p| 10.0 * p.log10()).collect();
+    let levels = response::smooth_db(frequencies, &levels[..], Smoothing::Fraction(SMOOTHING));
+    let n_seats = seats.len();
+    let cost = |filters: &[Biquad]| target.deviation_db(frequencies, &equalized(filters, frequencies, &levels[..])[..]);
+
+    let mut filters: Vec<Biquad> = Vec::with_capacity(n_filters);
+    for _ in 0..n_filters {
+        let eq = equalized(&filters[..], frequencies, &levels[..]);
+        let aligned = target.aligned_db(frequencies, &eq[..]);
+        let residual: Vec<f32> = eq.iter().zip(&aligned).map(|(db, t)| db - t).collect();
+        let gain = |r: f32| (-r).max(-MAX_CUT_DB).min(MAX_BOOST_DB);
+        let i = (0..residual.len())
+            .max_by(|&a, &b| gain(residual[a]).abs().partial_cmp(&gain(residual[b]).abs()).unwrap())
+            .unwrap();
+        if gain(residual[i]).abs() < MIN_GAIN_DB {
+            break
+        }
+        let filter = Biquad {
+            kind: FilterKind::Peaking,
+            frequency: frequencies[i],
+            q: peaks::estimate_q(frequencies, &residual[..], i).unwrap_or(1.0),
+            gain_db: gain(residual[i]),
+        };
+        filters.push(clamped(filter, f_min, f_max));
+    }
+
+    let mut best = cost(&filters[..]);
+    for _ in 0..MAX_PASSES {
+        let before = best;
+        for k in 0..filters.len() {
+            let nudges: [fn(&mut Biquad, f32); 3] = [
+                |f, s| f.frequency *= 2.0_f32.powf(s / 96.0),
+                |f, s| f.gain_db += 0.1 * s,
+                |f, s| f.q *= 1.05_f32.powf(s),
+            ];
+            for nudge in nudges.iter() {
+                for &sign in &[-1.0, 1.0] {
+                    let mut candidate = filters.clone();
+                    nudge(&mut candidate[k], sign);
+                    candidate[k] = clamped(candidate[k], f_min, f_max);
+                    let c = cost(&candidate[..]);
+                    if c < best {
+                        best = c;
+                        filters = candidate;
+                    }
+                }
+            }
+        }
+        if best >= before {
+            break
+        }
+    }
+
+    Fit {
+        before_db: cost(&[]),
+        after_db: best,
+        filters: filters,
+        n_seats: n_seats,
+    }
+}
+
+/// Print the filters as biquad coefficients at `sample_rate`, in the format
+/// that the advanced mode of a miniDSP PEQ block accepts.
+///
+/// The coefficients are normalized to a0 = 1, and miniDSP expects a1 and a2
+/// with the opposite sign of the Audio EQ Cookbook.
+fn print_minidsp(filters: &[Biquad], sample_rate: f64) {
+    let lines: Vec<String> = filters
+        .iter()
+        .enumerate()
+        .map(|(i, filter)| {
+            let [b0, b1, b2, a0, a1, a2] = filter.coefficients_at(sample_rate);
+            format!(
+                "biquad{},\nb0={:.15},\nb1={:.15},\nb2={:.15},\na1={:.15},\na2={:.15}",
+                i + 1, b0 / a0, b1 / a0, b2 / a0, -a1 / a0, -a2 / a0,
+            )
+        })
+        .collect();
+    println!("{}", lines.join(",\n"));
+}
+
+/// Fit the filters, see `fit()`, and print them as a table and as miniDSP biquads.
+pub fn print_filters(scene: &Scene, n_filters: usize, f_min: f32, f_max: f32, sample_rate: f64, seed: u64) {
+    let target = scene.target.clone().unwrap_or_else(Target::flat);
+    let fit = fit(scene, &target, n_filters, f_min, f_max, seed);
+
+    println!("Seed: {}", seed);
+    if fit.filters.is_empty() {
+        println!("The response is within {:.0} dB of the target, no filters needed.", MIN_GAIN_DB);
+        return
+    }
+
+    println!("{:>6}  {:>7}  {:>12}  {:>7}  {:>5}", "filter", "type", "frequency Hz", "gain dB", "Q");
+    for (i, filter) in fit.filters.iter().enumerate() {
+        println!(
+            "{:>6}  {:>7}  {:>12.1}  {:>+7.1}  {:>5.2}",
+            i + 1, "peaking", filter.frequency, filter.gain_db, filter.q,
+        );
+    }
+    println!();
+    println!(
+        "The RMS deviation from {} between {:.0} and {:.0} Hz goes from {:.1} dB to {:.1} dB, {}.",
+        if scene.target.is_some() { "the target" } else { "a flat response" },
+        f_min, f_max, fit.before_db, fit.after_db,
+        if fit.n_seats > 1 { format!("averaged over {} seats", fit.n_seats) } else { "at the listener".to_string() },
+    );
+    println!("Put the filters on the input, so they apply to every source.");
+    println!();
+    println!("miniDSP biquads at {:.0} Hz:", sample_rate);
+    print_minidsp(&fit.filters[..], sample_rate);
+}
@@ -0,0 +1,62 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::plot::{self, Color};
+
+/// Viridis, sampled at 16 evenly spaced points.
+const VIRIDIS: [Color; 16] = [
+    [68, 1, 84], [72, 26, 108], [71, 47, 125], [65, 68, 135],
+    [57, 86, 140], [49, 104, 142], [42, 120, 142], [35, 136, 142],
+    [31, 152, 139], [34, 168, 132], [53, 183, 121], [84, 197, 104],
+    [122, 209, 81], [165, 219, 54], [210, 226, 27], [253, 231, 37],
+];
+
+/// Inferno, sampled at 16 evenly spaced points.
+const INFERNO: [Color; 16] = [
+    [0, 0, 4], [12, 8, 38], [36, 12, 79], [66, 10, 104],
+    [93, 18, 110], [120, 28, 109], [147, 38, 103], [174, 48, 92],
+    [199, 62, 76], [220, 80, 57], [237, 105, 37], [248, 133, 15],
+    [252, 165, 10], [250, 198, 45], [242, 230, 97], [252, 255, 164],
+];
+
+/// The ColorBrewer red-blue scheme, from blue to red through white.
+const DIVERGING: [Color; 11] = [
+    [5, 48, 97], [33, 102, 172], [67, 147, 195], [146, 197, 222],
+    [209, 229, 240], [247, 247, 247], [253, 219, 199], [244, 165, 130],
+    [214, 96, 77], [178, 24, 43], [103, 0, 31],
+];
+
+/// How to color the levels of the image.
+///
+/// Viridis and inferno are perceptually uniform: equal steps in level look
+/// like equal steps in color, unlike the gray scale, where the differences
+/// between the darker grays are hard to see. The diverging map is white in
+/// the middle, for signed data such as the pressure of the time-domain
+/// solver, and for levels above and below the listener.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Colormap {
+    Gray,
+    Viridis,
+    Inferno,
+    Diverging,
+}
+
+impl Colormap {
+    /// Return the color of a level from 0 (lowest) to 255 (highest), as the
+    /// gray level of `renderer::gray_level()` would be.
+    pub fn color(self, level: u8) -> Color {
+        let table: &[Color] = match self {
+            Colormap::Gray => return [level, level, level],
+            Colormap::Viridis => &VIRIDIS[..],
+            Colormap::Inferno => &INFERNO[..],
+            Colormap::Diverging => &DIVERGING[..],
+        };
+        let t = level as f32 / 255.0 * (table.len() - 1) as f32;
+        let i = (t as usize).min(table.len() - 2);
+        plot::lerp_color(table[i], table[i + 1], t - i as f32)
+    }
+}
@@ -35,9 +35,22 @@ impl Complex {
         self.0.y
     }
 
+    pub fn norm_squared(&self) -> f32 {
+        self.0.norm_squared()
+    }
+
     pub fn norm(&self) -> f32 {
         self.0.norm()
     }
+
+    /// Return the argument, the angle in radians from -π to π.
+    pub fn arg(&self) -> f32 {
+        self.imag().atan2(self.real())
+    }
+
+    pub fn conj(&self) -> Complex {
+        Complex::new(self.real(), -self.imag())
+    }
 }
 
 impl ops::Add for Complex {
@@ -74,3 +87,119 @@ impl ops::Mul<Self> for Complex {
         )
     }
 }
+
+impl ops::Div<Self> for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        (self * other.conj()) * other.norm_squared().recip()
+    }
+}
+
+/// Add `z` to `sum` with Kahan's compensated summation.
+///
+/// Adding a small number to a large one loses the low digits of the small one.
+/// `compensation` holds what the previous additions lost, it starts at zero,
+/// and it is taken into account in the next addition. The error of a long sum
+/// then does not grow with the number of terms.
+pub fn kahan_add(sum: &mut Complex, compensation: &mut Complex, z: Complex) {
+    let y = z - *compensation;
+    let t = *sum + y;
+    *compensation = (t - *sum) - y;
+    *sum = t;
+}
+
+/// A complex number in double precision, to sum many samples in.
+///
+/// The samples themselves are single precision, see `Complex`. A sum of
+/// millions of them in single precision loses the small ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex64 {
+    pub real: f64,
+    pub imag: f64,
+}
+
+impl Complex64 {
+    pub fn zero() -> Complex64 {
+        Complex64 { real: 0.0, imag: 0.0 }
+    }
+
+    /// Round to single precision.
+    pub fn to_f32(&self) -> Complex {
+        Complex::new(self.real as f32, self.imag as f32)
+    }
+}
+
+impl From<Complex> for Complex64 {
+    fn from(z: Complex) -> Complex64 {
+        Complex64 {
+            real: z.real() as f64,
+            imag: z.imag() as f64,
+        }
+    }
+}
+
+impl ops::Add for Complex64 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Complex64 {
+            real: self.real + other.real,
+            imag: self.imag + other.imag,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rand::Rng;
+    use super::{Complex, Complex64, kahan_add};
+
+    /// The number of samples to sum, about as many as a pixel gets in a long
+    /// render.
+    const N_SAMPLES: u32 = 1 << 20;
+
+    /// Sum a seeded stream of samples that scatter around a mean, like those
+    /// of a pixel, with `add`, which adds a sample to the sum so far. Return
+    /// the relative error against the exact sum.
+    fn relative_error<F: FnMut(Complex, Complex) -> Complex>(mut add: F) -> f64 {
+        let mut rng = Rng::new(42);
+        let mut sum = Complex::zero();
+        let mut exact = Complex64::zero();
+        for _ in 0..N_SAMPLES {
+            let z = Complex::new(0.3 + rng.uniform() * 0.1, -0.2 + rng.uniform() * 0.1);
+            exact = exact + Complex64::from(z);
+            sum = add(sum, z);
+        }
+        let error_real = sum.real() as f64 - exact.real;
+        let error_imag = sum.imag() as f64 - exact.imag;
+        error_real.hypot(error_imag) / exact.real.hypot(exact.imag)
+    }
+
+    #[test]
+    fn single_sum_drifts() {
+        let error = relative_error(|sum, z| sum + z);
+        assert!(error > 1e-5, "relative error is {}", error);
+    }
+
+    #[test]
+    fn double_sum_does_not_drift() {
+        let mut sum = Complex64::zero();
+        let error = relative_error(|_, z| {
+            sum = sum + Complex64::from(z);
+            sum.to_f32()
+        });
+        // What remains is rounding the sum to single precision once.
+        assert!(error < 1e-7, "relative error is {}", error);
+    }
+
+    #[test]
+    fn compensated_sum_does_not_drift() {
+        let mut compensation = Complex::zero();
+        let error = relative_error(|mut sum, z| {
+            kahan_add(&mut sum, &mut compensation, z);
+            sum
+        });
+        assert!(error < 1e-7, "relative error is {}", error);
+    }
+}
@@ -0,0 +1,153 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::{Directivity, Scene, Source};
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// The number of Monte Carlo samples per frequency, per spot.
+const N_SAMPLES: u32 = 512;
+
+/// The distance of the spots from the walls, about half the depth of a sub.
+const INSET: f32 = 0.3;
+
+/// The height of the spots above the floor, where the driver of a sub is.
+const SUB_HEIGHT: f32 = 0.3;
+
+/// The largest distance between spots along a wall.
+const STEP: f32 = 0.5;
+
+/// Spots closer together than this are considered the same spot.
+const MIN_DISTANCE: f32 = 0.1;
+
+/// How the crawl works, for display next to the results.
+pub const EXPLANATION: &str = "\
+The sub crawl: put the sub at the listening position, then crawl along the
+walls and listen where the bass sounds strongest and most even. Those are the
+spots to put the sub. This works because of reciprocity: the response from a
+point A to a point B is the same as from B to A, so the field of a sub at the
+listener shows how every spot in the room would sound at the listener.";
+
+/// A candidate position for a sub along the walls, and how it sounds at the listener.
+pub struct Spot {
+    /// Where along the walls the spot is, e.g. "corner 2" or "wall 1, 1.5 m",
+    /// the distance from the start of the wall.
+    pub name: String,
+    pub position: Vec3,
+
+    /// The average level over the band, in dB relative to the loudest spot.
+    pub level_db: f32,
+
+    /// The standard deviation of the level over the band, lower is more even.
+    pub spread_db: f32,
+}
+
+/// Return the scene with its sources replaced by a single omni source at `listener`.
+///
+/// The source has no EQ or delay, it is the monopole that reciprocity applies to.
+pub fn reciprocal_scene(scene: &Scene, listener: Vec3) -> Scene {
+    let mut reciprocal = scene.clone();
+    let mut source = Source::new(listener, listener + Vec3::new(1.0, 0.0, 0.0));
+    source.directivity = Directivity::Omni;
+    reciprocal.sources = vec![source];
+    reciprocal.arrays = Vec::new();
+    reciprocal
+}
+
+/// Return the spots along the walls of the rooms, with their names.
+///
+/// The spots are `INSET` from the walls, with the first and last spot on
+/// every wall in the corners. Openings have no spots. Only scenes built from
+/// floor plans have walls.
+fn wall_spots(scene: &Scene) -> Vec<(String, Vec3)> {
+    let mut spots: Vec<(String, Vec3)> = Vec::new();
+    let mut first_wall = 0;
+    for room in &scene.rooms {
+        let n_corners = room.corners.len();
+        for j in 0..n_corners {
+            let i = first_wall + j;
+            let face = &scene.faces[i];
+            if face.opening {
+                continue
+            }
+            let start = room.corners[j];
+            let along = room.corners[(j + 1) % n_corners] - start;
+            let length = along.norm();
+            if length < 2.0 * INSET {
+                continue
+            }
+            let normal = face.normal();
+            let inward = Vec2::new(normal.x, normal.y);
+
+            let n = ((length - 2.0 * INSET) / STEP).ceil().max(1.0) as usize;
+            for k in 0..n + 1 {
+                let d = INSET + (length - 2.0 * INSET) * k as f32 / n as f32;
+                let p = start + along * (d / length) + inward * INSET;
+                let position = Vec3::new(p.x, p.y, scene.floor_height_at(p) + SUB_HEIGHT);
+                let is_new = spots.iter().all(|s| (s.1 - position).norm() >= MIN_DISTANCE);
+                if !scene.contains(position) || !is_new {
+                    continue
+                }
+                // Corners are numbered like walls, wall j starts at corner j.
+                let name = match k {
+                    0 => format!("corner {}", i),
+                    _ if k == n => format!("corner {}", first_wall + (j + 1) % n_corners),
+                    _ => format!("wall {}, {:.1} m", i, d),
+                };
+                spots.push((name, position));
+            }
+        }
+        first_wall += n_corners;
+    }
+    spots
+}
+
+/// Put a virtual sub at `listener`, and rank the spots along the walls by
+/// the level there over the band from `f_min` to `f_max` Hz, loudest first.
+pub fn crawl(scene: &Scene, listener: Vec3, f_min: f32, f_max: f32, seed: u64) -> Vec<Spot> {
+    let reciprocal = reciprocal_scene(scene, listener);
+    let frequencies = response::log_frequencies(f_min, f_max, 12);
+    let mut rng = Rng::new(seed);
+
+    let mut spots: Vec<Spot> = wall_spots(scene)
+        .into_iter()
+        .map(|(name, position)| {
+            let r = response::sample_source_response(&reciprocal, &mut rng, 0, position, &frequencies[..], N_SAMPLES);
+            let powers: Vec<f32> = r.iter().map(|z| z.norm_squared()).collect();
+            let mean_power = powers.iter().sum::<f32>() / powers.len() as f32;
+            Spot {
+                name: name,
+                position: position,
+                level_db: 10.0 * mean_power.log10(),
+                spread_db: response::flatness_db(&r[..]),
+            }
+        })
+        .collect();
+
+    spots.sort_by(|a, b| b.level_db.partial_cmp(&a.level_db).unwrap());
+    let loudest = spots.first().map(|s| s.level_db).unwrap_or(0.0);
+    for spot in spots.iter_mut() {
+        spot.level_db -= loudest;
+    }
+    spots
+}
+
+/// Describe the best `n` spots, one per line.
+pub fn describe(spots: &[Spot], n: usize) -> String {
+    let lines: Vec<String> = spots
+        .iter()
+        .take(n)
+        .enumerate()
+        .map(|(i, s)| format!(
+            "{}. {} ({:.1}, {:.1}): {:+.1} dB, spread {:.1} dB",
+            i + 1, s.name, s.position.x, s.position.y, s.level_db, s.spread_db,
+        ))
+        .collect();
+    lines.join("\n")
+}
@@ -0,0 +1,190 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::scene::Obstacle;
+use crate::vec3::Vec3;
+
+/// The longest piece of an edge that is treated as a single secondary source, in meters.
+///
+/// It is shortened at high frequencies to an eighth of the wavelength.
+const MAX_SEGMENT: f32 = 0.1;
+
+/// A free edge of an obstacle, a wedge that sound bends around.
+///
+/// The edge is a line segment where two faces of the obstacle meet, or for
+/// an obstacle with two corners, which is a thin partial wall, the end of a
+/// face. Angles around the edge are measured from the first face, through
+/// the air, to the second face at `wedge_angle`.
+#[derive(Clone, Debug)]
+pub struct Edge {
+    start: Vec3,
+    direction: Vec3,
+    length: f32,
+
+    /// The direction into the first face, perpendicular to the edge.
+    into_face: Vec3,
+
+    /// The normal of the first face, pointing into the air.
+    normal: Vec3,
+
+    /// The angle of the air around the edge, above π for a wedge that sticks out.
+    wedge_angle: f32,
+}
+
+/// The position of a point relative to an edge, in cylindrical coordinates.
+#[derive(Copy, Clone, Debug)]
+struct EdgePoint {
+    /// The distance from the line through the edge.
+    r: f32,
+
+    /// The angle from the first face, through the air.
+    theta: f32,
+
+    /// The distance along the edge from its start.
+    z: f32,
+}
+
+impl Edge {
+    /// Construct the edge from `start` to `end`.
+    ///
+    /// The first face extends from the edge in the direction `into_face`, with
+    /// `normal` pointing into the air, and the second face extends in the
+    /// direction `into_other`. Edges around which the air spans at most π are
+    /// not wedges that stick out, they do not diffract, and this returns `None`.
+    pub fn new(start: Vec3, end: Vec3, into_face: Vec3, normal: Vec3, into_other: Vec3) -> Option<Edge> {
+        let mut edge = Edge {
+            start: start,
+            direction: (end - start).normalized(),
+            length: (end - start).norm(),
+            into_face: into_face.normalized(),
+            normal: normal.normalized(),
+            wedge_angle: 2.0 * PI,
+        };
+
+        // For a thin wall, both faces extend in the same direction.
+        let angle = edge.locate(start + into_other).theta;
+        if angle > 1e-3 {
+            edge.wedge_angle = angle;
+        }
+
+        if edge.wedge_angle > PI + 1e-3 {
+            Some(edge)
+        } else {
+            None
+        }
+    }
+
+    /// Return the position of `p` relative to the edge.
+    fn locate(&self, p: Vec3) -> EdgePoint {
+        let d = p - self.start;
+        let z = d.dot(self.direction);
+        let perpendicular = d - self.direction * z;
+        let theta = perpendicular.dot(self.normal).atan2(perpendicular.dot(self.into_face));
+        EdgePoint {
+            r: perpendicular.norm(),
+            theta: if theta < 0.0 { theta + 2.0 * PI } else { theta },
+            z: z,
+        }
+    }
+
+    /// Return the midpoints of the pieces of the edge, and their length.
+    ///
+    /// The pieces are at most `MAX_SEGMENT` long, and at most an eighth of
+    /// the wavelength.
+    pub fn segments(&self, wavelength: f32) -> (Vec<Vec3>, f32) {
+        let max_length = MAX_SEGMENT.min(0.125 * wavelength);
+        let n = (self.length / max_length).ceil().max(1.0) as usize;
+        let dz = self.length / n as f32;
+        let points = (0..n)
+            .map(|i| self.start + self.direction * ((i as f32 + 0.5) * dz))
+            .collect();
+        (points, dz)
+    }
+
+    /// Return the factor that the edge contributes through a piece of length
+    /// `dz` at `point`, relative to a reflection path of the same length.
+    ///
+    /// This is the Biot–Tolstoy–Medwin expression for an infinite rigid
+    /// wedge, as discretized by Svensson et al., in the frequency domain. The
+    /// diffracted field of a piece of the edge at distances m and l from the
+    /// source and the receiver is -ν/4π β dz e^(ik(m + l)) / ml, with ν = π
+    /// over the wedge angle, and β a function of the angles that peaks where
+    /// the receiver crosses into the shadow of the edge, or out of a
+    /// reflection zone of one of its faces. At the point on the edge closest
+    /// to those boundaries, β is infinite, the midpoints of the pieces avoid
+    /// it. Returns `None` if the source or the receiver is inside the wedge.
+    pub fn coefficient(&self, source: Vec3, receiver: Vec3, point: Vec3, dz: f32) -> Option<f32> {
+        let s = self.locate(source);
+        let r = self.locate(receiver);
+        let is_outside = |p: EdgePoint| p.r > 1e-3 && p.theta <= self.wedge_angle;
+        if !is_outside(s) || !is_outside(r) {
+            return None
+        }
+
+        let z = self.locate(point).z;
+        let m = (point - source).norm();
+        let l = (point - receiver).norm();
+        let y = ((m * l + (z - s.z) * (z - r.z)) / (s.r * r.r)).max(1.0);
+        let eta = (y + (y * y - 1.0).sqrt()).ln();
+
+        let nu = PI / self.wedge_angle;
+        let cosh_nu_eta = (nu * eta).cosh();
+        let beta: f32 = [
+            PI + s.theta + r.theta,
+            PI + s.theta - r.theta,
+            PI - s.theta + r.theta,
+            PI - s.theta - r.theta,
+        ]
+            .iter()
+            .map(|&phi| (nu * phi).sin() / (cosh_nu_eta - (nu * phi).cos()).max(1e-6))
+            .sum();
+
+        Some(-nu / (4.0 * PI) * beta * dz * (m + l) / (m * l))
+    }
+}
+
+/// Return the free edges of an obstacle.
+///
+/// These are the edges around the top, and the vertical edges at the
+/// corners that stick out. The edges along the floor are not free.
+pub fn obstacle_edges(obstacle: &Obstacle) -> Vec<Edge> {
+    let corners: Vec<Vec3> = obstacle.corners.iter().map(|c| Vec3::new(c.x, c.y, 0.0)).collect();
+    let n = corners.len();
+    let up = Vec3::new(0.0, 0.0, 1.0);
+    let down = Vec3::new(0.0, 0.0, -1.0);
+    let h = obstacle.height;
+    let mut edges = Vec::new();
+
+    if n == 2 {
+        // A thin partial wall: its top, and its two ends, are like a screen.
+        let along = corners[1] - corners[0];
+        let normal = along.cross(up);
+        let ends = [(corners[0], along), (corners[1], -along)];
+        edges.extend(Edge::new(corners[0] + up * h, corners[1] + up * h, down, normal, down));
+        for &(p, into) in &ends {
+            edges.extend(Edge::new(p, p + up * h, into, normal, into));
+        }
+        return edges
+    }
+
+    // The sides face outward, to the right of the corners when they are
+    // counterclockwise, and to the left otherwise.
+    let twice_area: f32 = (0..n).map(|i| corners[i].cross(corners[(i + 1) % n]).z).sum();
+    let outward = |along: Vec3| if twice_area > 0.0 { along.cross(up) } else { up.cross(along) };
+
+    for i in 0..n {
+        let p0 = corners[i];
+        let p1 = corners[(i + 1) % n];
+        let prev = corners[(i + n - 1) % n];
+        let normal = outward(p1 - p0);
+        edges.extend(Edge::new(p0 + up * h, p1 + up * h, down, normal, -normal));
+        edges.extend(Edge::new(p0, p0 + up * h, p1 - p0, normal, prev - p0));
+    }
+    edges
+}
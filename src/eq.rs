@@ -0,0 +1,227 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::complex::Complex;
+
+/// The sample rate that the filters are designed for, in Hz.
+///
+/// Far below Nyquist, the response barely depends on the sample rate, but
+/// DSPs do not have analog filters, so neither do we.
+const SAMPLE_RATE: f32 = 48_000.0;
+
+/// The shape of a biquad filter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    /// A bell around the center frequency, boosting or cutting by the gain.
+    Peaking,
+
+    /// Boost or cut below the corner frequency.
+    LowShelf,
+
+    /// Boost or cut above the corner frequency.
+    HighShelf,
+
+    /// A 12 dB per octave high-pass, such as a subsonic filter. Ignores the gain.
+    HighPass,
+
+    /// A 12 dB per octave low-pass. Ignores the gain.
+    LowPass,
+}
+
+/// A biquad filter, as in Robert Bristow-Johnson's Audio EQ Cookbook.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Biquad {
+    pub kind: FilterKind,
+
+    /// The center or corner frequency in Hz.
+    pub frequency: f32,
+
+    /// The quality factor. For shelves, a Q of 0.707 gives the steepest slope
+    /// without overshoot, for a high-pass and low-pass it is Butterworth.
+    pub q: f32,
+
+    pub gain_db: f32,
+}
+
+impl Biquad {
+    /// Return the coefficients (b0, b1, b2, a0, a1, a2) at `sample_rate`.
+    ///
+    /// They are computed in double precision, because at bass frequencies and
+    /// high sample rates, the poles are so close to the unit circle that DSPs
+    /// need more digits than single precision has.
+    pub fn coefficients_at(&self, sample_rate: f64) -> [f64; 6] {
+        let a = 10.0_f64.powf(self.gain_db as f64 / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * self.frequency as f64 / sample_rate;
+        let (sin_w0, cos_w0) = (w0.sin(), w0.cos());
+        let alpha = sin_w0 / (2.0 * self.q as f64);
+
+        match self.kind {
+            FilterKind::Peaking => [
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ],
+            FilterKind::LowShelf => {
+                let s = 2.0 * a.sqrt() * alpha;
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 + s),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 - s),
+                    (a + 1.0) + (a - 1.0) * cos_w0 + s,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    (a + 1.0) + (a - 1.0) * cos_w0 - s,
+                ]
+            }
+            FilterKind::HighShelf => {
+                let s = 2.0 * a.sqrt() * alpha;
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 + s),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 - s),
+                    (a + 1.0) - (a - 1.0) * cos_w0 + s,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    (a + 1.0) - (a - 1.0) * cos_w0 - s,
+                ]
+            }
+            FilterKind::HighPass => [
+                (1.0 + cos_w0) * 0.5,
+                -(1.0 + cos_w0),
+                (1.0 + cos_w0) * 0.5,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ],
+            FilterKind::LowPass => [
+                (1.0 - cos_w0) * 0.5,
+                1.0 - cos_w0,
+                (1.0 - cos_w0) * 0.5,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ],
+        }
+    }
+
+    /// Return the coefficients at the sample rate of the simulation.
+    fn coefficients(&self) -> [f32; 6] {
+        let c = self.coefficients_at(SAMPLE_RATE as f64);
+        [c[0] as f32, c[1] as f32, c[2] as f32, c[3] as f32, c[4] as f32, c[5] as f32]
+    }
+
+    /// Return the transfer function at `frequency`, as a factor to multiply the output by.
+    ///
+    /// This is H(z) at z = e^(iω). A filter delays the signal, which is a
+    /// negative angle in H, but a positive angle here, see `Source::drive()`,
+    /// so we return the conjugate.
+    pub fn response(&self, frequency: f32) -> Complex {
+        let [b0, b1, b2, a0, a1, a2] = self.coefficients();
+        let w = 2.0 * PI * frequency / SAMPLE_RATE;
+        let z1 = Complex::exp_i(w);
+        let z2 = Complex::exp_i(2.0 * w);
+        let num = Complex::new(b0, 0.0) + z1 * b1 + z2 * b2;
+        let den = Complex::new(a0, 0.0) + z1 * a1 + z2 * a2;
+        num * den.conj() * den.norm_squared().recip()
+    }
+}
+
+/// The alignment of a crossover.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CrossoverKind {
+    /// The sub and mains are both -3 dB at the crossover frequency.
+    Butterworth,
+
+    /// Squared Butterworth filters, -6 dB at the crossover frequency, so the
+    /// sub and mains sum to a flat response when they are in phase.
+    LinkwitzRiley,
+}
+
+/// Which side of a crossover a source is on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Band {
+    /// The low-pass side, for subwoofers.
+    Sub,
+
+    /// The high-pass side, for the main speakers.
+    Mains,
+}
+
+/// A crossover between subwoofers and main speakers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Crossover {
+    pub kind: CrossoverKind,
+    pub frequency: f32,
+
+    /// The order of the filters: the slope is 6 dB per octave per order. For
+    /// Linkwitz-Riley the order is even, LR4 has order 4.
+    pub order: u32,
+}
+
+impl Crossover {
+    /// Return the response of `band` at `frequency`, as a factor to multiply the output by.
+    ///
+    /// These are analog filters, H(s) at s = iω. A filter delays the signal,
+    /// which is a negative angle in H, but a positive angle here, see
+    /// `Source::drive()`, so we evaluate H at s = -iω instead. Note that the
+    /// high-pass of an LR2 has the opposite phase of the low-pass, so the
+    /// mains need to be inverted to sum with the sub.
+    pub fn response(&self, band: Band, frequency: f32) -> Complex {
+        let (n, sections) = match self.kind {
+            CrossoverKind::Butterworth => (self.order, 1),
+            CrossoverKind::LinkwitzRiley => (self.order / 2, 2),
+        };
+
+        // The normalized frequency, inverted for a high-pass, which turns the
+        // low-pass prototype into a high-pass.
+        let s = match band {
+            Band::Sub => Complex::new(0.0, -frequency / self.frequency),
+            Band::Mains => Complex::new(0.0, self.frequency / frequency),
+        };
+
+        // A Butterworth low-pass of order n has its poles evenly spaced on the
+        // left half of the unit circle, and the product of their negations is 1.
+        let mut h = Complex::new(1.0, 0.0);
+        for k in 1..n + 1 {
+            let pole = Complex::exp_i(PI * (2 * k + n - 1) as f32 / (2 * n) as f32);
+            h = h * (Complex::zero() - pole) / (s - pole);
+        }
+
+        let mut result = Complex::new(1.0, 0.0);
+        for _ in 0..sections {
+            result = result * h;
+        }
+        result
+    }
+}
+
+/// The natural low-frequency roll-off of a full-range speaker.
+///
+/// A sealed box rolls off like a second-order high-pass below its resonance,
+/// a ported box like a fourth-order one. Both are close to Butterworth
+/// alignments, so we model the roll-off as a Butterworth high-pass.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rolloff {
+    /// The -3 dB frequency in Hz.
+    pub frequency: f32,
+    pub order: u32,
+}
+
+impl Rolloff {
+    /// Return the response at `frequency`, as a factor to multiply the output by.
+    pub fn response(&self, frequency: f32) -> Complex {
+        let high_pass = Crossover {
+            kind: CrossoverKind::Butterworth,
+            frequency: self.frequency,
+            order: self.order,
+        };
+        high_pass.response(Band::Mains, frequency)
+    }
+}
@@ -0,0 +1,345 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+use std::io;
+use std::io::Write;
+
+use crate::impulse;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The frequency at which the reflectivity of the faces is evaluated.
+///
+/// The boundaries of the grid absorb equally at all frequencies, we take the
+/// absorption in the middle of the bass range.
+const BOUNDARY_FREQUENCY_HZ: f32 = 100.0;
+
+/// The corner frequency of the high-pass filter on the pulses.
+const HIGH_PASS_HZ: f32 = 10.0;
+
+/// The grid resolution and time step of the time-domain solver.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FdtdParams {
+    /// The distance between grid points, in meters.
+    pub spacing: f32,
+
+    /// The time step as a fraction of the largest stable one, at most 1.
+    ///
+    /// The Courant-Friedrichs-Lewy condition limits the time step to the
+    /// spacing divided by c√3. Smaller steps cost time, but not accuracy.
+    pub courant: f32,
+}
+
+impl FdtdParams {
+    /// A 10 cm grid at the largest stable time step, good up to about 340 Hz.
+    pub fn standard() -> FdtdParams {
+        FdtdParams {
+            spacing: 0.1,
+            courant: 1.0,
+        }
+    }
+
+    /// Return the highest frequency that the grid resolves with little dispersion.
+    ///
+    /// That takes about 10 grid points per wavelength.
+    pub fn max_frequency(&self, speed_of_sound: f32) -> f32 {
+        speed_of_sound / (10.0 * self.spacing)
+    }
+}
+
+/// A pulse that one source emits.
+struct Pulse {
+    /// The index of the grid point of the source.
+    index: usize,
+
+    /// The gain and polarity of the source.
+    amplitude: f32,
+
+    /// The time of the peak of the pulse, in seconds.
+    t_peak: f32,
+
+    /// The previous input and outputs of the two high-pass stages.
+    previous: [f32; 3],
+}
+
+impl Pulse {
+    /// Return the next sample to inject, for the given time and Gaussian.
+    ///
+    /// A pulse that does not return to zero displacement would fill the room
+    /// with a static pressure that never leaves it, so the Gaussian is passed
+    /// through two one-pole high-pass filters, like a sealed box rolls off.
+    fn next(&mut self, t: f32, sigma: f32, alpha: f32) -> f32 {
+        let u = (t - self.t_peak) / sigma;
+        let x = self.amplitude * (-0.5 * u * u).exp();
+        let [x0, y0, z0] = self.previous;
+        let y = alpha * (y0 + x - x0);
+        let z = alpha * (z0 + y - y0);
+        self.previous = [x, y, z];
+        z
+    }
+}
+
+/// A finite-difference time-domain simulation of the pressure in the scene.
+///
+/// The room is voxelized into a regular grid, and the wave equation is
+/// integrated with the standard leapfrog scheme with a 7-point stencil. The
+/// faces are locally reacting boundaries with a frequency-independent
+/// admittance, derived from their reflectivity at `BOUNDARY_FREQUENCY_HZ`. The
+/// sources are omnidirectional, and emit a Gaussian pulse that contains the
+/// frequencies up to `FdtdParams::max_frequency()`, high-passed at
+/// `HIGH_PASS_HZ`, and delayed, scaled, and inverted according to their
+/// settings. Their EQ and directivity do not apply.
+pub struct Fdtd {
+    origin: Vec3,
+    spacing: f32,
+    nx: usize,
+    ny: usize,
+    nz: usize,
+
+    /// The time step in seconds. Its reciprocal is an integer sample rate.
+    dt: f32,
+
+    /// The Courant number c dt / spacing.
+    lambda: f32,
+
+    /// The pressure at the current and at the previous time step.
+    pressure: Vec<f32>,
+    previous: Vec<f32>,
+
+    /// For every grid point, the number of neighbors that lie inside the
+    /// space, or `None` if the point itself lies outside.
+    neighbors: Vec<Option<u8>>,
+
+    /// The normalized admittance of the boundaries.
+    beta: f32,
+
+    /// The standard deviation of the pulses, in seconds.
+    sigma: f32,
+    pulses: Vec<Pulse>,
+
+    /// The number of time steps taken.
+    steps: u32,
+}
+
+impl Fdtd {
+    /// Voxelize the scene and set up the pulses of the audible sources.
+    pub fn new(scene: &Scene, params: FdtdParams) -> Fdtd {
+        let c = scene.atmosphere.speed_of_sound();
+        let (min, max) = scene.bounding_box();
+        let h = params.spacing;
+        let n = |extent: f32| (extent / h).ceil() as usize + 1;
+        let (nx, ny, nz) = (n(max.x - min.x), n(max.y - min.y), n(max.z - min.z));
+
+        // The largest stable time step for the 7-point stencil in 3D is
+        // h / (c√3). Round the sample rate up to an integer, so impulse
+        // responses can be written as audio files.
+        let sample_rate = (c * 3.0_f32.sqrt() / (h * params.courant.min(1.0))).ceil();
+        let dt = sample_rate.recip();
+
+        let mut fdtd = Fdtd {
+            origin: min,
+            spacing: h,
+            nx: nx,
+            ny: ny,
+            nz: nz,
+            dt: dt,
+            lambda: c * dt / h,
+            pressure: vec![0.0; nx * ny * nz],
+            previous: vec![0.0; nx * ny * nz],
+            neighbors: vec![None; nx * ny * nz],
+            beta: 0.0,
+            // The spectrum of the pulse is 20 dB down at the maximum frequency.
+            sigma: 0.3416 / params.max_frequency(c),
+            pulses: Vec::new(),
+            steps: 0,
+        };
+
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    if scene.contains(fdtd.point(i, j, k)) {
+                        let idx = fdtd.index(i, j, k);
+                        fdtd.neighbors[idx] = Some(0);
+                    }
+                }
+            }
+        }
+        for idx in 0..fdtd.neighbors.len() {
+            if fdtd.neighbors[idx].is_some() {
+                let n_inside = fdtd.neighbor_indices(idx).iter().filter(|n| match **n {
+                    Some(ni) => fdtd.neighbors[ni].is_some(),
+                    None => false,
+                }).count();
+                fdtd.neighbors[idx] = Some(n_inside as u8);
+            }
+        }
+
+        // For a plane wave at normal incidence, a boundary with normalized
+        // admittance β reflects R = (1 - β) / (1 + β).
+        let reflectivities: Vec<f32> = scene.faces
+            .iter()
+            .filter(|f| !f.opening)
+            .map(|f| f.reflectivity_at(BOUNDARY_FREQUENCY_HZ).norm().min(1.0))
+            .collect();
+        let r = reflectivities.iter().sum::<f32>() / reflectivities.len().max(1) as f32;
+        fdtd.beta = (1.0 - r) / (1.0 + r);
+
+        for (si, source) in scene.sources.iter().enumerate() {
+            if !scene.is_audible(si) || !source.enabled {
+                continue
+            }
+            let index = match fdtd.nearest_inside(source.position) {
+                Some(index) => index,
+                None => continue,
+            };
+            let gain = 10.0_f32.powf(source.gain_db / 20.0);
+            fdtd.pulses.push(Pulse {
+                index: index,
+                amplitude: if source.invert { -gain } else { gain },
+                t_peak: 4.0 * fdtd.sigma + source.delay_ms * 1e-3,
+                previous: [0.0; 3],
+            });
+        }
+
+        fdtd
+    }
+
+    fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        (k * self.ny + j) * self.nx + i
+    }
+
+    fn point(&self, i: usize, j: usize, k: usize) -> Vec3 {
+        self.origin + Vec3::new(i as f32, j as f32, k as f32) * self.spacing
+    }
+
+    /// Return the indices of the 6 neighbors of a grid point, `None` beyond the grid.
+    fn neighbor_indices(&self, idx: usize) -> [Option<usize>; 6] {
+        let i = idx % self.nx;
+        let j = (idx / self.nx) % self.ny;
+        let k = idx / (self.nx * self.ny);
+        let sx = 1;
+        let sy = self.nx;
+        let sz = self.nx * self.ny;
+        [
+            if i > 0 { Some(idx - sx) } else { None },
+            if i + 1 < self.nx { Some(idx + sx) } else { None },
+            if j > 0 { Some(idx - sy) } else { None },
+            if j + 1 < self.ny { Some(idx + sy) } else { None },
+            if k > 0 { Some(idx - sz) } else { None },
+            if k + 1 < self.nz { Some(idx + sz) } else { None },
+        ]
+    }
+
+    /// Return the index of the grid point nearest to `p`, if it lies inside the space.
+    fn nearest_inside(&self, p: Vec3) -> Option<usize> {
+        let d = (p - self.origin) * self.spacing.recip();
+        let round = |x: f32, n: usize| {
+            let x = x.round();
+            if x < 0.0 || x >= n as f32 { None } else { Some(x as usize) }
+        };
+        let idx = self.index(round(d.x, self.nx)?, round(d.y, self.ny)?, round(d.z, self.nz)?);
+        self.neighbors[idx].map(|_| idx)
+    }
+
+    /// Return the simulated time, in seconds.
+    pub fn time(&self) -> f32 {
+        self.steps as f32 * self.dt
+    }
+
+    /// Return the number of time steps per second.
+    pub fn sample_rate(&self) -> u32 {
+        self.dt.recip().round() as u32
+    }
+
+    /// Advance the simulation by one time step.
+    pub fn step(&mut self) {
+        let l2 = self.lambda * self.lambda;
+        let sx = 1;
+        let sy = self.nx;
+        let sz = self.nx * self.ny;
+
+        // The new pressure only depends on the previous pressure at the same
+        // point, so we can overwrite the previous pressure in place.
+        for idx in 0..self.pressure.len() {
+            let k_inside = match self.neighbors[idx] {
+                Some(k) => k,
+                None => continue,
+            };
+            let p = self.pressure[idx];
+
+            // A neighbor outside the space does not contribute, as if it had
+            // the same pressure as the point itself: a rigid boundary. The
+            // admittance adds losses on top of that.
+            let mut sum = 0.0;
+            if k_inside == 6 {
+                sum = self.pressure[idx - sx] + self.pressure[idx + sx]
+                    + self.pressure[idx - sy] + self.pressure[idx + sy]
+                    + self.pressure[idx - sz] + self.pressure[idx + sz];
+            } else {
+                for n in self.neighbor_indices(idx).iter() {
+                    if let Some(ni) = *n {
+                        if self.neighbors[ni].is_some() {
+                            sum += self.pressure[ni];
+                        }
+                    }
+                }
+            }
+
+            let loss = 0.5 * (6 - k_inside) as f32 * self.lambda * self.beta;
+            let next = ((2.0 - k_inside as f32 * l2) * p + l2 * sum - (1.0 - loss) * self.previous[idx]) / (1.0 + loss);
+            self.previous[idx] = next;
+        }
+
+        std::mem::swap(&mut self.pressure, &mut self.previous);
+        self.steps += 1;
+
+        // Inject the pulses as soft sources, so the wave passes through the
+        // source point unhindered.
+        let t = self.time();
+        let alpha = (1.0 + 2.0 * PI * HIGH_PASS_HZ * self.dt).recip();
+        for pulse in self.pulses.iter_mut() {
+            self.pressure[pulse.index] += pulse.next(t, self.sigma, alpha);
+        }
+    }
+
+    /// Return the pressure at the grid point nearest to `p`, or NaN outside the space.
+    pub fn pressure_at(&self, p: Vec3) -> f32 {
+        match self.nearest_inside(p) {
+            Some(idx) => self.pressure[idx],
+            None => std::f32::NAN,
+        }
+    }
+}
+
+/// Simulate `duration_s` seconds, and write the pressure at `position` as a WAV file.
+///
+/// The samples are 32-bit floats at the sample rate of the simulation, which
+/// is not a standard audio rate, but any program that reads WAV files handles
+/// it. The response is normalized to a peak of 1. Progress is reported on
+/// stderr.
+pub fn write_impulse_response<W: Write>(
+    scene: &Scene,
+    params: FdtdParams,
+    position: Vec3,
+    duration_s: f32,
+    out: &mut W,
+) -> io::Result<()> {
+    let mut fdtd = Fdtd::new(scene, params);
+    let n_steps = (duration_s * fdtd.sample_rate() as f32).ceil() as u32;
+    let mut samples = Vec::with_capacity(n_steps as usize);
+    for i in 0..n_steps {
+        if i % 1000 == 0 {
+            eprint!("\rSimulating {:.0} of {:.0} ms ...", fdtd.time() * 1e3, duration_s * 1e3);
+        }
+        fdtd.step();
+        samples.push(fdtd.pressure_at(position));
+    }
+    eprintln!();
+
+    impulse::write_wav(out, fdtd.sample_rate(), &samples[..])
+}
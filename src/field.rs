@@ -0,0 +1,188 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use std::thread;
+
+use crate::complex::Complex;
+use crate::pool;
+use crate::rand::Rng;
+use crate::renderer::{Viewport, SLICE_HEIGHT};
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The names of the channels, in the order of the file, which must be sorted.
+const CHANNELS: [&str; 3] = ["im", "magnitude", "re"];
+
+/// Sample row `y` of the image, return the complex field at every pixel.
+///
+/// Every row has its own random stream, so the result does not depend on
+/// which thread samples it. Points outside of the space are NaN.
+fn sample_row(
+    scene: &Scene,
+    viewport: &Viewport,
+    width: u32,
+    frequency: f32,
+    n_samples: u32,
+    seed: u64,
+    y: u32,
+) -> Vec<Complex> {
+    let mut rng = Rng::new((seed << 32).wrapping_add(y as u64));
+    let weight = (n_samples as f32).recip();
+    (0..width)
+        .map(|x| {
+            let p = viewport.to_world(x, y);
+            let position = Vec3::new(p.x, p.y, scene.floor_height_at(p) + SLICE_HEIGHT);
+            if !scene.contains(position) {
+                return Complex::new(std::f32::NAN, std::f32::NAN)
+            }
+            let mut z = Complex::zero();
+            for _ in 0..n_samples {
+                z = z + scene.sample_at(&mut rng, frequency, position);
+            }
+            z * weight
+        })
+        .collect()
+}
+
+/// Sample the field at every pixel, with the rows spread over threads.
+fn sample_image(
+    scene: &Arc<Scene>,
+    width: u32,
+    height: u32,
+    frequency: f32,
+    n_samples: u32,
+    seed: u64,
+) -> Vec<Vec<Complex>> {
+    let viewport = Viewport::fit(scene, width, height);
+    let n_threads = pool::cpu_count();
+    let threads: Vec<_> = (0..n_threads)
+        .map(|t| {
+            let scene = scene.clone();
+            thread::spawn(move || {
+                (t as u32..height)
+                    .step_by(n_threads)
+                    .map(|y| (y, sample_row(&scene, &viewport, width, frequency, n_samples, seed, y)))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let mut rows = vec![Vec::new(); height as usize];
+    for handle in threads {
+        for (y, row) in handle.join().unwrap() {
+            rows[y as usize] = row;
+        }
+    }
+    rows
+}
+
+/// Append a header attribute: its name, type, size, and value.
+fn push_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Return the bytes of a `box2i` attribute that covers the image.
+fn image_box(width: u32, height: u32) -> Vec<u8> {
+    let mut value = Vec::with_capacity(16);
+    for &x in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        value.extend_from_slice(&x.to_le_bytes());
+    }
+    value
+}
+
+/// Write the field at `frequency` in the slice that the viewer shows as an OpenEXR file.
+///
+/// The image is `width` by `height` pixels and covers the scene like the
+/// viewer does, at ear height above the floor. It has three 32-bit float
+/// channels: `re` and `im` hold the complex pressure, and `magnitude` its
+/// absolute value, in the same arbitrary units as the viewer. There is no
+/// tone mapping, so the file can be re-exposed or analyzed without the loss
+/// of an 8-bit image. The frequency is stored in the `frequency` attribute.
+/// Points outside the space are NaN. Every pixel averages `n_samples` Monte
+/// Carlo samples, progress is reported on stderr.
+///
+/// The file is an uncompressed single-part scanline image, which every EXR
+/// reader supports.
+pub fn write_exr<W: Write>(
+    scene: Arc<Scene>,
+    width: u32,
+    height: u32,
+    frequency: f32,
+    n_samples: u32,
+    seed: u64,
+    out: &mut W,
+) -> io::Result<()> {
+    eprint!("\rSampling {} x {} pixels ...", width, height);
+    let rows = sample_image(&scene, width, height, frequency, n_samples, seed);
+    eprintln!();
+
+    let mut header = Vec::new();
+    // The magic number, and version 2 with no flags, a scanline image.
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    let mut channels = Vec::new();
+    for name in &CHANNELS {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        // Pixel type 2 is FLOAT, then pLinear and three reserved bytes, and
+        // no subsampling in x and y.
+        channels.extend_from_slice(&2_i32.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    push_attribute(&mut header, "channels", "chlist", &channels[..]);
+    push_attribute(&mut header, "compression", "compression", &[0]);
+    push_attribute(&mut header, "dataWindow", "box2i", &image_box(width, height)[..]);
+    push_attribute(&mut header, "displayWindow", "box2i", &image_box(width, height)[..]);
+    push_attribute(&mut header, "frequency", "float", &frequency.to_bits().to_le_bytes());
+    push_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    push_attribute(&mut header, "pixelAspectRatio", "float", &1.0_f32.to_bits().to_le_bytes());
+    push_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    push_attribute(&mut header, "screenWindowWidth", "float", &1.0_f32.to_bits().to_le_bytes());
+    header.push(0);
+    out.write_all(&header[..])?;
+
+    // Without compression, every scanline is a chunk of its own, and all
+    // chunks have the same size: the y coordinate, the size of the pixel
+    // data, and the pixel data, one channel after another.
+    let data_size = width as usize * CHANNELS.len() * 4;
+    let chunk_size = 8 + data_size;
+    let first_chunk = header.len() + height as usize * 8;
+    for y in 0..height as usize {
+        out.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    let mut chunk = Vec::with_capacity(chunk_size);
+    for (y, row) in rows.iter().enumerate() {
+        chunk.clear();
+        chunk.extend_from_slice(&(y as i32).to_le_bytes());
+        chunk.extend_from_slice(&(data_size as i32).to_le_bytes());
+        for z in row {
+            chunk.extend_from_slice(&z.imag().to_bits().to_le_bytes());
+        }
+        for z in row {
+            chunk.extend_from_slice(&z.norm().to_bits().to_le_bytes());
+        }
+        for z in row {
+            chunk.extend_from_slice(&z.real().to_bits().to_le_bytes());
+        }
+        out.write_all(&chunk[..])?;
+    }
+
+    out.flush()
+}
@@ -0,0 +1,107 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::complex::Complex;
+
+/// A measured frequency response, such as the on-axis response of a speaker.
+#[derive(Clone)]
+pub struct FrequencyResponse {
+    /// (Frequency in Hz, level in dB, phase in degrees) points, sorted by
+    /// frequency. The phase is unwrapped, so it has no jumps of 360 degrees.
+    points: Vec<(f32, f32, f32)>,
+}
+
+fn invalid_data<T>(msg: String) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+impl FrequencyResponse {
+    /// Load an FRD file, see `parse()`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<FrequencyResponse> {
+        let contents = fs::read_to_string(path)?;
+        FrequencyResponse::parse(&contents)
+    }
+
+    /// Parse an FRD file, with a frequency, level, and phase on every line.
+    ///
+    /// This is the format that REW and most speaker design tools export. The
+    /// phase is in degrees, and it is optional, without it the phase is zero.
+    /// Lines that start with `*`, `#`, or `;` are comments.
+    pub fn parse(contents: &str) -> io::Result<FrequencyResponse> {
+        let mut points = Vec::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('*') || line.starts_with('#') || line.starts_with(';') {
+                continue
+            }
+
+            let mut parts = line.split(|c: char| c.is_whitespace() || c == ',').filter(|p| !p.is_empty());
+            let mut next = || parts.next().map(|p| p.parse::<f32>().ok());
+            match (next(), next(), next()) {
+                (Some(Some(f)), Some(Some(db)), None) if f > 0.0 => points.push((f, db, 0.0)),
+                (Some(Some(f)), Some(Some(db)), Some(Some(phase))) if f > 0.0 => points.push((f, db, phase)),
+                _ => return invalid_data(format!("Expected frequency, level, and phase on line {}.", i + 1)),
+            }
+        }
+
+        FrequencyResponse::from_points(points)
+    }
+
+    /// Build a response from (frequency in Hz, level in dB, phase in degrees) points.
+    pub fn from_points(mut points: Vec<(f32, f32, f32)>) -> io::Result<FrequencyResponse> {
+        if points.is_empty() {
+            return invalid_data("The frequency response does not contain any points.".to_string())
+        }
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        // Measurements report the phase wrapped to (-180, 180]. Interpolating
+        // across a wrap would sweep through the full circle, so unwrap it.
+        let mut offset = 0.0;
+        for i in 1..points.len() {
+            let step = points[i].2 + offset - points[i - 1].2;
+            offset -= 360.0 * (step / 360.0).round();
+            points[i].2 += offset;
+        }
+
+        Ok(FrequencyResponse { points: points })
+    }
+
+    /// Return the points, with unwrapped phase.
+    pub fn points(&self) -> &[(f32, f32, f32)] {
+        &self.points[..]
+    }
+
+    /// Return the response at `frequency`, as a factor to multiply the output by.
+    ///
+    /// The level and phase are interpolated linearly in log-frequency, and
+    /// outside of the measured range they are those of the nearest point.
+    /// The level is applied as it is, so a response that reads 90 dB is a gain
+    /// of 90 dB. A measured phase lead is a negative angle here, because a
+    /// delay is a positive angle, see `Source::drive()`.
+    pub fn at(&self, frequency: f32) -> Complex {
+        let points = &self.points;
+        let n = points.len();
+        let (db, phase) = if frequency <= points[0].0 {
+            (points[0].1, points[0].2)
+        } else if frequency >= points[n - 1].0 {
+            (points[n - 1].1, points[n - 1].2)
+        } else {
+            let i = points.iter().position(|&(f, _, _)| f > frequency).unwrap();
+            let (f0, db0, phase0) = points[i - 1];
+            let (f1, db1, phase1) = points[i];
+            let t = (frequency / f0).ln() / (f1 / f0).ln();
+            (db0 + (db1 - db0) * t, phase0 + (phase1 - phase0) * t)
+        };
+        Complex::exp_i(-phase * PI / 180.0) * 10.0_f32.powf(db / 20.0)
+    }
+}
@@ -0,0 +1,276 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+use std::io;
+use std::io::Write;
+
+use crate::complex::Complex;
+use crate::plot::{self, Canvas};
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The sample rate of synthesized impulse responses, in Hz.
+///
+/// This covers the bass range with a margin, the response above `F_MAX` is
+/// not computed.
+pub const SAMPLE_RATE: u32 = 1024;
+
+/// The highest frequency in synthesized impulse responses, in Hz.
+///
+/// The spectrum is faded out from 3/4 of this frequency, to avoid the ringing
+/// that a hard cutoff causes.
+const F_MAX: f32 = 400.0;
+
+/// The band of the waterfall, in Hz, and its resolution.
+const WATERFALL_F_MIN: f32 = 20.0;
+const WATERFALL_F_MAX: f32 = 200.0;
+const WATERFALL_STEPS_PER_OCTAVE: u32 = 24;
+
+/// The time between the slices of the waterfall, in seconds, and the number of slices.
+const WATERFALL_STEP_S: f32 = 0.01;
+const WATERFALL_SLICES: usize = 21;
+
+/// The length of the window of every slice of the waterfall, in seconds.
+///
+/// A longer window resolves modes that are closer together, but it smears
+/// the decay over more time.
+const WATERFALL_WINDOW_S: f32 = 0.3;
+
+/// The pressure at a point over time, after the sources emit an impulse.
+pub struct ImpulseResponse {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+/// Compute the discrete Fourier transform of `data` in place.
+///
+/// With `inverse` set, compute the inverse transform, without the 1/n
+/// normalization. The length must be a power of two.
+fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "The FFT length must be a power of two.");
+
+    // Put the elements in bit-reversed order.
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    // Combine transforms of length len/2 into transforms of length len.
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let step = Complex::exp_i(sign * 2.0 * PI / len as f32);
+        for start in (0..n).step_by(len) {
+            let mut w = Complex::new(1.0, 0.0);
+            for k in 0..len / 2 {
+                let a = data[start + k];
+                let b = data[start + k + len / 2] * w;
+                data[start + k] = a + b;
+                data[start + k + len / 2] = a - b;
+                w = w * step;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+impl ImpulseResponse {
+    /// Synthesize the impulse response of all sources together at `position`.
+    ///
+    /// The field is sampled on a linear grid of frequencies, and transformed
+    /// back into the time domain. The grid is as fine as the duration
+    /// requires: the response covers at least `duration_s` seconds. Any sound
+    /// that arrives later wraps around to the start, so the duration should
+    /// cover the decay of the room. Every frequency averages `n_samples`
+    /// samples per source.
+    pub fn synthesize(scene: &Scene, position: Vec3, duration_s: f32, n_samples: u32, seed: u64) -> ImpulseResponse {
+        let n = ((duration_s * SAMPLE_RATE as f32).ceil() as usize).next_power_of_two();
+        let df = SAMPLE_RATE as f32 / n as f32;
+        let n_bins = ((F_MAX / df) as usize).min(n / 2 - 1);
+
+        // Bin 0, the static pressure, stays zero.
+        let frequencies: Vec<f32> = (1..n_bins + 1).map(|k| k as f32 * df).collect();
+        let mut rng = Rng::new(seed);
+        let response = response::sample_response(scene, &mut rng, position, &frequencies[..], n_samples);
+
+        // The field of a source delayed by t has phase e^(iωt), so the inverse
+        // transform has the negative exponent, like a forward FFT. The
+        // spectrum of a real signal is conjugate symmetric.
+        let mut spectrum = vec![Complex::zero(); n];
+        for (k, (&f, &z)) in frequencies.iter().zip(response.iter()).enumerate() {
+            let t = ((f - 0.75 * F_MAX) / (0.25 * F_MAX)).max(0.0).min(1.0);
+            let fade = 0.5 + 0.5 * (PI * t).cos();
+            spectrum[k + 1] = z * fade;
+            spectrum[n - k - 1] = (z * fade).conj();
+        }
+        fft(&mut spectrum[..], false);
+
+        ImpulseResponse {
+            sample_rate: SAMPLE_RATE,
+            samples: spectrum.iter().map(|z| z.real() / n as f32).collect(),
+        }
+    }
+
+    pub fn duration_ms(&self) -> f32 {
+        self.samples.len() as f32 * 1e3 / self.sample_rate as f32
+    }
+
+    /// Return the energy-time curve, the level of every sample in dB relative to the peak.
+    pub fn etc_db(&self) -> Vec<f32> {
+        let peak = self.samples.iter().map(|x| x * x).fold(0.0, f32::max);
+        self.samples.iter().map(|x| 10.0 * (x * x / peak).log10()).collect()
+    }
+
+    /// Plot the energy-time curve, with a grid line every 50 ms and every 10 dB.
+    pub fn paint(&self, width: u32, height: u32) -> Canvas {
+        let mut canvas = Canvas::new(width, height, plot::WHITE);
+        plot::plot_time(&mut canvas, &self.etc_db()[..], self.duration_ms(), 50.0, 0.0, 60.0, plot::BLACK);
+        canvas
+    }
+
+    /// Compute the cumulative spectral decay, the spectrum of what remains
+    /// of the response after every slice time.
+    ///
+    /// Slice k starts k * 10 ms after the impulse. Its spectrum is that of
+    /// the next 300 ms, faded out with a half Hann window, so the slice shows
+    /// the sound that is still ringing at its start. The response should be
+    /// at least 500 ms long, later slices are cut short otherwise.
+    pub fn waterfall(&self) -> Waterfall {
+        let frequencies = response::log_frequencies(WATERFALL_F_MIN, WATERFALL_F_MAX, WATERFALL_STEPS_PER_OCTAVE);
+        let sample_rate = self.sample_rate as f32;
+        let window_len = (WATERFALL_WINDOW_S * sample_rate) as usize;
+        let times_ms: Vec<f32> = (0..WATERFALL_SLICES).map(|k| k as f32 * WATERFALL_STEP_S * 1e3).collect();
+
+        let mut levels_db: Vec<Vec<f32>> = times_ms
+            .iter()
+            .map(|&t_ms| {
+                let start = (t_ms * 1e-3 * sample_rate).round() as usize;
+                let end = (start + window_len).min(self.samples.len());
+                frequencies
+                    .iter()
+                    .map(|&f| {
+                        let mut z = Complex::zero();
+                        for (j, &x) in self.samples[start.min(end)..end].iter().enumerate() {
+                            let fade = 0.5 + 0.5 * (PI * j as f32 / window_len as f32).cos();
+                            z = z + Complex::exp_i(-2.0 * PI * f * j as f32 / sample_rate) * (x * fade);
+                        }
+                        response::to_db(z)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let peak = levels_db.iter().flat_map(|levels| levels.iter().cloned()).fold(std::f32::NEG_INFINITY, f32::max);
+        for levels in levels_db.iter_mut() {
+            for db in levels.iter_mut() {
+                *db -= peak;
+            }
+        }
+
+        Waterfall {
+            frequencies: frequencies,
+            times_ms: times_ms,
+            levels_db: levels_db,
+        }
+    }
+
+    /// Write the response as a WAV file, see `write_wav()`.
+    pub fn write_wav<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write_wav(out, self.sample_rate, &self.samples[..])
+    }
+}
+
+/// The cumulative spectral decay of an impulse response, see `ImpulseResponse::waterfall()`.
+///
+/// The steady-state response shows how loud a mode is, the waterfall shows
+/// how long it keeps ringing after the source stops, which is what makes bass
+/// sound boomy.
+pub struct Waterfall {
+    pub frequencies: Vec<f32>,
+
+    /// The start of every slice, in ms after the impulse.
+    pub times_ms: Vec<f32>,
+
+    /// For every slice, the level in dB at each of the frequencies, relative
+    /// to the peak over all slices.
+    pub levels_db: Vec<Vec<f32>>,
+}
+
+impl Waterfall {
+    /// Return the frequency that rings the longest, and its decay time (T60)
+    /// in seconds, extrapolated from the decay over the slices.
+    ///
+    /// Only frequencies within 20 dB of the peak in the first slice count,
+    /// the decay of the quiet ones is mostly noise. Returns `None` if nothing
+    /// decays.
+    pub fn longest_ringing(&self) -> Option<(f32, f32)> {
+        let (first, last) = (&self.levels_db[0], &self.levels_db[self.levels_db.len() - 1]);
+        let duration_s = self.times_ms[self.times_ms.len() - 1] * 1e-3;
+        let (i, decay_db) = (0..self.frequencies.len())
+            .filter(|&i| first[i] > -20.0)
+            .map(|i| (i, first[i] - last[i]))
+            .filter(|&(_, decay_db)| decay_db > 0.0)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())?;
+        Some((self.frequencies[i], 60.0 * duration_s / decay_db))
+    }
+
+    /// Plot the slices front to back, from blue at the impulse to red at the
+    /// last slice, over 40 dB.
+    pub fn paint(&self, width: u32, height: u32) -> Canvas {
+        let mut canvas = Canvas::new(width, height, plot::WHITE);
+        let n = (self.levels_db.len().max(2) - 1) as f32;
+        let slices: Vec<(&[f32], plot::Color)> = self.levels_db
+            .iter()
+            .enumerate()
+            .map(|(k, levels)| (&levels[..], plot::lerp_color(plot::BLUE, plot::RED, k as f32 / n)))
+            .collect();
+        plot::plot_waterfall(&mut canvas, &self.frequencies[..], &slices[..], 0.0, 40.0);
+        canvas
+    }
+}
+
+/// Write mono 32-bit float samples as a WAV file, normalized to a peak of 1.
+pub fn write_wav<W: Write>(out: &mut W, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    let peak = samples.iter().map(|x| x.abs()).fold(0.0, f32::max);
+    let scale = if peak > 0.0 { peak.recip() } else { 1.0 };
+
+    // A RIFF header with a format chunk for IEEE float samples, which also
+    // needs a fact chunk with the number of samples.
+    let data_size = samples.len() as u32 * 4;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(4 + 26 + 12 + 8 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&18_u32.to_le_bytes())?;
+    out.write_all(&3_u16.to_le_bytes())?;
+    out.write_all(&1_u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 4).to_le_bytes())?;
+    out.write_all(&4_u16.to_le_bytes())?;
+    out.write_all(&32_u16.to_le_bytes())?;
+    out.write_all(&0_u16.to_le_bytes())?;
+    out.write_all(b"fact")?;
+    out.write_all(&4_u32.to_le_bytes())?;
+    out.write_all(&(samples.len() as u32).to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    for &x in samples {
+        out.write_all(&(x * scale).to_bits().to_le_bytes())?;
+    }
+    out.flush()
+}
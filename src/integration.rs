@@ -0,0 +1,112 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::complex::Complex;
+use crate::eq::Band;
+use crate::plot::{self, Canvas};
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The number of Monte Carlo samples per frequency, per source.
+const N_SAMPLES: u32 = 1024;
+
+/// The crossover region extends this factor below and above the crossover frequency.
+const REGION_FACTOR: f32 = 4.0;
+
+/// The responses of the subs, the mains, and their sum at the listener,
+/// around the crossover frequency.
+pub struct Integration {
+    pub crossover_hz: f32,
+    pub frequencies: Vec<f32>,
+
+    /// Levels in dB, relative to the peak of the sum.
+    pub subs: Vec<f32>,
+    pub mains: Vec<f32>,
+    pub total: Vec<f32>,
+}
+
+/// Return the crossover frequency of the scene, if any source is behind a crossover.
+pub fn crossover_frequency(scene: &Scene) -> Option<f32> {
+    scene.sources.iter().filter_map(|s| s.crossover).map(|(c, _)| c.frequency).next()
+}
+
+impl Integration {
+    /// Sample the responses at `listener` over the crossover region.
+    ///
+    /// The subs are the sources on the sub side of the crossover, the mains
+    /// are all other sources, including those without a band. Returns `None`
+    /// if the scene has no crossover.
+    pub fn compute(scene: &Scene, listener: Vec3, seed: u64) -> Option<Integration> {
+        let crossover_hz = crossover_frequency(scene)?;
+        let frequencies = response::log_frequencies(
+            crossover_hz / REGION_FACTOR,
+            crossover_hz * REGION_FACTOR,
+            24,
+        );
+        let mut rng = Rng::new(seed);
+
+        let mut subs = vec![Complex::zero(); frequencies.len()];
+        let mut mains = vec![Complex::zero(); frequencies.len()];
+        for (si, source) in scene.sources.iter().enumerate() {
+            let r = response::sample_source_response(scene, &mut rng, si, listener, &frequencies[..], N_SAMPLES);
+            let side = match source.crossover {
+                Some((_, Band::Sub)) => &mut subs,
+                _ => &mut mains,
+            };
+            for (acc, z) in side.iter_mut().zip(r) {
+                *acc = *acc + z;
+            }
+        }
+        let total: Vec<Complex> = subs.iter().zip(mains.iter()).map(|(&s, &m)| s + m).collect();
+
+        let reference = total.iter().map(|&z| response::to_db(z)).fold(std::f32::NEG_INFINITY, f32::max);
+        let to_levels = |r: &[Complex]| -> Vec<f32> {
+            r.iter().map(|&z| response::to_db(z) - reference).collect()
+        };
+
+        Some(Integration {
+            crossover_hz: crossover_hz,
+            subs: to_levels(&subs[..]),
+            mains: to_levels(&mains[..]),
+            total: to_levels(&total[..]),
+            frequencies: frequencies,
+        })
+    }
+
+    /// Summarize how well the subs and mains sum at the crossover.
+    ///
+    /// If the two are in phase at the crossover, the sum is louder than either
+    /// of them. If it is quieter than the louder one, they partially cancel.
+    pub fn describe(&self) -> String {
+        // The frequencies are spaced symmetrically around the crossover, so
+        // the middle one is the crossover frequency.
+        let k = self.frequencies.len() / 2;
+        let gain = self.total[k] - self.subs[k].max(self.mains[k]);
+        let (k_dip, dip) = self.total
+            .iter()
+            .enumerate()
+            .fold((0, std::f32::INFINITY), |(k, m), (i, &x)| if x < m { (i, x) } else { (k, m) });
+        format!(
+            "At {:.0} Hz the sum is {:+.1} dB relative to\nthe louder side. Deepest dip {:.1} dB at {:.0} Hz.",
+            self.crossover_hz, gain, dip, self.frequencies[k_dip],
+        )
+    }
+
+    /// Plot the subs in blue, the mains in orange, and the sum in black.
+    pub fn paint(&self, width: u32, height: u32) -> Canvas {
+        let mut canvas = Canvas::new(width, height, plot::WHITE);
+        let curves = [
+            (&self.subs[..], plot::BLUE),
+            (&self.mains[..], plot::ORANGE),
+            (&self.total[..], plot::BLACK),
+        ];
+        plot::plot_response(&mut canvas, &self.frequencies[..], &curves, 10.0, 50.0);
+        canvas
+    }
+}
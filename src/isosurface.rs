@@ -0,0 +1,142 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use crate::vec3::Vec3;
+use crate::volume::Grid;
+
+/// The level difference between isosurfaces, in dB.
+pub const STEP_DB: f32 = 6.0;
+
+/// The cube between grid points, split into six tetrahedra around the
+/// diagonal from corner 0 to corner 7. Corner `c` lies at offset
+/// (`c & 1`, `c >> 1 & 1`, `c >> 2`) from the first point.
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 3, 2, 7],
+    [0, 2, 6, 7],
+    [0, 6, 4, 7],
+    [0, 4, 5, 7],
+    [0, 5, 1, 7],
+];
+
+/// A triangle mesh.
+pub struct Mesh {
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// Return the surface where the level crosses `level_db`.
+///
+/// `levels_db` holds the level at every point of the grid, in the order of
+/// `Grid::index()`, NaN for points outside of the space. This is marching
+/// tetrahedra: every cube of the grid is split into six tetrahedra, and the
+/// surface crosses an edge of a tetrahedron where the level at one end is
+/// above `level_db` and the level at the other is not, at the point found by
+/// linear interpolation. Cubes with a corner outside of the space are skipped,
+/// so surfaces end open at the walls. Triangles face toward the lower level.
+pub fn extract(grid: &Grid, levels_db: &[f32], level_db: f32) -> Mesh {
+    assert_eq!(levels_db.len(), grid.n_points());
+
+    let mut mesh = Mesh { vertices: Vec::new(), triangles: Vec::new() };
+
+    // Neighboring tetrahedra share the vertices on their common edges.
+    let mut edge_vertices: HashMap<(usize, usize), usize> = HashMap::new();
+
+    for k in 0..grid.nz.saturating_sub(1) {
+        for j in 0..grid.ny.saturating_sub(1) {
+            for i in 0..grid.nx.saturating_sub(1) {
+                let mut corners = [0; 8];
+                for (c, corner) in corners.iter_mut().enumerate() {
+                    *corner = grid.index(i + (c & 1), j + (c >> 1 & 1), k + (c >> 2));
+                }
+                if corners.iter().any(|&p| levels_db[p].is_nan()) {
+                    continue
+                }
+
+                for tetrahedron in TETRAHEDRA.iter() {
+                    let points: Vec<usize> = tetrahedron.iter().map(|&c| corners[c]).collect();
+                    let (above, below): (Vec<usize>, Vec<usize>) = points
+                        .into_iter()
+                        .partition(|&p| levels_db[p] > level_db);
+                    if above.is_empty() || below.is_empty() {
+                        continue
+                    }
+
+                    let mut vertex = |a: usize, b: usize| {
+                        let key = (a.min(b), a.max(b));
+                        if let Some(&v) = edge_vertices.get(&key) {
+                            return v
+                        }
+                        let t = (level_db - levels_db[a]) / (levels_db[b] - levels_db[a]);
+                        let (pa, pb) = (grid.position(a), grid.position(b));
+                        mesh.vertices.push(pa + (pb - pa) * t);
+                        edge_vertices.insert(key, mesh.vertices.len() - 1);
+                        mesh.vertices.len() - 1
+                    };
+
+                    // One point on one side cuts a triangle off the
+                    // tetrahedron, two on either side cut it through a quad.
+                    let polygon: Vec<usize> = match (above.len(), below.len()) {
+                        (1, 3) => below.iter().map(|&b| vertex(above[0], b)).collect(),
+                        (3, 1) => above.iter().map(|&a| vertex(a, below[0])).collect(),
+                        _ => vec![
+                            vertex(above[0], below[0]),
+                            vertex(above[0], below[1]),
+                            vertex(above[1], below[1]),
+                            vertex(above[1], below[0]),
+                        ],
+                    };
+
+                    let centroid = |ps: &[usize]| {
+                        ps.iter().fold(Vec3::zero(), |sum, &p| sum + grid.position(p)) * (ps.len() as f32).recip()
+                    };
+                    let downhill = centroid(&below[..]) - centroid(&above[..]);
+                    for t in 1..polygon.len() - 1 {
+                        let mut triangle = [polygon[0], polygon[t], polygon[t + 1]];
+                        let (a, b, c) = (
+                            mesh.vertices[triangle[0]],
+                            mesh.vertices[triangle[1]],
+                            mesh.vertices[triangle[2]],
+                        );
+                        if (b - a).cross(c - a).dot(downhill) < 0.0 {
+                            triangle.swap(1, 2);
+                        }
+                        mesh.triangles.push(triangle);
+                    }
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Write the meshes as objects of a Wavefront OBJ file, with the given names.
+///
+/// The coordinates are those of the scene, with z up, in meters.
+pub fn write_obj<W: Write>(meshes: &[(String, Mesh)], out: &mut W) -> io::Result<()> {
+    writeln!(out, "# Basstrace isosurfaces")?;
+
+    // Vertex indices in OBJ files are global, and start at 1.
+    let mut offset = 1;
+    for &(ref name, ref mesh) in meshes {
+        writeln!(out, "o {}", name)?;
+        for v in &mesh.vertices {
+            writeln!(out, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        for t in &mesh.triangles {
+            writeln!(out, "f {} {} {}", t[0] + offset, t[1] + offset, t[2] + offset)?;
+        }
+        offset += mesh.vertices.len();
+    }
+
+    out.flush()
+}
@@ -6,119 +6,918 @@
 // of the License is available in the root of the repository.
 
 use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::process;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use gdk_pixbuf as gdk;
 use gio::prelude::*;
-use glib;
-use gtk::prelude::*;
 
+mod absorber;
+mod align;
+mod animation;
+mod array;
+mod arrival;
+mod atmosphere;
+mod audit;
+mod autoeq;
+mod colormap;
 mod complex;
+mod crawl;
+mod diffraction;
+mod eq;
+mod fdtd;
+mod field;
+mod frd;
+mod impulse;
+mod integration;
+mod isosurface;
+mod material;
+mod modal;
+mod multisub;
+mod obj;
+mod peaks;
+mod placement;
+mod plot;
+mod pool;
+mod probe;
+mod project;
 mod rand;
 mod renderer;
+mod residual;
+mod response;
+mod reverb;
+mod sbir;
 mod scene;
+mod scene_file;
+mod schematic;
+mod shell;
+mod spectrum;
+mod target;
+mod treatment;
+mod ui;
 mod vec2;
 mod vec3;
+mod volume;
 
-use renderer::Renderer;
-use scene::Scene;
+use complex::Complex;
+use impulse::ImpulseResponse;
+use pool::AnalysisPool;
+use project::{Project, RenderPreset};
+use rand::Rng;
+use renderer::{Precision, Renderer};
+use scene::{Scene, Solver};
+use spectrum::Spectrum;
 use vec3::Vec3;
 
-fn build_canvas() -> Option<gdk::Pixbuf> {
-    let has_alpha = false;
-    let bits_per_sample = 8;
-    let width = 1280;
-    let height = 720;
-    gdk::Pixbuf::new(
-        gdk::Colorspace::Rgb,
-        has_alpha,
-        bits_per_sample,
-        width,
-        height,
-    )
-}
-
-fn build_ui(application: &gtk::Application, renderer: &Arc<Renderer>) {
-    let window = gtk::ApplicationWindow::new(application);
-
-    window.set_title("Basstrace");
-    window.set_border_width(10);
-    window.set_position(gtk::WindowPosition::Center);
-    window.set_default_size(1280, 720);
-
-    let vbox = gtk::Box::new(
-        gtk::Orientation::Vertical,
-        10,
-    );
-    window.add(&vbox);
-
-    let canvas = build_canvas();
-    let image = gtk::Image::new_from_pixbuf(canvas.as_ref());
-
-    let expand = false;
-    let fill = false;
-    let padding = 0;
-    vbox.pack_start(&image, expand, fill, padding);
-
-    let min = 1.0;
-    let max = 4.0;
-    let step = 0.01;
-    let scale = gtk::Scale::new_with_range(
-        gtk::Orientation::Horizontal,
-        min, max, step,
-    );
-    let r_ref = renderer.clone();
-    scale.connect_value_changed(move |scale_ref| {
-        // Frequency = 10^slider_value.
-        let log10_frequency = scale_ref.get_value() as f32;
-        let frequency_hz = 2.0 * 10_f32.powf(log10_frequency);
+/// The number of Monte Carlo samples per frequency, per source, of the `impulse` command.
+const IMPULSE_SAMPLES: u32 = 1024;
+
+const USAGE: &str = "Usage:
+  basstrace [--scene <file>] [--seed <n>] [--frequency <hz>] [--band <octaves>] [--spectrum <spectrum>]
+            [--size <w>x<h>] [--scale <meters>] [--refresh <seconds>]
+            [--precision <p>]
+  basstrace [--scene <file>] [--seed <n>] align <source> <f_min> <f_max> [<x> <y> <z>]
+  basstrace [--scene <file>] [--seed <n>] animate <schedule> <out-dir> <frequency> [<samples>]
+  basstrace [--scene <file>] [--seed <n>] audit <frequency>
+  basstrace [--scene <file>] [--seed <n>] autoeq <filters> <f_min> <f_max> [<sample_rate>]
+  basstrace --scene <file> [--seed <n>] bundle <out.basstrace> [--frequency <hz>] [--band <octaves>] [--spectrum <spectrum>]
+  basstrace --scene <file> verify
+  basstrace [--scene <file>] [--seed <n>] crawl <f_min> <f_max> [<x> <y> <z>]
+  basstrace [--scene <file>] [--seed <n>] field <out.exr> <frequency> [<samples>]
+  basstrace [--scene <file>] [--seed <n>] impulse <out.wav> <duration_ms> [<x> <y> <z>]
+  basstrace [--scene <file>] [--seed <n>] isosurface <out.obj> <frequency> [<spacing>] [<samples>]
+  basstrace [--scene <file>] [--seed <n>] multisub <f_min> <f_max>
+  basstrace [--scene <file>] [--seed <n>] peaks <f_min> <f_max>
+  basstrace [--scene <file>] [--seed <n>] placement <zones> <f_min> <f_max>
+  basstrace [--scene <file>] schematic <out.svg> [<x> <y> <z>]
+  basstrace [--scene <file>] [--seed <n>] treatment <scenarios> <f_min> <f_max> [<x> <y> <z>]
+  basstrace [--scene <file>] [--seed <n>] volume <out.vtk> <frequency> [<spacing>] [<samples>]
+
+Options:
+  --scene <file>         Load the scene from a TOML file, instead of using the
+                         built-in example scene.
+  --seed <n>             Seed for the random sampling, defaults to 0. With the
+                         same seed, commands produce identical output.
+  --frequency <hz>       The frequency to show when the viewer opens, defaults
+                         to 440 Hz.
+  --band <octaves>       Average the level over a band of the given width,
+                         centered on the selected frequency. Defaults to 0,
+                         which renders only the selected frequency.
+  --spectrum <spectrum>  Weight the frequencies in the band by a program
+                         spectrum: 'pink' (the default), 'white', 'lfe', or the
+                         path of a file with frequency and dB columns.
+  --size <w>x<h>         The size of the image in pixels when the viewer opens,
+                         defaults to 1280x720. It follows the window after.
+  --scale <meters>       The size of a pixel in meters, e.g. 0.008. By default
+                         the scene is fitted into the image.
+  --refresh <seconds>    How often the viewer shows the new samples, defaults
+                         to 2 seconds. It also does once the first pass after
+                         a change completes.
+  --precision <p>        Sum the samples in 'single' (the default) or 'double'
+                         precision. Double takes three times the memory, but
+                         deep nulls stay clean after millions of samples.
+                         'compensated' sums in single precision with Kahan
+                         summation, about as clean in twice the memory.
+
+Commands:
+  align  Find the delay and polarity for source <source> (a zero-based index)
+         that maximize summation at the listener in the band from <f_min> to
+         <f_max> Hz.
+
+  animate  Render a clip at <frequency> Hz that mutes and unmutes sources
+           as the TOML file <schedule> says, e.g. sub 1 only, then sub 2
+           only, then both. Writes numbered PPM frames and captions.srt with
+           the labels of the steps to <out-dir>. Every pixel averages
+           <samples> samples, 64 by default. Encode the clip with e.g.
+           ffmpeg -i <out-dir>/frame_%05d.ppm -vf subtitles=captions.srt.
+
+  audit  Trace rays from the sources at <frequency> Hz, and report where the
+         energy goes: how much the faces absorb, how much escapes through
+         gaps in the scene, and whether energy is conserved.
+
+  autoeq  Fit up to <filters> peaking filters in the band from <f_min> to
+          <f_max> Hz that bring the response at the listener, or the average
+          over its listening area, closest to the [target] curve of the scene,
+          or to a flat response without one. Prints the frequency, gain, and
+          Q of the filters, and their biquad coefficients at <sample_rate>
+          Hz, 96000 by default, to paste into the advanced mode of a miniDSP.
+
+  bundle  Write a project bundle to <out.basstrace>: the scene, including its
+          mesh and materials, the viewer settings, and the response at the
+          listener. Notes in [[annotation]] tables of the scene are kept. Open
+          the bundle with --scene, like a scene file.
+
+  verify  Recompute the responses cached in a bundle, and report how much they
+          deviate from the cached ones.
+
+  crawl  Do a sub crawl: put a virtual sub at the listener, and rank spots
+         along the walls by the level there in the band from <f_min> to
+         <f_max> Hz, e.g. 20 to 120 Hz. By reciprocity, a sub at the loudest
+         spots is loudest at the listener.
+
+  field  Write the complex field at <frequency> Hz in the slice that the
+         viewer shows to an OpenEXR image of 1280 x 720 pixels, with float
+         channels re, im, and magnitude, without tone mapping. Every pixel
+         averages <samples> samples, 64 by default.
+
+  impulse  Write the impulse response at the listener over the first
+           <duration_ms> ms to <out.wav>, as 32-bit float samples normalized
+           to a peak of 1. It is synthesized from the field at frequencies up
+           to 400 Hz, at a sample rate of 1024 Hz. Sound that arrives after
+           the duration wraps around to the start, so cover the decay. With
+           the fdtd solver, the scene is simulated in the time domain instead,
+           at the sample rate of the simulation, about 6 kHz for a 10 cm grid.
+
+  isosurface  Write surfaces of equal level at <frequency> Hz to a Wavefront
+              OBJ file, every 6 dB relative to the listener, or to the median
+              level without one, each as an object named by its level. The
+              level is sampled on a 3D grid like for volume, 0.2 meters apart
+              by default, which the whole grid must fit in memory for.
+
+  multisub  Find the gain, delay, and polarity of every source that give the
+            flattest response over the listening area in the band from
+            <f_min> to <f_max> Hz, with the sources in place, and report the
+            DSP settings. Source 0 is the reference, its settings are kept.
+
+  peaks  List the peaks and dips in the response at the listener in the
+         band from <f_min> to <f_max> Hz, with their size and Q. With a
+         listening area, tell the peaks and dips that most seats share, which
+         modes cause, from those that depend on the position.
+
+  placement  Search the zones in the TOML file <zones> for the source
+             positions with the flattest response over the listening area in
+             the band from <f_min> to <f_max> Hz, e.g. 20 to 120 Hz, and
+             report the best ones.
+
+  schematic  Write a top-down drawing of the room with dimensions and the
+             listener to <out.svg>.
+
+  treatment  Compare the response at the listener in the band from <f_min> to
+             <f_max> Hz, for every treatment scenario in the TOML file
+             <scenarios>.
+
+  volume  Write the level at <frequency> Hz on a 3D grid over the scene to a
+          VTK file, for ParaView or VisIt. Points are <spacing> meters apart,
+          0.1 by default, and every point averages <samples> samples, 256 by
+          default. Slices are written as they are sampled, so memory use does
+          not grow with the size of the grid.
+
+The listener is the one from the scene, unless a position (<x>, <y>, <z>) is
+given.";
+
+fn exit_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1)
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: &str) -> T {
+    match arg.parse() {
+        Ok(x) => x,
+        Err(..) => {
+            eprintln!("Invalid argument: '{}'.\n", arg);
+            exit_usage()
+        }
+    }
+}
+
+/// Return the value that follows the option at index `i`.
+fn option_value(args: &[String], i: usize) -> &str {
+    match args.get(i + 1) {
+        Some(value) => &value[..],
+        None => {
+            eprintln!("Option '{}' expects a value.\n", args[i]);
+            exit_usage()
+        }
+    }
+}
+
+/// Remove the option `name` and its value from `args`, and return the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == name)?;
+    let value = option_value(args, i).to_string();
+    args.drain(i..i + 2);
+    Some(value)
+}
+
+/// Return the listener position given by `args`, or the listener of the scene.
+///
+/// The arguments are either empty, or the x, y, and z coordinates.
+fn parse_listener(scene: &Scene, args: &[String]) -> Vec3 {
+    let listener = match (args.len(), &scene.listener) {
+        (0, &Some(ref listener)) => listener.position,
+        (0, &None) => {
+            eprintln!("The scene has no listener, a listener position is required.");
+            process::exit(1)
+        }
+        (3, _) => Vec3::new(
+            parse_arg(&args[0]),
+            parse_arg(&args[1]),
+            parse_arg(&args[2]),
+        ),
+        _ => exit_usage(),
+    };
+
+    if !scene.contains(listener) {
+        eprintln!("The listener at ({}, {}, {}) is outside of the space.", listener.x, listener.y, listener.z);
+        process::exit(1);
+    }
+
+    listener
+}
+
+/// Load the scene from the file at `path`, or the example scene if there is none.
+fn load_scene(path: Option<&String>) -> Scene {
+    let path = match path {
+        Some(path) => path,
+        None => return Scene::new_example(),
+    };
+
+    match Scene::from_toml(path) {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("Failed to load scene '{}': {}", path, err);
+            process::exit(1)
+        }
+    }
+}
+
+/// Load the project tables of the scene file, if there is a scene file.
+fn load_project(path: Option<&String>) -> Project {
+    let path = match path {
+        Some(path) => path,
+        None => return Project::default(),
+    };
+
+    match Project::from_toml(path) {
+        Ok(project) => project,
+        Err(err) => {
+            eprintln!("Failed to load project '{}': {}", path, err);
+            process::exit(1)
+        }
+    }
+}
 
-        r_ref.set_frequency(frequency_hz);
+/// Options for the interactive viewer.
+struct ViewerOptions {
+    frequency_hz: f32,
+    bandwidth_octaves: f32,
+    spectrum: Spectrum,
+    width: u32,
+    height: u32,
+    meters_per_pixel: Option<f32>,
+    refresh_interval: Duration,
+    precision: Precision,
+}
+
+/// Parse the viewer options, with defaults from the render preset of the project.
+fn parse_viewer_options(args: &[String], preset: RenderPreset) -> ViewerOptions {
+    let mut opts = ViewerOptions {
+        frequency_hz: preset.frequency_hz.unwrap_or(440.0),
+        bandwidth_octaves: preset.bandwidth_octaves.unwrap_or(0.0),
+        spectrum: preset.spectrum.unwrap_or(Spectrum::Pink),
+        width: renderer::DEFAULT_WIDTH,
+        height: renderer::DEFAULT_HEIGHT,
+        meters_per_pixel: None,
+        refresh_interval: ui::DEFAULT_REFRESH_INTERVAL,
+        precision: Precision::Single,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        match &args[i][..] {
+            "--frequency" => {
+                opts.frequency_hz = parse_arg(option_value(args, i));
+            }
+            "--band" => {
+                opts.bandwidth_octaves = parse_arg(option_value(args, i));
+            }
+            "--spectrum" => {
+                let value = option_value(args, i);
+                opts.spectrum = match Spectrum::from_arg(value) {
+                    Ok(spectrum) => spectrum,
+                    Err(err) => {
+                        eprintln!("Failed to load spectrum '{}': {}", value, err);
+                        process::exit(1)
+                    }
+                };
+            }
+            "--size" => {
+                let value = option_value(args, i);
+                let mut parts = value.splitn(2, 'x');
+                opts.width = parse_arg(parts.next().unwrap());
+                opts.height = match parts.next() {
+                    Some(height) => parse_arg(height),
+                    None => {
+                        eprintln!("Invalid size: '{}', expected e.g. 1280x720.\n", value);
+                        exit_usage()
+                    }
+                };
+            }
+            "--scale" => {
+                opts.meters_per_pixel = Some(parse_arg(option_value(args, i)));
+            }
+            "--refresh" => {
+                let seconds: f32 = parse_arg(option_value(args, i));
+                let min_seconds = ui::MIN_REFRESH_INTERVAL.as_millis() as f32 * 1e-3;
+                if !seconds.is_finite() || seconds < min_seconds {
+                    eprintln!("The refresh interval must be at least {} seconds.", min_seconds);
+                    process::exit(1);
+                }
+                opts.refresh_interval = Duration::from_millis((seconds * 1e3).round() as u64);
+            }
+            "--precision" => {
+                opts.precision = match option_value(args, i) {
+                    "single" => Precision::Single,
+                    "double" => Precision::Double,
+                    "compensated" => Precision::Compensated,
+                    other => {
+                        eprintln!("Invalid precision: '{}', expected 'single', 'double', or 'compensated'.\n", other);
+                        exit_usage()
+                    }
+                };
+            }
+            unknown => {
+                eprintln!("Unknown option: '{}'.\n", unknown);
+                exit_usage()
+            }
+        }
+        i += 2;
+    }
+
+    if !(opts.bandwidth_octaves >= 0.0) {
+        eprintln!("The bandwidth must not be negative.");
+        process::exit(1);
+    }
+    if !(opts.frequency_hz > 0.0) {
+        eprintln!("The frequency must be positive.");
+        process::exit(1);
+    }
+    if opts.width == 0 || opts.height == 0 {
+        eprintln!("The size of the image must be positive.");
+        process::exit(1);
+    }
+    if let Some(s) = opts.meters_per_pixel {
+        if !(s > 0.0) {
+            eprintln!("The scale must be positive.");
+            process::exit(1);
+        }
+    }
+
+    opts
+}
+
+fn main_align(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 3 {
+        exit_usage();
+    }
+
+    let si: usize = parse_arg(&args[0]);
+    let f_min: f32 = parse_arg(&args[1]);
+    let f_max: f32 = parse_arg(&args[2]);
+    let listener = parse_listener(&scene, &args[3..]);
+
+    if si >= scene.sources.len() {
+        eprintln!("Source {} does not exist, the scene has {} sources.", si, scene.sources.len());
+        process::exit(1);
+    }
+    if !(f_min > 0.0 && f_max > f_min) {
+        eprintln!("Expected 0 < f_min < f_max.");
+        process::exit(1);
+    }
+
+    align::print_alignment(&scene, si, listener, f_min, f_max, seed);
+}
+
+fn main_animate(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 3 || args.len() > 4 {
+        exit_usage();
+    }
+
+    let frequency: f32 = parse_arg(&args[2]);
+    let n_samples: u32 = args.get(3).map(|x| parse_arg(x)).unwrap_or(64);
+    if !(frequency > 0.0) || n_samples == 0 {
+        eprintln!("The frequency and number of samples must be positive.");
+        process::exit(1);
+    }
+
+    let schedule = match animation::load_schedule(&args[0], &scene) {
+        Ok(schedule) => schedule,
+        Err(err) => {
+            eprintln!("Failed to load schedule '{}': {}", args[0], err);
+            process::exit(1)
+        }
+    };
+
+    let out_dir = Path::new(&args[1]);
+    if let Err(err) = animation::write_frames(&scene, &schedule, frequency, n_samples, seed, out_dir) {
+        eprintln!("Failed to write frames to '{}': {}", args[1], err);
+        process::exit(1);
+    }
+    eprintln!("Wrote {} frames at {} fps.", schedule.n_frames(), schedule.frames_per_second);
+}
+
+fn main_audit(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() != 1 {
+        exit_usage();
+    }
+
+    let frequency: f32 = parse_arg(&args[0]);
+    if !(frequency > 0.0) {
+        eprintln!("The frequency must be positive.");
+        process::exit(1);
+    }
+
+    audit::print_audit(&scene, frequency, seed);
+}
+
+fn main_bundle(scene: Scene, scene_path: Option<&String>, preset: RenderPreset, seed: u64, args: &[String]) {
+    let scene_path = match scene_path {
+        Some(path) => path,
+        None => {
+            eprintln!("The bundle command needs a scene file, pass one with --scene.");
+            process::exit(1)
+        }
+    };
+    if args.len() < 1 {
+        exit_usage();
+    }
+
+    let opts = parse_viewer_options(&args[1..], preset);
+    let render = RenderPreset {
+        frequency_hz: Some(opts.frequency_hz),
+        bandwidth_octaves: Some(opts.bandwidth_octaves),
+        spectrum: Some(opts.spectrum),
+        seed: Some(seed),
+    };
+
+    if let Err(err) = project::write_bundle(scene_path, &scene, &render, &args[0]) {
+        eprintln!("Failed to write bundle '{}': {}", args[0], err);
+        process::exit(1);
+    }
+}
+
+fn main_verify(scene: Scene, project: Project, args: &[String]) {
+    if args.len() != 0 {
+        exit_usage();
+    }
+    project::print_verification(&scene, &project);
+}
+
+fn main_multisub(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() != 2 {
+        exit_usage();
+    }
+
+    let f_min: f32 = parse_arg(&args[0]);
+    let f_max: f32 = parse_arg(&args[1]);
+    if !(f_min > 0.0 && f_max > f_min) {
+        eprintln!("Expected 0 < f_min < f_max.");
+        process::exit(1);
+    }
+    if scene.listener.is_none() {
+        eprintln!("The scene has no listener, optimization needs a listening position.");
+        process::exit(1);
+    }
+
+    multisub::print_settings(&scene, f_min, f_max, seed);
+}
+
+fn main_autoeq(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 3 || args.len() > 4 {
+        exit_usage();
+    }
+
+    let n_filters: usize = parse_arg(&args[0]);
+    let f_min: f32 = parse_arg(&args[1]);
+    let f_max: f32 = parse_arg(&args[2]);
+    let sample_rate: f64 = args.get(3).map(|a| parse_arg(a)).unwrap_or(96000.0);
+    if !(f_min > 0.0 && f_max > f_min) {
+        eprintln!("Expected 0 < f_min < f_max.");
+        process::exit(1);
+    }
+    if !(2.0 * (f_max as f64) < sample_rate) {
+        eprintln!("Expected f_max below half the sample rate.");
+        process::exit(1);
+    }
+    if scene.listener.is_none() {
+        eprintln!("The scene has no listener, auto-EQ needs a listening position.");
+        process::exit(1);
+    }
+
+    autoeq::print_filters(&scene, n_filters, f_min, f_max, sample_rate, seed);
+}
+
+fn main_peaks(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() != 2 {
+        exit_usage();
+    }
+
+    let f_min: f32 = parse_arg(&args[0]);
+    let f_max: f32 = parse_arg(&args[1]);
+    if !(f_min > 0.0 && f_max > f_min) {
+        eprintln!("Expected 0 < f_min < f_max.");
+        process::exit(1);
+    }
+    if scene.listener.is_none() {
+        eprintln!("The scene has no listener, the peak report needs a listening position.");
+        process::exit(1);
+    }
+
+    peaks::print_report(&scene, f_min, f_max, seed);
+}
+
+fn main_placement(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() != 3 {
+        exit_usage();
+    }
+
+    let f_min: f32 = parse_arg(&args[1]);
+    let f_max: f32 = parse_arg(&args[2]);
+    if !(f_min > 0.0 && f_max > f_min) {
+        eprintln!("Expected 0 < f_min < f_max.");
+        process::exit(1);
+    }
+    if scene.listener.is_none() {
+        eprintln!("The scene has no listener, placement needs a listening position.");
+        process::exit(1);
+    }
+
+    let zones = match placement::load_zones(&args[0], &scene) {
+        Ok(zones) => zones,
+        Err(err) => {
+            eprintln!("Failed to load zones '{}': {}", args[0], err);
+            process::exit(1)
+        }
+    };
+
+    placement::print_placements(&scene, &zones, f_min, f_max, seed);
+}
+
+fn main_schematic(scene: Scene, args: &[String]) {
+    if args.len() < 1 {
+        exit_usage();
+    }
+    let listener = parse_listener(&scene, &args[1..]);
+
+    let result = fs::File::create(&args[0])
+        .map(io::BufWriter::new)
+        .and_then(|mut out| schematic::write_svg(&scene, Some(listener), &mut out));
+
+    if let Err(err) = result {
+        eprintln!("Failed to write '{}': {}", args[0], err);
+        process::exit(1);
+    }
+}
+
+fn main_treatment(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 3 {
+        exit_usage();
+    }
+
+    let f_min: f32 = parse_arg(&args[1]);
+    let f_max: f32 = parse_arg(&args[2]);
+    let listener = parse_listener(&scene, &args[3..]);
+
+    if !(f_min > 0.0 && f_max > f_min) {
+        eprintln!("Expected 0 < f_min < f_max.");
+        process::exit(1);
+    }
+
+    let scenarios = match treatment::load_scenarios(&args[0], &scene) {
+        Ok(scenarios) => scenarios,
+        Err(err) => {
+            eprintln!("Failed to load scenarios '{}': {}", args[0], err);
+            process::exit(1)
+        }
+    };
+
+    treatment::print_comparison(&scene, &scenarios[..], listener, f_min, f_max, seed);
+}
+
+fn main_crawl(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 2 {
+        exit_usage();
+    }
+
+    let f_min: f32 = parse_arg(&args[0]);
+    let f_max: f32 = parse_arg(&args[1]);
+    let listener = parse_listener(&scene, &args[2..]);
+    if !(f_min > 0.0 && f_max > f_min) {
+        eprintln!("Expected 0 < f_min < f_max.");
+        process::exit(1);
+    }
+    if scene.rooms.is_empty() {
+        eprintln!("The sub crawl needs a scene built from a floor plan, a mesh has no walls to crawl along.");
+        process::exit(1);
+    }
+
+    let spots = crawl::crawl(&scene, listener, f_min, f_max, seed);
+    println!("{}\n", crawl::EXPLANATION);
+    println!("{}", crawl::describe(&spots[..], spots.len()));
+}
+
+fn main_field(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 2 || args.len() > 3 {
+        exit_usage();
+    }
+
+    let frequency: f32 = parse_arg(&args[1]);
+    let n_samples: u32 = args.get(2).map(|x| parse_arg(x)).unwrap_or(64);
+    if !(frequency > 0.0) || n_samples == 0 {
+        eprintln!("The frequency and number of samples must be positive.");
+        process::exit(1);
+    }
+
+    let result = fs::File::create(&args[0])
+        .map(io::BufWriter::new)
+        .and_then(|mut out| field::write_exr(Arc::new(scene), renderer::DEFAULT_WIDTH, renderer::DEFAULT_HEIGHT, frequency, n_samples, seed, &mut out));
+
+    if let Err(err) = result {
+        eprintln!("Failed to write '{}': {}", args[0], err);
+        process::exit(1);
+    }
+}
+
+fn main_impulse(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 2 {
+        exit_usage();
+    }
+
+    let duration_ms: f32 = parse_arg(&args[1]);
+    let listener = parse_listener(&scene, &args[2..]);
+    if !(duration_ms > 0.0) {
+        eprintln!("The duration must be positive.");
+        process::exit(1);
+    }
+
+    let result = fs::File::create(&args[0]).map(io::BufWriter::new).and_then(|mut out| {
+        if scene.simulation.solver == Solver::Fdtd {
+            let params = scene.simulation.fdtd;
+            fdtd::write_impulse_response(&scene, params, listener, duration_ms * 1e-3, &mut out)
+        } else {
+            let ir = ImpulseResponse::synthesize(&scene, listener, duration_ms * 1e-3, IMPULSE_SAMPLES, seed);
+            ir.write_wav(&mut out)
+        }
     });
-    scale.connect_format_value(move |_self, log10_frequency| {
-        let frequency_hz = 2.0 * 10_f32.powf(log10_frequency as f32);
-        format!("{:.1}", frequency_hz)
+
+    if let Err(err) = result {
+        eprintln!("Failed to write '{}': {}", args[0], err);
+        process::exit(1);
+    }
+}
+
+fn main_isosurface(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 2 || args.len() > 4 {
+        exit_usage();
+    }
+
+    let frequency: f32 = parse_arg(&args[1]);
+    let spacing: f32 = args.get(2).map(|x| parse_arg(x)).unwrap_or(0.2);
+    let n_samples: u32 = args.get(3).map(|x| parse_arg(x)).unwrap_or(256);
+    if !(frequency > 0.0) || !(spacing > 0.0) || n_samples == 0 {
+        eprintln!("The frequency, spacing, and number of samples must be positive.");
+        process::exit(1);
+    }
+
+    let grid = Arc::new(volume::Grid::new(&scene, spacing));
+    eprintln!("Grid of {} x {} x {} points.", grid.nx, grid.ny, grid.nz);
+    let reference_db = scene.listener.as_ref().map(|listener| {
+        let mut rng = Rng::new(seed);
+        let mut z = Complex::zero();
+        for _ in 0..n_samples {
+            z = z + scene.sample_at(&mut rng, frequency, listener.position);
+        }
+        response::to_db(z * (n_samples as f32).recip())
     });
-    scale.set_value((440.0_f64 / 2.0).log10());
+    let levels = volume::sample(Arc::new(scene), grid.clone(), frequency, n_samples, seed);
+
+    let mut finite: Vec<f32> = levels.iter().cloned().filter(|l| l.is_finite()).collect();
+    if finite.is_empty() {
+        eprintln!("No point of the grid lies inside the scene, try a smaller spacing.");
+        process::exit(1);
+    }
+    finite.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let reference_db = reference_db.unwrap_or(finite[finite.len() / 2]);
+    let (min, max) = (finite[0], finite[finite.len() - 1]);
+
+    // The surfaces at multiples of the step from the reference that lie
+    // within the range of the grid.
+    let n_min = ((min - reference_db) / isosurface::STEP_DB).ceil() as i32;
+    let n_max = ((max - reference_db) / isosurface::STEP_DB).floor() as i32;
+    let meshes: Vec<(String, isosurface::Mesh)> = (n_min..n_max + 1)
+        .map(|n| {
+            let relative_db = n as f32 * isosurface::STEP_DB;
+            let mesh = isosurface::extract(&grid, &levels[..], reference_db + relative_db);
+            eprintln!("{:+.0} dB: {} triangles.", relative_db, mesh.triangles.len());
+            (format!("level_{:+.0}dB", relative_db), mesh)
+        })
+        .filter(|&(_, ref mesh)| !mesh.triangles.is_empty())
+        .collect();
 
-    let expand = true;
-    let fill = false;
-    let padding = 0;
-    vbox.pack_start(&scale, expand, fill, padding);
+    let result = fs::File::create(&args[0])
+        .map(io::BufWriter::new)
+        .and_then(|mut out| isosurface::write_obj(&meshes[..], &mut out));
 
-    // Update the image every 2 seconds.
+    if let Err(err) = result {
+        eprintln!("Failed to write '{}': {}", args[0], err);
+        process::exit(1);
+    }
+}
+
+fn main_volume(scene: Scene, seed: u64, args: &[String]) {
+    if args.len() < 2 || args.len() > 4 {
+        exit_usage();
+    }
+
+    let frequency: f32 = parse_arg(&args[1]);
+    let spacing: f32 = args.get(2).map(|x| parse_arg(x)).unwrap_or(0.1);
+    let n_samples: u32 = args.get(3).map(|x| parse_arg(x)).unwrap_or(256);
+    if !(frequency > 0.0) || !(spacing > 0.0) || n_samples == 0 {
+        eprintln!("The frequency, spacing, and number of samples must be positive.");
+        process::exit(1);
+    }
+
+    let grid = volume::Grid::new(&scene, spacing);
+    eprintln!(
+        "Grid of {} x {} x {} points, {:.1} MB.",
+        grid.nx, grid.ny, grid.nz, (grid.n_points() * 4) as f32 * 1e-6,
+    );
+
+    let result = fs::File::create(&args[0])
+        .map(io::BufWriter::new)
+        .and_then(|mut out| volume::write_vtk(Arc::new(scene), grid, frequency, n_samples, seed, &mut out));
+
+    if let Err(err) = result {
+        eprintln!("Failed to write '{}': {}", args[0], err);
+        process::exit(1);
+    }
+}
+
+/// Reload the scene into the renderer whenever the scene file changes.
+///
+/// The returned monitor must be kept alive for as long as we want to watch.
+fn watch_scene(path: &str, renderer: &Arc<Renderer>) -> gio::FileMonitor {
+    let file = gio::File::new_for_path(path);
+    let monitor = match file.monitor_file(gio::FileMonitorFlags::NONE, None::<&gio::Cancellable>) {
+        Ok(monitor) => monitor,
+        Err(err) => {
+            eprintln!("Failed to watch '{}': {}", path, err);
+            process::exit(1);
+        }
+    };
+
+    let path = path.to_string();
     let r_ref = renderer.clone();
-    glib::source::timeout_add_seconds_local(2, move || {
-        if let Some(mut pixbuf) = image.get_pixbuf() {
-            r_ref.paint(&mut pixbuf);
-            image.set_from_pixbuf(Some(&pixbuf));
+    monitor.connect_changed(move |_monitor, _file, _other, event| {
+        // Editors often write a file in several steps, so wait until they are
+        // done. Editors that save by renaming a new file over the old one cause
+        // a created event instead.
+        match event {
+            gio::FileMonitorEvent::ChangesDoneHint => {}
+            gio::FileMonitorEvent::Created => {}
+            _ => return,
+        }
+
+        // If the file is invalid, keep the old scene. It may well be that the
+        // user is still editing it.
+        match Scene::from_toml(&path) {
+            Ok(scene) => {
+                println!("Reloaded scene '{}'.", path);
+                r_ref.set_scene(scene);
+            }
+            Err(err) => eprintln!("Failed to reload scene '{}': {}", path, err),
         }
-        glib::source::Continue(true)
     });
 
-    window.show_all();
+    monitor
 }
 
 fn main() {
+    let mut args: Vec<_> = env::args().collect();
+    let scene_path = take_option(&mut args, "--scene");
+    let scene = load_scene(scene_path.as_ref());
+    let mut project = load_project(scene_path.as_ref());
+    let seed = match take_option(&mut args, "--seed") {
+        Some(s) => parse_arg(&s),
+        None => project.render.seed.unwrap_or(0),
+    };
+    let preset = mem::replace(&mut project.render, RenderPreset::default());
+
+    match args.get(1).map(|a| &a[..]) {
+        Some("align") => return main_align(scene, seed, &args[2..]),
+        Some("animate") => return main_animate(scene, seed, &args[2..]),
+        Some("audit") => return main_audit(scene, seed, &args[2..]),
+        Some("autoeq") => return main_autoeq(scene, seed, &args[2..]),
+        Some("bundle") => return main_bundle(scene, scene_path.as_ref(), preset, seed, &args[2..]),
+        Some("verify") => return main_verify(scene, project, &args[2..]),
+        Some("crawl") => return main_crawl(scene, seed, &args[2..]),
+        Some("field") => return main_field(scene, seed, &args[2..]),
+        Some("impulse") => return main_impulse(scene, seed, &args[2..]),
+        Some("isosurface") => return main_isosurface(scene, seed, &args[2..]),
+        Some("multisub") => return main_multisub(scene, seed, &args[2..]),
+        Some("peaks") => return main_peaks(scene, seed, &args[2..]),
+        Some("placement") => return main_placement(scene, seed, &args[2..]),
+        Some("schematic") => return main_schematic(scene, &args[2..]),
+        Some("treatment") => return main_treatment(scene, seed, &args[2..]),
+        Some("volume") => return main_volume(scene, seed, &args[2..]),
+        Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            return
+        }
+        _ => {}
+    }
+
+    let opts = parse_viewer_options(&args[1..], preset);
+
     let application = gtk::Application::new(
         Some("nl.ruuda.basstrace"),
         Default::default(),
     ).unwrap();
 
-    let renderer = Arc::new(Renderer::new());
+    let renderer = Arc::new(Renderer::new(
+        scene,
+        opts.spectrum,
+        opts.bandwidth_octaves,
+        seed,
+        opts.width,
+        opts.height,
+        opts.meters_per_pixel,
+    ));
+    renderer.set_frequency(opts.frequency_hz);
+    renderer.set_precision(opts.precision);
+
+    // Analyses get threads of their own, while they run, as many render
+    // threads pause. There is a render thread for every CPU but one, which
+    // is left for the UI.
+    let pool = AnalysisPool::new(2);
 
-    for i in 0..7 {
+    for i in 0..pool::cpu_count().saturating_sub(1).max(1) as u64 {
         let r_ref = renderer.clone();
+        let p_ref = pool.clone();
         thread::spawn(move || {
-            r_ref.run_render_loop(i);
+            r_ref.run_render_loop(i, p_ref.busy());
         });
     }
 
+    let _monitor = scene_path.map(|path| watch_scene(&path, &renderer));
+
+    let refresh_interval = opts.refresh_interval;
     application.connect_activate(move |app| {
-        build_ui(app, &renderer);
+        ui::build_ui(
+            app,
+            &renderer,
+            &pool,
+            &project.annotations[..],
+            &project.measurements[..],
+            refresh_interval,
+        );
     });
 
-    let args: Vec<_> = env::args().collect();
-    application.run(&args);
+    // We handle the options ourselves, do not pass them on to GTK.
+    application.run(&args[..1]);
 }
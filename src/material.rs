@@ -0,0 +1,115 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::complex::Complex;
+
+/// The number of octave bands that materials are specified in.
+pub const N_BANDS: usize = 8;
+
+/// The center frequencies of the octave bands, in Hz.
+pub const BAND_CENTERS_HZ: [f32; N_BANDS] = [31.5, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0];
+
+/// Absorption coefficients of common surfaces, per octave band.
+///
+/// Values from 125 Hz up are typical values from published tables. Data for
+/// the lowest bands is scarce; there we mostly extend the 125 Hz values.
+const BUILTIN: [(&str, [f32; N_BANDS]); 7] = [
+    ("concrete", [0.01, 0.01, 0.01, 0.01, 0.015, 0.02, 0.02, 0.02]),
+    ("brick", [0.02, 0.03, 0.03, 0.03, 0.03, 0.04, 0.05, 0.07]),
+    ("drywall", [0.20, 0.25, 0.29, 0.10, 0.05, 0.04, 0.07, 0.09]),
+    ("glass", [0.30, 0.35, 0.35, 0.25, 0.18, 0.12, 0.07, 0.04]),
+    ("wood_floor", [0.12, 0.15, 0.15, 0.11, 0.10, 0.07, 0.06, 0.07]),
+    ("carpet", [0.01, 0.02, 0.02, 0.06, 0.14, 0.37, 0.60, 0.65]),
+    ("absorber", [0.10, 0.25, 0.60, 0.90, 0.95, 0.95, 0.95, 0.95]),
+];
+
+/// The acoustic properties of a surface.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+
+    /// The pressure reflection coefficient per octave band.
+    ///
+    /// The coefficient is complex, because the reflection can shift the phase.
+    reflectivity: [Complex; N_BANDS],
+}
+
+impl Material {
+    /// A material with the same reflection coefficient at every frequency.
+    pub fn constant(name: String, reflectivity: Complex) -> Material {
+        Material {
+            name: name,
+            reflectivity: [reflectivity; N_BANDS],
+        }
+    }
+
+    /// A material with the given absorption coefficient per octave band.
+    ///
+    /// The absorption coefficient is the fraction of the incident energy that
+    /// is absorbed, so the magnitude of the pressure reflection coefficient is
+    /// `sqrt(1 - absorption)`. Like the default material, reflections invert
+    /// the phase.
+    pub fn from_absorption(name: String, absorption: [f32; N_BANDS]) -> Material {
+        let mut reflectivity = [Complex::zero(); N_BANDS];
+        for (r, a) in reflectivity.iter_mut().zip(absorption.iter()) {
+            *r = Complex::new(-(1.0 - a.max(0.0).min(1.0)).sqrt(), 0.0);
+        }
+        Material {
+            name: name,
+            reflectivity: reflectivity,
+        }
+    }
+
+    /// A material with the given surface impedance per octave band.
+    ///
+    /// The impedance is the specific acoustic impedance of the surface,
+    /// normalized to the characteristic impedance of air. At normal incidence,
+    /// the pressure reflection coefficient is then `(z - 1) / (z + 1)`, which
+    /// is 1 for a rigid wall. To be consistent with the other materials, where
+    /// a hard wall inverts the phase, we negate it.
+    pub fn from_impedance(name: String, impedance: [Complex; N_BANDS]) -> Material {
+        let one = Complex::new(1.0, 0.0);
+        let mut reflectivity = [Complex::zero(); N_BANDS];
+        for (r, &z) in reflectivity.iter_mut().zip(impedance.iter()) {
+            *r = (one - z) / (z + one);
+        }
+        Material {
+            name: name,
+            reflectivity: reflectivity,
+        }
+    }
+
+    /// The material of faces that have no material specified.
+    pub fn default_material() -> Material {
+        Material::constant("default".to_string(), Complex::new(-0.95, 0.0))
+    }
+
+    /// Look up one of the built-in materials by name.
+    pub fn builtin(name: &str) -> Option<Material> {
+        BUILTIN
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(n, absorption)| Material::from_absorption(n.to_string(), absorption))
+    }
+
+    /// Return the names of the built-in materials.
+    pub fn builtin_names() -> Vec<&'static str> {
+        BUILTIN.iter().map(|&(n, _)| n).collect()
+    }
+
+    /// Return the reflection coefficient at `frequency`.
+    ///
+    /// We interpolate linearly in log-frequency between the band centers, and
+    /// extend the outermost bands beyond the range of the bands.
+    #[inline]
+    pub fn reflectivity_at(&self, frequency: f32) -> Complex {
+        let t = (frequency / BAND_CENTERS_HZ[0]).log2().max(0.0).min((N_BANDS - 1) as f32);
+        let i = (t as usize).min(N_BANDS - 2);
+        let u = t - i as f32;
+        self.reflectivity[i] * (1.0 - u) + self.reflectivity[i + 1] * u
+    }
+}
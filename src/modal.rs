@@ -0,0 +1,222 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::complex::Complex;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// Modes up to this factor times the frequency, plus `MODE_MARGIN_HZ`, are summed.
+///
+/// Modes far above the frequency contribute little, except close to the
+/// source, where the sum converges slowly.
+const MODE_FACTOR: f32 = 2.0;
+const MODE_MARGIN_HZ: f32 = 50.0;
+
+/// The kind of a room mode, by the number of axes that it spans.
+///
+/// Axial modes bounce between two opposite faces, and are the strongest.
+/// Tangential modes involve four faces, and oblique modes all six, so they
+/// lose more energy per period, and are weaker.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModeKind {
+    Axial,
+    Tangential,
+    Oblique,
+}
+
+impl ModeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ModeKind::Axial => "axial",
+            ModeKind::Tangential => "tangential",
+            ModeKind::Oblique => "oblique",
+        }
+    }
+}
+
+/// A resonance of a shoebox room.
+#[derive(Copy, Clone, Debug)]
+pub struct Mode {
+    /// The number of half wavelengths along the x, y, and z axes.
+    pub order: [u32; 3],
+    pub kind: ModeKind,
+    pub frequency: f32,
+}
+
+/// A rectangular room with axis-aligned walls, a shoebox.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Shoebox {
+    /// The corner with the lowest coordinates, on the floor.
+    pub origin: Vec3,
+
+    /// The length, width, and height of the room.
+    pub size: Vec3,
+}
+
+impl Shoebox {
+    /// Return the shoebox that the scene is, if it is one.
+    ///
+    /// That is the case for a single room with four corners that form a
+    /// rectangle along the x and y axes, without openings or obstacles.
+    pub fn of(scene: &Scene) -> Option<Shoebox> {
+        if scene.rooms.len() != 1 || !scene.obstacles.is_empty() {
+            return None
+        }
+        let room = &scene.rooms[0];
+        if room.corners.len() != 4 || !room.openings.is_empty() {
+            return None
+        }
+
+        let is_axis_aligned = (0..4).all(|i| {
+            let d = room.corners[(i + 1) % 4] - room.corners[i];
+            d.x.abs() < 1e-4 || d.y.abs() < 1e-4
+        });
+        if !is_axis_aligned {
+            return None
+        }
+
+        let min_x = room.corners.iter().map(|c| c.x).fold(std::f32::INFINITY, f32::min);
+        let min_y = room.corners.iter().map(|c| c.y).fold(std::f32::INFINITY, f32::min);
+        let max_x = room.corners.iter().map(|c| c.x).fold(std::f32::NEG_INFINITY, f32::max);
+        let max_y = room.corners.iter().map(|c| c.y).fold(std::f32::NEG_INFINITY, f32::max);
+        Some(Shoebox {
+            origin: Vec3::new(min_x, min_y, 0.0),
+            size: Vec3::new(max_x - min_x, max_y - min_y, scene.height),
+        })
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.size.x * self.size.y * self.size.z
+    }
+
+    /// Return the modes of the room up to `f_max` Hz, from low to high.
+    ///
+    /// The frequency of mode (nx, ny, nz) is c/2 √((nx/Lx)² + (ny/Ly)² + (nz/Lz)²).
+    pub fn modes(&self, speed_of_sound: f32, f_max: f32) -> Vec<Mode> {
+        let c = speed_of_sound;
+        let max_order = |length: f32| (2.0 * f_max * length / c) as u32;
+        let mut modes = Vec::new();
+        for nx in 0..max_order(self.size.x) + 1 {
+            for ny in 0..max_order(self.size.y) + 1 {
+                for nz in 0..max_order(self.size.z) + 1 {
+                    let n_axes = [nx, ny, nz].iter().filter(|&&n| n > 0).count();
+                    let kind = match n_axes {
+                        0 => continue,
+                        1 => ModeKind::Axial,
+                        2 => ModeKind::Tangential,
+                        _ => ModeKind::Oblique,
+                    };
+                    let x = nx as f32 / self.size.x;
+                    let y = ny as f32 / self.size.y;
+                    let z = nz as f32 / self.size.z;
+                    let frequency = 0.5 * c * (x * x + y * y + z * z).sqrt();
+                    if frequency <= f_max {
+                        modes.push(Mode {
+                            order: [nx, ny, nz],
+                            kind: kind,
+                            frequency: frequency,
+                        });
+                    }
+                }
+            }
+        }
+        modes.sort_by(|a, b| a.frequency.partial_cmp(&b.frequency).unwrap());
+        modes
+    }
+
+    /// Return the equivalent absorption area at `frequency`, in square meters.
+    ///
+    /// The faces of a shoebox are the four walls, the floor, and the ceiling.
+    /// Their absorption coefficient is the part of the energy that the
+    /// material does not reflect. Resonant absorbers add to it, but where in
+    /// the room they are does not matter here.
+    pub fn absorption_area(&self, scene: &Scene, frequency: f32) -> f32 {
+        let s = self.size;
+        let corners = &scene.rooms[0].corners;
+        let areas = (0..4)
+            .map(|i| (corners[(i + 1) % 4] - corners[i]).norm() * s.z)
+            .chain(vec![s.x * s.y, s.x * s.y]);
+        scene.faces
+            .iter()
+            .zip(areas)
+            .map(|(face, area)| (1.0 - face.reflectivity_at(frequency).norm_squared()) * area)
+            .sum::<f32>() + scene.absorber_area(frequency)
+    }
+
+    /// Return the product of the cosines of axis `axis` at `p` and `q`, for
+    /// the modes with wavenumber along the axis below `k_max`.
+    ///
+    /// The product is doubled for every mode but the zeroth, which accounts
+    /// for the normalization of the mode shapes.
+    fn axis_products(&self, axis_length: f32, p: f32, q: f32, k_max: f32) -> Vec<(f32, f32)> {
+        let mut products = Vec::new();
+        let mut i = 0;
+        loop {
+            let k = PI * i as f32 / axis_length;
+            if k > k_max {
+                return products
+            }
+            let weight = if i == 0 { 1.0 } else { 2.0 };
+            products.push((k * k, weight * (k * p).cos() * (k * q).cos()));
+            i += 1;
+        }
+    }
+
+    /// Return the field of source `si` at `position`, by summing the modes of the room.
+    ///
+    /// This is the Green's function of a shoebox with nearly rigid walls,
+    /// where every mode decays at the rate that Sabine's formula gives for the
+    /// absorption of the faces. Sources are treated as monopoles: their drive
+    /// applies, but their directivity does not. The level is scaled such that
+    /// the direct sound at 1 meter matches `Source::sample_at()`, which the
+    /// reflections of the path tracer do not follow exactly, because it lets
+    /// the amplitude fall off with the distance squared.
+    pub fn field_at(&self, scene: &Scene, si: usize, frequency: f32, position: Vec3) -> Complex {
+        let source = &scene.sources[si];
+        let c = scene.atmosphere.speed_of_sound();
+        let k = 2.0 * PI * frequency / c;
+        let k_max = 2.0 * PI * (MODE_FACTOR * frequency + MODE_MARGIN_HZ) / c;
+
+        // The decay rate of the energy is cA/4V, so the amplitude decays at cA/8V.
+        let v = self.volume();
+        let kappa = self.absorption_area(scene, frequency) / (8.0 * v);
+        let damping = 2.0 * k * kappa;
+
+        let p = position - self.origin;
+        let q = source.position - self.origin;
+        let xs = self.axis_products(self.size.x, p.x, q.x, k_max);
+        let ys = self.axis_products(self.size.y, p.y, q.y, k_max);
+        let zs = self.axis_products(self.size.z, p.z, q.z, k_max);
+
+        let k_max_squared = k_max * k_max;
+        let mut z = Complex::zero();
+        for &(kx2, px) in &xs {
+            for &(ky2, py) in &ys {
+                let kxy2 = kx2 + ky2;
+                if kxy2 > k_max_squared {
+                    break
+                }
+                for &(kz2, pz) in &zs {
+                    let kn2 = kxy2 + kz2;
+                    if kn2 > k_max_squared {
+                        break
+                    }
+                    // 1 / (kn² - k² - 2ikκ) = (kn² - k² + 2ikκ) / |kn² - k² - 2ikκ|².
+                    let a = kn2 - k * k;
+                    let weight = px * py * pz / (a * a + damping * damping);
+                    z = z + Complex::new(a, damping) * weight;
+                }
+            }
+        }
+
+        // The sum over image sources of e^(ikr) / r is 4π times the Green's
+        // function, and the path tracer scales every path by 1/4096.
+        z * source.drive(frequency) * (4.0 * PI / (v * 4096.0))
+    }
+}
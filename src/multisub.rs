@@ -0,0 +1,225 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::complex::Complex;
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+
+/// The number of Monte Carlo samples per frequency, per seat, per source.
+const N_SAMPLES: u32 = 4096;
+
+/// The largest delay change that we consider, in either direction, in ms.
+const MAX_DELAY_MS: f32 = 20.0;
+
+/// The delay search step, in ms.
+const DELAY_STEP_MS: f32 = 0.1;
+
+/// The range of gain changes that we consider, in dB.
+///
+/// Cutting a source is always an option to flatten the response, but it
+/// costs output, so we do not cut more than this.
+const MIN_GAIN_DB: f32 = -6.0;
+const MAX_GAIN_DB: f32 = 6.0;
+
+/// The gain search step, in dB.
+const GAIN_STEP_DB: f32 = 0.5;
+
+/// The most passes over all sources, if the settings keep improving.
+const MAX_PASSES: u32 = 8;
+
+/// A change to the gain, delay, and polarity of a source.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Setting {
+    pub gain_db: f32,
+    pub delay_ms: f32,
+    pub invert: bool,
+}
+
+impl Setting {
+    /// The setting that leaves the source unchanged.
+    pub fn identity() -> Setting {
+        Setting {
+            gain_db: 0.0,
+            delay_ms: 0.0,
+            invert: false,
+        }
+    }
+
+    /// Return the complex factor that applying the setting at `frequency`
+    /// amounts to, with the phase convention of `Source::drive()`.
+    pub fn factor(&self, frequency: f32) -> Complex {
+        let polarity = if self.invert { -1.0 } else { 1.0 };
+        let gain = 10.0_f32.powf(self.gain_db / 20.0) * polarity;
+        Complex::exp_i(2.0 * PI * frequency * self.delay_ms * 1e-3) * gain
+    }
+}
+
+/// The response of every source at every seat, and the settings to apply.
+struct Responses {
+    frequencies: Vec<f32>,
+    n_seats: usize,
+
+    /// For every source, its responses as returned by `response::sample_seats()`.
+    sources: Vec<Vec<Complex>>,
+}
+
+impl Responses {
+    /// Return the summed response at every seat with `settings` applied.
+    fn sum(&self, settings: &[Setting]) -> Vec<Complex> {
+        let nf = self.frequencies.len();
+        let mut total = vec![Complex::zero(); self.n_seats * nf];
+        for (r, setting) in self.sources.iter().zip(settings) {
+            let factors: Vec<Complex> = self.frequencies.iter().map(|&f| setting.factor(f)).collect();
+            for (i, (acc, &z)) in total.iter_mut().zip(r.iter()).enumerate() {
+                *acc = *acc + z * factors[i % nf];
+            }
+        }
+        total
+    }
+
+    /// Return the mean over frequencies of the standard deviation of the level between seats.
+    fn seat_to_seat_db(&self, total: &[Complex]) -> f32 {
+        let nf = self.frequencies.len();
+        let n = self.n_seats as f32;
+        let mut sum = 0.0;
+        for k in 0..nf {
+            let levels: Vec<f32> = (0..self.n_seats).map(|s| response::to_db(total[s * nf + k])).collect();
+            let mean = levels.iter().sum::<f32>() / n;
+            let var = levels.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+            sum += var.sqrt();
+        }
+        sum / nf as f32
+    }
+
+    /// Return the power averaged over seats and frequencies, in dB.
+    fn mean_level_db(&self, total: &[Complex]) -> f32 {
+        let power = total.iter().map(|z| z.norm_squared()).sum::<f32>() / total.len() as f32;
+        10.0 * power.log10()
+    }
+
+    /// Find the best setting for source `si`, with the other settings fixed.
+    ///
+    /// Returns the setting and the flatness that it achieves.
+    fn best_setting(&self, settings: &[Setting], si: usize) -> (Setting, f32) {
+        let mut candidate_settings = settings.to_vec();
+        let mut best = settings[si];
+        let mut best_flatness = response::flatness_db(&self.sum(settings)[..]);
+
+        let n_delays = (MAX_DELAY_MS / DELAY_STEP_MS) as i32;
+        let n_gains = ((MAX_GAIN_DB - MIN_GAIN_DB) / GAIN_STEP_DB) as i32;
+        for &invert in &[false, true] {
+            for d in -n_delays..n_delays + 1 {
+                for g in 0..n_gains + 1 {
+                    let candidate = Setting {
+                        gain_db: MIN_GAIN_DB + g as f32 * GAIN_STEP_DB,
+                        delay_ms: d as f32 * DELAY_STEP_MS,
+                        invert: invert,
+                    };
+                    candidate_settings[si] = candidate;
+                    let flatness = response::flatness_db(&self.sum(&candidate_settings[..])[..]);
+                    if flatness < best_flatness {
+                        best = candidate;
+                        best_flatness = flatness;
+                    }
+                }
+            }
+        }
+
+        (best, best_flatness)
+    }
+}
+
+/// Optimize the gain, delay, and polarity of every source for a flat response
+/// over the listening area, and print the DSP settings.
+///
+/// This is what MSO (Multi-Sub Optimizer) does with measurements: the
+/// positions are fixed, and the settings minimize the variation of the level
+/// between seats and over the band from `f_min` to `f_max`. Source 0 is the
+/// reference, its settings do not change. The other sources are optimized one
+/// at a time, by a grid search with the others fixed, in passes over all
+/// sources until the response stops improving. The scene must have a listener.
+pub fn print_settings(scene: &Scene, f_min: f32, f_max: f32, seed: u64) {
+    let seats = scene.listener.as_ref().expect("Optimization needs a listener.").seats();
+    let frequencies = response::log_frequencies(f_min, f_max, 12);
+    let mut rng = Rng::new(seed);
+
+    let sources = (0..scene.sources.len())
+        .map(|si| {
+            eprint!("\rSampling source {} of {} ...", si + 1, scene.sources.len());
+            response::sample_seats(scene, &mut rng, si, &seats[..], &frequencies[..], N_SAMPLES)
+        })
+        .collect();
+    eprintln!();
+    let responses = Responses {
+        frequencies: frequencies,
+        n_seats: seats.len(),
+        sources: sources,
+    };
+
+    let mut settings = vec![Setting::identity(); scene.sources.len()];
+    let mut flatness = response::flatness_db(&responses.sum(&settings[..])[..]);
+    for pass in 0..MAX_PASSES {
+        eprint!("\rOptimizing, pass {} ...", pass + 1);
+        let before = flatness;
+        for si in 1..scene.sources.len() {
+            let (setting, f) = responses.best_setting(&settings[..], si);
+            settings[si] = setting;
+            flatness = f;
+        }
+        if flatness >= before {
+            break
+        }
+    }
+    eprintln!();
+
+    let before = responses.sum(&vec![Setting::identity(); scene.sources.len()][..]);
+    let after = responses.sum(&settings[..]);
+
+    // A delay cannot be negative, so if a source needs to play earlier than it
+    // does now, delay all the others instead. This changes nothing at the seats.
+    let min_delay_ms = scene.sources
+        .iter()
+        .zip(settings.iter())
+        .map(|(s, setting)| s.delay_ms + setting.delay_ms)
+        .fold(std::f32::INFINITY, f32::min)
+        .min(0.0);
+
+    println!("Seed: {}", seed);
+    println!("{:>6}  {:>8}  {:>9}  {:>8}", "source", "gain dB", "delay ms", "polarity");
+    for (si, (source, setting)) in scene.sources.iter().zip(settings.iter()).enumerate() {
+        println!(
+            "{:>6}  {:>8.1}  {:>9.2}  {:>8}",
+            si,
+            source.gain_db + setting.gain_db,
+            source.delay_ms + setting.delay_ms - min_delay_ms,
+            if source.invert != setting.invert { "inverted" } else { "normal" },
+        );
+    }
+    println!();
+
+    println!("{:>15}  {:>6}  {:>5}", "", "before", "after");
+    println!(
+        "{:>15}  {:>6.1}  {:>5.1}",
+        "flatness dB", response::flatness_db(&before[..]), response::flatness_db(&after[..]),
+    );
+    println!(
+        "{:>15}  {:>6.1}  {:>5.1}",
+        "seat to seat dB", responses.seat_to_seat_db(&before[..]), responses.seat_to_seat_db(&after[..]),
+    );
+    println!();
+    println!(
+        "The mean level changes by {:+.1} dB.",
+        responses.mean_level_db(&after[..]) - responses.mean_level_db(&before[..]),
+    );
+    println!(
+        "Flatness is the standard deviation of the level over {} seats, {:.0} to {:.0} Hz.",
+        seats.len(), f_min, f_max,
+    );
+}
@@ -0,0 +1,108 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::vec3::Vec3;
+
+/// A polygon of a mesh, with the names of the part of the model that it belongs to.
+pub struct Polygon {
+    pub vertices: Vec<Vec3>,
+
+    /// The object or group name (`o` or `g`), or the empty string.
+    pub object: String,
+
+    /// The material name (`usemtl`), or the empty string.
+    pub material: String,
+}
+
+fn invalid_data<T>(line_number: usize, msg: &str) -> io::Result<T> {
+    let msg = format!("Line {}: {}", line_number, msg);
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Load the polygons of a Wavefront OBJ file, see `parse()`.
+pub fn load<P: AsRef<Path>>(path: P, scale: f32, y_up: bool) -> io::Result<Vec<Polygon>> {
+    let contents = fs::read_to_string(path)?;
+    parse(&contents, scale, y_up)
+}
+
+/// Parse the polygons of a Wavefront OBJ file.
+///
+/// Only vertex positions and faces are used, texture coordinates, normals,
+/// lines, and so on are ignored. Faces with more than three vertices are kept
+/// as a single polygon, so they should be planar and convex, as exporters
+/// produce them for walls. Vertices are multiplied by `scale`. If `y_up` is
+/// true, the model is rotated from the y-up convention that most modelling
+/// programs export in, into our z-up coordinates.
+pub fn parse(contents: &str, scale: f32, y_up: bool) -> io::Result<Vec<Polygon>> {
+    let mut vertices = Vec::new();
+    let mut polygons = Vec::new();
+    let mut object = String::new();
+    let mut material = String::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line_number = i + 1;
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("v") => {
+                let mut xs = [0.0; 3];
+                for x in xs.iter_mut() {
+                    *x = match parts.next().map(|p| p.parse::<f32>()) {
+                        Some(Ok(x)) => x * scale,
+                        _ => return invalid_data(line_number, "Expected a vertex with three coordinates."),
+                    };
+                }
+                let v = if y_up {
+                    Vec3::new(xs[0], -xs[2], xs[1])
+                } else {
+                    Vec3::new(xs[0], xs[1], xs[2])
+                };
+                vertices.push(v);
+            }
+            Some("f") => {
+                let mut polygon = Vec::new();
+                for p in parts {
+                    // A vertex is 'v', 'v/vt', 'v//vn', or 'v/vt/vn', and
+                    // negative indices count back from the last vertex.
+                    let index = match p.split('/').next().unwrap().parse::<i64>() {
+                        Ok(j) if j > 0 => j as usize - 1,
+                        Ok(j) if j < 0 && ((-j) as usize) <= vertices.len() => vertices.len() - (-j) as usize,
+                        _ => return invalid_data(line_number, "Invalid vertex index."),
+                    };
+                    match vertices.get(index) {
+                        Some(&v) => polygon.push(v),
+                        None => return invalid_data(line_number, "Vertex index out of range."),
+                    }
+                }
+                if polygon.len() < 3 {
+                    return invalid_data(line_number, "Expected a face with at least three vertices.")
+                }
+                polygons.push(Polygon {
+                    vertices: polygon,
+                    object: object.clone(),
+                    material: material.clone(),
+                });
+            }
+            Some("o") | Some("g") => {
+                object = parts.collect::<Vec<_>>().join(" ");
+            }
+            Some("usemtl") => {
+                material = parts.collect::<Vec<_>>().join(" ");
+            }
+            _ => {}
+        }
+    }
+
+    if polygons.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "The file contains no faces."))
+    }
+
+    Ok(polygons)
+}
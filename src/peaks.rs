@@ -0,0 +1,198 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::rand::Rng;
+use crate::response::{self, Smoothing};
+use crate::scene::Scene;
+
+/// The number of Monte Carlo samples per frequency, per seat.
+const N_SAMPLES: u32 = 2048;
+
+/// The resolution of the response, which limits the highest Q that can be told apart.
+const STEPS_PER_OCTAVE: u32 = 24;
+
+/// Peaks and dips are measured against the response smoothed over this
+/// fraction of an octave, so they stand out from the broad trend.
+const BASELINE_SMOOTHING: u32 = 1;
+
+/// Peaks and dips smaller than this, in dB, are not listed.
+const MIN_SIZE_DB: f32 = 3.0;
+
+/// How far from the frequency at the listener a seat may have the same peak
+/// or dip, as a fraction of an octave.
+const MAX_SHIFT_OCTAVES: f32 = 1.0 / 6.0;
+
+/// With the peak or dip at at least this part of the seats, a mode causes it.
+const MIN_SHARED: f32 = 2.0 / 3.0;
+
+/// A peak or dip in the response at the listener.
+#[derive(Copy, Clone, Debug)]
+pub struct Extremum {
+    pub frequency: f32,
+
+    /// The level relative to the smoothed response, positive for a peak.
+    pub size_db: f32,
+
+    /// The center frequency over the bandwidth at half the size in dB, as
+    /// for a parametric EQ filter. `None` if the peak or dip extends beyond
+    /// the band on both sides.
+    pub q: Option<f32>,
+
+    /// The number of seats in the listening area that share the peak or dip.
+    pub n_shared: usize,
+}
+
+/// Return the highest Q that the resolution can tell apart, that of a
+/// bandwidth of one step.
+fn max_q() -> f32 {
+    1.0 / (2.0_f32.powf(1.0 / STEPS_PER_OCTAVE as f32) - 1.0)
+}
+
+/// Return the level relative to the response smoothed by `BASELINE_SMOOTHING`.
+fn deviations(frequencies: &[f32], levels_db: &[f32]) -> Vec<f32> {
+    let baseline = response::smooth_db(frequencies, levels_db, Smoothing::Fraction(BASELINE_SMOOTHING));
+    levels_db.iter().zip(&baseline).map(|(db, base)| db - base).collect()
+}
+
+/// Return the frequency at which the deviation crosses `half` walking from
+/// index `i` in the direction `step`, interpolated in log-frequency.
+fn crossing(frequencies: &[f32], d: &[f32], i: usize, step: isize, half: f32) -> Option<f32> {
+    let mut j = i as isize;
+    loop {
+        let k = j + step;
+        if k < 0 || k >= d.len() as isize {
+            return None
+        }
+        let (a, b) = (d[j as usize], d[k as usize]);
+        if (b - half) * (a - half) <= 0.0 {
+            let t = (a - half) / (a - b);
+            let (f0, f1) = (frequencies[j as usize], frequencies[k as usize]);
+            return Some(f0 * (f1 / f0).powf(t))
+        }
+        j = k;
+    }
+}
+
+/// Estimate the Q of the peak or dip at index `i`, from its half-size bandwidth.
+///
+/// If the bandwidth extends beyond the band on one side, the peak or dip is
+/// assumed to be symmetric in log-frequency.
+pub fn estimate_q(frequencies: &[f32], d: &[f32], i: usize) -> Option<f32> {
+    let f = frequencies[i];
+    let half = 0.5 * d[i];
+    let (lo, hi) = match (crossing(frequencies, d, i, -1, half), crossing(frequencies, d, i, 1, half)) {
+        (Some(lo), Some(hi)) => (lo, hi),
+        (Some(lo), None) => (lo, f * f / lo),
+        (None, Some(hi)) => (f * f / hi, hi),
+        (None, None) => return None,
+    };
+    Some(f / (hi - lo))
+}
+
+/// Return the indices of the local extremes of `d` that are at least `MIN_SIZE_DB`.
+fn find_extremes(d: &[f32]) -> Vec<usize> {
+    (1..d.len() - 1)
+        .filter(|&i| {
+            let is_peak = d[i] >= MIN_SIZE_DB && d[i] > d[i - 1] && d[i] >= d[i + 1];
+            let is_dip = d[i] <= -MIN_SIZE_DB && d[i] < d[i - 1] && d[i] <= d[i + 1];
+            is_peak || is_dip
+        })
+        .collect()
+}
+
+/// Find the peaks and dips in the response of all sources at the listener,
+/// between `f_min` and `f_max`, and count the seats that share them.
+///
+/// A peak or dip is measured against the response smoothed over an octave.
+/// A seat shares it if its response deviates at least as much in the same
+/// direction within a sixth of an octave. The scene must have a listener.
+pub fn find(scene: &Scene, f_min: f32, f_max: f32, seed: u64) -> (Vec<Extremum>, usize) {
+    let listener = scene.listener.as_ref().expect("The peak report needs a listener.");
+    let frequencies = response::log_frequencies(f_min, f_max, STEPS_PER_OCTAVE);
+    let sample = |position| {
+        let mut rng = Rng::new(seed);
+        let r = response::sample_response(scene, &mut rng, position, &frequencies[..], N_SAMPLES);
+        let levels: Vec<f32> = r.into_iter().map(response::to_db).collect();
+        deviations(&frequencies[..], &levels[..])
+    };
+
+    let d = sample(listener.position);
+    let seats: Vec<Vec<f32>> = match listener.area {
+        Some(..) => listener.seats().into_iter().map(&sample).collect(),
+        None => Vec::new(),
+    };
+
+    let extremes = find_extremes(&d[..])
+        .into_iter()
+        .map(|i| {
+            let f = frequencies[i];
+            let is_shared = |seat: &Vec<f32>| {
+                frequencies
+                    .iter()
+                    .zip(seat)
+                    .filter(|&(&g, _)| (g / f).log2().abs() <= MAX_SHIFT_OCTAVES)
+                    .any(|(_, &x)| if d[i] > 0.0 { x >= MIN_SIZE_DB } else { x <= -MIN_SIZE_DB })
+            };
+            Extremum {
+                frequency: f,
+                size_db: d[i],
+                q: estimate_q(&frequencies[..], &d[..], i),
+                n_shared: seats.iter().filter(|s| is_shared(s)).count(),
+            }
+        })
+        .collect();
+
+    (extremes, seats.len())
+}
+
+/// Print a table of the peaks and dips at the listener, see `find()`.
+///
+/// With a listening area, a peak or dip at most of its seats is caused by a
+/// mode of the room, which EQ can address. One at a few seats depends on the
+/// position, and EQ for it makes the other seats worse.
+pub fn print_report(scene: &Scene, f_min: f32, f_max: f32, seed: u64) {
+    let (extremes, n_seats) = find(scene, f_min, f_max, seed);
+
+    println!("Seed: {}", seed);
+    if extremes.is_empty() {
+        println!("No peaks or dips of {:.0} dB or more between {:.0} and {:.0} Hz.", MIN_SIZE_DB, f_min, f_max);
+        return
+    }
+
+    println!("{:>4}  {:>12}  {:>7}  {:>5}  {:>5}  {:>8}", "", "frequency Hz", "size dB", "Q", "seats", "cause");
+    for e in &extremes {
+        let q = match e.q {
+            Some(q) if q > max_q() => format!(">{:.0}", max_q()),
+            Some(q) => format!("{:.1}", q),
+            None => "-".to_string(),
+        };
+        let (seats, cause) = if n_seats == 0 {
+            ("-".to_string(), "-")
+        } else if e.n_shared as f32 >= MIN_SHARED * n_seats as f32 {
+            (format!("{}/{}", e.n_shared, n_seats), "mode")
+        } else {
+            (format!("{}/{}", e.n_shared, n_seats), "position")
+        };
+        println!(
+            "{:>4}  {:>12.1}  {:>+7.1}  {:>5}  {:>5}  {:>8}",
+            if e.size_db > 0.0 { "peak" } else { "dip" }, e.frequency, e.size_db, q, seats, cause,
+        );
+    }
+    println!();
+    println!(
+        "Sizes are relative to the response smoothed over {} octave, Q is from the bandwidth at half the size.",
+        BASELINE_SMOOTHING,
+    );
+    if n_seats == 0 {
+        println!("The listener has no listening area, so peaks and dips are not compared between seats.");
+    } else {
+        println!(
+            "Peaks and dips at {:.0}% of the seats or more are caused by modes, the others depend on the position.",
+            MIN_SHARED * 100.0,
+        );
+    }
+}
@@ -0,0 +1,266 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::fs;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::complex::Complex;
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+use crate::scene_file::{self, Result, invalid};
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// The number of Monte Carlo samples per frequency, per seat, per candidate.
+const N_SAMPLES: u32 = 1024;
+
+/// The most combinations of candidate positions that we score.
+const MAX_COMBINATIONS: usize = 1_000_000;
+
+/// The number of placements to report.
+const N_BEST: usize = 5;
+
+/// A rectangle in the floor plan where a source may be placed.
+pub struct Zone {
+    pub source: usize,
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+/// The allowed zones, and how finely to search them.
+pub struct Zones {
+    /// The distance between candidate positions, in meters.
+    pub step: f32,
+    pub zones: Vec<Zone>,
+}
+
+/// Load the allowed zones from a TOML file.
+///
+/// `scene` is the scene that the zones apply to, it is used to validate the
+/// source indices. A zones file looks like this:
+///
+/// ```toml
+/// # The distance between candidate positions, defaults to 0.25 m.
+/// step = 0.25
+///
+/// # A source may have multiple zones, sources without zones stay in place.
+/// [[zone]]
+/// source = 0
+/// min = [0.2, 0.2]
+/// max = [1.2, 3.0]
+///
+/// [[zone]]
+/// source = 1
+/// min = [3.0, 0.2]
+/// max = [4.8, 0.6]
+/// ```
+pub fn load_zones<P: AsRef<Path>>(path: P, scene: &Scene) -> Result<Zones> {
+    let contents = fs::read_to_string(path)?;
+    let root: Value = contents.parse()?;
+
+    let step = match root.get("step") {
+        Some(v) => scene_file::as_f32(v, "step")?,
+        None => 0.25,
+    };
+    if !(step > 0.0) {
+        return invalid("'step' must be positive.".to_string())
+    }
+
+    let mut zones = Vec::new();
+    let zone_values = scene_file::as_array(scene_file::get(&root, "", "zone")?, "zone")?;
+    for (i, z) in zone_values.iter().enumerate() {
+        let path = format!("zone[{}]", i);
+        let source_path = format!("{}.source", path);
+        let source = scene_file::as_usize(scene_file::get(z, &path, "source")?, &source_path)?;
+        if source >= scene.sources.len() {
+            return invalid(format!(
+                "'{}' refers to source {}, but the scene has {} sources.",
+                source_path, source, scene.sources.len(),
+            ))
+        }
+
+        let mut corners = Vec::with_capacity(2);
+        for key in &["min", "max"] {
+            let key_path = format!("{}.{}", path, key);
+            let xs = scene_file::as_array(scene_file::get(z, &path, key)?, &key_path)?;
+            if xs.len() != 2 {
+                return invalid(format!("Expected '{}' to be an array of 2 numbers.", key_path))
+            }
+            corners.push(Vec2::new(
+                scene_file::as_f32(&xs[0], &key_path)?,
+                scene_file::as_f32(&xs[1], &key_path)?,
+            ));
+        }
+
+        zones.push(Zone {
+            source: source,
+            min: Vec2::new(corners[0].x.min(corners[1].x), corners[0].y.min(corners[1].y)),
+            max: Vec2::new(corners[0].x.max(corners[1].x), corners[0].y.max(corners[1].y)),
+        });
+    }
+
+    let zones = Zones {
+        step: step,
+        zones: zones,
+    };
+
+    let mut n_combinations = 1_usize;
+    for si in zones.moving_sources(scene) {
+        let n = candidates(scene, &zones, si).len();
+        if n == 0 {
+            return invalid(format!("The zones of source {} contain no positions inside the space.", si))
+        }
+        n_combinations = n_combinations.saturating_mul(n);
+    }
+    if n_combinations > MAX_COMBINATIONS {
+        return invalid(format!(
+            "The zones allow {} combinations of positions, at most {} are supported. Increase the step.",
+            n_combinations, MAX_COMBINATIONS,
+        ))
+    }
+
+    Ok(zones)
+}
+
+impl Zones {
+    /// Return the indices of the sources that have a zone.
+    fn moving_sources(&self, scene: &Scene) -> Vec<usize> {
+        (0..scene.sources.len())
+            .filter(|&si| self.zones.iter().any(|zone| zone.source == si))
+            .collect()
+    }
+}
+
+/// Return the candidate positions of source `si`: grid points in its zones
+/// that lie inside the space, at the height of the source.
+fn candidates(scene: &Scene, zones: &Zones, si: usize) -> Vec<Vec3> {
+    let z = scene.sources[si].position.z;
+    let mut result = Vec::new();
+    for zone in zones.zones.iter().filter(|zone| zone.source == si) {
+        let nx = ((zone.max.x - zone.min.x) / zones.step).floor() as u32 + 1;
+        let ny = ((zone.max.y - zone.min.y) / zones.step).floor() as u32 + 1;
+        for j in 0..ny {
+            for i in 0..nx {
+                let p = Vec3::new(
+                    zone.min.x + i as f32 * zones.step,
+                    zone.min.y + j as f32 * zones.step,
+                    z,
+                );
+                if scene.contains(p) {
+                    result.push(p);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// A combination of positions for the sources that move, and its score.
+struct Placement {
+    positions: Vec<Vec3>,
+    flatness_db: f32,
+}
+
+/// Search the zones for the placements with the flattest response over the
+/// listening area, and print the best ones.
+///
+/// The field is linear in the sources, so we sample the response of every
+/// source at every candidate position once, and score every combination of
+/// candidates by summing responses, without sampling again. The scene must
+/// have a listener.
+pub fn print_placements(scene: &Scene, zones: &Zones, f_min: f32, f_max: f32, seed: u64) {
+    let seats = scene.listener.as_ref().expect("Placement needs a listener.").seats();
+    let frequencies = response::log_frequencies(f_min, f_max, 12);
+    let mut rng = Rng::new(seed);
+
+    let moving = zones.moving_sources(scene);
+    let candidates: Vec<Vec<Vec3>> = moving.iter().map(|&si| candidates(scene, zones, si)).collect();
+    let n_combinations: usize = candidates.iter().map(|cs| cs.len()).product();
+
+    // The sources that stay in place contribute the same at every candidate.
+    let mut fixed = vec![Complex::zero(); seats.len() * frequencies.len()];
+    for si in (0..scene.sources.len()).filter(|si| !moving.contains(si)) {
+        let r = response::sample_seats(scene, &mut rng, si, &seats[..], &frequencies[..], N_SAMPLES);
+        for (acc, z) in fixed.iter_mut().zip(r) {
+            *acc = *acc + z;
+        }
+    }
+
+    let mut moved = scene.clone();
+    let mut responses: Vec<Vec<Vec<Complex>>> = Vec::with_capacity(moving.len());
+    for (&si, cs) in moving.iter().zip(candidates.iter()) {
+        let mut rs = Vec::with_capacity(cs.len());
+        for (k, &p) in cs.iter().enumerate() {
+            eprint!("\rSampling source {}, position {} of {} ...", si, k + 1, cs.len());
+            moved.sources[si].position = p;
+            rs.push(response::sample_seats(&moved, &mut rng, si, &seats[..], &frequencies[..], N_SAMPLES));
+        }
+        moved.sources[si].position = scene.sources[si].position;
+        responses.push(rs);
+    }
+    eprintln!();
+
+    // Enumerate the combinations like an odometer, one digit per moving source.
+    let mut best: Vec<Placement> = Vec::with_capacity(N_BEST + 1);
+    let mut digits = vec![0; moving.len()];
+    let mut total = fixed.clone();
+    for _ in 0..n_combinations {
+        total.copy_from_slice(&fixed[..]);
+        for (rs, &d) in responses.iter().zip(digits.iter()) {
+            for (acc, &z) in total.iter_mut().zip(rs[d].iter()) {
+                *acc = *acc + z;
+            }
+        }
+        let score = response::flatness_db(&total[..]);
+        if best.len() < N_BEST || score < best[best.len() - 1].flatness_db {
+            let positions = digits.iter().zip(candidates.iter()).map(|(&d, cs)| cs[d]).collect();
+            let i = best.iter().position(|b| b.flatness_db > score).unwrap_or(best.len());
+            best.insert(i, Placement { positions: positions, flatness_db: score });
+            best.truncate(N_BEST);
+        }
+
+        for (d, cs) in digits.iter_mut().zip(candidates.iter()) {
+            *d += 1;
+            if *d < cs.len() {
+                break
+            }
+            *d = 0;
+        }
+    }
+
+    let mut current = fixed.clone();
+    for &si in &moving {
+        let r = response::sample_seats(scene, &mut rng, si, &seats[..], &frequencies[..], N_SAMPLES);
+        for (acc, z) in current.iter_mut().zip(r) {
+            *acc = *acc + z;
+        }
+    }
+
+    println!("Seed: {}", seed);
+    println!(
+        "Flatness is the standard deviation of the level over {} seats, {:.0} to {:.0} Hz.",
+        seats.len(), f_min, f_max,
+    );
+    println!("Current placement: {:.1} dB.", response::flatness_db(&current[..]));
+    println!();
+
+    print!("{:>4}  {:>11}", "rank", "flatness dB");
+    for &si in &moving {
+        print!("  {:>14}", format!("source {} x, y", si));
+    }
+    println!();
+    for (rank, placement) in best.iter().enumerate() {
+        print!("{:>4}  {:>11.1}", rank + 1, placement.flatness_db);
+        for p in &placement.positions {
+            print!("  {:>14}", format!("{:.2}, {:.2}", p.x, p.y));
+        }
+        println!();
+    }
+}
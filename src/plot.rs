@@ -0,0 +1,346 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+pub type Color = [u8; 3];
+
+pub const WHITE: Color = [255, 255, 255];
+pub const GRID: Color = [200, 200, 200];
+pub const BLUE: Color = [31, 119, 180];
+pub const ORANGE: Color = [255, 127, 14];
+pub const RED: Color = [214, 39, 40];
+pub const GREEN: Color = [44, 160, 44];
+pub const PURPLE: Color = [148, 103, 189];
+pub const BROWN: Color = [140, 86, 75];
+pub const PINK: Color = [227, 119, 194];
+pub const BLACK: Color = [0, 0, 0];
+
+/// Return the color a fraction `t` of the way from `a` to `b`.
+pub fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    let mut color = a;
+    for (c, &x) in color.iter_mut().zip(b.iter()) {
+        *c = (*c as f32 + (x as f32 - *c as f32) * t) as u8;
+    }
+    color
+}
+
+/// An RGB image to draw simple plots on.
+///
+/// Pixels are stored row by row, with 3 bytes per pixel, the same layout that
+/// `Renderer::paint()` produces.
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, background: Color) -> Canvas {
+        let n = width as usize * height as usize;
+        let mut rgb = Vec::with_capacity(n * 3);
+        for _ in 0..n {
+            rgb.extend_from_slice(&background);
+        }
+        Canvas {
+            width: width,
+            height: height,
+            rgb: rgb,
+        }
+    }
+
+    /// Set the pixel at (x, y), if it lies inside the canvas.
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        self.rgb[i..i + 3].copy_from_slice(&color);
+    }
+
+    /// Draw a one pixel wide line from (x0, y0) to (x1, y1).
+    pub fn draw_line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, color: Color) {
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let n = dx.abs().max(dy.abs()).ceil().max(1.0) as u32;
+        for i in 0..n + 1 {
+            let t = i as f32 / n as f32;
+            let x = x0 + dx * t;
+            let y = y0 + dy * t;
+            self.set_pixel(x.round() as i32, y.round() as i32, color);
+        }
+    }
+
+    /// Draw line segments between consecutive points.
+    pub fn draw_polyline(&mut self, points: &[(f32, f32)], color: Color) {
+        for w in points.windows(2) {
+            self.draw_line(w[0].0, w[0].1, w[1].0, w[1].1, color);
+        }
+    }
+
+    pub fn draw_circle(&mut self, cx: f32, cy: f32, r: f32, color: Color) {
+        let n = 96;
+        let points: Vec<_> = (0..n + 1)
+            .map(|i| {
+                let t = 2.0 * PI * i as f32 / n as f32;
+                (cx + r * t.cos(), cy + r * t.sin())
+            })
+            .collect();
+        self.draw_polyline(&points[..], color);
+    }
+}
+
+/// Draw a polar plot of levels in dB, relative to 0 dB at the outer ring.
+///
+/// The `levels` are (angle in radians, level in dB) pairs. An angle of zero
+/// points up, and angles increase counterclockwise. There is a ring every
+/// 10 dB down to `-range_db`, the center of the plot.
+pub fn plot_polar(canvas: &mut Canvas, levels: &[(f32, f32)], range_db: f32, color: Color) {
+    let cx = canvas.width as f32 * 0.5;
+    let cy = canvas.height as f32 * 0.5;
+    let r_max = cx.min(cy) - 4.0;
+
+    let mut db = 0.0;
+    while db < range_db {
+        canvas.draw_circle(cx, cy, r_max * (1.0 - db / range_db), GRID);
+        db += 10.0;
+    }
+    for i in 0..12 {
+        let t = i as f32 * PI / 6.0;
+        canvas.draw_line(cx, cy, cx - r_max * t.sin(), cy - r_max * t.cos(), GRID);
+    }
+
+    let mut points: Vec<_> = levels
+        .iter()
+        .map(|&(t, db)| {
+            let r = r_max * (1.0 + db.max(-range_db) / range_db);
+            (cx - r * t.sin(), cy - r * t.cos())
+        })
+        .collect();
+
+    // Close the curve.
+    if let Some(&first) = points.first() {
+        points.push(first);
+    }
+
+    canvas.draw_polyline(&points[..], color);
+}
+
+/// Draw frequency responses, with a logarithmic frequency axis.
+///
+/// Every curve is a level in dB for each of the `frequencies`, which must be
+/// increasing. The level axis runs from `max_db` at the top down to
+/// `max_db - range_db`, with a grid line every 10 dB. There are vertical grid
+/// lines at 10, 20, 50, 100, 200, 500 Hz, and so on.
+pub fn plot_response(
+    canvas: &mut Canvas,
+    frequencies: &[f32],
+    curves: &[(&[f32], Color)],
+    max_db: f32,
+    range_db: f32,
+) {
+    plot_values(canvas, frequencies, curves, max_db, range_db, 10.0);
+}
+
+/// Draw quantities other than levels against frequency, such as phase.
+///
+/// This is `plot_response()` with a grid line every `step` units of the
+/// vertical axis, instead of every 10 dB.
+pub fn plot_values(
+    canvas: &mut Canvas,
+    frequencies: &[f32],
+    curves: &[(&[f32], Color)],
+    max: f32,
+    range: f32,
+    step: f32,
+) {
+    let (f_min, f_max) = (frequencies[0], frequencies[frequencies.len() - 1]);
+    let w = canvas.width as f32 - 1.0;
+    let h = canvas.height as f32 - 1.0;
+    let x = |f: f32| w * (f / f_min).ln() / (f_max / f_min).ln();
+    let y = |v: f32| h * (max - v).max(0.0).min(range) / range;
+
+    let mut decade = 1.0;
+    while decade < f_max {
+        for &m in &[1.0, 2.0, 5.0] {
+            let f = decade * m;
+            if f >= f_min && f <= f_max {
+                canvas.draw_line(x(f), 0.0, x(f), h, GRID);
+            }
+        }
+        decade *= 10.0;
+    }
+    let mut v = max;
+    while v >= max - range {
+        canvas.draw_line(0.0, y(v), w, y(v), GRID);
+        v -= step;
+    }
+
+    for &(values, color) in curves {
+        let points: Vec<_> = frequencies
+            .iter()
+            .zip(values)
+            .map(|(&f, &v)| (x(f), y(v)))
+            .collect();
+        canvas.draw_polyline(&points[..], color);
+    }
+}
+
+/// Shade the range between two curves, on the axes of `plot_response()`.
+///
+/// Call this before `plot_response()`, so the grid and the curves are drawn
+/// over the shading.
+pub fn plot_envelope(
+    canvas: &mut Canvas,
+    frequencies: &[f32],
+    lower: &[f32],
+    upper: &[f32],
+    max_db: f32,
+    range_db: f32,
+    color: Color,
+) {
+    let (f_min, f_max) = (frequencies[0], frequencies[frequencies.len() - 1]);
+    let w = canvas.width as f32 - 1.0;
+    let h = canvas.height as f32 - 1.0;
+    let y = |db: f32| h * (max_db - db).max(0.0).min(range_db) / range_db;
+
+    // Fill every column between the curves, interpolated between the frequencies.
+    let mut i = 0;
+    for px in 0..canvas.width {
+        let f = f_min * (f_max / f_min).powf(px as f32 / w);
+        while i + 2 < frequencies.len() && frequencies[i + 1] < f {
+            i += 1;
+        }
+        let t = ((f - frequencies[i]) / (frequencies[i + 1] - frequencies[i])).max(0.0).min(1.0);
+        let lo = lower[i] + (lower[i + 1] - lower[i]) * t;
+        let hi = upper[i] + (upper[i + 1] - upper[i]) * t;
+        for py in y(hi).round() as i32..y(lo).round() as i32 + 1 {
+            canvas.set_pixel(px as i32, py, color);
+        }
+    }
+}
+
+/// Draw a vertical line at each of the `markers`, on the frequency axis of
+/// `plot_response()` for the same `frequencies`.
+pub fn plot_markers(canvas: &mut Canvas, frequencies: &[f32], markers: &[f32], color: Color) {
+    let (f_min, f_max) = (frequencies[0], frequencies[frequencies.len() - 1]);
+    let w = canvas.width as f32 - 1.0;
+    let h = canvas.height as f32 - 1.0;
+    for &f in markers {
+        if f >= f_min && f <= f_max {
+            let x = w * (f / f_min).ln() / (f_max / f_min).ln();
+            canvas.draw_line(x, 0.0, x, h, color);
+        }
+    }
+}
+
+/// Draw levels over time, with a linear time axis from 0 to `duration_ms`.
+///
+/// The curve is a level in dB for every sample, spaced evenly over the
+/// duration. The level axis is as for `plot_response()`. There are vertical
+/// grid lines every `grid_ms` milliseconds.
+pub fn plot_time(
+    canvas: &mut Canvas,
+    levels: &[f32],
+    duration_ms: f32,
+    grid_ms: f32,
+    max_db: f32,
+    range_db: f32,
+    color: Color,
+) {
+    let w = canvas.width as f32 - 1.0;
+    let h = canvas.height as f32 - 1.0;
+    let y = |db: f32| h * (max_db - db).max(0.0).min(range_db) / range_db;
+
+    let mut t = grid_ms;
+    while t < duration_ms {
+        let x = w * t / duration_ms;
+        canvas.draw_line(x, 0.0, x, h, GRID);
+        t += grid_ms;
+    }
+    let mut db = max_db;
+    while db >= max_db - range_db {
+        canvas.draw_line(0.0, y(db), w, y(db), GRID);
+        db -= 10.0;
+    }
+
+    let n = levels.len().max(2) as f32 - 1.0;
+    let points: Vec<_> = levels
+        .iter()
+        .enumerate()
+        .map(|(i, &db)| (w * i as f32 / n, y(db)))
+        .collect();
+    canvas.draw_polyline(&points[..], color);
+}
+
+/// Draw a waterfall of frequency responses, later slices further back.
+///
+/// The `slices` are ordered front to back, each a level in dB for each of
+/// the `frequencies`, with the axes of `plot_response()`. Every slice is
+/// shifted up and to the right of the one in front of it, and hides the
+/// parts of the slices behind it that are below its curve. The back plane
+/// has the grid lines of `plot_response()`.
+pub fn plot_waterfall(
+    canvas: &mut Canvas,
+    frequencies: &[f32],
+    slices: &[(&[f32], Color)],
+    max_db: f32,
+    range_db: f32,
+) {
+    let (f_min, f_max) = (frequencies[0], frequencies[frequencies.len() - 1]);
+    let w = canvas.width as f32 - 1.0;
+    let h = canvas.height as f32 - 1.0;
+
+    // The depth of the waterfall takes a quarter of the width and a third of
+    // the height, the rest is for the planes of the slices.
+    let (depth_x, depth_y) = (0.25 * w, 0.33 * h);
+    let (plane_w, plane_h) = (w - depth_x, h - depth_y);
+    let n = (slices.len().max(2) - 1) as f32;
+    let origin = |k: usize| (depth_x * k as f32 / n, depth_y * (1.0 - k as f32 / n));
+    let x = |k: usize, f: f32| origin(k).0 + plane_w * (f / f_min).ln() / (f_max / f_min).ln();
+    let y = |k: usize, db: f32| origin(k).1 + plane_h * (max_db - db).max(0.0).min(range_db) / range_db;
+
+    let back = slices.len() - 1;
+    let mut decade = 1.0;
+    while decade < f_max {
+        for &m in &[1.0, 2.0, 5.0] {
+            let f = decade * m;
+            if f >= f_min && f <= f_max {
+                canvas.draw_line(x(back, f), y(back, max_db), x(back, f), y(back, max_db - range_db), GRID);
+            }
+        }
+        decade *= 10.0;
+    }
+    let mut db = max_db;
+    while db >= max_db - range_db {
+        canvas.draw_line(x(back, f_min), y(back, db), x(back, f_max), y(back, db), GRID);
+        db -= 10.0;
+    }
+
+    for (k, &(levels, color)) in slices.iter().enumerate().rev() {
+        // Clear the area below the curve, interpolated between the frequencies.
+        let mut i = 0;
+        let bottom = y(k, max_db - range_db).round() as i32;
+        for px in x(k, f_min).round() as i32..x(k, f_max).round() as i32 + 1 {
+            let f = f_min * (f_max / f_min).powf((px as f32 - origin(k).0) / plane_w);
+            while i + 2 < frequencies.len() && frequencies[i + 1] < f {
+                i += 1;
+            }
+            let t = ((f - frequencies[i]) / (frequencies[i + 1] - frequencies[i])).max(0.0).min(1.0);
+            let level = levels[i] + (levels[i + 1] - levels[i]) * t;
+            for py in y(k, level).round() as i32..bottom + 1 {
+                canvas.set_pixel(px, py, WHITE);
+            }
+        }
+
+        let points: Vec<_> = frequencies
+            .iter()
+            .zip(levels)
+            .map(|(&f, &db)| (x(k, f), y(k, db)))
+            .collect();
+        canvas.draw_polyline(&points[..], color);
+    }
+}
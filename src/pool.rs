@@ -0,0 +1,156 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// The number of CPUs to assume when the system does not tell.
+const DEFAULT_CPU_COUNT: usize = 8;
+
+/// Return the number of CPUs, as listed in `/proc/cpuinfo` on Linux.
+pub fn cpu_count() -> usize {
+    let n = fs::read_to_string("/proc/cpuinfo")
+        .map(|info| info.lines().filter(|line| line.starts_with("processor")).count())
+        .unwrap_or(0);
+    if n > 0 { n } else { DEFAULT_CPU_COUNT }
+}
+
+/// How urgent an analysis is. Urgent jobs run first, in order of submission.
+///
+/// Variants are ordered from least to most urgent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Long searches that may yield to everything else, such as the crawl.
+    Background,
+
+    /// Work that the user is waiting for, such as a preview in the side panel.
+    Interactive,
+}
+
+/// A boxed closure that can be called once.
+///
+/// A `Box<dyn FnOnce()>` cannot be called directly, it needs to be moved out
+/// of the box, which this trait does.
+trait Task: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Task for F {
+    fn run(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+struct Job {
+    priority: Priority,
+
+    /// The order of submission, to run jobs of equal priority first-come first-served.
+    sequence: u64,
+    work: Box<dyn Task>,
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Job) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Job {}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Job) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Job) -> Ordering {
+        // The heap pops the greatest job: highest priority, lowest sequence.
+        self.priority.cmp(&other.priority).then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+struct Queue {
+    jobs: BinaryHeap<Job>,
+    next_sequence: u64,
+}
+
+/// Threads for analyses, such as responses and optimizers, separate from the
+/// render threads.
+///
+/// The render loop never finishes, so analyses cannot share its threads.
+/// Instead, while analyses run, as many render threads pause, so analyses do
+/// not compete with rendering for cores, and neither starves the other.
+pub struct AnalysisPool {
+    queue: Mutex<Queue>,
+    available: Condvar,
+
+    /// The number of jobs that are running.
+    busy: AtomicUsize,
+}
+
+impl AnalysisPool {
+    /// Start a pool with `n_threads` worker threads.
+    pub fn new(n_threads: usize) -> Arc<AnalysisPool> {
+        let pool = Arc::new(AnalysisPool {
+            queue: Mutex::new(Queue {
+                jobs: BinaryHeap::new(),
+                next_sequence: 0,
+            }),
+            available: Condvar::new(),
+            busy: AtomicUsize::new(0),
+        });
+
+        for _ in 0..n_threads {
+            let p_ref = pool.clone();
+            thread::spawn(move || p_ref.run_worker());
+        }
+
+        pool
+    }
+
+    /// Queue `work` to run on one of the pool threads.
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, priority: Priority, work: F) {
+        let mut queue = self.queue.lock().unwrap();
+        let job = Job {
+            priority: priority,
+            sequence: queue.next_sequence,
+            work: Box::new(work),
+        };
+        queue.next_sequence += 1;
+        queue.jobs.push(job);
+        self.available.notify_one();
+    }
+
+    /// Return the number of jobs that are running, for the render threads
+    /// to make way for, see `Renderer::run_render_loop()`.
+    pub fn busy(&self) -> &AtomicUsize {
+        &self.busy
+    }
+
+    fn run_worker(&self) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    match queue.jobs.pop() {
+                        Some(job) => break job,
+                        None => queue = self.available.wait(queue).unwrap(),
+                    }
+                }
+            };
+
+            self.busy.fetch_add(1, atomic::Ordering::SeqCst);
+            job.work.run();
+            self.busy.fetch_sub(1, atomic::Ordering::SeqCst);
+        }
+    }
+}
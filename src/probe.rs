@@ -0,0 +1,319 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::complex::Complex;
+use crate::plot::{self, Canvas, Color};
+use crate::rand::Rng;
+use crate::response::{self, Smoothing};
+use crate::scene::Scene;
+use crate::target::Target;
+use crate::vec3::Vec3;
+
+/// The band of the overlaid responses, in Hz.
+pub const F_MIN: f32 = 20.0;
+pub const F_MAX: f32 = 200.0;
+
+/// The resolution of the overlaid responses.
+const STEPS_PER_OCTAVE: u32 = 24;
+
+/// The colors of the curves, with their names for the legend.
+///
+/// The listener comes first, in the red of its marker on the map. Black is
+/// for the average over the listening area.
+const COLORS: [(Color, &str); 7] = [
+    (plot::RED, "red"),
+    (plot::BLUE, "blue"),
+    (plot::ORANGE, "orange"),
+    (plot::GREEN, "green"),
+    (plot::PURPLE, "purple"),
+    (plot::BROWN, "brown"),
+    (plot::PINK, "pink"),
+];
+
+/// The color of the shading between the quietest and the loudest seat.
+const ENVELOPE_COLOR: Color = [225, 225, 225];
+
+/// The color of the target curve.
+const TARGET_COLOR: Color = [110, 110, 110];
+
+/// The group delay plot is cut off at these delays, in ms. Near deep dips,
+/// the phase turns so fast that the group delay has spikes far beyond.
+const GROUP_DELAY_MIN_MS: f32 = -100.0;
+const GROUP_DELAY_MAX_MS: f32 = 500.0;
+
+/// Return the color of the curve of point `i`, and its name.
+///
+/// Point 0 is the listener, and point `i + 1` is probe `i`. Beyond seven
+/// points, the colors repeat.
+pub fn color(i: usize) -> (Color, &'static str) {
+    COLORS[i % COLORS.len()]
+}
+
+/// Return the name and position of every point to overlay: the listener,
+/// if there is one, and then the probes.
+pub fn points(scene: &Scene) -> Vec<(String, Vec3)> {
+    let listener = scene.listener.iter().map(|l| ("listener".to_string(), l.position));
+    let probes = scene.probes.iter().map(|p| (p.name.clone(), p.position));
+    listener.chain(probes).collect()
+}
+
+/// The response over the seats of the listening area, see `Listener::seats()`.
+///
+/// At low frequencies, the level changes a lot over the width of a sofa, so
+/// the response at a single point says little about what everyone on the
+/// sofa hears.
+pub struct AreaResponse {
+    pub n_seats: usize,
+
+    /// The level of the power averaged over the seats, in dB.
+    pub mean_db: Vec<f32>,
+
+    /// The level at the quietest and the loudest seat, in dB.
+    pub min_db: Vec<f32>,
+    pub max_db: Vec<f32>,
+}
+
+impl AreaResponse {
+    /// Combine the responses at every seat, each a level for every frequency.
+    fn from_seats(seats: &[Vec<f32>]) -> AreaResponse {
+        let n = seats[0].len();
+        let column = |k: usize| seats.iter().map(move |levels| levels[k]);
+        AreaResponse {
+            n_seats: seats.len(),
+            mean_db: (0..n)
+                .map(|k| {
+                    let power = column(k).map(|db| 10.0_f32.powf(db / 10.0)).sum::<f32>() / seats.len() as f32;
+                    10.0 * power.log10()
+                })
+                .collect(),
+            min_db: (0..n).map(|k| column(k).fold(std::f32::INFINITY, f32::min)).collect(),
+            max_db: (0..n).map(|k| column(k).fold(std::f32::NEG_INFINITY, f32::max)).collect(),
+        }
+    }
+}
+
+/// The phase of the response at the listener, and its group delay.
+///
+/// A mode that rings delays the sound around its frequency, which the level
+/// does not show, but which is audible as boom that lingers.
+pub struct Phase {
+    /// The unwrapped phase at each of the frequencies, in degrees.
+    pub phase_deg: Vec<f32>,
+
+    /// The group delay at each of the frequencies, in ms.
+    pub group_delay_ms: Vec<f32>,
+}
+
+/// Return the top and the range of a vertical axis from `lo` to `hi`, and
+/// the step between its grid lines: the smallest of `steps` with at most
+/// eight grid lines, or the largest.
+fn axis(lo: f32, hi: f32, steps: &[f32]) -> (f32, f32, f32) {
+    let fits = |step: f32| ((hi / step).ceil() - (lo / step).floor()) <= 8.0;
+    let step = steps.iter().cloned().find(|&s| fits(s)).unwrap_or(steps[steps.len() - 1]);
+    let (top, bottom) = ((hi / step).ceil() * step, (lo / step).floor() * step);
+    (top, (top - bottom).max(step), step)
+}
+
+/// The responses at all points, on the same frequencies.
+pub struct Overlay {
+    pub names: Vec<String>,
+    pub frequencies: Vec<f32>,
+
+    /// For every point, the level in dB at each of the frequencies.
+    pub levels_db: Vec<Vec<f32>>,
+
+    /// The response over the listening area, if the listener has one.
+    pub area: Option<AreaResponse>,
+
+    /// The target curve of the scene, if it has one.
+    pub target: Option<Target>,
+
+    /// The phase at the listener, if there is one.
+    pub phase: Option<Phase>,
+}
+
+impl Overlay {
+    /// Sample the response of all sources at every point.
+    ///
+    /// Every point is sampled with the same seed, so the Monte Carlo noise
+    /// is alike at nearby points, and the differences between the curves
+    /// are mostly the differences between the points.
+    pub fn compute(scene: &Scene, seed: u64, n_samples: u32) -> Overlay {
+        let frequencies = response::log_frequencies(F_MIN, F_MAX, STEPS_PER_OCTAVE);
+        let sample = |position: Vec3| -> Vec<Complex> {
+            let mut rng = Rng::new(seed);
+            response::sample_response(scene, &mut rng, position, &frequencies[..], n_samples)
+        };
+        let to_db = |r: Vec<Complex>| -> Vec<f32> { r.into_iter().map(response::to_db).collect() };
+
+        let mut names = Vec::new();
+        let mut levels_db = Vec::new();
+        let mut phase = None;
+        for (i, (name, position)) in points(scene).into_iter().enumerate() {
+            let r = sample(position);
+            if i == 0 && scene.listener.is_some() {
+                let phase_deg = response::unwrapped_phase_deg(&r[..]);
+                phase = Some(Phase {
+                    group_delay_ms: response::group_delay_ms(&frequencies[..], &phase_deg[..]),
+                    phase_deg: phase_deg,
+                });
+            }
+            names.push(name);
+            levels_db.push(to_db(r));
+        }
+
+        let area = match scene.listener {
+            Some(ref listener) if listener.area.is_some() => {
+                let seats: Vec<Vec<f32>> = listener.seats().into_iter().map(|p| to_db(sample(p))).collect();
+                Some(AreaResponse::from_seats(&seats[..]))
+            }
+            _ => None,
+        };
+
+        Overlay {
+            names: names,
+            frequencies: frequencies,
+            levels_db: levels_db,
+            area: area,
+            target: scene.target.clone(),
+            phase: phase,
+        }
+    }
+
+    /// Plot the unwrapped phase at the listener, with a grid line every
+    /// multiple of 90 degrees that fits. Returns `None` without a listener.
+    pub fn paint_phase(&self, width: u32, height: u32) -> Option<Canvas> {
+        let phase = &self.phase.as_ref()?.phase_deg[..];
+        let lo = phase.iter().cloned().fold(std::f32::INFINITY, f32::min);
+        let hi = phase.iter().cloned().fold(std::f32::NEG_INFINITY, f32::max);
+        let (top, range, step) = axis(lo, hi, &[90.0, 180.0, 360.0, 720.0, 1440.0, 2880.0, 5760.0]);
+        let mut canvas = Canvas::new(width, height, plot::WHITE);
+        plot::plot_values(&mut canvas, &self.frequencies[..], &[(phase, color(0).0)], top, range, step);
+        Some(canvas)
+    }
+
+    /// Plot the group delay at the listener, from 0 ms or below, with a grid
+    /// line every 5, 10, 20, 50, 100, or 200 ms. Returns `None` without a listener.
+    pub fn paint_group_delay(&self, width: u32, height: u32) -> Option<Canvas> {
+        let delay = &self.phase.as_ref()?.group_delay_ms[..];
+        let lo = delay.iter().cloned().fold(0.0, f32::min).max(GROUP_DELAY_MIN_MS);
+        let hi = delay.iter().cloned().fold(0.0, f32::max).min(GROUP_DELAY_MAX_MS);
+        let (top, range, step) = axis(lo, hi, &[5.0, 10.0, 20.0, 50.0, 100.0, 200.0]);
+        let mut canvas = Canvas::new(width, height, plot::WHITE);
+        plot::plot_values(&mut canvas, &self.frequencies[..], &[(delay, color(0).0)], top, range, step);
+        Some(canvas)
+    }
+
+    /// Plot every response in its color, and the average over the listening
+    /// area in black, over the range of its seats in light gray. The target
+    /// curve is dark gray, at the level of the first point.
+    ///
+    /// The curves are smoothed first. The levels are relative to the peak
+    /// over all points, so the curves keep their differences in level.
+    pub fn paint(&self, smoothing: Smoothing, width: u32, height: u32) -> Canvas {
+        let (max_db, range_db) = (10.0, 50.0);
+        let mut canvas = Canvas::new(width, height, plot::WHITE);
+        let f = &self.frequencies[..];
+        let smooth = |levels: &[f32]| response::smooth_db(f, levels, smoothing);
+        let levels_db: Vec<Vec<f32>> = self.levels_db.iter().map(|levels| smooth(&levels[..])).collect();
+        let area = self.area.as_ref().map(|a| AreaResponse {
+            n_seats: a.n_seats,
+            mean_db: smooth(&a.mean_db[..]),
+            min_db: smooth(&a.min_db[..]),
+            max_db: smooth(&a.max_db[..]),
+        });
+
+        let peak = levels_db
+            .iter()
+            .chain(area.iter().map(|a| &a.max_db))
+            .flat_map(|levels| levels.iter().cloned())
+            .fold(std::f32::NEG_INFINITY, f32::max);
+        let relative = |levels: &[f32]| -> Vec<f32> { levels.iter().map(|db| db - peak).collect() };
+        let points: Vec<Vec<f32>> = levels_db.iter().map(|levels| relative(&levels[..])).collect();
+
+        // Draw the listener last, so it is on top.
+        let mut curves: Vec<(&[f32], Color)> = points
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, levels)| (&levels[..], color(i).0))
+            .collect();
+
+        let area_mean;
+        if let Some(ref area) = area {
+            let (lower, upper) = (relative(&area.min_db[..]), relative(&area.max_db[..]));
+            plot::plot_envelope(&mut canvas, f, &lower[..], &upper[..], max_db, range_db, ENVELOPE_COLOR);
+            area_mean = relative(&area.mean_db[..]);
+            curves.insert(0, (&area_mean[..], plot::BLACK));
+        }
+
+        let target;
+        if let (Some(t), Some(first)) = (self.target.as_ref(), points.first()) {
+            target = t.aligned_db(f, &first[..]);
+            curves.insert(0, (&target[..], TARGET_COLOR));
+        }
+
+        plot::plot_response(&mut canvas, f, &curves[..], max_db, range_db);
+        canvas
+    }
+
+    /// Describe the color of every point, its average level relative to the
+    /// first point, and how far it deviates from the target curve.
+    pub fn describe(&self) -> String {
+        let mean = |levels: &[f32]| levels.iter().sum::<f32>() / levels.len() as f32;
+        let reference = match self.levels_db.first() {
+            Some(levels) => mean(&levels[..]),
+            None => return "No listener or probes in the scene.".to_string(),
+        };
+        let deviation = |levels: &[f32]| match self.target {
+            Some(ref t) => format!(", {:.1} dB RMS from the target", t.deviation_db(&self.frequencies[..], levels)),
+            None => String::new(),
+        };
+        let mut lines: Vec<String> = self.names
+            .iter()
+            .zip(&self.levels_db)
+            .enumerate()
+            .map(|(i, (name, levels))| {
+                if i == 0 {
+                    format!("{}: {}{}", color(i).1, name, deviation(&levels[..]))
+                } else {
+                    format!(
+                        "{}: {}, {:+.1} dB on average{}",
+                        color(i).1, name, mean(&levels[..]) - reference, deviation(&levels[..]),
+                    )
+                }
+            })
+            .collect();
+        if let Some(ref area) = self.area {
+            let spread: f32 = area.max_db
+                .iter()
+                .zip(&area.min_db)
+                .map(|(hi, lo)| hi - lo)
+                .sum::<f32>() / area.max_db.len() as f32;
+            lines.push(format!(
+                "black: average over {} seats, {:+.1} dB on average{}",
+                area.n_seats, mean(&area.mean_db[..]) - reference, deviation(&area.mean_db[..]),
+            ));
+            lines.push(format!("light gray: the seats are {:.1} dB apart on average", spread));
+        }
+        if self.target.is_some() {
+            lines.push("dark gray: the target curve".to_string());
+        }
+        if let Some(ref phase) = self.phase {
+            let (i, peak) = phase.group_delay_ms
+                .iter()
+                .cloned()
+                .enumerate()
+                .fold((0, std::f32::NEG_INFINITY), |(k, m), (i, x)| if x > m { (i, x) } else { (k, m) });
+            lines.push(format!(
+                "Group delay at the listener peaks at {:.0} ms, at {:.0} Hz.",
+                peak, self.frequencies[i],
+            ));
+        }
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,376 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::fs;
+use std::path::Path;
+
+use toml::Value;
+use toml::value::Table;
+
+use crate::frd::FrequencyResponse;
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+use crate::scene_file::{self, Result, invalid};
+use crate::spectrum::Spectrum;
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// The band of the cached responses, in Hz.
+const RESPONSE_F_MIN: f32 = 20.0;
+const RESPONSE_F_MAX: f32 = 200.0;
+
+/// The number of Monte Carlo samples per frequency for cached responses.
+const N_SAMPLES: u32 = 4096;
+
+/// Viewer settings, so a project opens the way it was saved.
+///
+/// Settings that are not in the project are `None`, then the viewer uses its
+/// defaults. Command-line options take precedence over the project.
+#[derive(Default)]
+pub struct RenderPreset {
+    pub frequency_hz: Option<f32>,
+    pub bandwidth_octaves: Option<f32>,
+    pub spectrum: Option<Spectrum>,
+    pub seed: Option<u64>,
+}
+
+/// A note about a point in the scene, such as "null at the sofa at 55 Hz".
+#[derive(Clone)]
+pub struct Annotation {
+    pub position: Vec2,
+    pub text: String,
+}
+
+/// A frequency response measured at a point in the room, to compare with the model.
+#[derive(Clone)]
+pub struct Measurement {
+    pub name: String,
+    pub position: Vec3,
+    pub response: FrequencyResponse,
+}
+
+/// The response at a point, as it was when the bundle was written.
+pub struct CachedResponse {
+    pub name: String,
+    pub position: Vec3,
+
+    /// The seed that the response was sampled with, to reproduce it.
+    pub seed: u64,
+    pub frequencies: Vec<f32>,
+
+    /// Levels relative to the peak of the response.
+    pub levels_db: Vec<f32>,
+}
+
+impl CachedResponse {
+    /// Sample the response of all sources at `position`.
+    pub fn compute(scene: &Scene, name: String, position: Vec3, seed: u64) -> CachedResponse {
+        let frequencies = response::log_frequencies(RESPONSE_F_MIN, RESPONSE_F_MAX, 12);
+        let mut rng = Rng::new(seed);
+        let r = response::sample_response(scene, &mut rng, position, &frequencies[..], N_SAMPLES);
+        let levels: Vec<f32> = r.iter().map(|&z| response::to_db(z)).collect();
+        let reference = levels.iter().cloned().fold(std::f32::NEG_INFINITY, f32::max);
+
+        CachedResponse {
+            name: name,
+            position: position,
+            seed: seed,
+            frequencies: frequencies,
+            levels_db: levels.iter().map(|l| l - reference).collect(),
+        }
+    }
+
+    fn to_toml(&self) -> Value {
+        let floats = |xs: &[f32]| Value::Array(xs.iter().map(|&x| float(x)).collect());
+        let p = self.position;
+        let mut t = Table::new();
+        t.insert("name".to_string(), Value::String(self.name.clone()));
+        t.insert("position".to_string(), floats(&[p.x, p.y, p.z]));
+        t.insert("seed".to_string(), Value::Integer(self.seed as i64));
+        t.insert("frequencies".to_string(), floats(&self.frequencies[..]));
+        t.insert("levels_db".to_string(), floats(&self.levels_db[..]));
+        Value::Table(t)
+    }
+}
+
+/// Everything in a project bundle besides the scene.
+///
+/// A bundle is a scene file with extra tables, so one file holds a complete
+/// analysis that someone else can open and reproduce. The scene part is
+/// loaded with `Scene::from_toml()`, which ignores the extra tables.
+#[derive(Default)]
+pub struct Project {
+    pub render: RenderPreset,
+    pub annotations: Vec<Annotation>,
+    pub measurements: Vec<Measurement>,
+    pub responses: Vec<CachedResponse>,
+}
+
+/// Convert to a TOML float, without the digits that an f64 would add.
+fn float(x: f32) -> Value {
+    // The shortest representation of the f32 is also valid for the f64.
+    Value::Float(x.to_string().parse().unwrap())
+}
+
+fn as_f32s(value: &Value, path: &str) -> Result<Vec<f32>> {
+    scene_file::as_array(value, path)?.iter().map(|x| scene_file::as_f32(x, path)).collect()
+}
+
+fn as_point(value: &Value, path: &str, n: usize) -> Result<Vec<f32>> {
+    let xs = as_f32s(value, path)?;
+    if xs.len() != n {
+        return invalid(format!("Expected '{}' to have {} coordinates.", path, n))
+    }
+    Ok(xs)
+}
+
+fn parse_spectrum(value: &Value) -> Result<Spectrum> {
+    if let Some(name) = value.as_str() {
+        return match name {
+            "white" => Ok(Spectrum::White),
+            "pink" => Ok(Spectrum::Pink),
+            "lfe" => Ok(Spectrum::Lfe),
+            _ => invalid("Expected 'render.spectrum' to be 'white', 'pink', 'lfe', or a curve.".to_string()),
+        }
+    }
+    let mut points = Vec::new();
+    for p in scene_file::as_array(value, "render.spectrum")? {
+        let xs = as_point(p, "render.spectrum", 2)?;
+        points.push((xs[0], xs[1]));
+    }
+    if points.is_empty() {
+        return invalid("Expected 'render.spectrum' to contain points.".to_string())
+    }
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    Ok(Spectrum::Curve(points))
+}
+
+fn spectrum_to_toml(spectrum: &Spectrum) -> Value {
+    match *spectrum {
+        Spectrum::White => Value::String("white".to_string()),
+        Spectrum::Pink => Value::String("pink".to_string()),
+        Spectrum::Lfe => Value::String("lfe".to_string()),
+        Spectrum::Curve(ref points) => Value::Array(
+            points
+                .iter()
+                .map(|&(f, db)| Value::Array(vec![float(f), float(db)]))
+                .collect()
+        ),
+    }
+}
+
+impl Project {
+    /// Load the project tables of a bundle. A plain scene file has none.
+    ///
+    /// The tables look like this:
+    ///
+    /// ```toml
+    /// # Viewer settings, all optional.
+    /// [render]
+    /// frequency = 55.0
+    /// band = 0.33
+    /// # A name, or a curve of [frequency, dB] points.
+    /// spectrum = "pink"
+    /// seed = 7
+    ///
+    /// [[annotation]]
+    /// position = [1.4, 3.0]
+    /// text = "Null at the sofa at 55 Hz"
+    ///
+    /// # A measured response, relative to the directory of the file, or as
+    /// # points like the `frd` of a source. The viewer maps the difference
+    /// # with the simulation, at least two measurements are needed.
+    /// [[measurement]]
+    /// name = "sofa left"
+    /// position = [1.0, 3.0, 1.0]
+    /// frd = "sofa-left.frd"
+    ///
+    /// # Written by the bundle command.
+    /// [[response]]
+    /// name = "listener"
+    /// position = [1.4, 3.0, 1.0]
+    /// seed = 0
+    /// frequencies = [20.0, 21.2, 22.4]
+    /// levels_db = [-12.1, -10.4, -9.8]
+    /// ```
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Project> {
+        let contents = fs::read_to_string(&path)?;
+        let root: Value = contents.parse()?;
+        let mut project = Project::default();
+        let dir = path.as_ref().parent().unwrap_or(Path::new("."));
+
+        if let Some(render) = root.get("render") {
+            if let Some(f) = render.get("frequency") {
+                project.render.frequency_hz = Some(scene_file::as_f32(f, "render.frequency")?);
+            }
+            if let Some(b) = render.get("band") {
+                project.render.bandwidth_octaves = Some(scene_file::as_f32(b, "render.band")?);
+            }
+            if let Some(s) = render.get("spectrum") {
+                project.render.spectrum = Some(parse_spectrum(s)?);
+            }
+            if let Some(s) = render.get("seed") {
+                project.render.seed = Some(scene_file::as_usize(s, "render.seed")? as u64);
+            }
+        }
+
+        if let Some(annotations) = root.get("annotation") {
+            for (i, a) in scene_file::as_array(annotations, "annotation")?.iter().enumerate() {
+                let path = format!("annotation[{}]", i);
+                let position_path = format!("{}.position", path);
+                let xs = as_point(scene_file::get(a, &path, "position")?, &position_path, 2)?;
+                let text = scene_file::as_str(scene_file::get(a, &path, "text")?, &format!("{}.text", path))?;
+                project.annotations.push(Annotation {
+                    position: Vec2::new(xs[0], xs[1]),
+                    text: text.to_string(),
+                });
+            }
+        }
+
+        if let Some(measurements) = root.get("measurement") {
+            for (i, m) in scene_file::as_array(measurements, "measurement")?.iter().enumerate() {
+                let path = format!("measurement[{}]", i);
+                let field = |key: &str| scene_file::get(m, &path, key);
+                let field_path = |key: &str| format!("{}.{}", path, key);
+                let xs = as_point(field("position")?, &field_path("position"), 3)?;
+                project.measurements.push(Measurement {
+                    name: scene_file::as_str(field("name")?, &field_path("name"))?.to_string(),
+                    position: Vec3::new(xs[0], xs[1], xs[2]),
+                    response: scene_file::parse_frd(field("frd")?, dir, &field_path("frd"))?,
+                });
+            }
+        }
+
+        if let Some(responses) = root.get("response") {
+            for (i, r) in scene_file::as_array(responses, "response")?.iter().enumerate() {
+                let path = format!("response[{}]", i);
+                let field = |key: &str| scene_file::get(r, &path, key);
+                let field_path = |key: &str| format!("{}.{}", path, key);
+                let xs = as_point(field("position")?, &field_path("position"), 3)?;
+                let response = CachedResponse {
+                    name: scene_file::as_str(field("name")?, &field_path("name"))?.to_string(),
+                    position: Vec3::new(xs[0], xs[1], xs[2]),
+                    seed: scene_file::as_usize(field("seed")?, &field_path("seed"))? as u64,
+                    frequencies: as_f32s(field("frequencies")?, &field_path("frequencies"))?,
+                    levels_db: as_f32s(field("levels_db")?, &field_path("levels_db"))?,
+                };
+                if response.frequencies.len() != response.levels_db.len() {
+                    return invalid(format!("Expected '{}' to have a level for every frequency.", path))
+                }
+                project.responses.push(response);
+            }
+        }
+
+        Ok(project)
+    }
+}
+
+/// Write a bundle of the scene file at `scene_path` to `out_path`.
+///
+/// The bundle contains the scene file, with the contents of the mesh file and
+/// FRD files if there are any, the `render` preset, and the response at the listener.
+/// Annotations and measurements in the scene file are kept, cached responses
+/// are replaced.
+pub fn write_bundle<P: AsRef<Path>, Q: AsRef<Path>>(
+    scene_path: P,
+    scene: &Scene,
+    render: &RenderPreset,
+    out_path: Q,
+) -> Result<()> {
+    let contents = fs::read_to_string(&scene_path)?;
+    let mut root: Value = contents.parse()?;
+    let dir = scene_path.as_ref().parent().unwrap_or(Path::new("."));
+
+    let table = root.as_table_mut().unwrap();
+
+    if let Some(&mut Value::Table(ref mut mesh)) = table.get_mut("mesh") {
+        let obj_path = match mesh.remove("path") {
+            Some(Value::String(p)) => Some(dir.join(p)),
+            _ => None,
+        };
+        if let Some(obj_path) = obj_path {
+            let obj = fs::read_to_string(&obj_path)?;
+            mesh.insert("obj".to_string(), Value::String(obj));
+        }
+    }
+
+    for &key in ["source", "measurement"].iter() {
+        if let Some(&mut Value::Array(ref mut items)) = table.get_mut(key) {
+            for item in items.iter_mut().filter_map(|t| t.as_table_mut()) {
+                let frd_path = match item.get("frd") {
+                    Some(&Value::String(ref p)) => dir.join(p),
+                    _ => continue,
+                };
+                let response = FrequencyResponse::load(&frd_path)?;
+                let points = response
+                    .points()
+                    .iter()
+                    .map(|&(f, db, phase)| Value::Array(vec![float(f), float(db), float(phase)]))
+                    .collect();
+                item.insert("frd".to_string(), Value::Array(points));
+            }
+        }
+    }
+
+    let mut preset = Table::new();
+    if let Some(f) = render.frequency_hz {
+        preset.insert("frequency".to_string(), float(f));
+    }
+    if let Some(b) = render.bandwidth_octaves {
+        preset.insert("band".to_string(), float(b));
+    }
+    if let Some(ref s) = render.spectrum {
+        preset.insert("spectrum".to_string(), spectrum_to_toml(s));
+    }
+    if let Some(s) = render.seed {
+        preset.insert("seed".to_string(), Value::Integer(s as i64));
+    }
+    table.insert("render".to_string(), Value::Table(preset));
+
+    let seed = render.seed.unwrap_or(0);
+    let responses: Vec<Value> = scene.listener
+        .iter()
+        .map(|listener| CachedResponse::compute(scene, "listener".to_string(), listener.position, seed).to_toml())
+        .collect();
+    table.remove("response");
+    if !responses.is_empty() {
+        table.insert("response".to_string(), Value::Array(responses));
+    }
+
+    let header = "# A Basstrace project bundle: a scene with viewer settings, notes,\n\
+                  # and cached results. Open it with 'basstrace --scene <file>'.\n\n";
+    fs::write(out_path, format!("{}{}", header, root))?;
+    Ok(())
+}
+
+/// Recompute every cached response, and print how much it deviates.
+///
+/// With the same scene and seed, the responses are identical, so a deviation
+/// means the bundle was edited, or the model changed since it was written.
+pub fn print_verification(scene: &Scene, project: &Project) {
+    if project.responses.is_empty() {
+        println!("The project contains no cached responses.");
+        return
+    }
+
+    for cached in &project.responses {
+        let fresh = CachedResponse::compute(scene, cached.name.clone(), cached.position, cached.seed);
+        let same_band = fresh.frequencies.len() == cached.frequencies.len()
+            && fresh.frequencies.iter().zip(&cached.frequencies).all(|(a, b)| (a - b).abs() < 0.01);
+        if !same_band {
+            println!("{}: sampled at different frequencies, cannot compare.", cached.name);
+            continue
+        }
+        let max_deviation = fresh.levels_db
+            .iter()
+            .zip(&cached.levels_db)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        println!("{}: largest deviation {:.2} dB.", cached.name, max_deviation);
+    }
+}
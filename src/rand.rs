@@ -64,6 +64,13 @@ impl Rng {
         xoshiro256pp_next(&mut self.state)
     }
 
+    /// Return a random number, uniformly distributed in [0, 1).
+    #[inline]
+    pub fn uniform(&mut self) -> f32 {
+        // Use the top 24 bits, that is as much precision as an f32 has.
+        (self.next() >> 40) as f32 * (1.0 / (1 << 24) as f32)
+    }
+
     /// Return a random index into the slice.
     #[inline]
     pub fn index<T>(&mut self, xs: &[T]) -> usize {
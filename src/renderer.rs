@@ -5,175 +5,2545 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
-use std::sync::Mutex;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::iter;
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use gdk_pixbuf as gdk;
-
-use crate::complex::Complex;
+use crate::colormap::Colormap;
+use crate::complex::{self, Complex, Complex64};
+use crate::fdtd::Fdtd;
+use crate::probe;
 use crate::rand::Rng;
-use crate::scene::Scene;
+use crate::scene::{Scene, Solver, Source};
+use crate::spectrum::Spectrum;
+use crate::vec2::Vec2;
 use crate::vec3::Vec3;
 
+/// The number of frequencies that we sample when averaging over a band.
+const BAND_FREQUENCIES: usize = 8;
+
+/// The number of frequencies per octave that we sample in bands that are so
+/// wide that `BAND_FREQUENCIES` would miss the peaks and dips between them.
+const BAND_FREQUENCIES_PER_OCTAVE: f32 = 6.0;
+
+/// The size of the image when no other size is given, in pixels.
+pub const DEFAULT_WIDTH: u32 = 1280;
+pub const DEFAULT_HEIGHT: u32 = 720;
+
+/// The width and height of a tile, in pixels.
+const TILE_SIZE: u32 = 64;
+
+/// The size of the blocks of the preview, in pixels, see `run_render_loop()`.
+/// This divides `TILE_SIZE`, so a row of blocks lies in one row of tiles.
+const PREVIEW_BLOCK: u32 = 8;
+
+/// How much memory the renders for other frequencies may take, in bytes, see
+/// `Renderer::set_frequency()`. At 1280 by 720 pixels, a render of a single
+/// frequency takes about 15 MB.
+const CACHE_BYTES: usize = 256 << 20;
+
+/// The number of passes to sample the neighboring frequencies for, once the
+/// current one converged, see `Renderer::set_neighbors()`. After a few passes
+/// the image is settled enough to judge, sampling continues once it is current.
+const PRECOMPUTE_PASSES: usize = 8;
+
+/// Estimate the convergence from one in this many pixels. This is prime, so
+/// the pixels do not line up in columns.
+const CONVERGENCE_STRIDE: usize = 61;
+
+/// How long a render thread should sample before it merges into the buffer.
+///
+/// Shorter makes the image more lively, but merging takes the buffer lock,
+/// so merging too often makes the threads contend for it.
+const CHUNK_DURATION: Duration = Duration::from_millis(50);
+
+/// The height of the rendered slice above the floor, in meters, about ear
+/// height of a seated listener. The image can show other heights, see
+/// `Renderer::set_slice_height()`.
+pub const SLICE_HEIGHT: f32 = 1.0;
+
+/// The margin around the scene when fitting the viewport, as a fraction of its size.
+const FIT_MARGIN: f32 = 0.05;
+
+/// The size of a pixel in meters for scenes that have no extent to fit.
+const FALLBACK_METERS_PER_PIXEL: f32 = 0.008;
+
+/// The color of the listener marker, red stands out from the grayscale field.
+pub const MARKER_COLOR: [u8; 3] = [220, 40, 40];
+
+/// The color of the wavelength arcs around the sources, and of the sources.
+const SOURCE_ARC_COLOR: [u8; 3] = [31, 119, 180];
+
+/// The color of the walls. Outside the room the field is black, so the
+/// outline stands out on at least one side.
+const WALL_COLOR: [u8; 3] = [255, 255, 255];
+
+/// The colors of pixels outside the room, which are hatched, so they do not
+/// look like a deep null.
+const EXTERIOR_COLOR: [u8; 3] = [48, 48, 48];
+const HATCH_COLOR: [u8; 3] = [80, 80, 80];
+
+/// The distance between the lines of the hatching, in pixels.
+const HATCH_SPACING: u32 = 8;
+
+/// The length of the arrow that shows where a source is aimed, in pixels.
+const AIM_ARROW_LENGTH: f32 = 24.0;
+
+/// The color of the intensity arrows, orange stands out from the field and the markers.
+const ARROW_COLOR: [u8; 3] = [255, 150, 20];
+
+/// The level difference between contour lines, in dB.
+const CONTOUR_STEP_DB: f32 = 3.0;
+
+/// The color of the contour lines. The line at the level of the listener is
+/// in the color of its marker.
+const CONTOUR_COLOR: [u8; 3] = [0, 0, 0];
+
+/// The color of the marker that the user pinned by clicking the image.
+const PIN_COLOR: [u8; 3] = [230, 60, 230];
+
+/// The distance between intensity arrows, in pixels.
+const ARROW_SPACING: u32 = 48;
+
+/// The distance between the pixels that the intensity is averaged over, per arrow.
+const ARROW_SUBSAMPLING: u32 = 4;
+
+/// How much simulated time the time-domain solver advances per frame, in seconds.
+const FDTD_FRAME_DURATION: f32 = 0.001;
+
+/// How long the time-domain solver shows a frame, at least.
+///
+/// Together with `FDTD_FRAME_DURATION`, this makes the wavefronts move at
+/// about 7 meters per second, slow enough to follow them.
+const FDTD_FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+/// With the time-domain solver, restart the pulse after this many seconds,
+/// unless the scene sets a time limit.
+const FDTD_DURATION: f32 = 0.3;
+
+/// If the exposure changes by less than this (in log10 units), we only repaint
+/// tiles that received new samples. This is about 0.2 dB.
+const EXPOSURE_TOLERANCE: f32 = 0.01;
+
+/// The distance over which the particle velocity takes the gradient of the
+/// pressure, as a fraction of the wavelength.
+///
+/// Shorter resolves the field better, longer averages out more of the noise
+/// of the Monte Carlo samples.
+const GRADIENT_STEP: f32 = 1.0 / 16.0;
+
+/// How the buffer sums the samples, see `Renderer::set_precision()`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Precision {
+    /// Sum in single precision, like the samples.
+    Single,
+
+    /// Sum in double precision, and keep the sums rounded to single precision
+    /// in the buffer, for painting. This takes three times the memory.
+    Double,
+
+    /// Sum in single precision, but with compensated summation, see
+    /// `complex::kahan_add()`. This takes twice the memory, and is about as
+    /// accurate as double precision.
+    Compensated,
+}
+
+/// What the buffer needs besides its sums in single precision, to sum with a
+/// given precision.
+#[derive(Clone)]
+enum Sums {
+    Single,
+
+    /// The sums in double precision, an element for every element of the buffer.
+    Double(Vec<Complex64>),
+
+    /// The compensation for every element of the buffer.
+    Compensated(Vec<Complex>),
+}
+
+impl Sums {
+    fn new(precision: Precision, len: usize) -> Sums {
+        match precision {
+            Precision::Single => Sums::Single,
+            Precision::Double => Sums::Double(vec![Complex64::zero(); len]),
+            Precision::Compensated => Sums::Compensated(vec![Complex::zero(); len]),
+        }
+    }
+
+    /// Reset to zero, for a buffer of `len` elements.
+    fn clear(&mut self, len: usize) {
+        match *self {
+            Sums::Single => {}
+            Sums::Double(ref mut sums) => {
+                sums.clear();
+                sums.resize(len, Complex64::zero());
+            }
+            Sums::Compensated(ref mut compensation) => {
+                compensation.clear();
+                compensation.resize(len, Complex::zero());
+            }
+        }
+    }
+
+    fn precision(&self) -> Precision {
+        match *self {
+            Sums::Single => Precision::Single,
+            Sums::Double(..) => Precision::Double,
+            Sums::Compensated(..) => Precision::Compensated,
+        }
+    }
+
+    fn size_bytes(&self) -> usize {
+        match *self {
+            Sums::Single => 0,
+            Sums::Double(ref sums) => sums.len() * mem::size_of::<Complex64>(),
+            Sums::Compensated(ref compensation) => compensation.len() * mem::size_of::<Complex>(),
+        }
+    }
+
+    /// Add `z` to element `j` of `buffer`, or with `replace`, replace it.
+    fn add(&mut self, buffer: &mut [Complex], j: usize, z: Complex, replace: bool) {
+        match *self {
+            Sums::Single => {
+                buffer[j] = if replace { z } else { buffer[j] + z };
+            }
+            Sums::Double(ref mut sums) => {
+                let z = Complex64::from(z);
+                sums[j] = if replace { z } else { sums[j] + z };
+                buffer[j] = sums[j].to_f32();
+            }
+            Sums::Compensated(ref mut compensation) => {
+                if replace {
+                    buffer[j] = z;
+                    compensation[j] = Complex::zero();
+                } else {
+                    complex::kahan_add(&mut buffer[j], &mut compensation[j], z);
+                }
+            }
+        }
+    }
+}
+
+/// What the image shows of the field.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Quantity {
+    /// The magnitude of the pressure, what a microphone picks up.
+    Pressure,
+
+    /// The magnitude of the particle velocity in the plane of the slice.
+    ///
+    /// This is where porous absorbers work: they take energy out of the air
+    /// that moves through them. It is shown times the impedance of air, so
+    /// in a plane wave, it is as bright as the pressure.
+    Velocity,
+
+    /// The phase of the pressure as the hue, and its magnitude as the lightness.
+    ///
+    /// Where two colors that are half a turn apart meet, the field cancels.
+    /// In a band, the phase is that of its middle frequency, the phases of
+    /// different frequencies do not add up to anything meaningful.
+    Phase,
+
+    /// The pressure at one instant, Re(z e^(iωt)), with t advancing on every
+    /// paint, so the standing waves oscillate.
+    ///
+    /// Mid gray is zero, as with the time-domain solver. One period takes
+    /// `OSCILLATION_FRAMES` paints, whatever the frequency. In a band, this
+    /// shows its middle frequency, like `Phase`.
+    Instantaneous,
+}
+
+/// The number of labels along the color bar, see `Renderer::legend()`.
+pub const LEGEND_LABELS: usize = 11;
+
+/// The number of slices of `View::Heights`, and the number of columns that
+/// they are arranged in.
+pub const HEIGHTS_CELLS: usize = 4;
+const HEIGHTS_COLUMNS: u32 = 2;
+
+/// The number of paints per period of the `Instantaneous` display.
+const OSCILLATION_FRAMES: u32 = 20;
+
+/// The part of the horizontal plane that the image shows.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    /// The point in meters at the top left corner of the image.
+    pub origin: Vec2,
+
+    /// The size of a pixel, in meters.
+    pub meters_per_pixel: f32,
+}
+
+impl Viewport {
+    /// Return the viewport that fits the scene into a `width` by `height`
+    /// image, with a margin around it, centered.
+    pub fn fit(scene: &Scene, width: u32, height: u32) -> Viewport {
+        let (min, max) = scene.bounding_box();
+        Viewport::fit_box(Vec2::new(min.x, min.y), Vec2::new(max.x, max.y), width, height)
+    }
+
+    /// Return the viewport that fits the rectangle from `min` to `max` into a
+    /// `width` by `height` image, with a margin around it, centered.
+    ///
+    /// An empty rectangle, such as the bounding box of a scene without rooms or
+    /// faces, gets a default scale around the origin, and a single point a
+    /// default scale around it.
+    pub fn fit_box(min: Vec2, max: Vec2, width: u32, height: u32) -> Viewport {
+        let size = Vec2::new(max.x - min.x, max.y - min.y) * (1.0 + 2.0 * FIT_MARGIN);
+        let mut meters_per_pixel = (size.x / width as f32).max(size.y / height as f32);
+        let mut center = Vec2::new(min.x + max.x, min.y + max.y) * 0.5;
+        if !(center.x.is_finite() && center.y.is_finite()) {
+            center = Vec2::zero();
+        }
+        if !(meters_per_pixel > 0.0 && meters_per_pixel.is_finite()) {
+            meters_per_pixel = FALLBACK_METERS_PER_PIXEL;
+        }
+        let half_image = Vec2::new(width as f32, height as f32) * (0.5 * meters_per_pixel);
+        Viewport {
+            origin: center - half_image,
+            meters_per_pixel: meters_per_pixel,
+        }
+    }
+
+    /// Return the viewport with the same center as this one in a `width` by
+    /// `height` image, at a different scale.
+    pub fn with_scale(&self, meters_per_pixel: f32, width: u32, height: u32) -> Viewport {
+        let half_image = Vec2::new(width as f32, height as f32) * 0.5;
+        let center = self.origin + half_image * self.meters_per_pixel;
+        Viewport {
+            origin: center - half_image * meters_per_pixel,
+            meters_per_pixel: meters_per_pixel,
+        }
+    }
+
+    /// Return the point in meters at the center of pixel (`x`, `y`).
+    pub fn to_world(&self, x: u32, y: u32) -> Vec2 {
+        self.origin + Vec2::new(x as f32 + 0.5, y as f32 + 0.5) * self.meters_per_pixel
+    }
+
+    /// Return the pixel that contains the point, which may lie outside the image.
+    pub fn to_pixel(&self, p: Vec2) -> (i32, i32) {
+        let d = (p - self.origin) * self.meters_per_pixel.recip();
+        (d.x.floor() as i32, d.y.floor() as i32)
+    }
+}
+
+/// Which plane through the scene the image shows.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum View {
+    /// The horizontal slice, seen from above.
+    Plan,
+
+    /// The vertical plane through the line between two points of the floor
+    /// plan, seen from the side, with the floor at the bottom.
+    ///
+    /// In the viewport, x is the distance from the first point along the
+    /// line, and y is minus the height, so up is up.
+    Section(Vec2, Vec2),
+
+    /// Horizontal slices at `HEIGHTS_CELLS` heights above the floor, from
+    /// high to low, in a grid of cells of `cell_width` by `cell_height`
+    /// pixels, in reading order.
+    ///
+    /// The viewport is that of the top left cell. All cells share the
+    /// exposure, so their levels can be compared.
+    Heights {
+        heights: [f32; HEIGHTS_CELLS],
+        cell_width: u32,
+        cell_height: u32,
+    },
+}
+
+impl View {
+    /// Return the vertical section through `p` along the x axis (if
+    /// `along_x`) or the y axis, across the whole scene.
+    pub fn section_through(scene: &Scene, p: Vec2, along_x: bool) -> View {
+        let (min, max) = scene.bounding_box();
+        if along_x {
+            View::Section(Vec2::new(min.x, p.y), Vec2::new(max.x, p.y))
+        } else {
+            View::Section(Vec2::new(p.x, min.y), Vec2::new(p.x, max.y))
+        }
+    }
+
+    /// Return slices at evenly spaced heights between the floor and the
+    /// ceiling, in cells that tile a `width` by `height` image.
+    pub fn heights(scene: &Scene, width: u32, height: u32) -> View {
+        let ceiling = scene.bounding_box().1.z;
+        let mut heights = [0.0; HEIGHTS_CELLS];
+        for (k, h) in heights.iter_mut().enumerate() {
+            *h = ceiling * (HEIGHTS_CELLS - k) as f32 / (HEIGHTS_CELLS + 1) as f32;
+        }
+        View::Heights {
+            heights: heights,
+            cell_width: width / HEIGHTS_COLUMNS,
+            cell_height: height / (HEIGHTS_CELLS as u32 / HEIGHTS_COLUMNS),
+        }
+    }
+
+    /// Return the viewport that fits the scene, as seen in this view.
+    ///
+    /// With a fixed `meters_per_pixel`, the scene is centered at that scale
+    /// instead, and it may not fit.
+    pub fn fit(&self, scene: &Scene, width: u32, height: u32, meters_per_pixel: Option<f32>) -> Viewport {
+        let (width, height) = match *self {
+            View::Heights { cell_width, cell_height, .. } => (cell_width, cell_height),
+            _ => (width, height),
+        };
+        let viewport = match *self {
+            View::Plan | View::Heights { .. } => Viewport::fit(scene, width, height),
+            View::Section(from, to) => {
+                let (min, max) = scene.bounding_box();
+                let length = (to - from).norm();
+                Viewport::fit_box(Vec2::new(0.0, -max.z), Vec2::new(length, -min.z), width, height)
+            }
+        };
+        match meters_per_pixel {
+            Some(s) => viewport.with_scale(s, width, height),
+            None => viewport,
+        }
+    }
+}
+
+/// The band of frequencies that the level is averaged over.
+///
+/// The power at every frequency in the band is weighted by the program
+/// spectrum, so with pink noise, the image shows what a measurement with pink
+/// noise in that band would show.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Averaging {
+    /// A band of the given width in octaves, centered on the selected
+    /// frequency. When zero, we render the field at the selected frequency only.
+    Centered(f32),
+
+    /// The band between two frequencies in Hz, whatever the selected frequency.
+    Fixed(f32, f32),
+}
+
 #[derive(Copy, Clone, PartialEq)]
 struct RenderParams {
     frequency_hz: f32,
+
+    /// The band to average over.
+    averaging: Averaging,
+
+    /// Incremented every time the scene is replaced.
+    scene_revision: u32,
+
+    /// The seed that the render threads derive their random streams from.
+    seed: u64,
+
+    /// Where the image lies in the scene.
+    viewport: Viewport,
+
+    /// The height of the slice above the floor, in meters, for the plan view.
+    slice_height: f32,
+
+    /// Which plane the image shows.
+    view: View,
+
+    /// The size of a pixel in meters, or `None` to fit the scene into the image.
+    meters_per_pixel: Option<f32>,
+
+    /// The size of the image, in pixels.
+    width: u32,
+    height: u32,
 }
 
-pub struct Renderer {
-    scene: Scene,
-    width: u32,
-    height: u32,
-    params: Mutex<RenderParams>,
-    buffer: Mutex<Vec<Complex>>,
-}
+impl RenderParams {
+    /// Return the frequencies to sample, spaced logarithmically over the band.
+    ///
+    /// There are `BAND_FREQUENCIES` of them, or `BAND_FREQUENCIES_PER_OCTAVE`
+    /// for wide bands, so the number only changes with the width of the band.
+    fn frequencies(&self) -> Vec<f32> {
+        let (center, octaves) = match self.averaging {
+            Averaging::Centered(octaves) => (self.frequency_hz, octaves),
+            Averaging::Fixed(f_min, f_max) => ((f_min * f_max).sqrt(), (f_max / f_min).log2()),
+        };
+        if octaves == 0.0 {
+            return vec![center]
+        }
+
+        // Sample at the centers of equally wide (in octaves) sub-bands.
+        let n = BAND_FREQUENCIES.max((octaves * BAND_FREQUENCIES_PER_OCTAVE).ceil() as usize);
+        (0..n)
+            .map(|k| {
+                let t = (k as f32 + 0.5) / n as f32 - 0.5;
+                center * 2.0_f32.powf(t * octaves)
+            })
+            .collect()
+    }
+
+    /// Return the point in the scene at the center of pixel (`x`, `y`).
+    fn position(&self, scene: &Scene, x: u32, y: u32) -> Vec3 {
+        let p = self.viewport.to_world(x, y);
+        match self.view {
+            // The slice follows raised parts of the floor, where the
+            // listeners' ears are higher too.
+            View::Plan => Vec3::new(p.x, p.y, scene.floor_height_at(p) + self.slice_height),
+            View::Section(from, to) => {
+                let d = to - from;
+                let q = from + d * (p.x / d.norm());
+                Vec3::new(q.x, q.y, -p.y)
+            }
+            View::Heights { heights, cell_width, cell_height } => {
+                let column = (x / cell_width).min(HEIGHTS_COLUMNS - 1);
+                let row = (y / cell_height).min(HEIGHTS_CELLS as u32 / HEIGHTS_COLUMNS - 1);
+                let k = (row * HEIGHTS_COLUMNS + column) as usize;
+                let q = p - self.cell_offset(k);
+                Vec3::new(q.x, q.y, scene.floor_height_at(q) + heights[k])
+            }
+        }
+    }
+
+    /// Return how far cell `k` of `View::Heights` lies from the top left
+    /// one, in the coordinates of the viewport.
+    fn cell_offset(&self, k: usize) -> Vec2 {
+        match self.view {
+            View::Heights { cell_width, cell_height, .. } => {
+                let (column, row) = (k as u32 % HEIGHTS_COLUMNS, k as u32 / HEIGHTS_COLUMNS);
+                Vec2::new((column * cell_width) as f32, (row * cell_height) as f32) * self.viewport.meters_per_pixel
+            }
+            _ => Vec2::zero(),
+        }
+    }
+
+    /// Return the point in the plane of the image that `p` projects onto, in
+    /// the coordinates of the viewport.
+    ///
+    /// With `View::Heights`, this is the point in the slice closest in height.
+    fn project(&self, p: Vec3) -> Vec2 {
+        match self.view {
+            View::Plan => Vec2::new(p.x, p.y),
+            View::Section(from, to) => {
+                let d = to - from;
+                let q = Vec2::new(p.x, p.y) - from;
+                Vec2::new((q.x * d.x + q.y * d.y) / d.norm(), -p.z)
+            }
+            View::Heights { heights, .. } => {
+                let k = (0..HEIGHTS_CELLS)
+                    .min_by(|&a, &b| (heights[a] - p.z).abs().partial_cmp(&(heights[b] - p.z).abs()).unwrap())
+                    .unwrap();
+                Vec2::new(p.x, p.y) + self.cell_offset(k)
+            }
+        }
+    }
+
+    /// Return the points in the image that `p` projects onto: one in every
+    /// cell of `View::Heights`, so markers show in all of them, or the one
+    /// of `project()` otherwise.
+    fn projections(&self, p: Vec3) -> Vec<Vec2> {
+        match self.view {
+            View::Heights { .. } => (0..HEIGHTS_CELLS).map(|k| Vec2::new(p.x, p.y) + self.cell_offset(k)).collect(),
+            _ => vec![self.project(p)],
+        }
+    }
+
+    /// Return whether the image shows the same points as for `other`, so
+    /// what only depends on the geometry can be reused.
+    fn same_geometry(&self, other: &RenderParams) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.scene_revision == other.scene_revision
+            && self.viewport == other.viewport
+            && self.slice_height.to_bits() == other.slice_height.to_bits()
+            && self.view == other.view
+    }
+
+    /// Return the number of pixels in the image.
+    fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Return the number of rows of blocks of the preview.
+    fn preview_rows(&self) -> u32 {
+        (self.height + PREVIEW_BLOCK - 1) / PREVIEW_BLOCK
+    }
+
+    /// Return the tiles that cover the image, row by row.
+    fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y in (0..self.height).step_by(TILE_SIZE as usize) {
+            for x in (0..self.width).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile {
+                    x: x,
+                    y: y,
+                    width: TILE_SIZE.min(self.width - x),
+                    height: TILE_SIZE.min(self.height - y),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+/// The level over the listening area, in dB relative to the listener.
+#[derive(Copy, Clone, Debug)]
+pub struct AreaStats {
+    /// The level of the power averaged over the area.
+    pub mean_db: f32,
+
+    /// The standard deviation of the level between points in the area, a
+    /// measure of how different the seats sound.
+    pub std_db: f32,
+
+    pub min_db: f32,
+    pub max_db: f32,
+}
+
+/// How far the render has converged, see `Renderer::convergence()`.
+#[derive(Copy, Clone, Debug)]
+pub struct Convergence {
+    /// The number of samples per pixel, averaged over the image.
+    pub passes: f32,
+
+    /// The median over the image of the standard error of the level, in dB.
+    pub uncertainty_db: f32,
+}
+
+/// What we know about the samples of a pixel, besides their sum.
+#[derive(Copy, Clone, Debug)]
+struct PixelStats {
+    /// The number of samples accumulated into the buffer. Where this is zero,
+    /// the buffer holds the preview, if anything.
+    passes: u32,
+
+    /// The sum of the squared magnitudes of the samples at the middle
+    /// frequency of the band, for the variance.
+    sum_squares: f32,
+}
+
+impl PixelStats {
+    fn zero() -> PixelStats {
+        PixelStats { passes: 0, sum_squares: 0.0 }
+    }
+}
+
+/// The samples accumulated for params that are no longer current.
+struct CachedRender {
+    params: RenderParams,
+    buffer: Vec<Complex>,
+    sums: Sums,
+    stats: Vec<PixelStats>,
+}
+
+impl CachedRender {
+    fn size_bytes(&self) -> usize {
+        self.buffer.len() * mem::size_of::<Complex>()
+            + self.sums.size_bytes()
+            + self.stats.len() * mem::size_of::<PixelStats>()
+    }
+}
+
+/// A rectangular part of the image, the unit of accumulation and repainting.
+#[derive(Copy, Clone, Debug)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+/// What `paint` needs to know about what it painted previously.
+struct PaintState {
+    /// For every tile, whether it received new samples since the last paint.
+    dirty: Vec<bool>,
+
+    /// The exposure that we painted with last time, if we painted at all.
+    exposure: Option<f32>,
+
+    /// Whether to draw arcs at a quarter and half wavelength around the
+    /// sources and the listener.
+    show_wavelengths: bool,
+
+    /// With the time-domain solver, the simulated time of the pressure in
+    /// the buffer, in seconds.
+    pressure_time: Option<f32>,
+
+    /// What to show of the accumulated field.
+    quantity: Quantity,
+
+    /// Whether to draw arrows in the direction of the net flow of energy.
+    show_intensity: bool,
+
+    /// The number of paints so far, which sets the time of the `Instantaneous` display.
+    frame: u32,
+
+    /// How to color the levels, see `Colormap`.
+    colormap: Colormap,
+
+    /// Whether to draw contour lines at every `CONTOUR_STEP_DB`.
+    show_contours: bool,
+
+    /// The point that the user pinned, if any.
+    pin: Option<Vec2>,
+
+    /// For every pixel, whether it lies outside the room, with the params
+    /// that it was computed for.
+    exterior: Option<(RenderParams, Arc<Vec<bool>>)>,
+
+    /// The convergence at the end of the last pass over the tiles, if any.
+    convergence: Option<Convergence>,
+
+    /// The uncertainty in dB below which the render threads stop sampling, if any.
+    target_uncertainty_db: Option<f32>,
+
+    /// The number of pixels without samples of their own, see
+    /// `is_first_pass_complete()`.
+    unsampled: usize,
+
+    /// Whether the render threads idle until resumed, see `set_paused()`.
+    paused: bool,
+
+    /// The fraction of the time that a render thread may sample, see `set_max_cpu()`.
+    max_cpu: f32,
+}
+
+pub struct Renderer {
+    scene: Mutex<Arc<Scene>>,
+
+    /// Program spectrum used to weight the frequencies in a band.
+    spectrum: Spectrum,
+
+    params: Mutex<RenderParams>,
+
+    /// The accumulated field, one frame for every frequency in the band.
+    buffer: Mutex<Vec<Complex>>,
+
+    /// What summing into `buffer` needs for the precision, see `set_precision()`.
+    sums: Mutex<Sums>,
+
+    /// For every pixel, the number of samples accumulated into `buffer`, and
+    /// how much they vary.
+    stats: Mutex<Vec<PixelStats>>,
+
+    /// The next row of preview blocks to sample. Only changes with the params
+    /// lock held, so it belongs to the current params.
+    preview_row: AtomicUsize,
+
+    /// The number of tiles that the render threads took so far, the next tile
+    /// is this modulo the number of tiles, see `run_render_loop()`.
+    next_tile: AtomicUsize,
+
+    /// Incremented by every `clear()`, so render threads can abandon samples
+    /// for old params halfway, see `render_tile()`.
+    generation: AtomicUsize,
+
+    /// Renders for params that we may return to, most recently used first,
+    /// at most `CACHE_BYTES` of them.
+    cache: Mutex<VecDeque<CachedRender>>,
+
+    /// The frequencies to sample into the cache once the current one is
+    /// converged, see `set_neighbors()`.
+    neighbors: Mutex<Vec<f32>>,
+
+    /// The number of neighbor tiles that the render threads took so far, see
+    /// `claim_precompute()`. Reset with the generation.
+    next_precompute: AtomicUsize,
+
+    paint_state: Mutex<PaintState>,
+}
+
+impl Renderer {
+    pub fn new(
+        scene: Scene,
+        spectrum: Spectrum,
+        bandwidth_octaves: f32,
+        seed: u64,
+        width: u32,
+        height: u32,
+        meters_per_pixel: Option<f32>,
+    ) -> Renderer {
+        let params = RenderParams {
+            frequency_hz: 440.0,
+            averaging: Averaging::Centered(bandwidth_octaves),
+            scene_revision: 0,
+            seed: seed,
+            viewport: View::Plan.fit(&scene, width, height, meters_per_pixel),
+            slice_height: SLICE_HEIGHT,
+            view: View::Plan,
+            meters_per_pixel: meters_per_pixel,
+            width: width,
+            height: height,
+        };
+
+        let buffer: Vec<_> = iter::repeat(Complex::zero())
+            .take(params.area() * params.frequencies().len())
+            .collect();
+
+        let paint_state = PaintState {
+            dirty: vec![true; params.tiles().len()],
+            exposure: None,
+            show_wavelengths: false,
+            pressure_time: None,
+            quantity: Quantity::Pressure,
+            show_intensity: false,
+            frame: 0,
+            colormap: Colormap::Gray,
+            show_contours: false,
+            pin: None,
+            exterior: None,
+            convergence: None,
+            target_uncertainty_db: None,
+            unsampled: params.area(),
+            paused: false,
+            max_cpu: 1.0,
+        };
+
+        Renderer {
+            scene: Mutex::new(Arc::new(scene)),
+            spectrum: spectrum,
+            params: Mutex::new(params),
+            stats: Mutex::new(vec![PixelStats::zero(); params.area()]),
+            buffer: Mutex::new(buffer),
+            sums: Mutex::new(Sums::Single),
+            preview_row: AtomicUsize::new(0),
+            next_tile: AtomicUsize::new(0),
+            generation: AtomicUsize::new(0),
+            cache: Mutex::new(VecDeque::new()),
+            neighbors: Mutex::new(Vec::new()),
+            next_precompute: AtomicUsize::new(0),
+            paint_state: Mutex::new(paint_state),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.params.lock().unwrap().width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.params.lock().unwrap().height
+    }
+
+    /// Render an image of a different size, and discard everything rendered.
+    ///
+    /// The viewport is fitted to the new size, or keeps its center with a
+    /// fixed scale. The caller should paint into a buffer of the new size.
+    pub fn set_size(&self, width: u32, height: u32) {
+        let mut p = self.params.lock().unwrap();
+        let scene = self.scene.lock().unwrap().clone();
+        p.width = width;
+        p.height = height;
+        if let View::Heights { .. } = p.view {
+            p.view = View::heights(&scene, width, height);
+        }
+        p.viewport = p.view.fit(&scene, width, height, p.meters_per_pixel);
+        let n_frequencies = p.frequencies().len();
+        self.buffer.lock().unwrap().resize(p.area() * n_frequencies, Complex::zero());
+        self.stats.lock().unwrap().resize(p.area(), PixelStats::zero());
+        {
+            let mut state = self.paint_state.lock().unwrap();
+            state.dirty = vec![true; p.tiles().len()];
+            state.exposure = None;
+            state.exterior = None;
+        }
+        self.clear();
+    }
+
+    /// Return the scene that is currently being rendered.
+    pub fn scene(&self) -> Arc<Scene> {
+        self.scene.lock().unwrap().clone()
+    }
+
+    /// Return the frequency that is currently being rendered, in Hz.
+    pub fn frequency(&self) -> f32 {
+        self.params.lock().unwrap().frequency_hz
+    }
+
+    /// Return the band that the level is averaged over.
+    pub fn averaging(&self) -> Averaging {
+        self.params.lock().unwrap().averaging
+    }
+
+    /// Return the seed of the random streams of the render threads.
+    pub fn seed(&self) -> u64 {
+        self.params.lock().unwrap().seed
+    }
+
+    /// Discard everything rendered, and start over with the preview.
+    ///
+    /// Callers hold the params lock, so the render threads cannot merge
+    /// samples for the old params after this, see `accumulate_move()`.
+    pub fn clear(&self) {
+        let mut b = self.buffer.lock().unwrap();
+        for z in b.iter_mut() {
+            *z = Complex::zero();
+        }
+        // The buffer may have been resized, the sums follow.
+        self.sums.lock().unwrap().clear(b.len());
+        let mut stats = self.stats.lock().unwrap();
+        for pixel in stats.iter_mut() {
+            *pixel = PixelStats::zero();
+        }
+        self.preview_row.store(0, Ordering::SeqCst);
+        self.next_tile.store(0, Ordering::SeqCst);
+        self.next_precompute.store(0, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        let mut state = self.paint_state.lock().unwrap();
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+        state.pressure_time = None;
+        state.convergence = None;
+        state.unsampled = stats.len();
+    }
+
+    /// Render a different frequency.
+    ///
+    /// The samples for the old frequency are kept, so when we return to a
+    /// frequency that we rendered before, the image is there right away, and
+    /// sampling continues where it left off.
+    pub fn set_frequency(&self, f_hz: f32) {
+        let mut p = self.params.lock().unwrap();
+        self.stash(&p);
+        p.frequency_hz = f_hz;
+        if !self.restore(&p) {
+            self.clear();
+        }
+    }
+
+    /// Put the samples for `params` in the cache, if there are any, and evict
+    /// the least recently used renders that do not fit.
+    ///
+    /// Callers hold the params lock, `params` are the current params.
+    fn stash(&self, params: &RenderParams) {
+        let buffer = self.buffer.lock().unwrap();
+        let sums = self.sums.lock().unwrap();
+        let stats = self.stats.lock().unwrap();
+        if stats.iter().all(|pixel| pixel.passes == 0) {
+            return
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|entry| entry.params != *params);
+        cache.push_front(CachedRender {
+            params: *params,
+            buffer: buffer.clone(),
+            sums: sums.clone(),
+            stats: stats.clone(),
+        });
+        evict(&mut cache);
+    }
+
+    /// Continue from the cached samples for `params`, if there are any.
+    ///
+    /// This is `clear()` without discarding the samples, and it returns
+    /// whether there were samples. Callers hold the params lock, `params` are
+    /// the new current params.
+    fn restore(&self, params: &RenderParams) -> bool {
+        let entry = {
+            let mut cache = self.cache.lock().unwrap();
+            match cache.iter().position(|entry| entry.params == *params) {
+                Some(i) => cache.remove(i).unwrap(),
+                None => return false,
+            }
+        };
+        // A render that we stashed has samples for every pixel, there is no
+        // need for a preview. A neighbor that was sampled into the cache may
+        // lack some tiles, see `precompute_tile()`.
+        let unsampled = entry.stats.iter().filter(|pixel| pixel.passes == 0).count();
+        *self.buffer.lock().unwrap() = entry.buffer;
+        *self.sums.lock().unwrap() = entry.sums;
+        *self.stats.lock().unwrap() = entry.stats;
+
+        let preview_row = if unsampled == 0 { params.preview_rows() } else { 0 };
+        self.preview_row.store(preview_row as usize, Ordering::SeqCst);
+        self.next_tile.store(0, Ordering::SeqCst);
+        self.next_precompute.store(0, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        let mut state = self.paint_state.lock().unwrap();
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+        state.pressure_time = None;
+        state.convergence = None;
+        state.unsampled = unsampled;
+        true
+    }
+
+    /// Sample `frequencies` into the cache whenever the current render is
+    /// converged, so they show right away once they are current.
+    ///
+    /// These are typically the frequencies next to the current one on the
+    /// slider. They are sampled for `PRECOMPUTE_PASSES` passes, with the other
+    /// params as they are now.
+    pub fn set_neighbors(&self, frequencies: &[f32]) {
+        let _p = self.params.lock().unwrap();
+        *self.neighbors.lock().unwrap() = frequencies.to_vec();
+        self.next_precompute.store(0, Ordering::SeqCst);
+    }
+
+    /// Average the level over a different band, and discard everything rendered.
+    ///
+    /// The buffer holds a frame per frequency in the band, so it is resized
+    /// if the number of frequencies changes.
+    pub fn set_averaging(&self, averaging: Averaging) {
+        let mut p = self.params.lock().unwrap();
+        p.averaging = averaging;
+        let n_frequencies = p.frequencies().len();
+        self.buffer.lock().unwrap().resize(p.area() * n_frequencies, Complex::zero());
+        self.clear();
+    }
+
+    /// Sum the samples with a different precision, and discard everything
+    /// rendered.
+    ///
+    /// The samples are single precision in any case. In single precision, once
+    /// a pixel has a few million samples, a sample is small relative to the
+    /// sum, and it loses most of its digits. This shows most in deep nulls,
+    /// where the samples cancel. Double precision and compensated summation
+    /// both keep the digits.
+    pub fn set_precision(&self, precision: Precision) {
+        let _p = self.params.lock().unwrap();
+        {
+            let buffer = self.buffer.lock().unwrap();
+            *self.sums.lock().unwrap() = Sums::new(precision, buffer.len());
+        }
+
+        // Cached renders have sums in the old precision.
+        self.cache.lock().unwrap().clear();
+        self.clear();
+    }
+
+    /// Return the height of the slice above the floor, in meters.
+    pub fn slice_height(&self) -> f32 {
+        self.params.lock().unwrap().slice_height
+    }
+
+    /// Render the slice at a different height above the floor, and discard
+    /// everything rendered at the old height.
+    pub fn set_slice_height(&self, height: f32) {
+        let mut p = self.params.lock().unwrap();
+        p.slice_height = height;
+        self.clear();
+    }
+
+    /// Restart rendering with random streams derived from a different seed.
+    pub fn set_seed(&self, seed: u64) {
+        let mut p = self.params.lock().unwrap();
+        p.seed = seed;
+        self.clear();
+    }
+
+    /// Replace the scene, and discard everything rendered for the old scene.
+    ///
+    /// The viewport is fitted to the new scene.
+    pub fn set_scene(&self, scene: Scene) {
+        let mut p = self.params.lock().unwrap();
+        p.viewport = p.view.fit(&scene, p.width, p.height, p.meters_per_pixel);
+        *self.scene.lock().unwrap() = Arc::new(scene);
+        p.scene_revision += 1;
+
+        // Renders of older revisions cannot become current again.
+        self.cache.lock().unwrap().clear();
+        self.clear();
+    }
+
+    /// Show a different plane, fitted into the image, and discard everything
+    /// rendered for the old one.
+    ///
+    /// The pinned marker is removed, its coordinates are those of the old view.
+    pub fn set_view(&self, view: View) {
+        let mut p = self.params.lock().unwrap();
+        p.view = view;
+        p.viewport = view.fit(&self.scene.lock().unwrap(), p.width, p.height, p.meters_per_pixel);
+        self.paint_state.lock().unwrap().pin = None;
+        self.clear();
+    }
+
+    /// Return the fixed size of a pixel in meters, `None` when the scene is
+    /// fitted into the image.
+    pub fn meters_per_pixel(&self) -> Option<f32> {
+        self.params.lock().unwrap().meters_per_pixel
+    }
+
+    /// Show the scene at a fixed scale, or fitted into the image with `None`,
+    /// and discard everything rendered at the old scale.
+    ///
+    /// The pinned marker stays where it is in the scene.
+    pub fn set_meters_per_pixel(&self, meters_per_pixel: Option<f32>) {
+        let mut p = self.params.lock().unwrap();
+        p.meters_per_pixel = meters_per_pixel;
+        p.viewport = p.view.fit(&self.scene.lock().unwrap(), p.width, p.height, meters_per_pixel);
+        self.clear();
+    }
+
+    /// Show or hide the wavelength arcs, see `paint()`.
+    pub fn set_show_wavelengths(&self, show: bool) {
+        let mut state = self.paint_state.lock().unwrap();
+        state.show_wavelengths = show;
+
+        // Hiding the arcs requires repainting the tiles underneath them.
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+    }
+
+    /// Show or hide the intensity arrows, see `paint()`.
+    pub fn set_show_intensity(&self, show: bool) {
+        let mut state = self.paint_state.lock().unwrap();
+        state.show_intensity = show;
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+    }
+
+    /// Show or hide the contour lines, see `paint()`.
+    pub fn set_show_contours(&self, show: bool) {
+        let mut state = self.paint_state.lock().unwrap();
+        state.show_contours = show;
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+    }
+
+    /// Pin a marker at a point, or remove it, see `paint()`.
+    pub fn set_pin(&self, pin: Option<Vec2>) {
+        let mut state = self.paint_state.lock().unwrap();
+        state.pin = pin;
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+    }
+
+    /// Return the point that the user pinned, if any.
+    pub fn get_pin(&self) -> Option<Vec2> {
+        self.paint_state.lock().unwrap().pin
+    }
+
+    /// Show the pressure or the particle velocity, see `paint()`.
+    pub fn set_quantity(&self, quantity: Quantity) {
+        let mut state = self.paint_state.lock().unwrap();
+        state.quantity = quantity;
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+    }
+
+    /// Color the levels with `colormap`, see `paint()`.
+    pub fn set_colormap(&self, colormap: Colormap) {
+        let mut state = self.paint_state.lock().unwrap();
+        state.colormap = colormap;
+        for d in state.dirty.iter_mut() {
+            *d = true;
+        }
+    }
+
+    /// Return the color bar of the image, and the labels along it.
+    ///
+    /// The bar is `width` by `height` RGB pixels, from the highest value at
+    /// the top to the lowest at the bottom. There are `LEGEND_LABELS` labels,
+    /// from top to bottom. Label k names the value at the middle of the k-th
+    /// of as many equal parts of the bar, so they line up with the bar when
+    /// they are spread evenly over its height. Levels are in dB relative to
+    /// the listener, the signed pressure is relative to the peak in view.
+    pub fn legend(&self, width: u32, height: u32) -> (Vec<u8>, Vec<String>) {
+        let state = self.paint_state.lock().unwrap();
+        let is_signed = state.quantity == Quantity::Instantaneous
+            || self.scene.lock().unwrap().simulation.solver == Solver::Fdtd;
+        let n = LEGEND_LABELS as f32;
+
+        // The value of the bar, from 0 at the bottom label to 1 at the top label.
+        let color = |t: f32| -> [u8; 3] {
+            let level = (t.max(0.0).min(1.0) * 255.0) as u8;
+            match state.quantity {
+                Quantity::Phase if !is_signed => hue_color((2.0 * t - 1.0) * std::f32::consts::PI, 128),
+                _ => state.colormap.color(level),
+            }
+        };
+        let label = |t: f32| -> String {
+            match state.quantity {
+                _ if is_signed => {
+                    // The inverse of `pressure_level()`.
+                    let s = 2.0 * t - 1.0;
+                    format!("{:+.0}%", 100.0 * s * s.abs())
+                }
+                Quantity::Phase => format!("{:+.0}°", (2.0 * t - 1.0) * 180.0),
+                // The inverse of `gray_level()`, in dB.
+                _ => format!("{:+.0} dB", (t - 0.5) * 100.0),
+            }
+        };
+
+        let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+        for y in 0..height {
+            let t = 1.0 - ((y as f32 + 0.5) / height as f32 - 0.5 / n) * n / (n - 1.0);
+            for _ in 0..width {
+                rgb.extend_from_slice(&color(t));
+            }
+        }
+        let labels = (0..LEGEND_LABELS).map(|k| label(1.0 - k as f32 / (n - 1.0))).collect();
+        (rgb, labels)
+    }
+
+    /// Return whether the image changes on every paint, even without new
+    /// samples, so it should be painted as often as possible.
+    pub fn is_animated(&self) -> bool {
+        self.paint_state.lock().unwrap().quantity == Quantity::Instantaneous
+    }
+
+    /// Mute or unmute source `si`, see `Scene::is_audible()`.
+    pub fn set_mute(&self, si: usize, muted: bool) {
+        self.update_source(si, |s| s.muted = muted);
+    }
+
+    /// Solo or unsolo source `si`, see `Scene::is_audible()`.
+    pub fn set_solo(&self, si: usize, solo: bool) {
+        self.update_source(si, |s| s.solo = solo);
+    }
+
+    /// Replace source `si` of the scene with the result of `f`, and restart rendering.
+    ///
+    /// The scene may have been reloaded with fewer sources, then this does nothing.
+    pub fn update_source<F: FnOnce(&mut Source)>(&self, si: usize, f: F) {
+        let mut scene = (*self.scene()).clone();
+        if let Some(source) = scene.sources.get_mut(si) {
+            f(source);
+            self.set_scene(scene);
+        }
+    }
+
+    /// Return the current parameters, and the scene that they apply to.
+    fn get_params(&self) -> (RenderParams, Arc<Scene>) {
+        let p = self.params.lock().unwrap();
+        let scene = self.scene.lock().unwrap().clone();
+        (*p, scene)
+    }
+
+    /// Add `buffer` to `chunk` of the internal buffer, and zero `buffer` itself.
+    ///
+    /// In a sense, move the density out of `buffer` into `self.buffer`. Takes
+    /// the render params to confirm that they are the same as the values that
+    /// `buffer` was filled for; we would not want to merge a stale render.
+    /// The chunk is part of tile `ti`, it is marked dirty. The first sample of
+    /// a pixel replaces the preview.
+    fn accumulate_move(&self, params: &RenderParams, ti: usize, chunk: Tile, buffer: &mut [Complex]) {
+        let n_frequencies = params.frequencies().len();
+        assert_eq!(buffer.len(), chunk.area() * n_frequencies);
+
+        // Only accumulate if the values we want to add were computed for the
+        // same parameters. Hold on to the params lock while we accumulate, so
+        // the params cannot change (and the buffer cannot be resized) halfway.
+        let p = self.params.lock().unwrap();
+        if *p != *params {
+            return
+        }
+
+        let mut b = self.buffer.lock().unwrap();
+        let mut sums = self.sums.lock().unwrap();
+        let mut stats = self.stats.lock().unwrap();
+        assert_eq!(b.len(), p.area() * n_frequencies);
+
+        for (k, chunk_frame) in buffer.chunks_mut(chunk.area()).enumerate() {
+            for (dy, src_row) in chunk_frame.chunks_mut(chunk.width as usize).enumerate() {
+                let start = (chunk.y as usize + dy) * p.width as usize + chunk.x as usize;
+                for (dx, src) in src_row.iter_mut().enumerate() {
+                    let i = start + dx;
+                    let j = k * p.area() + i;
+                    let pixel = &mut stats[i];
+                    sums.add(&mut b[..], j, *src, pixel.passes == 0);
+                    if k == n_frequencies / 2 {
+                        pixel.sum_squares += src.norm_squared();
+                    }
+                    *src = Complex::zero();
+                }
+            }
+        }
+        let mut newly_sampled = 0;
+        for dy in 0..chunk.height as usize {
+            let start = (chunk.y as usize + dy) * p.width as usize + chunk.x as usize;
+            for pixel in &mut stats[start..start + chunk.width as usize] {
+                if pixel.passes == 0 {
+                    newly_sampled += 1;
+                }
+                pixel.passes += 1;
+            }
+        }
+
+        let mut state = self.paint_state.lock().unwrap();
+        state.dirty[ti] = true;
+        state.unsampled -= newly_sampled;
+    }
+
+    /// Keep sampling the tiles, forever.
+    ///
+    /// Every render thread should pass a different `thread_index`, so the
+    /// threads draw different samples. Thread `i` draws from a random stream
+    /// seeded with `(seed << 16) + i`, so for a given seed, every thread always
+    /// draws the same samples. Which samples have been accumulated at the time
+    /// of painting still depends on scheduling.
+    ///
+    /// The threads take tiles from a shared queue, in order, so they share
+    /// every pass over the image, however many threads there are. After a
+    /// change, the image fills in from the top, at the pace of all threads.
+    ///
+    /// A thread samples a tile in chunks of rows, and merges every chunk into
+    /// the buffer. The number of rows adapts to how expensive the scene is,
+    /// such that sampling a chunk takes about `CHUNK_DURATION`. For cheap
+    /// scenes a chunk is a full tile.
+    ///
+    /// After every change of the params, the threads first sample the preview:
+    /// one sample per block of `PREVIEW_BLOCK` pixels square, shown over the
+    /// whole block until its pixels have samples of their own. The preview
+    /// takes a fraction of a pass, and shows the overall structure long
+    /// before the noise of the first pass settles.
+    ///
+    /// Threads with an index below `paused` do not sample, to leave cores
+    /// free for analyses, see `AnalysisPool`. No thread samples while the
+    /// renderer is paused with `set_paused()`, and with `set_max_cpu()`, the
+    /// threads sleep after every chunk. Once the target uncertainty is
+    /// reached, see `set_target_uncertainty()`, the threads sample the
+    /// neighboring frequencies instead, see `set_neighbors()`, and after
+    /// that they idle.
+    ///
+    /// With the time-domain solver, thread 0 runs the simulation, see
+    /// `run_fdtd()`, and the other threads idle.
+    pub fn run_render_loop(&self, thread_index: u64, paused: &AtomicUsize) {
+        let mut buffer = Vec::new();
+        let mut seed = None;
+        let mut rng = Rng::new(0);
+        let mut chunk_rows = TILE_SIZE;
+
+        loop {
+            // Take the next tile from the queue that the threads share. When
+            // the queue wraps around, a pass over the image is complete.
+            let (tile_params, _) = self.get_params();
+            let tiles = tile_params.tiles();
+            let claimed = self.next_tile.fetch_add(1, Ordering::SeqCst);
+            let ti = claimed % tiles.len();
+            let tile = tiles[ti];
+            if ti == 0 && claimed > 0 {
+                self.update_convergence();
+            }
+
+            let mut dy = 0;
+            while dy < tile.height {
+                if thread_index < paused.load(Ordering::SeqCst) as u64 || self.is_paused() {
+                    thread::sleep(CHUNK_DURATION);
+                    continue
+                }
+                if self.is_converged() {
+                    let start = Instant::now();
+                    if self.precompute_tile(&mut rng, &mut buffer) {
+                        self.throttle(start.elapsed());
+                    } else {
+                        thread::sleep(CHUNK_DURATION);
+                    }
+                    continue
+                }
+
+                // The tiles change with the size of the image. Read the
+                // generation first, if it is older than the params, we only
+                // abandon the chunk needlessly.
+                let generation = self.generation.load(Ordering::SeqCst);
+                let (params, scene) = self.get_params();
+                if (params.width, params.height) != (tile_params.width, tile_params.height) {
+                    break
+                }
+                if scene.simulation.solver == Solver::Fdtd {
+                    if thread_index == 0 {
+                        self.run_fdtd(paused);
+                    } else {
+                        thread::sleep(CHUNK_DURATION);
+                    }
+                    continue
+                }
+                if seed != Some(params.seed) {
+                    rng = Rng::new((params.seed << 16).wrapping_add(thread_index));
+                    seed = Some(params.seed);
+                }
+                if let Some(row) = self.claim_preview_row(&params) {
+                    self.render_preview_row(&scene, &params, &mut rng, row);
+                    continue
+                }
+                let chunk = Tile {
+                    x: tile.x,
+                    y: tile.y + dy,
+                    width: tile.width,
+                    height: chunk_rows.min(tile.height - dy),
+                };
+                let frequencies = params.frequencies();
+                buffer.resize(chunk.area() * frequencies.len(), Complex::zero());
+
+                let start = Instant::now();
+                let current = (&self.generation, generation);
+                let complete = render_tile(&scene, &params, &frequencies[..], &mut rng, &mut buffer[..], chunk, current);
+                if !complete {
+                    continue
+                }
+                let elapsed = start.elapsed();
+                chunk_rows = adapt_chunk_rows(chunk.height, elapsed);
+
+                self.accumulate_move(&params, ti, chunk, &mut buffer[..]);
+                dy += chunk.height;
+                self.throttle(elapsed);
+            }
+        }
+    }
+
+    /// Estimate how far the render has converged, from a sample of the pixels.
+    ///
+    /// The samples of a pixel scatter around their mean, and the standard
+    /// error of the mean shrinks with the square root of the number of
+    /// samples. Relative to the mean, it is the uncertainty of the level. We
+    /// take the median over the pixels, in nulls the uncertainty is large.
+    /// Only the middle frequency of the band counts.
+    fn update_convergence(&self) {
+        let p = self.params.lock().unwrap();
+        let buffer = self.buffer.lock().unwrap();
+        let stats = self.stats.lock().unwrap();
+        let area = p.area();
+        let k = p.frequencies().len() / 2;
+        let middle = &buffer[k * area..(k + 1) * area];
+
+        let mut total_passes = 0_u64;
+        let mut n_pixels = 0_u64;
+        let mut errors_db = Vec::new();
+        for i in (0..area).step_by(CONVERGENCE_STRIDE) {
+            let pixel = stats[i];
+            total_passes += pixel.passes as u64;
+            n_pixels += 1;
+
+            // Pixels outside the room have no field.
+            let n = pixel.passes as f32;
+            let mean_squared = (middle[i] * n.recip()).norm_squared();
+            if pixel.passes < 2 || !(mean_squared > 0.0) {
+                continue
+            }
+            let variance = ((pixel.sum_squares - n * mean_squared) / (n - 1.0)).max(0.0);
+            let relative_error = (variance / (n * mean_squared)).sqrt();
+            if relative_error.is_finite() {
+                errors_db.push(20.0 * (1.0 + relative_error).log10());
+            }
+        }
+
+        let mut state = self.paint_state.lock().unwrap();
+        if errors_db.is_empty() {
+            state.convergence = None;
+            return
+        }
+        errors_db.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        state.convergence = Some(Convergence {
+            passes: total_passes as f32 / n_pixels as f32,
+            uncertainty_db: errors_db[errors_db.len() / 2],
+        });
+    }
+
+    /// Return how far the render has converged, as of the last pass over the
+    /// tiles, or `None` before the second pass, or with the time-domain solver.
+    pub fn convergence(&self) -> Option<Convergence> {
+        self.paint_state.lock().unwrap().convergence
+    }
+
+    /// Stop sampling once the uncertainty is below `target_db`, or never with `None`.
+    ///
+    /// Sampling resumes when anything changes that discards the render.
+    pub fn set_target_uncertainty(&self, target_db: Option<f32>) {
+        self.paint_state.lock().unwrap().target_uncertainty_db = target_db;
+    }
+
+    /// Return whether the render reached the target uncertainty, if there is one.
+    pub fn is_converged(&self) -> bool {
+        let state = self.paint_state.lock().unwrap();
+        match (state.convergence, state.target_uncertainty_db) {
+            (Some(c), Some(target)) => c.uncertainty_db <= target,
+            _ => false,
+        }
+    }
+
+    /// Sample a tile of a neighboring frequency into the cache, and return
+    /// whether there was one left to sample.
+    ///
+    /// Unlike the current render, this samples a whole tile at once, the
+    /// thread can only respond to the target after that.
+    fn precompute_tile(&self, rng: &mut Rng, buffer: &mut Vec<Complex>) -> bool {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let (params, ti) = match self.claim_precompute() {
+            Some(claim) => claim,
+            None => return false,
+        };
+        let scene = self.scene();
+        if scene.simulation.solver == Solver::Fdtd {
+            return false
+        }
+        let tile = params.tiles()[ti];
+        let frequencies = params.frequencies();
+        buffer.resize(tile.area() * frequencies.len(), Complex::zero());
+
+        let current = (&self.generation, generation);
+        if render_tile(&scene, &params, &frequencies[..], rng, &mut buffer[..], tile, current) {
+            self.accumulate_cached(&params, generation, tile, &buffer[..]);
+        }
+        true
+    }
+
+    /// Return the params of a neighboring frequency and the index of a tile
+    /// to sample for it, if any tile needs samples.
+    ///
+    /// The claims go pass by pass, and every pass over all neighbors, so the
+    /// neighbors fill in together. A tile is skipped if the cache has the
+    /// samples already, for example for the frequency that was current before.
+    fn claim_precompute(&self) -> Option<(RenderParams, usize)> {
+        let p = self.params.lock().unwrap();
+        let neighbors = self.neighbors.lock().unwrap();
+        let cache = self.cache.lock().unwrap();
+        let tiles = p.tiles();
+        let per_pass = neighbors.len() * tiles.len();
+
+        loop {
+            let n = self.next_precompute.fetch_add(1, Ordering::SeqCst);
+            if n >= per_pass * PRECOMPUTE_PASSES {
+                return None
+            }
+            let mut params = *p;
+            params.frequency_hz = neighbors[n % per_pass / tiles.len()];
+            let ti = n % tiles.len();
+            let tile = tiles[ti];
+
+            let i = (tile.y * p.width + tile.x) as usize;
+            let passes = cache
+                .iter()
+                .find(|entry| entry.params == params)
+                .map_or(0, |entry| entry.stats[i].passes);
+            if passes as usize <= n / per_pass {
+                return Some((params, ti))
+            }
+        }
+    }
+
+    /// Add the samples in `buffer` for `tile` to the cached render for
+    /// `params`, or to a new one, unless the current params changed since
+    /// `generation`.
+    fn accumulate_cached(&self, params: &RenderParams, generation: usize, tile: Tile, buffer: &[Complex]) {
+        let _p = self.params.lock().unwrap();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return
+        }
 
-impl Renderer {
-    pub fn new() -> Renderer {
-        let params = RenderParams {
-            frequency_hz: 440.0,
+        // Read the precision under the cache lock, so a new entry cannot get
+        // sums in a precision that `set_precision()` is replacing.
+        let sums = self.sums.lock().unwrap();
+        let mut cache = self.cache.lock().unwrap();
+        let precision = sums.precision();
+        let n_frequencies = params.frequencies().len();
+        let i = match cache.iter().position(|entry| entry.params == *params) {
+            Some(i) => i,
+            None => {
+                cache.push_front(CachedRender {
+                    params: *params,
+                    buffer: vec![Complex::zero(); params.area() * n_frequencies],
+                    sums: Sums::new(precision, params.area() * n_frequencies),
+                    stats: vec![PixelStats::zero(); params.area()],
+                });
+                0
+            }
         };
 
-        let width = 1280;
-        let height = 720;
+        {
+            let entry = &mut cache[i];
+            for (k, tile_frame) in buffer.chunks(tile.area()).enumerate() {
+                for (dy, src_row) in tile_frame.chunks(tile.width as usize).enumerate() {
+                    let start = (tile.y as usize + dy) * params.width as usize + tile.x as usize;
+                    for (dx, &src) in src_row.iter().enumerate() {
+                        let i = start + dx;
+                        let j = k * params.area() + i;
+                        entry.sums.add(&mut entry.buffer[..], j, src, false);
+                        if k == n_frequencies / 2 {
+                            entry.stats[i].sum_squares += src.norm_squared();
+                        }
+                    }
+                }
+            }
+            for dy in 0..tile.height as usize {
+                let start = (tile.y as usize + dy) * params.width as usize + tile.x as usize;
+                for pixel in &mut entry.stats[start..start + tile.width as usize] {
+                    pixel.passes += 1;
+                }
+            }
+        }
+
+        evict(&mut cache);
+    }
 
-        let buffer: Vec<_> = iter::repeat(Complex::zero())
-            .take(width * height)
-            .collect();
+    /// Stop sampling until `set_paused(false)`, or resume.
+    ///
+    /// Unlike reaching the target uncertainty, this holds whatever changes.
+    /// Changes still discard the render, so it is empty when resumed.
+    pub fn set_paused(&self, paused: bool) {
+        self.paint_state.lock().unwrap().paused = paused;
+    }
 
-        Renderer {
-            scene: Scene::new_example(),
-            width: width as u32,
-            height: height as u32,
-            params: Mutex::new(params),
-            buffer: Mutex::new(buffer),
+    /// Return whether the render threads are paused, see `set_paused()`.
+    pub fn is_paused(&self) -> bool {
+        self.paint_state.lock().unwrap().paused
+    }
+
+    /// Let every render thread sample at most `fraction` of the time, between
+    /// 0 and 1, and sleep for the rest. At 1.0, the threads never sleep.
+    pub fn set_max_cpu(&self, fraction: f32) {
+        assert!(fraction > 0.0 && fraction <= 1.0, "The CPU fraction must be in (0, 1].");
+        self.paint_state.lock().unwrap().max_cpu = fraction;
+    }
+
+    /// Sleep after sampling for `busy`, as long as `set_max_cpu()` asks for.
+    fn throttle(&self, busy: Duration) {
+        let fraction = self.paint_state.lock().unwrap().max_cpu;
+        if fraction < 1.0 {
+            let busy_s = busy.as_secs() as f32 + busy.subsec_nanos() as f32 * 1e-9;
+            let sleep_s = busy_s * (1.0 - fraction) / fraction;
+            thread::sleep(Duration::from_micros((sleep_s * 1e6) as u64));
         }
     }
 
-    #[inline]
-    fn area(&self) -> usize {
-        self.width as usize * self.height as usize
+    /// Return whether every pixel has a sample of its own since the render was
+    /// last discarded. Until then, some pixels show the preview.
+    pub fn is_first_pass_complete(&self) -> bool {
+        self.paint_state.lock().unwrap().unsampled == 0
     }
 
-    pub fn clear(&self) {
-        let mut b = self.buffer.lock().unwrap();
-        for z in b.iter_mut() {
-            *z = Complex::zero();
+    /// Return the next row of preview blocks to sample, if the preview for
+    /// `params` is not complete yet.
+    fn claim_preview_row(&self, params: &RenderParams) -> Option<u32> {
+        // Claim with the params lock held, `clear()` resets the rows under it.
+        let p = self.params.lock().unwrap();
+        if *p != *params {
+            return None
         }
+        let row = self.preview_row.fetch_add(1, Ordering::SeqCst);
+        if row < p.preview_rows() as usize { Some(row as u32) } else { None }
     }
 
-    pub fn set_frequency(&self, f_hz: f32) {
-        let mut p = self.params.lock().unwrap();
-        p.frequency_hz = f_hz;
-        self.clear();
+    /// Sample the blocks in row `row` of the preview, and fill the pixels of
+    /// the blocks that have no samples yet.
+    ///
+    /// A block gets the mean of a sample at the center of each of its four
+    /// quarters, that is as large as a single sample, like the pixels that
+    /// the first pass fills in, but less noisy.
+    fn render_preview_row(&self, scene: &Scene, params: &RenderParams, rng: &mut Rng, row: u32) {
+        let y0 = row * PREVIEW_BLOCK;
+        let y1 = (y0 + PREVIEW_BLOCK).min(params.height);
+        let quarter = PREVIEW_BLOCK / 4;
+        let blocks: Vec<(u32, u32)> = (0..params.width)
+            .step_by(PREVIEW_BLOCK as usize)
+            .map(|x0| (x0, (x0 + PREVIEW_BLOCK).min(params.width)))
+            .collect();
+
+        let frequencies = params.frequencies();
+        let n_frequencies = frequencies.len();
+        let mut samples = vec![Complex::zero(); blocks.len() * n_frequencies];
+        let mut z = vec![Complex::zero(); n_frequencies];
+        for (bi, &(x0, x1)) in blocks.iter().enumerate() {
+            for &(dx, dy) in &[(1, 1), (3, 1), (1, 3), (3, 3)] {
+                let x = (x0 + dx * quarter).min(x1 - 1);
+                let y = (y0 + dy * quarter).min(y1 - 1);
+                scene.sample_at_frequencies(rng, &frequencies[..], params.position(scene, x, y), &mut z[..]);
+                for (k, &zk) in z.iter().enumerate() {
+                    let sample = &mut samples[k * blocks.len() + bi];
+                    *sample = *sample + zk * 0.25;
+                }
+            }
+        }
+
+        // Like `accumulate_move()`, merge only for the current params.
+        let p = self.params.lock().unwrap();
+        if *p != *params {
+            return
+        }
+        let mut b = self.buffer.lock().unwrap();
+        let stats = self.stats.lock().unwrap();
+        for (frame, frame_samples) in b.chunks_mut(p.area()).zip(samples.chunks(blocks.len())) {
+            for (&(x0, x1), &sample) in blocks.iter().zip(frame_samples) {
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        let i = (y * p.width + x) as usize;
+                        if stats[i].passes == 0 {
+                            frame[i] = sample;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut state = self.paint_state.lock().unwrap();
+        for (tile, dirty) in p.tiles().iter().zip(state.dirty.iter_mut()) {
+            if tile.y <= y0 && y0 < tile.y + tile.height {
+                *dirty = true;
+            }
+        }
     }
 
-    /// Add `buffer` to the internal buffer, and zero `buffer` itself.
+    /// Simulate the pressure over time, and show it, until the solver changes.
     ///
-    /// In a sense, move the density out of `buffer` into `self.buffer`. Takes
-    /// the render params to confirm that they are the same as the values that
-    /// `buffer` was filled for; we would not want to merge a stale render.
-    fn accumulate_move(&self, params: &RenderParams, buffer: &mut [Complex]) {
-        assert_eq!(buffer.len(), self.area());
+    /// Every frame advances the simulation by `FDTD_FRAME_DURATION`, and
+    /// replaces the first frame of the buffer with the pressure in the slice,
+    /// as the real part. The simulation restarts when the scene or viewport
+    /// changes, and when it reaches the time limit of the scene. While any
+    /// render thread is paused, or the renderer is, so is the simulation.
+    fn run_fdtd(&self, paused: &AtomicUsize) {
+        let mut simulation: Option<(RenderParams, Fdtd)> = None;
 
-        // Only accumulate if the values we want to add were computed for the
-        // same parameters.
-        {
-            let p = self.params.lock().unwrap();
-            if *p != *params {
+        loop {
+            let (params, scene) = self.get_params();
+            if scene.simulation.solver != Solver::Fdtd {
                 return
             }
+            if paused.load(Ordering::SeqCst) > 0 || self.is_paused() {
+                thread::sleep(FDTD_FRAME_INTERVAL);
+                continue
+            }
+
+            let duration = scene.simulation.max_time_ms.map(|t| t * 1e-3).unwrap_or(FDTD_DURATION);
+            let is_current = match simulation {
+                Some((p, ref fdtd)) => {
+                    p.scene_revision == params.scene_revision
+                        && p.viewport == params.viewport
+                        && fdtd.time() < duration
+                }
+                None => false,
+            };
+            if !is_current {
+                simulation = Some((params, Fdtd::new(&scene, scene.simulation.fdtd)));
+            }
+            let fdtd = &mut simulation.as_mut().unwrap().1;
+
+            let start = Instant::now();
+            let until = fdtd.time() + FDTD_FRAME_DURATION;
+            while fdtd.time() < until {
+                fdtd.step();
+            }
+            self.store_pressure(&params, &scene, fdtd);
+
+            if let Some(remaining) = FDTD_FRAME_INTERVAL.checked_sub(start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    }
+
+    /// Replace the buffer with the pressure in the slice, and mark every tile dirty.
+    ///
+    /// Like `accumulate_move()`, this does nothing if the params changed.
+    fn store_pressure(&self, params: &RenderParams, scene: &Scene, fdtd: &Fdtd) {
+        let p = self.params.lock().unwrap();
+        if p.scene_revision != params.scene_revision
+            || p.viewport != params.viewport
+            || (p.width, p.height) != (params.width, params.height)
+        {
+            return
         }
 
         let mut b = self.buffer.lock().unwrap();
-        assert_eq!(b.len(), buffer.len());
+        for z in b.iter_mut() {
+            *z = Complex::zero();
+        }
+        for y in 0..p.height {
+            for x in 0..p.width {
+                let i = (y * p.width + x) as usize;
+                b[i] = Complex::new(fdtd.pressure_at(p.position(scene, x, y)), 0.0);
+            }
+        }
 
-        for (dst, src) in b.iter_mut().zip(buffer.iter_mut()) {
-            *dst = *dst + *src;
-            *src = Complex::zero();
+        let mut state = self.paint_state.lock().unwrap();
+        for d in state.dirty.iter_mut() {
+            *d = true;
         }
+        state.pressure_time = Some(fdtd.time());
     }
 
-    pub fn run_render_loop(&self, seed: u64) {
-        let mut buffer: Vec<_> = iter::repeat(Complex::zero())
-            .take(self.area())
-            .collect();
+    /// With the time-domain solver, return the simulated time that is shown, in ms.
+    pub fn pressure_time_ms(&self) -> Option<f32> {
+        self.paint_state.lock().unwrap().pressure_time.map(|t| t * 1e3)
+    }
 
-        let mut rng = Rng::new(seed);
+    /// Return for every pixel whether it lies outside the room, or inside an obstacle.
+    fn exterior_mask(&self, scene: &Scene, params: &RenderParams) -> Vec<bool> {
+        let mut mask = Vec::with_capacity(params.area());
+        for y in 0..params.height {
+            for x in 0..params.width {
+                mask.push(!scene.contains(params.position(scene, x, y)));
+            }
+        }
+        mask
+    }
 
-        loop {
-            let params = self.params.lock().unwrap().clone();
-            render_one(&self.scene, &params, &mut rng, &mut buffer[..], self.width, self.height);
-            self.accumulate_move(&params, &mut buffer[..]);
+    /// Return log10 of the weighted RMS magnitude over the band at pixel `i`.
+    ///
+    /// For a single frequency, this is simply log10 of the magnitude.
+    fn magnitude_at(&self, params: &RenderParams, buffer: &[Complex], weights: &[f32], i: usize) -> f32 {
+        let area = params.area();
+        let mut power = 0.0;
+        for (k, w) in weights.iter().enumerate() {
+            power += w * buffer[k * area + i].norm_squared();
+        }
+        0.5 * power.log10()
+    }
+
+    /// Return the pressure at pixel (`x`, `y`) of `frame`, and its gradient
+    /// along the x and y axes of the image, per meter.
+    ///
+    /// The gradient is taken by central differences over `GRADIENT_STEP` of
+    /// the wavelength. Pixels outside the room have no field, and this returns
+    /// `None` for them. Next to a wall, the difference is one-sided.
+    fn gradient_at(&self, params: &RenderParams, frame: &[Complex], wavenumber: f32, x: u32, y: u32) -> Option<(Complex, Complex, Complex)> {
+        let meters_per_pixel = params.viewport.meters_per_pixel;
+        let at = |x: u32, y: u32| frame[(y * params.width + x) as usize];
+        let wavelength = 2.0 * std::f32::consts::PI / wavenumber;
+        let step = (GRADIENT_STEP * wavelength / meters_per_pixel).round().max(1.0) as u32;
+
+        let center = at(x, y);
+        if center.norm_squared() == 0.0 {
+            return None
+        }
+        let (x0, x1) = (x.saturating_sub(step), (x + step).min(params.width - 1));
+        let (y0, y1) = (y.saturating_sub(step), (y + step).min(params.height - 1));
+        let dx = derivative(at(x0, y), center, at(x1, y), x - x0, x1 - x);
+        let dy = derivative(at(x, y0), center, at(x, y1), y - y0, y1 - y);
+        let scale = meters_per_pixel.recip();
+        Some((center, dx * scale, dy * scale))
+    }
+
+    /// Return log10 of the weighted RMS particle velocity over the band at
+    /// pixel (`x`, `y`), times the impedance of air.
+    ///
+    /// The velocity is the gradient of the pressure over iωρ, so times ρc, it
+    /// is the gradient over ik, see `gradient_at()`. Only the components in
+    /// the plane of the slice are included.
+    fn velocity_at(&self, params: &RenderParams, buffer: &[Complex], weights: &[f32], wavenumbers: &[f32], x: u32, y: u32) -> f32 {
+        let area = params.area();
+        let mut power = 0.0;
+        for (k, (w, &wavenumber)) in weights.iter().zip(wavenumbers.iter()).enumerate() {
+            let frame = &buffer[k * area..(k + 1) * area];
+            if let Some((_, dx, dy)) = self.gradient_at(params, frame, wavenumber, x, y) {
+                power += w * (dx.norm_squared() + dy.norm_squared()) / (wavenumber * wavenumber);
+            }
+        }
+        0.5 * power.log10()
+    }
+
+    /// Return the weighted time-averaged intensity over the band at pixel
+    /// (`x`, `y`), along the x and y axes of the image, times the impedance of air.
+    ///
+    /// The intensity is ½ Re(p v*), the net flow of energy. In a standing wave
+    /// the pressure and velocity are a quarter period apart, and it is zero.
+    /// Times ρc, the velocity is ∇p / ik, so the intensity is -½ Im(p ∇p*) / k.
+    fn intensity_at(&self, params: &RenderParams, buffer: &[Complex], weights: &[f32], wavenumbers: &[f32], x: u32, y: u32) -> Option<Vec2> {
+        let area = params.area();
+        let mut intensity = Vec2::zero();
+        for (k, (w, &wavenumber)) in weights.iter().zip(wavenumbers.iter()).enumerate() {
+            let frame = &buffer[k * area..(k + 1) * area];
+            let (p, dx, dy) = self.gradient_at(params, frame, wavenumber, x, y)?;
+            let ix = -0.5 * (p * dx.conj()).imag() / wavenumber;
+            let iy = -0.5 * (p * dy.conj()).imag() / wavenumber;
+            intensity = intensity + Vec2::new(ix, iy) * *w;
         }
+        Some(intensity)
     }
 
-    pub fn get_exposure(&self, buffer: &[Complex], width: u32) -> f32 {
+    /// Return the magnitude that maps to the middle gray, in log10 units.
+    ///
+    /// This is the magnitude at the listener, so the image shows levels
+    /// relative to the listening position. Without a listener, it is the
+    /// magnitude 1 m in front of the speakers.
+    fn get_exposure(&self, params: &RenderParams, buffer: &[Complex], weights: &[f32]) -> f32 {
+        let width = params.width as i32;
+        let height = params.height as i32;
         let mut magnitude = 0.0;
         let mut n = 0.0;
 
-        for s in &self.scene.sources[..] {
-            // We want to get the magnitude at 1m in front of the speaker, and
-            // define that to be 0 dB.
-            let p = s.position + s.direction;
+        let scene = self.scene.lock().unwrap().clone();
+        let anchors: Vec<Vec3> = match scene.listener {
+            Some(ref listener) => vec![listener.position],
+            None => scene.sources.iter().map(|s| s.position + s.direction).collect(),
+        };
+        for p in anchors {
+            let (xi, yi) = params.viewport.to_pixel(params.project(p));
 
             for dx in &[-1, 0, 1] {
                 for dy in &[-1, 0, 1] {
-                    // TODO: Ensure in bounds.
-                    let xi = ((p.x + 0.5) / 0.008) as i32;
-                    let yi = ((p.y + 0.5) / 0.008) as i32;
-                    let i = ((yi + dy) * width as i32) + (xi + dx);
-
-                    magnitude += buffer[i as usize].norm().log10();
+                    let (x, y) = (xi + dx, yi + dy);
+                    if x < 0 || y < 0 || x >= width || y >= height {
+                        continue
+                    }
+                    let i = y * width + x;
+                    magnitude += self.magnitude_at(params, buffer, weights, i as usize);
                     n += 1.0;
                 }
             }
         }
 
-        magnitude / n
+        // If no anchor is in view, expose for a magnitude of 1.
+        if n == 0.0 { 0.0 } else { magnitude / n }
+    }
+
+    /// Tone-map the accumulated field into an RGB image.
+    ///
+    /// The image is stored row by row in `rgb`, with 3 bytes per pixel and no
+    /// padding between rows. Only tiles that received new samples since the
+    /// previous call are repainted, unless the exposure changed, so `rgb`
+    /// should be the same buffer on every call. Returns the tiles that were
+    /// repainted, the other pixels did not change, markers included.
+    ///
+    /// With `set_quantity()`, the image shows the particle velocity instead of
+    /// the pressure, with the same exposure, so the two can be compared. Or
+    /// it colors the pressure by its phase, see `hue_color()`, or it shows the
+    /// pressure at an instant that advances on every paint, see
+    /// `Quantity::Instantaneous`. Other than the phase, the levels are colored
+    /// with the colormap of `set_colormap()`, gray by default.
+    ///
+    /// With the time-domain solver, the image shows the signed pressure
+    /// instead, relative to the largest pressure in view: mid gray is zero,
+    /// positive pressure is lighter and negative pressure darker.
+    ///
+    /// If enabled with `set_show_contours()`, lines of equal level run every
+    /// 3 dB, see `draw_contours()`.
+    ///
+    /// If enabled with `set_show_intensity()`, arrows on a grid show the
+    /// direction of the net flow of energy, see `draw_intensity()`.
+    ///
+    /// If enabled with `set_show_wavelengths()`, circles at a quarter (solid)
+    /// and half (dashed) wavelength of the current frequency are drawn around
+    /// the sources in blue and around the listener in red.
+    pub fn paint(&self, rgb: &mut [u8]) -> Vec<Tile> {
+        let params = self.params.lock().unwrap();
+        assert_eq!(rgb.len(), params.area() * 3);
+
+        let buffer = self.buffer.lock().unwrap();
+        let weights = self.spectrum.weights(&params.frequencies()[..]);
+        assert_eq!(buffer.len(), params.area() * weights.len());
+
+        let mut state = self.paint_state.lock().unwrap();
+        let exposure = self.get_exposure(&params, &buffer[..], &weights[..]);
+
+        // If the exposure changed noticeably, every pixel changes, not only
+        // those in dirty tiles. Otherwise keep the old exposure, so tiles that
+        // we repaint match the tiles that we don't repaint.
+        let exposure = match state.exposure {
+            Some(e) if (e - exposure).abs() < EXPOSURE_TOLERANCE => e,
+            _ => {
+                for d in state.dirty.iter_mut() {
+                    *d = true;
+                }
+                state.exposure = Some(exposure);
+                exposure
+            }
+        };
+
+        // The arrows change with every sample, and we cannot erase them from
+        // tiles that we do not repaint.
+        let is_pressure = self.scene.lock().unwrap().simulation.solver == Solver::Fdtd;
+        let show_intensity = state.show_intensity && !is_pressure;
+        if show_intensity {
+            for d in state.dirty.iter_mut() {
+                *d = true;
+            }
+        }
+
+        // The instantaneous pressure is that of the middle frequency, every
+        // pixel changes on every paint.
+        let middle = &buffer[weights.len() / 2 * params.area()..(weights.len() / 2 + 1) * params.area()];
+        let is_instantaneous = state.quantity == Quantity::Instantaneous && !is_pressure;
+        let rotation = if is_instantaneous {
+            state.frame = (state.frame + 1) % OSCILLATION_FRAMES;
+            for d in state.dirty.iter_mut() {
+                *d = true;
+            }
+            Complex::exp_i(2.0 * std::f32::consts::PI * state.frame as f32 / OSCILLATION_FRAMES as f32)
+        } else {
+            Complex::new(1.0, 0.0)
+        };
+
+        // A contour line runs between pixels, so it changes with the pixels
+        // on both sides, also those in tiles that did not change. The signed
+        // pressure has no levels to draw contours of.
+        let show_contours = state.show_contours && !is_pressure && !is_instantaneous;
+        let mut levels_db = Vec::new();
+        if show_contours {
+            for d in state.dirty.iter_mut() {
+                *d = true;
+            }
+            levels_db = vec![std::f32::NAN; params.area()];
+        }
+
+        let peak_pressure = if is_pressure {
+            buffer[..params.area()]
+                .iter()
+                .map(|z| z.real().abs())
+                .filter(|p| p.is_finite())
+                .fold(0.0, f32::max)
+        } else if is_instantaneous {
+            // The peak over a whole period, so the brightness does not pulse.
+            middle
+                .iter()
+                .map(|z| z.norm())
+                .filter(|p| p.is_finite())
+                .fold(0.0, f32::max)
+        } else {
+            0.0
+        };
+
+        let c = self.scene.lock().unwrap().atmosphere.speed_of_sound();
+        let wavenumbers: Vec<f32> = params
+            .frequencies()
+            .iter()
+            .map(|f| 2.0 * std::f32::consts::PI * f / c)
+            .collect();
+        let quantity = state.quantity;
+        let colormap = state.colormap;
+        let mut painted = Vec::new();
+
+        // Which pixels lie outside the room only changes with the scene and
+        // the viewport, and testing every pixel is slow for a mesh.
+        let exterior = match state.exterior {
+            Some((ref p, ref mask)) if p.same_geometry(&params) => mask.clone(),
+            _ => {
+                let mask = Arc::new(self.exterior_mask(&self.scene.lock().unwrap(), &params));
+                state.exterior = Some((*params, mask.clone()));
+                for d in state.dirty.iter_mut() {
+                    *d = true;
+                }
+                mask
+            }
+        };
+
+        for (tile, dirty) in params.tiles().iter().zip(state.dirty.iter_mut()) {
+            if !*dirty {
+                continue
+            }
+            *dirty = false;
+            painted.push(*tile);
+
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    let i = (y * params.width + x) as usize;
+                    let color = if exterior[i] {
+                        if (x + y) % HATCH_SPACING == 0 { HATCH_COLOR } else { EXTERIOR_COLOR }
+                    } else if is_pressure {
+                        colormap.color(pressure_level(buffer[i].real(), peak_pressure))
+                    } else if is_instantaneous {
+                        colormap.color(pressure_level((middle[i] * rotation).real(), peak_pressure))
+                    } else {
+                        let magnitude = match quantity {
+                            Quantity::Pressure | Quantity::Phase | Quantity::Instantaneous => {
+                                self.magnitude_at(&params, &buffer[..], &weights[..], i)
+                            }
+                            Quantity::Velocity => {
+                                self.velocity_at(&params, &buffer[..], &weights[..], &wavenumbers[..], x, y)
+                            }
+                        };
+                        if show_contours {
+                            levels_db[i] = 20.0 * (magnitude - exposure);
+                        }
+                        let r = gray_level(magnitude - exposure);
+                        match quantity {
+                            Quantity::Phase => hue_color(middle[i].arg(), r),
+                            _ => colormap.color(r),
+                        }
+                    };
+
+                    let pixel = &mut rgb[i * 3..i * 3 + 3];
+                    pixel.copy_from_slice(&color);
+                }
+            }
+        }
+
+        if !painted.is_empty() && show_contours {
+            draw_contours(rgb, params.width, params.height, &levels_db[..]);
+        }
+
+        // Tiles that we repainted may have covered the arcs and markers.
+        if !painted.is_empty() && state.show_wavelengths {
+            // At a quarter wavelength from a wall, the reflection arrives half
+            // a period late and cancels the direct sound; at half a wavelength
+            // it arrives a full period late and sums.
+            let scene = self.scene.lock().unwrap().clone();
+            let wavelength = scene.atmosphere.speed_of_sound() / params.frequency_hz;
+            let sources = scene.sources.iter().map(|s| (s.position, SOURCE_ARC_COLOR));
+            let listener = scene.listener.iter().map(|l| (l.position, MARKER_COLOR));
+            for (p, color) in sources.chain(listener) {
+                for q in params.projections(p) {
+                    let (x, y) = params.viewport.to_pixel(q);
+                    let r_quarter = 0.25 * wavelength / params.viewport.meters_per_pixel;
+                    draw_ring(rgb, params.width, params.height, x, y, r_quarter, color, false);
+                    draw_ring(rgb, params.width, params.height, x, y, 2.0 * r_quarter, color, true);
+                }
+            }
+        }
+        if !painted.is_empty() && show_intensity {
+            self.draw_intensity(&params, rgb, &buffer[..], &weights[..], &wavenumbers[..]);
+        }
+        if !painted.is_empty() {
+            let scene = self.scene.lock().unwrap();
+            let to_pixel = |p: Vec2| (p - params.viewport.origin) * params.viewport.meters_per_pixel.recip();
+            // The plans at their heights, with the offset of their cell. In a
+            // section, the hatching outside the room shows the walls.
+            let plans: Vec<(f32, Vec2)> = match params.view {
+                View::Plan => vec![(params.slice_height, Vec2::zero())],
+                View::Section(..) => Vec::new(),
+                View::Heights { heights, .. } => (0..HEIGHTS_CELLS).map(|k| (heights[k], params.cell_offset(k))).collect(),
+            };
+            for &(height, offset) in &plans {
+                for (p0, p1) in scene.outline(height) {
+                    draw_line(rgb, params.width, params.height, to_pixel(p0 + offset), to_pixel(p1 + offset), WALL_COLOR);
+                }
+            }
+
+            // The sources with an arrow in the direction they are aimed, in
+            // the plane of the image. A source aimed straight at the viewer
+            // has no arrow.
+            for source in &scene.sources {
+                let aim = match params.view {
+                    View::Section(..) => params.project(source.position + source.direction) - params.project(source.position),
+                    _ => Vec2::new(source.direction.x, source.direction.y),
+                };
+                for p in params.projections(source.position) {
+                    let (x, y) = params.viewport.to_pixel(p);
+                    draw_marker(rgb, params.width, params.height, x, y, SOURCE_ARC_COLOR);
+                    if aim.norm() > 0.1 {
+                        // Start just outside the ring of the marker.
+                        let unit = aim * aim.norm().recip();
+                        let start = to_pixel(p) + unit * 8.0;
+                        draw_arrow(rgb, params.width, params.height, start, start + unit * AIM_ARROW_LENGTH, SOURCE_ARC_COLOR);
+                    }
+                }
+            }
+
+            if let Some(ref listener) = scene.listener {
+                for p in params.projections(listener.position) {
+                    let (x, y) = params.viewport.to_pixel(p);
+                    draw_marker(rgb, params.width, params.height, x, y, MARKER_COLOR);
+                }
+                if let Some(ref area) = listener.area {
+                    for &(_, offset) in &plans {
+                        let p0 = params.viewport.to_pixel(area.min + offset);
+                        let p1 = params.viewport.to_pixel(area.max + offset);
+                        draw_rectangle(rgb, params.width, params.height, p0, p1);
+                    }
+                }
+            }
+
+            // The probes in the colors of their curves in the response panel.
+            for (i, probe) in scene.probes.iter().enumerate() {
+                for p in params.projections(probe.position) {
+                    let (x, y) = params.viewport.to_pixel(p);
+                    draw_marker(rgb, params.width, params.height, x, y, probe::color(i + 1).0);
+                }
+            }
+
+            if let Some(p) = state.pin {
+                let (x, y) = params.viewport.to_pixel(p);
+                draw_marker(rgb, params.width, params.height, x, y, PIN_COLOR);
+            }
+        }
+
+        painted
+    }
+
+    /// Draw an arrow every `ARROW_SPACING` pixels in the direction of the
+    /// intensity, averaged over the square around it.
+    ///
+    /// The longest arrow in view spans most of the spacing. The length grows
+    /// with the square root of the intensity, so the weaker flows remain
+    /// visible. Where the arrows are short, standing waves dominate.
+    fn draw_intensity(&self, params: &RenderParams, rgb: &mut [u8], buffer: &[Complex], weights: &[f32], wavenumbers: &[f32]) {
+        // Average over every cell of the grid, the gradients of single
+        // pixels are noisy while the Monte Carlo sum converges.
+        let mut arrows = Vec::new();
+        for y0 in (0..params.height).step_by(ARROW_SPACING as usize) {
+            for x0 in (0..params.width).step_by(ARROW_SPACING as usize) {
+                let mut sum = Vec2::zero();
+                let mut n = 0;
+                for y in (y0..(y0 + ARROW_SPACING).min(params.height)).step_by(ARROW_SUBSAMPLING as usize) {
+                    for x in (x0..(x0 + ARROW_SPACING).min(params.width)).step_by(ARROW_SUBSAMPLING as usize) {
+                        if let Some(intensity) = self.intensity_at(params, buffer, weights, wavenumbers, x, y) {
+                            sum = sum + intensity;
+                            n += 1;
+                        }
+                    }
+                }
+                if n > 0 {
+                    let center = (x0 + ARROW_SPACING / 2, y0 + ARROW_SPACING / 2);
+                    arrows.push((center.0, center.1, sum * (1.0 / n as f32)));
+                }
+            }
+        }
+
+        let max_intensity = arrows.iter().map(|a| a.2.norm()).filter(|i| i.is_finite()).fold(0.0, f32::max);
+        if max_intensity == 0.0 {
+            return
+        }
+        let max_length = 0.9 * ARROW_SPACING as f32;
+        for &(x, y, intensity) in &arrows {
+            let norm = intensity.norm();
+            if !(norm > 0.0) {
+                continue
+            }
+            let length = max_length * (norm / max_intensity).sqrt();
+            let d = intensity * (0.5 * length / norm);
+            let center = Vec2::new(x as f32, y as f32);
+            draw_arrow(rgb, params.width, params.height, center - d, center + d, ARROW_COLOR);
+        }
+    }
+
+    /// Return the point in meters at the center of pixel (`x`, `y`) of the
+    /// image, in the coordinates of the viewport, see `View`.
+    pub fn to_world(&self, x: u32, y: u32) -> Vec2 {
+        self.params.lock().unwrap().viewport.to_world(x, y)
+    }
+
+    /// Return the point in the scene that pixel `p` of the image shows, where
+    /// `p` is in the coordinates of the viewport, or `None` outside the image.
+    pub fn position_at(&self, p: Vec2) -> Option<Vec3> {
+        let params = self.params.lock().unwrap();
+        let (x, y) = params.viewport.to_pixel(p);
+        if x < 0 || y < 0 || x >= params.width as i32 || y >= params.height as i32 {
+            return None
+        }
+        Some(params.position(&self.scene.lock().unwrap(), x as u32, y as u32))
+    }
+
+    /// Return the level at the pixel that contains `p`, as it was painted.
+    ///
+    /// The level is in dB relative to the exposure of the last paint, so it is
+    /// relative to the listener position, of the pressure or of the particle
+    /// velocity, whichever the image shows. Points outside the image or the
+    /// room have no level, and neither does the time-domain solver.
+    pub fn level_at(&self, p: Vec2) -> Option<f32> {
+        if self.scene.lock().unwrap().simulation.solver == Solver::Fdtd {
+            return None
+        }
+        let params = self.params.lock().unwrap();
+        let buffer = self.buffer.lock().unwrap();
+        let weights = self.spectrum.weights(&params.frequencies()[..]);
+        let state = self.paint_state.lock().unwrap();
+        let exposure = state.exposure?;
+
+        let (x, y) = params.viewport.to_pixel(p);
+        if x < 0 || y < 0 || x >= params.width as i32 || y >= params.height as i32 {
+            return None
+        }
+        let (x, y) = (x as u32, y as u32);
+        let magnitude = match state.quantity {
+            Quantity::Pressure | Quantity::Phase | Quantity::Instantaneous => {
+                self.magnitude_at(&params, &buffer[..], &weights[..], (y * params.width + x) as usize)
+            }
+            Quantity::Velocity => {
+                let c = self.scene.lock().unwrap().atmosphere.speed_of_sound();
+                let wavenumbers: Vec<f32> = params
+                    .frequencies()
+                    .iter()
+                    .map(|f| 2.0 * std::f32::consts::PI * f / c)
+                    .collect();
+                self.velocity_at(&params, &buffer[..], &weights[..], &wavenumbers[..], x, y)
+            }
+        };
+
+        let level = 20.0 * (magnitude - exposure);
+        if level.is_finite() { Some(level) } else { None }
     }
 
-    pub fn paint(&self, pixbuf: &mut gdk::Pixbuf) {
+    /// Return statistics of the level over the listening area, if there is one.
+    ///
+    /// Levels are in dB relative to the exposure of the last paint, so they
+    /// are relative to the listener position. The time-domain solver has no
+    /// levels.
+    pub fn area_stats(&self) -> Option<AreaStats> {
+        if self.scene.lock().unwrap().simulation.solver == Solver::Fdtd {
+            return None
+        }
+        let params = self.params.lock().unwrap();
+        if params.view != View::Plan {
+            return None
+        }
         let buffer = self.buffer.lock().unwrap();
-        assert_eq!(buffer.len(), self.area());
+        let weights = self.spectrum.weights(&params.frequencies()[..]);
+        let exposure = self.paint_state.lock().unwrap().exposure?;
+
+        let scene = self.scene.lock().unwrap().clone();
+        let area = scene.listener.as_ref()?.area?;
+        let (x0, y0) = params.viewport.to_pixel(area.min);
+        let (x1, y1) = params.viewport.to_pixel(area.max);
+
+        let mut levels = Vec::new();
+        for y in y0.max(0)..(y1 + 1).min(params.height as i32) {
+            for x in x0.max(0)..(x1 + 1).min(params.width as i32) {
+                let i = (y * params.width as i32 + x) as usize;
+                let level = 20.0 * (self.magnitude_at(&params, &buffer[..], &weights[..], i) - exposure);
+                // Points outside the room, or not yet sampled, have no level.
+                if level.is_finite() {
+                    levels.push(level);
+                }
+            }
+        }
+
+        if levels.is_empty() {
+            return None
+        }
+
+        // Average the power, not the level in dB, which would be dominated by
+        // the deep nulls.
+        let n = levels.len() as f32;
+        let mean_power = levels.iter().map(|l| 10.0_f32.powf(l / 10.0)).sum::<f32>() / n;
+        let mean_level = levels.iter().sum::<f32>() / n;
+        let var = levels.iter().map(|l| (l - mean_level) * (l - mean_level)).sum::<f32>() / n;
+
+        Some(AreaStats {
+            mean_db: 10.0 * mean_power.log10(),
+            std_db: var.sqrt(),
+            min_db: levels.iter().cloned().fold(std::f32::INFINITY, f32::min),
+            max_db: levels.iter().cloned().fold(std::f32::NEG_INFINITY, f32::max),
+        })
+    }
+}
 
-        let exposure = self.get_exposure(&buffer[..], self.width);
+/// Return the derivative of the field at a pixel per pixel, from the field
+/// `d_minus` pixels before it, at the pixel itself, and `d_plus` pixels after it.
+///
+/// Pixels without field, outside the room, are left out.
+fn derivative(minus: Complex, center: Complex, plus: Complex, d_minus: u32, d_plus: u32) -> Complex {
+    let has_minus = d_minus > 0 && minus.norm_squared() > 0.0;
+    let has_plus = d_plus > 0 && plus.norm_squared() > 0.0;
+    match (has_minus, has_plus) {
+        (true, true) => (plus - minus) * (1.0 / (d_minus + d_plus) as f32),
+        (false, true) => (plus - center) * (1.0 / d_plus as f32),
+        (true, false) => (center - minus) * (1.0 / d_minus as f32),
+        (false, false) => Complex::zero(),
+    }
+}
+
+/// Map a signed pressure relative to the peak pressure to a gray level.
+///
+/// The square root makes the weaker reflections visible next to the direct
+/// sound. Points outside the space, where the pressure is NaN, are black.
+fn pressure_level(pressure: f32, peak: f32) -> u8 {
+    if pressure.is_nan() {
+        return 0
+    }
+    let t = if peak > 0.0 { (pressure.abs() / peak).sqrt() } else { 0.0 };
+    let rf = (0.5 + 0.5 * t.min(1.0) * pressure.signum()).max(0.0).min(1.0);
+    (rf * 255.0) as u8
+}
+
+/// Map a magnitude in log10 units relative to the exposure to a gray level.
+///
+/// The exposure is middle gray, black and white are 50 dB below and above it.
+pub fn gray_level(magnitude: f32) -> u8 {
+    let rf = (0.5 + magnitude * 0.2).max(0.0).min(1.0);
+    (rf * 255.0) as u8
+}
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let i = y * self.width + x;
+/// Color a phase in radians as a hue, at the lightness of a gray level.
+///
+/// The hues go around the color wheel once per turn, from red at zero phase
+/// through yellow, green, cyan, blue, and magenta. The colors are most
+/// saturated at middle gray, and fade to black and white at the extremes of
+/// the level, as in the HSL color model.
+pub fn hue_color(phase: f32, lightness: u8) -> [u8; 3] {
+    let l = lightness as f32 / 255.0;
+    let chroma = 1.0 - (2.0 * l - 1.0).abs();
+    let turns = phase / (2.0 * std::f32::consts::PI);
+    let h = (turns - turns.floor()) * 6.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = l - 0.5 * chroma;
+    let to_byte = |c: f32| ((c + m).max(0.0).min(1.0) * 255.0) as u8;
+    [to_byte(r), to_byte(g), to_byte(b)]
+}
 
-                let magnitude = buffer[i as usize].norm().log10() - exposure;
-                let rf = (0.5 + magnitude * 0.2).max(0.0).min(1.0);
+/// Draw a contour line every `CONTOUR_STEP_DB` into `rgb`, given the level
+/// in dB relative to the listener of every pixel.
+///
+/// A pixel is on a line if a multiple of the step lies between its level and
+/// that of the pixel to the right or below. Pixels where the level is not
+/// finite, such as those outside the space, have no lines.
+fn draw_contours(rgb: &mut [u8], width: u32, height: u32, levels_db: &[f32]) {
+    let band = |db: f32| (db / CONTOUR_STEP_DB).floor() as i32;
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            let a = levels_db[i];
+            if !a.is_finite() {
+                continue
+            }
+            let right = if x + 1 < width { levels_db[i + 1] } else { std::f32::NAN };
+            let below = if y + 1 < height { levels_db[i + width as usize] } else { std::f32::NAN };
+            for &b in [right, below].iter().filter(|b| b.is_finite()) {
+                if band(a) != band(b) {
+                    // Between bands -1 and 0 lies the level of the listener.
+                    let is_reference = band(a).max(band(b)) == 0;
+                    let color = if is_reference { MARKER_COLOR } else { CONTOUR_COLOR };
+                    rgb[i * 3..i * 3 + 3].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
 
-                let r = (rf * 255.0) as u8;
-                let g = r;
-                let b = r;
-                let a = 255;
+/// Draw the outline of the rectangle between two corner pixels into `rgb`.
+fn draw_rectangle(rgb: &mut [u8], width: u32, height: u32, p0: (i32, i32), p1: (i32, i32)) {
+    let mut set = |x: i32, y: i32| {
+        if x >= 0 && y >= 0 && x < width as i32 && y < height as i32 {
+            let i = (y * width as i32 + x) as usize;
+            rgb[i * 3..i * 3 + 3].copy_from_slice(&MARKER_COLOR);
+        }
+    };
+    let (x0, x1) = (p0.0.min(p1.0), p0.0.max(p1.0));
+    let (y0, y1) = (p0.1.min(p1.1), p0.1.max(p1.1));
+    for x in x0..x1 + 1 {
+        set(x, y0);
+        set(x, y1);
+    }
+    for y in y0..y1 + 1 {
+        set(x0, y);
+        set(x1, y);
+    }
+}
 
-                pixbuf.put_pixel(x as i32, y as i32, r, g, b, a);
+/// Draw a ring around pixel (`x`, `y`) into `rgb`, to mark the listener or a probe.
+pub fn draw_marker(rgb: &mut [u8], width: u32, height: u32, x: i32, y: i32, color: [u8; 3]) {
+    let (r_inner, r_outer) = (5, 7);
+    for dy in -r_outer..r_outer + 1 {
+        for dx in -r_outer..r_outer + 1 {
+            let d2 = dx * dx + dy * dy;
+            let (px, py) = (x + dx, y + dy);
+            if d2 < r_inner * r_inner || d2 > r_outer * r_outer {
+                continue
+            }
+            if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+                continue
             }
+            let i = (py * width as i32 + px) as usize;
+            rgb[i * 3..i * 3 + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+/// Draw a circle of radius `r` pixels around pixel (`x`, `y`) into `rgb`.
+///
+/// A dashed circle alternates 6 pixels on and 6 pixels off.
+fn draw_ring(rgb: &mut [u8], width: u32, height: u32, x: i32, y: i32, r: f32, color: [u8; 3], dashed: bool) {
+    // Step about half a pixel along the circumference, so it has no gaps.
+    let n = (4.0 * std::f32::consts::PI * r).ceil().max(8.0) as u32;
+    for i in 0..n {
+        if dashed && (i / 12) % 2 == 1 {
+            continue
+        }
+        let t = 2.0 * std::f32::consts::PI * i as f32 / n as f32;
+        let px = x + (r * t.cos()).round() as i32;
+        let py = y + (r * t.sin()).round() as i32;
+        if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+            continue
+        }
+        let i = (py * width as i32 + px) as usize;
+        rgb[i * 3..i * 3 + 3].copy_from_slice(&color);
+    }
+}
+
+/// Draw a line from `p0` to `p1` into `rgb`, with an arrowhead at `p1`.
+fn draw_arrow(rgb: &mut [u8], width: u32, height: u32, p0: Vec2, p1: Vec2, color: [u8; 3]) {
+    let d = p1 - p0;
+    let length = d.norm();
+    if length < 1.0 {
+        return
+    }
+
+    // The head is two lines back from the tip, at 30 degrees from the shaft.
+    let back = d * (-1.0 / length);
+    let head = (0.3 * length).max(3.0);
+    let (sin, cos) = (0.5, 0.866);
+    let left = Vec2::new(back.x * cos - back.y * sin, back.x * sin + back.y * cos);
+    let right = Vec2::new(back.x * cos + back.y * sin, -back.x * sin + back.y * cos);
+    for &(a, b) in &[(p0, p1), (p1, p1 + left * head), (p1, p1 + right * head)] {
+        draw_line(rgb, width, height, a, b, color);
+    }
+}
+
+/// Draw a line from `p0` to `p1`, in pixel coordinates, into `rgb`.
+fn draw_line(rgb: &mut [u8], width: u32, height: u32, p0: Vec2, p1: Vec2, color: [u8; 3]) {
+    // Step half a pixel at a time, so the line has no gaps.
+    let n = (p1 - p0).norm().ceil().max(1.0) as u32 * 2;
+    for i in 0..n + 1 {
+        let p = p0 + (p1 - p0) * (i as f32 / n as f32);
+        let (px, py) = (p.x.round() as i32, p.y.round() as i32);
+        if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+            continue
         }
+        let j = (py * width as i32 + px) as usize;
+        rgb[j * 3..j * 3 + 3].copy_from_slice(&color);
     }
 }
 
-fn render_one(
+/// Drop the least recently used renders from the cache, until it takes at
+/// most `CACHE_BYTES`.
+fn evict(cache: &mut VecDeque<CachedRender>) {
+    let mut total_bytes = 0;
+    let n_keep = cache
+        .iter()
+        .take_while(|entry| {
+            total_bytes += entry.size_bytes();
+            total_bytes <= CACHE_BYTES
+        })
+        .count();
+    cache.truncate(n_keep);
+}
+
+/// Return the number of rows to sample next, given how long `rows` rows took.
+///
+/// The number grows by at most a factor two at a time, so one quick chunk
+/// does not cause a very slow one.
+fn adapt_chunk_rows(rows: u32, elapsed: Duration) -> u32 {
+    let elapsed_s = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+    let target_s = CHUNK_DURATION.as_millis() as f32 * 1e-3;
+    let ideal = rows as f32 * target_s / elapsed_s.max(1e-6);
+    (ideal as u32).max(1).min(rows * 2).min(TILE_SIZE)
+}
+
+/// Sample the field for every pixel in the tile into `buffer`, which holds a
+/// frame per frequency in `frequencies`. The frequencies share the paths.
+///
+/// The params are those of generation `current.1` of the renderer, the
+/// counter is `current.0`. When it moves on, the params changed, and the
+/// samples would be discarded, so we stop after the row, and return false.
+fn render_tile(
     scene: &Scene,
     params: &RenderParams,
+    frequencies: &[f32],
     rng: &mut Rng,
     buffer: &mut [Complex],
-    width: u32,
-    height: u32,
-) {
-    for y in 0..height {
-        let ym = y as f32 * 0.008;
-
-        for x in 0..width {
-            let i = (y * width + x) as usize;
+    tile: Tile,
+    current: (&AtomicUsize, usize),
+) -> bool {
+    assert_eq!(buffer.len(), tile.area() * frequencies.len());
+    let mut z = vec![Complex::zero(); frequencies.len()];
+    for y in 0..tile.height {
+        for x in 0..tile.width {
+            let i = (y * tile.width + x) as usize;
 
-            let xm = x as f32 * 0.008;
-            let position = Vec3::new(xm - 0.5, ym - 0.5, 1.0);
-            buffer[i] = scene.sample_at(rng, params.frequency_hz, position);
+            let position = params.position(scene, tile.x + x, tile.y + y);
+            scene.sample_at_frequencies(rng, frequencies, position, &mut z[..]);
+            for (k, &zk) in z.iter().enumerate() {
+                buffer[k * tile.area() + i] = zk;
+            }
+        }
+        if current.0.load(Ordering::SeqCst) != current.1 {
+            return false
         }
     }
+    true
 }
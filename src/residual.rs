@@ -0,0 +1,86 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::plot::{self, Canvas, Color};
+use crate::project::Measurement;
+use crate::rand::Rng;
+use crate::renderer::Viewport;
+use crate::response;
+use crate::scene::Scene;
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// The number of Monte Carlo samples for the simulated level at a measurement.
+const N_SAMPLES: u32 = 4096;
+
+/// Residuals of this many dB or more get the most saturated color.
+const RANGE_DB: f32 = 10.0;
+
+/// Return measured minus simulated level in dB at every measurement.
+///
+/// The level of the simulation is arbitrary, so the residuals are relative to
+/// their mean: only the differences between positions are meaningful. A
+/// positive residual means that the room is louder there than the model says.
+pub fn compute(scene: &Scene, measurements: &[Measurement], frequency: f32, seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let raw: Vec<f32> = measurements
+        .iter()
+        .map(|m| {
+            let simulated = response::sample_response(scene, &mut rng, m.position, &[frequency], N_SAMPLES)[0];
+            response::to_db(m.response.at(frequency)) - response::to_db(simulated)
+        })
+        .collect();
+    let mean = raw.iter().sum::<f32>() / raw.len() as f32;
+    raw.iter().map(|r| r - mean).collect()
+}
+
+/// Interpolate the residuals at `p` by inverse distance weighting.
+fn interpolate(measurements: &[Measurement], residuals: &[f32], p: Vec2) -> f32 {
+    let mut sum = 0.0;
+    let mut total_weight = 0.0;
+    for (m, &r) in measurements.iter().zip(residuals) {
+        let d = Vec2::new(m.position.x, m.position.y) - p;
+        let w = d.norm_squared().max(1e-6).recip();
+        sum += w * r;
+        total_weight += w;
+    }
+    sum / total_weight
+}
+
+/// Return white for zero, shading to red for positive and blue for negative residuals.
+fn residual_color(residual_db: f32) -> Color {
+    let t = (residual_db / RANGE_DB).max(-1.0).min(1.0);
+    let target = if t > 0.0 { plot::RED } else { plot::BLUE };
+    plot::lerp_color(plot::WHITE, target, t.abs())
+}
+
+/// Paint the interpolated residuals over the floor plan, seen from above.
+///
+/// Points outside of the space are gray, measurement positions are circled.
+/// The map is a slice at the mean height of the measurements.
+pub fn paint_map(scene: &Scene, measurements: &[Measurement], residuals: &[f32], width: u32, height: u32) -> Canvas {
+    let mut canvas = Canvas::new(width, height, plot::GRID);
+    let viewport = Viewport::fit(scene, width, height);
+    let z = measurements.iter().map(|m| m.position.z).sum::<f32>() / measurements.len() as f32;
+
+    for y in 0..height {
+        for x in 0..width {
+            let p = viewport.to_world(x, y);
+            if scene.contains(Vec3::new(p.x, p.y, z)) {
+                let color = residual_color(interpolate(measurements, residuals, p));
+                canvas.set_pixel(x as i32, y as i32, color);
+            }
+        }
+    }
+
+    for m in measurements {
+        let (x, y) = viewport.to_pixel(Vec2::new(m.position.x, m.position.y));
+        canvas.draw_circle(x as f32 + 0.5, y as f32 + 0.5, 3.0, plot::BLACK);
+    }
+
+    canvas
+}
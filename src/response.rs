@@ -0,0 +1,182 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::complex::Complex;
+use crate::rand::Rng;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// Return logarithmically spaced frequencies from `f_min` up to `f_max`.
+///
+/// There are `steps_per_octave` frequencies per octave, and both endpoints are
+/// included.
+pub fn log_frequencies(f_min: f32, f_max: f32, steps_per_octave: u32) -> Vec<f32> {
+    let n_octaves = (f_max / f_min).log2();
+    let n = (n_octaves * steps_per_octave as f32).ceil().max(1.0) as u32;
+    (0..n + 1)
+        .map(|i| f_min * (f_max / f_min).powf(i as f32 / n as f32))
+        .collect()
+}
+
+/// Convert a complex amplitude into a level in dB.
+pub fn to_db(z: Complex) -> f32 {
+    20.0 * z.norm().log10()
+}
+
+/// Estimate the response of all sources together at `position`, at every frequency.
+pub fn sample_response(
+    scene: &Scene,
+    rng: &mut Rng,
+    position: Vec3,
+    frequencies: &[f32],
+    n_samples: u32,
+) -> Vec<Complex> {
+    let mut total = vec![Complex::zero(); frequencies.len()];
+    for si in 0..scene.sources.len() {
+        let r = sample_source_response(scene, rng, si, position, frequencies, n_samples);
+        for (acc, z) in total.iter_mut().zip(r) {
+            *acc = *acc + z;
+        }
+    }
+    total
+}
+
+/// Estimate the response of source `si` at `position`, at every frequency.
+///
+/// Every frequency is estimated by averaging `n_samples` Monte Carlo samples.
+/// The frequencies share the sampled paths, see
+/// `Scene::sample_source_at_frequencies()`.
+pub fn sample_source_response(
+    scene: &Scene,
+    rng: &mut Rng,
+    si: usize,
+    position: Vec3,
+    frequencies: &[f32],
+    n_samples: u32,
+) -> Vec<Complex> {
+    let weight = (n_samples as f32).recip();
+    let mut total = vec![Complex::zero(); frequencies.len()];
+    let mut z = vec![Complex::zero(); frequencies.len()];
+    for _ in 0..n_samples {
+        scene.sample_source_at_frequencies(rng, si, frequencies, position, &mut z[..]);
+        for (acc, &zk) in total.iter_mut().zip(&z) {
+            *acc = *acc + zk;
+        }
+    }
+    total.into_iter().map(|acc| acc * weight).collect()
+}
+
+/// Estimate the response of source `si` at every seat, concatenated.
+///
+/// The result holds the response at `frequencies` for the first seat, then
+/// for the second seat, and so on.
+pub fn sample_seats(
+    scene: &Scene,
+    rng: &mut Rng,
+    si: usize,
+    seats: &[Vec3],
+    frequencies: &[f32],
+    n_samples: u32,
+) -> Vec<Complex> {
+    let mut result = Vec::with_capacity(seats.len() * frequencies.len());
+    for &seat in seats {
+        result.extend(sample_source_response(scene, rng, si, seat, frequencies, n_samples));
+    }
+    result
+}
+
+/// Return the unwrapped phase of a response at increasing frequencies, in degrees.
+///
+/// A delay makes the phase lag, so it decreases with frequency, as in
+/// measurement programs. The field has the opposite sign, see
+/// `ImpulseResponse::synthesize()`. A jump of more than half a turn between
+/// neighbouring frequencies is taken to wrap around, so the frequencies must
+/// be close enough together that the true phase changes less than that.
+pub fn unwrapped_phase_deg(response: &[Complex]) -> Vec<f32> {
+    let mut phase = Vec::with_capacity(response.len());
+    let mut turns = 0.0;
+    let mut prev: Option<f32> = None;
+    for z in response {
+        let wrapped = -z.arg().to_degrees();
+        if let Some(p) = prev {
+            turns += ((p - wrapped) / 360.0).round();
+        }
+        prev = Some(wrapped);
+        phase.push(wrapped + 360.0 * turns);
+    }
+    phase
+}
+
+/// Return the group delay in ms, minus the derivative of the phase with
+/// respect to the angular frequency.
+///
+/// This is the delay of the envelope of a narrow band around every
+/// frequency. Around a mode, the energy keeps ringing, which shows as a peak
+/// in the group delay. The derivative is a central difference, one-sided at
+/// the ends.
+pub fn group_delay_ms(frequencies: &[f32], phase_deg: &[f32]) -> Vec<f32> {
+    let n = frequencies.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (i.max(1) - 1, (i + 1).min(n - 1));
+            if a == b {
+                return 0.0
+            }
+            -(phase_deg[b] - phase_deg[a]) / (360.0 * (frequencies[b] - frequencies[a])) * 1e3
+        })
+        .collect()
+}
+
+/// Return the standard deviation of the level in dB over all seats and frequencies.
+///
+/// This captures both the ripple of the response at a seat, and the
+/// differences between seats. Lower is flatter.
+pub fn flatness_db(responses: &[Complex]) -> f32 {
+    let levels: Vec<f32> = responses.iter().map(|&z| to_db(z)).collect();
+    let n = levels.len() as f32;
+    let mean = levels.iter().sum::<f32>() / n;
+    let var = levels.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+    var.sqrt()
+}
+
+/// How much to smooth a response before plotting it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Smoothing {
+    Off,
+
+    /// Smooth over a fraction 1/n of an octave.
+    Fraction(u32),
+}
+
+/// Smooth levels in dB at increasing `frequencies` over a fraction of an octave.
+///
+/// Every level becomes the power average of the levels within 1/2n octave on
+/// either side of its frequency, as measurement programs such as REW smooth
+/// their responses, so narrow peaks and dips are evened out while the broad
+/// shape remains.
+pub fn smooth_db(frequencies: &[f32], levels_db: &[f32], smoothing: Smoothing) -> Vec<f32> {
+    let n = match smoothing {
+        Smoothing::Off => return levels_db.to_vec(),
+        Smoothing::Fraction(n) => n,
+    };
+    let half_width = 2.0_f32.powf(0.5 / n as f32);
+    frequencies
+        .iter()
+        .map(|&f| {
+            let (lo, hi) = (f / half_width, f * half_width);
+            let mut power = 0.0;
+            let mut count = 0;
+            for (&g, &db) in frequencies.iter().zip(levels_db) {
+                if g >= lo && g <= hi {
+                    power += 10.0_f32.powf(db / 10.0);
+                    count += 1;
+                }
+            }
+            10.0 * (power / count as f32).log10()
+        })
+        .collect()
+}
@@ -0,0 +1,181 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::material::{BAND_CENTERS_HZ, N_BANDS};
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// The reverberation time of the scene in one octave band.
+#[derive(Copy, Clone, Debug)]
+pub struct Reverb {
+    pub frequency: f32,
+
+    /// The average absorption coefficient of the faces.
+    pub mean_absorption: f32,
+
+    /// The time for the level to drop by 60 dB, by Sabine's and Eyring's formula, in seconds.
+    pub sabine_s: f32,
+    pub eyring_s: f32,
+}
+
+/// Return the area of a planar polygon, in square meters.
+fn polygon_area(polygon: &[Vec3]) -> f32 {
+    let mut twice_area = Vec3::zero();
+    for i in 0..polygon.len() {
+        twice_area = twice_area + polygon[i].cross(polygon[(i + 1) % polygon.len()]);
+    }
+    0.5 * twice_area.norm()
+}
+
+/// Return the area of every face, in square meters.
+///
+/// In a scene built from floor plans, the floor and the ceiling are unbounded
+/// planes, their area is that of the floor plans. Openings have no area.
+fn face_areas(scene: &Scene) -> Vec<f32> {
+    let n_walls = scene.n_walls();
+    let floor_area: f32 = scene.rooms.iter().map(|r| r.area()).sum();
+    scene.faces
+        .iter()
+        .enumerate()
+        .map(|(i, face)| {
+            if face.opening {
+                0.0
+            } else if !face.polygon().is_empty() {
+                polygon_area(face.polygon())
+            } else if i < n_walls {
+                (face.end() - face.start()).norm() * scene.height
+            } else if i == n_walls || i == n_walls + 1 {
+                floor_area
+            } else {
+                (face.end() - face.start()).norm() * face.height()
+            }
+        })
+        .collect()
+}
+
+/// Return the volume of the air in the scene, in cubic meters.
+///
+/// For a mesh, this is the volume that it encloses. For floor plans, it is
+/// the floor area times the height, minus the volume of the obstacles.
+pub fn volume(scene: &Scene) -> f32 {
+    if scene.rooms.is_empty() {
+        // By the divergence theorem, as in `Scene::new_mesh()`, but the faces
+        // face inward, so the signed volume is negative.
+        let mut volume_6 = 0.0;
+        for face in &scene.faces {
+            let v = face.polygon();
+            for i in 1..v.len() - 1 {
+                volume_6 += v[0].dot(v[i].cross(v[i + 1]));
+            }
+        }
+        return -volume_6 / 6.0
+    }
+
+    let floor_area: f32 = scene.rooms.iter().map(|r| r.area()).sum();
+    let obstacles: f32 = scene.obstacles
+        .iter()
+        .filter(|o| o.corners.len() >= 3)
+        .map(|o| {
+            let corners: Vec<Vec3> = o.corners.iter().map(|c| Vec3::new(c.x, c.y, 0.0)).collect();
+            polygon_area(&corners[..]) * o.height
+        })
+        .sum();
+    floor_area * scene.height - obstacles
+}
+
+/// Estimate the reverberation time in every octave band from the absorption of the faces.
+///
+/// Both formulas assume a diffuse field, which small rooms at low frequencies
+/// are far from, so the times are rough estimates. Sabine's formula holds for
+/// live rooms; with more absorption it overestimates, and Eyring's is more
+/// accurate. The absorption of the air is included. The absorption
+/// coefficient of a face is the part of the energy that it does not reflect
+/// at normal incidence. Resonant absorbers add to it in their band.
+pub fn reverb_times(scene: &Scene) -> Vec<Reverb> {
+    let areas = face_areas(scene);
+    let total_area: f32 = areas.iter().sum();
+    let v = volume(scene);
+
+    // 24 ln(10) / c, which is about 0.161 s/m at room temperature.
+    let k = 24.0 * 10.0_f32.ln() / scene.atmosphere.speed_of_sound();
+
+    (0..N_BANDS)
+        .map(|b| {
+            let f = BAND_CENTERS_HZ[b];
+            let absorption_area: f32 = scene.faces
+                .iter()
+                .zip(areas.iter())
+                .map(|(face, area)| (1.0 - face.reflectivity_at(f).norm_squared()) * area)
+                .sum::<f32>() + scene.absorber_area(f);
+            let mean = (absorption_area / total_area).max(0.0).min(0.999);
+
+            // The power attenuation coefficient of the air, in 1/m.
+            let m = scene.atmosphere.absorption_db_per_m(f) / (10.0 * 1.0_f32.exp().log10());
+            let air = 4.0 * m * v;
+
+            Reverb {
+                frequency: f,
+                mean_absorption: mean,
+                sabine_s: k * v / (absorption_area + air),
+                eyring_s: k * v / (-total_area * (1.0 - mean).ln() + air),
+            }
+        })
+        .collect()
+}
+
+/// Return the frequency above which the modes of the room overlap, in Hz.
+///
+/// This is the Schroeder frequency, 2000 √(T/V), with the reverberation
+/// time by Eyring's formula in the octave band closest to it. Below it, the
+/// field is dominated by individual modes, and the steady state that the
+/// renderer shows is what a listener hears. Above it, the field is closer to
+/// diffuse, and its fine structure changes with every small change to the
+/// scene.
+pub fn schroeder_frequency(scene: &Scene) -> f32 {
+    let times = reverb_times(scene);
+    let v = volume(scene);
+    let closest_band = |f: f32| {
+        (0..N_BANDS)
+            .min_by(|&a, &b| {
+                let da = (BAND_CENTERS_HZ[a] / f).ln().abs();
+                let db = (BAND_CENTERS_HZ[b] / f).ln().abs();
+                da.partial_cmp(&db).unwrap()
+            })
+            .unwrap()
+    };
+
+    // Start from the 125 Hz band, then use the band of the first estimate.
+    let f = 2000.0 * (times[closest_band(125.0)].eyring_s / v).sqrt();
+    2000.0 * (times[closest_band(f)].eyring_s / v).sqrt()
+}
+
+/// Return the number of modes per Hz around `frequency`.
+///
+/// This is the asymptotic mode count of a room with rigid walls, with the
+/// terms for the volume and the surface area. At low frequencies, where the
+/// modes are few, it is only a rough guide.
+pub fn modal_density(scene: &Scene, frequency: f32) -> f32 {
+    let c = scene.atmosphere.speed_of_sound();
+    let v = volume(scene);
+    let s: f32 = face_areas(scene).iter().sum();
+    let f = frequency;
+    4.0 * PI * v * f * f / (c * c * c) + PI * s * f / (2.0 * c * c)
+}
+
+/// Describe the reverberation times, one band per line.
+pub fn describe(reverbs: &[Reverb]) -> String {
+    let lines: Vec<String> = reverbs
+        .iter()
+        .map(|r| format!(
+            "{:>6} Hz  α {:.2}  Sabine {:.2} s  Eyring {:.2} s",
+            r.frequency, r.mean_absorption, r.sabine_s, r.eyring_s,
+        ))
+        .collect();
+    lines.join("\n")
+}
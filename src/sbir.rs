@@ -0,0 +1,195 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::complex::Complex;
+use crate::plot::{self, Canvas};
+use crate::response;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// Faces further from the source than this, in meters, are not near boundaries.
+///
+/// The reflections off further faces arrive late enough that they are part
+/// of the modes of the room, rather than of the response of the speaker.
+const MAX_DISTANCE: f32 = 2.0;
+
+/// The band to look for dips in, in Hz.
+pub const F_MIN: f32 = 20.0;
+pub const F_MAX: f32 = 320.0;
+
+/// The resolution of the search for dips.
+const STEPS_PER_OCTAVE: u32 = 96;
+
+/// Dips shallower than this, in dB, are not listed.
+const MIN_DEPTH_DB: f32 = 3.0;
+
+/// A frequency where the reflection off a boundary cancels the direct sound.
+#[derive(Copy, Clone, Debug)]
+pub struct Dip {
+    pub frequency: f32,
+
+    /// The level of the direct sound and the reflection together, relative
+    /// to the direct sound alone, in dB.
+    pub depth_db: f32,
+}
+
+/// A face near a source, and the dips that its reflection causes at the listener.
+#[derive(Clone, Debug)]
+pub struct Boundary {
+    pub face: usize,
+
+    /// The distance from the source to the face, in meters.
+    pub distance: f32,
+
+    /// The dips in the band, lowest first. A dip at `F_MIN` may extend below it.
+    pub dips: Vec<Dip>,
+}
+
+/// Return the reflection off face `fi` relative to the direct sound of source `si` at `listener`.
+///
+/// This is the first order image source path that the simulation sums too,
+/// so it includes the directivity of the source and the material of the
+/// face. Returns `None` if the reflection does not reach the listener.
+fn relative_reflection(scene: &Scene, si: usize, fi: usize, listener: Vec3, frequency: f32) -> Option<Complex> {
+    let source = &scene.sources[si];
+    let face = &scene.faces[fi];
+    let image = face.reflect(listener);
+    let gain = scene.path_gain(frequency, source.position, &[listener, image], &[fi])?;
+    let direct = source.sample_at(&scene.atmosphere, frequency, listener);
+    let reflected = source.sample_at(&scene.atmosphere, frequency, image) * face.reflectivity_at(frequency) * gain;
+    if direct.norm_squared() == 0.0 {
+        return None
+    }
+    Some(reflected / direct)
+}
+
+/// Return the faces within `MAX_DISTANCE` of source `si`, closest first.
+fn near_faces(scene: &Scene, si: usize) -> Vec<(usize, f32)> {
+    let p = scene.sources[si].position;
+    let mut faces: Vec<(usize, f32)> = scene.faces
+        .iter()
+        .enumerate()
+        .filter(|&(_, face)| !face.opening)
+        .filter_map(|(i, face)| {
+            let distance = face.normal().dot(p - face.start());
+            let foot = p - face.normal() * distance;
+            if distance > 0.0 && distance <= MAX_DISTANCE && face.bounds_contain(foot) {
+                Some((i, distance))
+            } else {
+                None
+            }
+        })
+        .collect();
+    faces.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+    faces
+}
+
+/// Find the speaker-boundary interference dips of source `si` at `listener`.
+///
+/// For every face near the source, the direct sound and the first reflection
+/// off the face are summed, and the dips in that sum between `F_MIN` and
+/// `F_MAX` are where the reflection cancels the direct sound. That is where
+/// the extra path length of the reflection, at most twice the distance of
+/// the source to the face, puts it out of phase with the direct sound, taking
+/// the phase of the reflection coefficient into account. Unlike the modes of
+/// the room, the dips move when the source moves, but hardly when the
+/// listener moves.
+pub fn boundaries(scene: &Scene, si: usize, listener: Vec3) -> Vec<Boundary> {
+    let frequencies = response::log_frequencies(F_MIN, F_MAX, STEPS_PER_OCTAVE);
+    near_faces(scene, si)
+        .into_iter()
+        .map(|(fi, distance)| {
+            let levels: Vec<f32> = frequencies
+                .iter()
+                .map(|&f| match relative_reflection(scene, si, fi, listener, f) {
+                    Some(r) => response::to_db(Complex::new(1.0, 0.0) + r),
+                    None => 0.0,
+                })
+                .collect();
+            let dips = (0..levels.len() - 1)
+                .filter(|&i| (i == 0 || levels[i] < levels[i - 1]) && levels[i] <= levels[i + 1])
+                .filter(|&i| levels[i] <= -MIN_DEPTH_DB)
+                .map(|i| Dip {
+                    frequency: frequencies[i],
+                    depth_db: levels[i],
+                })
+                .collect();
+            Boundary {
+                face: fi,
+                distance: distance,
+                dips: dips,
+            }
+        })
+        .collect()
+}
+
+/// Return the level of the direct sound of source `si` and the reflections
+/// off all near faces at `listener`, relative to the direct sound, in dB.
+///
+/// This is the response that the boundaries alone would cause, without the
+/// modes of the room.
+pub fn predicted_db(scene: &Scene, si: usize, listener: Vec3, frequencies: &[f32]) -> Vec<f32> {
+    let faces = near_faces(scene, si);
+    frequencies
+        .iter()
+        .map(|&f| {
+            let mut z = Complex::new(1.0, 0.0);
+            for &(fi, _) in &faces {
+                if let Some(r) = relative_reflection(scene, si, fi, listener, f) {
+                    z = z + r;
+                }
+            }
+            response::to_db(z)
+        })
+        .collect()
+}
+
+/// Plot the response of a source at the listener in black, the response that
+/// the near boundaries predict in blue, and their dips in red.
+///
+/// The response is relative to its peak, and the prediction is relative to
+/// the direct sound.
+pub fn paint(
+    boundaries: &[Boundary],
+    frequencies: &[f32],
+    response_db: &[f32],
+    predicted_db: &[f32],
+    width: u32,
+    height: u32,
+) -> Canvas {
+    let mut canvas = Canvas::new(width, height, plot::WHITE);
+    let peak = response_db.iter().cloned().fold(std::f32::NEG_INFINITY, f32::max);
+    let response: Vec<f32> = response_db.iter().map(|db| db - peak).collect();
+    let curves = [(&response[..], plot::BLACK), (predicted_db, plot::BLUE)];
+    plot::plot_response(&mut canvas, frequencies, &curves, 10.0, 50.0);
+    let dips: Vec<f32> = boundaries.iter().flat_map(|b| b.dips.iter().map(|d| d.frequency)).collect();
+    plot::plot_markers(&mut canvas, frequencies, &dips[..], plot::RED);
+    canvas
+}
+
+/// Describe the near boundaries and their dips, one boundary per line.
+pub fn describe(scene: &Scene, boundaries: &[Boundary]) -> String {
+    if boundaries.is_empty() {
+        return format!("No faces within {:.1} m of the source.", MAX_DISTANCE)
+    }
+    let lines: Vec<String> = boundaries
+        .iter()
+        .map(|b| {
+            let dips: Vec<String> = b.dips
+                .iter()
+                .map(|d| format!("{:.0} Hz ({:.0} dB)", d.frequency, d.depth_db))
+                .collect();
+            format!(
+                "{} at {:.2} m: {}",
+                scene.face_name(b.face),
+                b.distance,
+                if dips.is_empty() { "no dips".to_string() } else { dips.join(", ") },
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
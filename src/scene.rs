@@ -7,18 +7,193 @@
 
 use std::f32::consts::PI;
 
-use crate::complex::Complex;
+use crate::absorber::Absorber;
+use crate::array::SourceArray;
+use crate::atmosphere::Atmosphere;
+use crate::complex::{self, Complex};
+use crate::diffraction::{self, Edge};
+use crate::eq::{Band, Biquad, Crossover, Rolloff};
+use crate::frd::FrequencyResponse;
+use crate::fdtd::FdtdParams;
+use crate::material::Material;
+use crate::modal::Shoebox;
+use crate::shell::Shell;
+use crate::target::Target;
+use crate::vec2::Vec2;
 use crate::vec3::Vec3;
 use crate::rand::Rng;
 
-/// The speed of sound in m/s in air at 25 degrees Celsius and 1 atm.
-/// TODO: Parametrize temperature and pressure.
-const SPEED_OF_SOUND: f32 = 346.3;
+/// The highest reflection order that can be computed exactly.
+pub const MAX_EXACT_ORDER: u32 = 6;
+
+/// The number of reflections of a sampled path, without a time limit.
+const MAX_BOUNCES: usize = 30;
+
+/// The most reflections of a sampled path, with a time limit.
+pub const MAX_TIMED_BOUNCES: usize = 120;
+
+/// The most frequencies that share a sampled path, see
+/// `Scene::sample_source_at_frequencies()`. More frequencies take a path per
+/// group of this many.
+const FREQUENCIES_PER_PATH: usize = 16;
+
+/// How the field is computed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Solver {
+    /// Sum the paths from the sources by reflection, see `Scene::sample_source_at()`.
+    PathTracing,
+
+    /// Sum the modes of a shoebox room, see `Shoebox::field_at()`.
+    ///
+    /// This is exact up to the damping model, so it has no noise, but it only
+    /// applies to rectangular rooms. Other scenes fall back to path tracing.
+    Modal,
+
+    /// Integrate the wave equation over time on a grid, see `fdtd::Fdtd`.
+    ///
+    /// The viewer shows the pressure as it evolves after the sources emit a
+    /// pulse, rather than the field at one frequency. Where a frequency is
+    /// needed, as for the response at the listener, path tracing is used.
+    Fdtd,
+}
+
+/// Settings that trade accuracy for speed, or choose between methods.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Simulation {
+    /// Reflections up to this order are computed exactly, see `Scene::sample_source_at()`.
+    ///
+    /// At 0, all reflections are sampled. The number of paths to check grows
+    /// as the number of faces to the power of the order, so keep it low for
+    /// scenes with many faces. At most `MAX_EXACT_ORDER`.
+    pub exact_order: u32,
+
+    pub solver: Solver,
+
+    /// Leave out reflections that arrive later than this, in ms after the
+    /// sound leaves the source.
+    ///
+    /// Without a limit, paths are followed for a fixed number of reflections,
+    /// which in a small room covers less time than in a large one. With a
+    /// limit, paths are followed for up to `MAX_TIMED_BOUNCES` reflections,
+    /// until they take longer. The modal solver has no notion of time, it
+    /// ignores the limit.
+    pub max_time_ms: Option<f32>,
+
+    /// The grid and time step of the time-domain solver.
+    pub fdtd: FdtdParams,
+
+    /// Whether sound bends around the free edges of obstacles, see `Scene::sum_diffraction()`.
+    pub diffraction: bool,
+}
+
+impl Simulation {
+    /// Path tracing, with all reflections sampled, up to 30 of them.
+    pub fn standard() -> Simulation {
+        Simulation {
+            exact_order: 0,
+            solver: Solver::PathTracing,
+            max_time_ms: None,
+            fdtd: FdtdParams::standard(),
+            diffraction: false,
+        }
+    }
+}
+
+/// How the output of a source depends on the direction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Directivity {
+    /// The same output in every direction. Sealed subwoofers are close to
+    /// this, because the wavelength is much larger than the cabinet.
+    Omni,
+
+    /// An open baffle: the front and back radiate with opposite phase.
+    ///
+    /// Modelled as two opposite monopoles `spacing` meters apart, the path
+    /// length from front to back around the baffle. There is no output to the
+    /// sides, and the output drops by 6 dB per octave at low frequencies,
+    /// where the front and back cancel.
+    Dipole { spacing: f32 },
+
+    /// A null at the back, half the on-axis amplitude to the sides.
+    Cardioid,
+
+    /// The output is proportional to the cosine of the angle with the aim
+    /// direction, with the phase inverted behind the source.
+    ///
+    /// Like a dipole without the low-frequency cancellation. This was the
+    /// only model before directivity became selectable, so it is the default.
+    Cosine,
+}
+
+/// The port of a bass reflex box.
+///
+/// Below the tuning frequency, the port moves air in opposition to the cone,
+/// so the two cancel. Above it, they are in phase, but the port output drops
+/// by 12 dB per octave. Around the tuning frequency the port does most of the
+/// work, and the cone barely moves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Port {
+    /// The resonance frequency of the box and port, in Hz.
+    pub tuning_hz: f32,
+
+    /// The quality factor of the losses in the box, typically around 7.
+    pub q_loss: f32,
+}
+
+impl Port {
+    /// Return the volume velocity of the port relative to the cone at `frequency`.
+    ///
+    /// The air in the box is a spring that pushes both the cone and the air
+    /// in the port, the air in the port is a mass, so with x = f / tuning, the
+    /// port moves -1 / (1 - x^2 + ix/Q) times as much air as the cone. The
+    /// phase is conjugated, see `Source::drive()`.
+    pub fn response(&self, frequency: f32) -> Complex {
+        let x = frequency / self.tuning_hz;
+        Complex::new(-1.0, 0.0) / Complex::new(1.0 - x * x, -x / self.q_loss)
+    }
+}
 
 /// A speaker, emitting sound in the given direction.
+#[derive(Clone)]
 pub struct Source {
     pub position: Vec3,
     pub direction: Vec3,
+    pub directivity: Directivity,
+
+    /// The gain of the signal that drives the source, in dB.
+    pub gain_db: f32,
+
+    /// The delay of the signal that drives the source, in milliseconds.
+    pub delay_ms: f32,
+
+    /// Whether the polarity of the source is inverted.
+    pub invert: bool,
+
+    /// The measured on-axis response of the speaker, if there is one.
+    pub response: Option<FrequencyResponse>,
+
+    /// Filters in the signal that drives the source, as in a DSP.
+    pub eq: Vec<Biquad>,
+
+    /// The crossover that the source is behind, and on which side.
+    pub crossover: Option<(Crossover, Band)>,
+
+    /// The low-frequency roll-off of the speaker itself, for full-range
+    /// speakers without a measured response.
+    pub rolloff: Option<Rolloff>,
+
+    /// Whether the source emits sound at all, see `array::set_enabled()`.
+    pub enabled: bool,
+
+    /// Silence the source, to hear the other sources without it.
+    pub muted: bool,
+
+    /// Silence all sources that are not soloed, see `Scene::is_audible()`.
+    pub solo: bool,
+
+    /// If set, this source is the port of a bass reflex box, and it emits the
+    /// signal of the cone, the source at the front of the box, through the port.
+    pub port: Option<Port>,
 }
 
 impl Source {
@@ -26,31 +201,112 @@ impl Source {
         Source {
             position: position,
             direction: (aimed_at - position).normalized(),
+            directivity: Directivity::Cosine,
+            gain_db: 0.0,
+            delay_ms: 0.0,
+            invert: false,
+            response: None,
+            eq: Vec::new(),
+            crossover: None,
+            rolloff: None,
+            enabled: true,
+            muted: false,
+            solo: false,
+            port: None,
+        }
+    }
+
+    /// Return the factor that the gain, delay, polarity, EQ, crossover,
+    /// roll-off, port, and measured response multiply the output by.
+    ///
+    /// A delay shifts the phase in the same way as a longer path does, so
+    /// delaying a source by 1 ms is like moving it 0.35 m further away.
+    pub fn drive(&self, frequency: f32) -> Complex {
+        if !self.enabled {
+            return Complex::zero()
+        }
+        let gain = 10.0_f32.powf(self.gain_db / 20.0);
+        let polarity = if self.invert { -gain } else { gain };
+        let mut drive = Complex::exp_i(2.0 * PI * frequency * self.delay_ms * 0.001) * polarity;
+        for filter in &self.eq {
+            drive = drive * filter.response(frequency);
         }
+        if let Some((crossover, band)) = self.crossover {
+            drive = drive * crossover.response(band, frequency);
+        }
+        if let Some(rolloff) = self.rolloff {
+            drive = drive * rolloff.response(frequency);
+        }
+        if let Some(port) = self.port {
+            drive = drive * port.response(frequency);
+        }
+        match self.response {
+            Some(ref response) => drive * response.at(frequency),
+            None => drive,
+        }
+    }
+
+    /// Return the gain of the source in the given (normalized) direction.
+    ///
+    /// * `frequency` specifies the source frequency in Hz.
+    pub fn directivity(&self, atmosphere: &Atmosphere, frequency: f32, direction: Vec3) -> Complex {
+        let cos_t = direction.dot(self.direction);
+        match self.directivity {
+            Directivity::Omni => Complex::new(1.0, 0.0),
+            Directivity::Dipole { spacing } => {
+                // The front monopole is half the spacing closer in direction
+                // `direction` than the center, the back one half further:
+                // e^(ikx) - e^(-ikx) = 2i sin(kx).
+                let kx = PI * frequency * spacing * cos_t / atmosphere.speed_of_sound();
+                Complex::new(0.0, 2.0 * kx.sin())
+            }
+            Directivity::Cardioid => Complex::new(0.5 * (1.0 + cos_t), 0.0),
+            Directivity::Cosine => Complex::new(cos_t, 0.0),
+        }
+    }
+
+    /// Return the level relative to on-axis at `n` angles around the source.
+    ///
+    /// Returns (angle in radians, level in dB) pairs. The angles lie in the
+    /// horizontal plane, counterclockwise from the aim direction when seen from
+    /// above.
+    pub fn horizontal_directivity_db(&self, atmosphere: &Atmosphere, frequency: f32, n: u32) -> Vec<(f32, f32)> {
+        let on_axis = self.directivity(atmosphere, frequency, self.direction).norm();
+        let d = self.direction;
+        (0..n)
+            .map(|i| {
+                let t = 2.0 * PI * i as f32 / n as f32;
+                let (sin_t, cos_t) = t.sin_cos();
+                let direction = Vec3::new(
+                    d.x * cos_t - d.y * sin_t,
+                    d.x * sin_t + d.y * cos_t,
+                    d.z,
+                );
+                let gain = self.directivity(atmosphere, frequency, direction).norm() / on_axis;
+                (t, 20.0 * gain.log10())
+            })
+            .collect()
     }
 
     /// Sample the field produced by the source at the given position.
     ///
     /// * `frequency` specifies the source frequency in Hz.
     /// * `position` specifies the position measured in meters from the origin.
-    pub fn sample_at(&self, frequency: f32, position: Vec3) -> Complex {
+    pub fn sample_at(&self, atmosphere: &Atmosphere, frequency: f32, position: Vec3) -> Complex {
         // The energy falls off with radius squared.
         let distance_squared = (position - self.position).norm_squared();
         let attenuation_distance = distance_squared.recip();
 
-        // The phase is proportional to the distance.
+        // The phase is proportional to the distance, and so is the absorption
+        // in the air, in dB.
         let distance = distance_squared.sqrt();
-        let n_waves = frequency * distance / SPEED_OF_SOUND;
+        let n_waves = frequency * distance / atmosphere.speed_of_sound();
+        let attenuation_air = atmosphere.attenuation(frequency, distance);
 
-        // Furthermore, if we are behind the speaker, the phase is inverted, and
-        // we assume that the speaker does not emit sound sideways. We model
-        // this with another attenuation factor, proportional to the dot product
-        // between the normalized direction to the target, and speaker output
-        // direction.
-        let dot = (position - self.position).dot(self.direction);
-        let attenuation_phase = dot * distance.recip();
+        let direction = (position - self.position) * distance.recip();
+        let gain = self.directivity(atmosphere, frequency, direction) * self.drive(frequency);
 
-        Complex::exp_i(2.0 * PI * n_waves) * attenuation_distance * attenuation_phase
+        Complex::exp_i(2.0 * PI * n_waves) * gain * (attenuation_distance * attenuation_air)
     }
 }
 
@@ -61,11 +317,40 @@ impl Source {
 ///   `origin`, perpendicular to both `normal` and `tangent`.
 /// * The band is bounded from the other side by the line (in the plane) through
 ///   `origin + width * tangent` perpendicular to both `normal` and `tangent`.
+/// * If `width` is infinite, the band is unbounded on both sides, and it covers
+///   the entire plane.
+/// * The band can be cut off at `height` along `tangent × normal`, which for
+///   a wall is up. A wall that does not reach the ceiling has a finite height.
+///
+/// Alternatively, a face is a convex polygon, for example a triangle of a mesh.
+/// Then the band runs along its first edge, and the polygon bounds it further.
+#[derive(Clone)]
 pub struct Face {
     origin: Vec3,
     normal: Vec3,
     tangent: Vec3,
     width: f32,
+    height: f32,
+
+    /// The vertices of the polygon, counterclockwise around the normal, or
+    /// empty if the face is a band.
+    polygon: Vec<Vec3>,
+
+    /// Determines the factor that the amplitude is multiplied by upon reflection.
+    pub material: Material,
+
+    /// Whether the face is an opening into an adjacent room.
+    ///
+    /// Openings do not reflect, and sound passes through them freely.
+    pub opening: bool,
+
+    /// The factor that the amplitude of sound passing through the face is
+    /// multiplied by, 0.0 for a face that lets nothing through.
+    ///
+    /// The energy that passes through is lost to the reflection, see
+    /// `reflectivity_at()`. It reaches adjacent rooms, a path between rooms
+    /// passes through the walls of both.
+    pub transmission: f32,
 }
 
 impl Face {
@@ -79,75 +364,1216 @@ impl Face {
             normal: forward.cross(p2 - p1).normalized(),
             tangent: (p2 - p1).normalized(),
             width: (p2 - p1).norm(),
+            height: std::f32::INFINITY,
+            polygon: Vec::new(),
+            material: Material::default_material(),
+            opening: false,
+            transmission: 0.0,
+        }
+    }
+
+    /// Construct a face from a planar convex polygon.
+    ///
+    /// The normal points to the side from which the vertices are in
+    /// counterclockwise order.
+    pub fn new_polygon(vertices: Vec<Vec3>) -> Face {
+        assert!(vertices.len() >= 3, "A polygon needs at least 3 vertices.");
+
+        // Newell's method, which is robust for polygons that are not quite planar.
+        let n = vertices.len();
+        let mut normal = Vec3::zero();
+        for i in 0..n {
+            let (a, b) = (vertices[i], vertices[(i + 1) % n]);
+            normal.x += (a.y - b.y) * (a.z + b.z);
+            normal.y += (a.z - b.z) * (a.x + b.x);
+            normal.z += (a.x - b.x) * (a.y + b.y);
+        }
+
+        let (p0, p1) = (vertices[0], vertices[1]);
+        Face {
+            origin: p0,
+            normal: normal.normalized(),
+            tangent: (p1 - p0).normalized(),
+            width: (p1 - p0).norm(),
+            height: std::f32::INFINITY,
+            polygon: vertices,
+            material: Material::default_material(),
+            opening: false,
+            transmission: 0.0,
         }
     }
 
+    /// Return the same polygon face, but facing the other way.
+    pub fn flipped(&self) -> Face {
+        assert!(!self.polygon.is_empty(), "Only polygon faces can be flipped.");
+        let mut vertices = self.polygon.clone();
+        vertices.reverse();
+        Face {
+            material: self.material.clone(),
+            opening: self.opening,
+            transmission: self.transmission,
+            .. Face::new_polygon(vertices)
+        }
+    }
+
+    /// Construct a face that covers the entire plane through `p1` and `p2`.
+    ///
+    /// The normal is the same as for `Face::new()`.
+    pub fn new_unbounded(p1: Vec3, p2: Vec3, forward: Vec3) -> Face {
+        Face {
+            width: std::f32::INFINITY,
+            .. Face::new(p1, p2, forward)
+        }
+    }
+
+    /// Return the point where the band starts, on the line through `origin`.
+    pub fn start(&self) -> Vec3 {
+        self.origin
+    }
+
+    /// Return the point where the band ends, `width` along the tangent.
+    pub fn end(&self) -> Vec3 {
+        self.origin + self.tangent * self.width
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    /// Return the direction along the band, or along the first edge of a polygon.
+    pub fn tangent(&self) -> Vec3 {
+        self.tangent
+    }
+
+    /// Return how far the band extends along `tangent × normal`.
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    /// Return the reflection coefficient at `frequency`.
+    ///
+    /// This is the reflectivity of the material, reduced by the energy that
+    /// passes through the face.
+    pub fn reflectivity_at(&self, frequency: f32) -> Complex {
+        let t = self.transmission;
+        self.material.reflectivity_at(frequency) * (1.0 - t * t).sqrt()
+    }
+
     /// Reflect the point p in the plane.
     pub fn reflect(&self, p: Vec3) -> Vec3 {
         let d = self.normal.dot(p - self.origin);
         p - self.normal * (d + d)
     }
 
-    /// Return whether the point is on the front side of the face.
+    /// Return where the segment from `p` to `q` crosses the plane, if it does.
+    ///
+    /// The segment must cross from the front to the back of the face.
+    pub fn intersect(&self, p: Vec3, q: Vec3) -> Option<Vec3> {
+        let dp = self.normal.dot(p - self.origin);
+        let dq = self.normal.dot(q - self.origin);
+        if dp < 0.0 || dq >= 0.0 {
+            return None
+        }
+        let t = dp / (dp - dq);
+        Some(p + (q - p) * t)
+    }
+
+    /// Return whether the segment from `p` to `q` passes through the face.
+    ///
+    /// Unlike `intersect()`, this works in both directions. Segments that only
+    /// touch the plane, such as those that start or end at a reflection point
+    /// on the face, do not count.
+    pub fn blocks(&self, p: Vec3, q: Vec3) -> bool {
+        let eps = 1e-4;
+        let dp = self.normal.dot(p - self.origin);
+        let dq = self.normal.dot(q - self.origin);
+        if !((dp > eps && dq < -eps) || (dp < -eps && dq > eps)) {
+            return false
+        }
+        let t = dp / (dp - dq);
+        self.bounds_contain(p + (q - p) * t)
+    }
+
+    /// Return a point inside the face, away from its edges.
+    ///
+    /// For a wall of unbounded height, this is just below `ceiling`, above
+    /// any obstacles. A face that covers the entire plane has no such point.
+    fn interior_point(&self, ceiling: f32) -> Option<Vec3> {
+        if !self.polygon.is_empty() {
+            let sum = self.polygon.iter().fold(Vec3::zero(), |acc, &v| acc + v);
+            return Some(sum * (1.0 / self.polygon.len() as f32))
+        }
+        if self.width == std::f32::INFINITY {
+            return None
+        }
+        let up = self.tangent.cross(self.normal);
+        let h = if self.height == std::f32::INFINITY { ceiling - 0.02 } else { self.height * 0.5 };
+        Some(self.origin + self.tangent * (self.width * 0.5) + up * h)
+    }
+
+    /// Return the vertices, if the face is a polygon.
+    pub fn polygon(&self) -> &[Vec3] {
+        &self.polygon[..]
+    }
+
+    /// Return whether a point in the plane lies within the bounds of the face.
+    pub fn bounds_contain(&self, p: Vec3) -> bool {
+        if !self.polygon.is_empty() {
+            // The point is inside a convex polygon if it lies to the left of
+            // every edge, when looking along the normal.
+            let n = self.polygon.len();
+            return (0..n).all(|i| {
+                let (a, b) = (self.polygon[i], self.polygon[(i + 1) % n]);
+                (b - a).cross(p - a).dot(self.normal) >= -1e-6
+            })
+        }
+        if self.height != std::f32::INFINITY {
+            let s = self.tangent.cross(self.normal).dot(p - self.origin);
+            if s < 0.0 || s > self.height {
+                return false
+            }
+        }
+        if self.width == std::f32::INFINITY {
+            return true
+        }
+        let t = self.tangent.dot(p - self.origin);
+        t >= 0.0 && t <= self.width
+    }
+}
+
+/// A small obstacle, such as the head and shoulders of a listener, modeled as a sphere.
+///
+/// Sound that passes through the sphere is attenuated, but only at frequencies
+/// where the wavelength is short compared to the sphere. Long waves diffract
+/// around it as if it were not there.
+#[derive(Clone)]
+pub struct Body {
+    pub center: Vec3,
+    pub radius: f32,
+
+    /// The amplitude factor directly behind the body at high frequencies.
+    pub shadow_gain: f32,
+}
+
+impl Body {
+    /// Return the factor that sound travelling from `p` to `q` is multiplied by.
+    ///
+    /// When `p` or `q` lies inside the body, it does not shadow the segment.
+    pub fn transmission(&self, atmosphere: &Atmosphere, frequency: f32, p: Vec3, q: Vec3) -> f32 {
+        let r2 = self.radius * self.radius;
+        if (p - self.center).norm_squared() < r2 || (q - self.center).norm_squared() < r2 {
+            return 1.0
+        }
+
+        // Find the point on the segment closest to the center.
+        let d = q - p;
+        let t = ((self.center - p).dot(d) / d.norm_squared()).max(0.0).min(1.0);
+        let dist = (p + d * t - self.center).norm();
+        if dist >= self.radius {
+            return 1.0
+        }
+
+        // The shadow is deepest for rays through the center, and fades towards
+        // the edge. The Helmholtz number ka determines how much of the shadow
+        // remains: for ka << 1 the wave bends around the body.
+        let ka = 2.0 * PI * frequency * self.radius / atmosphere.speed_of_sound();
+        let ka2 = ka * ka;
+        let depth = (1.0 - dist / self.radius) * ka2 / (1.0 + ka2);
+        1.0 - depth * (1.0 - self.shadow_gain)
+    }
+}
+
+/// A rectangular region in the horizontal plane, such as a sofa.
+///
+/// At low frequencies, the level can change a lot over a short distance, so
+/// the response at a single point is not representative for all seats.
+#[derive(Copy, Clone, Debug)]
+pub struct ListeningArea {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl ListeningArea {
+    /// Construct the area between two opposite corners.
+    pub fn new(a: Vec2, b: Vec2) -> ListeningArea {
+        ListeningArea {
+            min: Vec2::new(a.x.min(b.x), a.y.min(b.y)),
+            max: Vec2::new(a.x.max(b.x), a.y.max(b.y)),
+        }
+    }
+
+    /// Return the corners, counterclockwise.
+    pub fn corners(&self) -> [Vec2; 4] {
+        [
+            self.min,
+            Vec2::new(self.max.x, self.min.y),
+            self.max,
+            Vec2::new(self.min.x, self.max.y),
+        ]
+    }
+}
+
+/// The listening position, where analyses of the scene are done.
+#[derive(Clone)]
+pub struct Listener {
+    pub position: Vec3,
+
+    /// The head and shoulders of the listener, if they should shadow sound.
+    pub body: Option<Body>,
+
+    /// The seats around the listening position, at the height of the listener.
+    pub area: Option<ListeningArea>,
+}
+
+impl Listener {
+    pub fn new(position: Vec3) -> Listener {
+        Listener {
+            position: position,
+            body: None,
+            area: None,
+        }
+    }
+
+    /// Return the positions that represent the listening area.
     ///
-    /// The front side of the face is the side where the normal points.
-    pub fn is_facing(&self, p: Vec3) -> bool {
-        self.normal.dot(p - self.origin) > 0.0
+    /// These are the points of a 3 by 3 grid over the area, at the height of
+    /// the listener, or only the listener position if there is no area.
+    pub fn seats(&self) -> Vec<Vec3> {
+        let area = match self.area {
+            Some(ref area) => area,
+            None => return vec![self.position],
+        };
+        let mut seats = Vec::with_capacity(9);
+        for j in 0..3 {
+            for i in 0..3 {
+                let t = Vec2::new(i as f32, j as f32) * 0.5;
+                let p = area.min + Vec2::new((area.max.x - area.min.x) * t.x, (area.max.y - area.min.y) * t.y);
+                seats.push(Vec3::new(p.x, p.y, self.position.z));
+            }
+        }
+        seats
     }
 }
 
+/// A point where the response is of interest besides the listener, such as the seat next to it.
+#[derive(Clone, Debug)]
+pub struct Probe {
+    pub name: String,
+    pub position: Vec3,
+}
+
+/// Return twice the signed area of the polygon, positive if counterclockwise.
+fn signed_area_2(polygon: &[Vec2]) -> f32 {
+    let n = polygon.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum()
+}
+
+/// Return whether the polygon is convex. Collinear corners are allowed.
+fn is_convex(polygon: &[Vec2]) -> bool {
+    let n = polygon.len();
+    let mut n_left = 0;
+    let mut n_right = 0;
+    for i in 0..n {
+        let (a, b, c) = (polygon[i], polygon[(i + 1) % n], polygon[(i + 2) % n]);
+        let (u, v) = (b - a, c - b);
+        let cross = u.x * v.y - u.y * v.x;
+        if cross > 0.0 { n_left += 1 }
+        if cross < 0.0 { n_right += 1 }
+    }
+    n_left == 0 || n_right == 0
+}
+
+/// Return the ends of wall `i` of a floor plan, such that the wall faces inward.
+///
+/// Normals of the walls point to the left of the wall direction, so if the
+/// corners are in clockwise order, we must build the walls in the opposite
+/// direction to make them face inward.
+fn wall_ends(corners: &[Vec2], clockwise: bool, i: usize) -> (Vec3, Vec3) {
+    let c0 = corners[i];
+    let c1 = corners[(i + 1) % corners.len()];
+    let p0 = Vec3::new(c0.x, c0.y, 0.0);
+    let p1 = Vec3::new(c1.x, c1.y, 0.0);
+    if clockwise { (p1, p0) } else { (p0, p1) }
+}
+
+/// Return whether `p` lies inside the polygon, using the even-odd rule.
+fn polygon_contains(polygon: &[Vec2], p: Vec2) -> bool {
+    let n = polygon.len();
+    let mut inside = false;
+    for i in 0..n {
+        let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+        // Count crossings of a ray from `p` in the positive x direction.
+        if (a.y > p.y) != (b.y > p.y) {
+            let x = a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if x > p.x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// An object that stands on the floor, such as a kitchen island or a pony wall.
+///
+/// The obstacle is a prism: a floor plan extruded upward to `height`, which is
+/// less than the height of the room. A floor plan of only two corners is a
+/// free-standing wall without thickness.
+#[derive(Clone)]
+pub struct Obstacle {
+    pub corners: Vec<Vec2>,
+    pub height: f32,
+}
+
+/// A part of the floor that is raised, such as a riser in a home theater.
+///
+/// Listeners stand or sit on the raised floor, so the horizontal slice that
+/// we render follows it. This does not affect the sound, model the riser
+/// itself as an obstacle for that.
+#[derive(Clone)]
+pub struct FloorLevel {
+    pub corners: Vec<Vec2>,
+
+    /// The height of the floor above z = 0.
+    pub height: f32,
+}
+
+/// The floor plan of one room of a scene.
+#[derive(Clone)]
+pub struct Room {
+    /// The corners of the floor plan, see `Scene::new_polygon()`.
+    pub corners: Vec<Vec2>,
+
+    /// The indices of the walls that are openings into an adjacent room.
+    pub openings: Vec<usize>,
+}
+
+impl Room {
+    /// Return the area of the floor plan, in square meters.
+    pub fn area(&self) -> f32 {
+        0.5 * signed_area_2(&self.corners[..]).abs()
+    }
+}
+
+#[derive(Clone)]
 pub struct Scene {
     pub sources: Vec<Source>,
     pub faces: Vec<Face>,
+
+    /// The floor plans of the rooms, if the scene was built from them.
+    ///
+    /// Without floor plans, the faces form a closed mesh.
+    pub rooms: Vec<Room>,
+
+    /// The height of the ceiling, for rooms built from a floor plan, or the
+    /// highest point of a mesh.
+    pub height: f32,
+
+    /// The listening position, if the scene defines one.
+    pub listener: Option<Listener>,
+
+    /// Points to compare the response at the listener with.
+    pub probes: Vec<Probe>,
+
+    /// The curve that the response at the listener should follow, if the scene has one.
+    pub target: Option<Target>,
+
+    /// Raised parts of the floor, later levels take precedence over earlier ones.
+    pub floor_levels: Vec<FloorLevel>,
+
+    /// Objects inside the rooms. Their faces come after the ceiling.
+    pub obstacles: Vec<Obstacle>,
+
+    /// Sources that were added behind other sources to make them directional.
+    pub arrays: Vec<SourceArray>,
+
+    /// The air, which determines the speed of sound and its absorption.
+    pub atmosphere: Atmosphere,
+
+    /// How to compute the field.
+    pub simulation: Simulation,
+
+    /// The outer shell around the rooms, for a room within a room.
+    pub shell: Option<Shell>,
+
+    /// Resonant bass traps on the faces.
+    pub absorbers: Vec<Absorber>,
+
+    /// Indices of the faces that reflect, all faces except openings.
+    reflectors: Vec<usize>,
+
+    /// Indices of the walls that a path might pass through.
+    ///
+    /// In a single convex room, no straight path between two points inside the
+    /// room crosses a wall, so then this is empty.
+    occluders: Vec<usize>,
+
+    /// The free edges of the obstacles, which diffract sound.
+    edges: Vec<Edge>,
 }
 
 impl Scene {
     /// Return an example scene with hard-coded data.
     pub fn new_example() -> Scene {
-        let up = Vec3::new(0.0, 0.0, 1.0);
-        let side = Vec3::new(0.0, 1.0, 0.0);
-
-        let p0 = Vec3::new(0.00, 0.00, 0.0);
-        let p1 = Vec3::new(8.32, 0.00, 0.0);
-        let p2 = Vec3::new(8.32, 3.35, 0.0);
-        let p3 = Vec3::new(0.00, 3.35, 0.0);
+        let corners = [
+            Vec2::new(0.00, 0.00),
+            Vec2::new(8.32, 0.00),
+            Vec2::new(8.32, 3.35),
+            Vec2::new(0.00, 3.35),
+        ];
 
         let s1 = Vec3::new(0.60, 0.30, 1.0);
         let s2 = Vec3::new(2.20, 0.30, 1.0);
         let listener = Vec3::new(1.40, 3.0, 1.0);
 
-        let ceil_off = Vec3::new(0.0, 0.0, 2.8);
+        let sources = vec![
+            Source::new(s1, listener),
+            Source::new(s2, listener),
+        ];
+
+        let mut scene = Scene::new_polygon(&corners, 2.8, sources);
+        let mut listener = Listener::new(listener);
+        listener.area = Some(ListeningArea::new(Vec2::new(0.90, 2.70), Vec2::new(1.90, 3.20)));
+        scene.listener = Some(listener);
+        scene
+    }
+
+    /// Build a room with vertical walls from a floor plan.
+    ///
+    /// The floor plan is a polygon of corners in the z = 0 plane, in order,
+    /// either clockwise or counterclockwise. It does not need to be convex, so
+    /// L-shaped and trapezoidal rooms are fine. The walls are `height` high,
+    /// and the room has a floor and ceiling.
+    ///
+    /// The faces are the walls in the order of the corners (wall `i` runs from
+    /// corner `i` to corner `i + 1`), followed by the floor and the ceiling.
+    pub fn new_polygon(corners: &[Vec2], height: f32, sources: Vec<Source>) -> Scene {
+        let room = Room {
+            corners: corners.to_vec(),
+            openings: Vec::new(),
+        };
+        Scene::new_rooms(vec![room], height, sources)
+    }
+
+    /// Build a scene of several rooms with vertical walls, such as a living
+    /// room that is open to a kitchen.
+    ///
+    /// Every room is built like in `new_polygon()`. Rooms must not overlap.
+    /// Where two rooms are connected, both should have a wall there that is
+    /// marked as an opening. To make part of a wall an opening, split it with a
+    /// corner. The rooms share the floor and ceiling, so they have the same
+    /// height.
+    ///
+    /// The faces are the walls of all rooms, with the walls of the first room
+    /// first, followed by the floor and the ceiling.
+    pub fn new_rooms(rooms: Vec<Room>, height: f32, sources: Vec<Source>) -> Scene {
+        assert!(rooms.len() > 0, "A scene needs at least one room.");
+
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let ceil_off = up * height;
+        let mut faces = Vec::new();
+
+        // Walls, between every pair of consecutive corners.
+        for room in &rooms {
+            let corners = &room.corners[..];
+            assert!(corners.len() >= 3, "A floor plan needs at least 3 corners.");
+            let clockwise = signed_area_2(corners) < 0.0;
+            for i in 0..corners.len() {
+                let (p0, p1) = wall_ends(corners, clockwise, i);
+                let mut face = Face::new(p0, p1, up);
+                face.opening = room.openings.contains(&i);
+                faces.push(face);
+            }
+        }
+        let n_walls = faces.len();
+
+        // Floor and ceiling, through the first wall. We pick the forward
+        // vector such that their normals point into the room. They cover the
+        // entire floor plan, so we leave them unbounded.
+        let corners = &rooms[0].corners[..];
+        let (p0, p1) = wall_ends(corners, signed_area_2(corners) < 0.0, 0);
+        let along = p1 - p0;
+        faces.push(Face::new_unbounded(p0, p1, along.cross(up)));
+        faces.push(Face::new_unbounded(p0 + ceil_off, p1 + ceil_off, up.cross(along)));
+
+        let reflectors = (0..faces.len()).filter(|&i| !faces[i].opening).collect();
+        let occluders = if rooms.len() == 1 && is_convex(&rooms[0].corners[..]) {
+            Vec::new()
+        } else {
+            (0..n_walls).filter(|&i| !faces[i].opening).collect()
+        };
+
+        Scene {
+            sources: sources,
+            faces: faces,
+            rooms: rooms,
+            height: height,
+            listener: None,
+            probes: Vec::new(),
+            target: None,
+            floor_levels: Vec::new(),
+            obstacles: Vec::new(),
+            arrays: Vec::new(),
+            atmosphere: Atmosphere::standard(),
+            simulation: Simulation::standard(),
+            shell: None,
+            absorbers: Vec::new(),
+            reflectors: reflectors,
+            occluders: occluders,
+            edges: Vec::new(),
+        }
+    }
+
+    /// Build a scene from the polygons of a closed mesh, such as an imported 3D model.
+    ///
+    /// The faces must be polygon faces. If their normals point out of the
+    /// mesh, as modelling programs usually make them, all faces are flipped,
+    /// so they face into the room.
+    pub fn new_mesh(faces: Vec<Face>, sources: Vec<Source>) -> Scene {
+        assert!(faces.iter().all(|f| f.polygon.len() >= 3), "Mesh faces must be polygons.");
+
+        // For a closed mesh, the divergence theorem gives the enclosed volume
+        // as the sum of the signed volumes of the tetrahedra between the origin
+        // and every triangle. It is positive if the normals point outward.
+        let mut volume_6 = 0.0;
+        for face in &faces {
+            let v = &face.polygon;
+            for i in 1..v.len() - 1 {
+                volume_6 += v[0].dot(v[i].cross(v[i + 1]));
+            }
+        }
+        let faces: Vec<Face> = if volume_6 > 0.0 {
+            faces.iter().map(|f| f.flipped()).collect()
+        } else {
+            faces
+        };
+
+        // The mesh is convex if every vertex is on or in front of every face.
+        let is_convex = faces.iter().all(|f| {
+            faces.iter().all(|g| g.polygon.iter().all(|&p| f.normal.dot(p - f.origin) > -1e-3))
+        });
+
+        let height = faces
+            .iter()
+            .flat_map(|f| f.polygon.iter().map(|p| p.z))
+            .fold(std::f32::NEG_INFINITY, f32::max);
+
+        let reflectors = (0..faces.len()).collect();
+        let occluders = if is_convex { Vec::new() } else { (0..faces.len()).collect() };
 
         Scene {
-            sources: vec![
-                Source::new(s1, listener),
-                Source::new(s2, listener),
-            ],
+            sources: sources,
+            faces: faces,
+            rooms: Vec::new(),
+            height: height,
+            listener: None,
+            probes: Vec::new(),
+            target: None,
+            floor_levels: Vec::new(),
+            obstacles: Vec::new(),
+            arrays: Vec::new(),
+            atmosphere: Atmosphere::standard(),
+            simulation: Simulation::standard(),
+            shell: None,
+            absorbers: Vec::new(),
+            reflectors: reflectors,
+            occluders: occluders,
+            edges: Vec::new(),
+        }
+    }
+
+    /// Return the number of walls, the faces that come before the floor.
+    ///
+    /// This is only meaningful for scenes built from floor plans.
+    pub fn n_walls(&self) -> usize {
+        self.rooms.iter().map(|r| r.corners.len()).sum()
+    }
+
+    /// Return the corners of the axis-aligned box that contains the rooms or the mesh.
+    pub fn bounding_box(&self) -> (Vec3, Vec3) {
+        let inf = std::f32::INFINITY;
+        let mut min = Vec3::new(inf, inf, 0.0);
+        let mut max = Vec3::new(-inf, -inf, self.height);
+
+        let points: Vec<Vec3> = if self.rooms.is_empty() {
+            min.z = inf;
+            self.faces.iter().flat_map(|f| f.polygon.iter().cloned()).collect()
+        } else {
+            self.rooms.iter().flat_map(|r| r.corners.iter().map(|c| Vec3::new(c.x, c.y, 0.0))).collect()
+        };
+        for p in points {
+            min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+
+        (min, max)
+    }
+
+    /// Return a human-readable name for face `i`, such as "Wall 2" or "Floor".
+    pub fn face_name(&self, i: usize) -> String {
+        if self.rooms.is_empty() {
+            return format!("Face {}", i)
+        }
+        let n_walls = self.n_walls();
+        match i {
+            _ if i < n_walls => format!("Wall {}", i),
+            _ if i == n_walls => "Floor".to_string(),
+            _ if i == n_walls + 1 => "Ceiling".to_string(),
+            _ => format!("Obstacle face {}", i - n_walls - 2),
+        }
+    }
+
+    /// Add an obstacle to a scene built from floor plans.
+    ///
+    /// The obstacle faces get `material`. Its sides face outward, and it has
+    /// a top if it has at least three corners. Unlike walls of the room, the
+    /// faces of obstacles can block paths even in a convex room.
+    pub fn add_obstacle(&mut self, obstacle: Obstacle, material: Material) {
+        assert!(obstacle.corners.len() >= 2, "An obstacle needs at least 2 corners.");
+        let corners = &obstacle.corners[..];
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let first = self.faces.len();
+
+        if corners.len() == 2 {
+            let p0 = Vec3::new(corners[0].x, corners[0].y, 0.0);
+            let p1 = Vec3::new(corners[1].x, corners[1].y, 0.0);
+            self.faces.push(Face::new(p0, p1, up));
+            self.faces.push(Face::new(p1, p0, up));
+        } else {
+            // Build the sides the other way around than walls, so they face
+            // out of the floor plan.
+            let clockwise = signed_area_2(corners) < 0.0;
+            for i in 0..corners.len() {
+                let (p0, p1) = wall_ends(corners, !clockwise, i);
+                self.faces.push(Face::new(p0, p1, up));
+            }
+
+            // The top faces up if its vertices are counterclockwise from above.
+            let mut top: Vec<Vec3> = corners
+                .iter()
+                .map(|c| Vec3::new(c.x, c.y, obstacle.height))
+                .collect();
+            if clockwise {
+                top.reverse();
+            }
+            self.faces.push(Face::new_polygon(top));
+        }
+
+        for i in first..self.faces.len() {
+            let face = &mut self.faces[i];
+            if face.polygon.is_empty() {
+                face.height = obstacle.height;
+            }
+            face.material = material.clone();
+            self.reflectors.push(i);
+            self.occluders.push(i);
+        }
+        self.edges.extend(diffraction::obstacle_edges(&obstacle));
+        self.obstacles.push(obstacle);
+    }
+
+    /// Cut off a corner of the floor plan with a new wall of the given material.
+    ///
+    /// Corners are numbered like walls: corner `i` is where wall `i` starts.
+    /// The new wall runs between the points `size` meters away from `corner`
+    /// along both walls that meet there, which is how a corner bass trap sits
+    /// in a room. The new wall gets index `corner`, and walls after it shift up
+    /// by one. Faces keep their materials and transmission.
+    pub fn chamfer_corner(&self, corner: usize, size: f32, material: Material) -> Scene {
+        assert!(corner < self.n_walls(), "Corner {} does not exist.", corner);
+
+        let mut rooms = self.rooms.clone();
+        let mut j = corner;
+        let room = rooms.iter_mut().find(|r| {
+            if j < r.corners.len() { return true }
+            j -= r.corners.len();
+            false
+        }).unwrap();
+
+        let n = room.corners.len();
+        let c = room.corners[j];
+        let prev = room.corners[(j + n - 1) % n];
+        let next = room.corners[(j + 1) % n];
+        let a = c + (prev - c) * (size / (prev - c).norm());
+        let b = c + (next - c) * (size / (next - c).norm());
+
+        room.corners.splice(j..j + 1, vec![a, b]);
+        for o in room.openings.iter_mut() {
+            if *o >= j {
+                *o += 1;
+            }
+        }
+
+        let mut scene = Scene::new_rooms(rooms, self.height, self.sources.clone());
+        scene.listener = self.listener.clone();
+        scene.probes = self.probes.clone();
+        scene.target = self.target.clone();
+        scene.floor_levels = self.floor_levels.clone();
+        scene.arrays = self.arrays.clone();
+        scene.atmosphere = self.atmosphere;
+        scene.simulation = self.simulation;
+        scene.shell = self.shell.clone();
+        if let Some(ref mut shell) = scene.shell {
+            for &mut (ref mut i, _) in shell.faces.iter_mut() {
+                if *i >= corner {
+                    *i += 1;
+                }
+            }
+        }
+        scene.absorbers = self.absorbers.clone();
+        for absorber in scene.absorbers.iter_mut() {
+            if absorber.face >= corner {
+                absorber.face += 1;
+            }
+        }
+        for obstacle in &self.obstacles {
+            scene.add_obstacle(obstacle.clone(), Material::default_material());
+        }
+        for (i, face) in scene.faces.iter_mut().enumerate() {
+            if i == corner {
+                face.material = material.clone();
+                continue
+            }
+            let old = if i < corner { &self.faces[i] } else { &self.faces[i - 1] };
+            face.material = old.material.clone();
+            face.transmission = old.transmission;
+        }
+
+        scene
+    }
+
+    /// Check the scene for mistakes that would make the results meaningless.
+    ///
+    /// Such mistakes often do not cause an error anywhere else, they just
+    /// produce an image that is black, or that is plausible but wrong. Returns
+    /// a description of every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        for (i, face) in self.faces.iter().enumerate() {
+            // A normal that is not of unit length is NaN, because the face
+            // spans no area: its points coincide or lie on a line, or the
+            // forward vector is parallel to it.
+            if !(face.normal.norm() > 0.5) || !(face.width > 0.0) {
+                problems.push(format!(
+                    "{} is degenerate, its corners coincide or lie on one line.", self.face_name(i)
+                ));
+                continue
+            }
+
+            // Obstacle faces may be flush with a wall, so we only check the
+            // faces that bound the space.
+            let is_bounding = self.rooms.is_empty() || i < self.n_walls();
+            let p = match face.interior_point(self.height) {
+                Some(p) if is_bounding => p,
+                _ => continue,
+            };
+            if !self.contains(p + face.normal * 0.01) {
+                problems.push(format!(
+                    "{} faces out of the space. Check that the floor plan does not \
+                    intersect itself, or that the mesh is closed.", self.face_name(i)
+                ));
+            }
+        }
+
+        if let Some(ref listener) = self.listener {
+            let p = listener.position;
+            if !self.contains(p) {
+                problems.push(format!("The listener at ({}, {}, {}) is outside of the space.", p.x, p.y, p.z));
+            }
+            if let Some(ref area) = listener.area {
+                if area.corners().iter().any(|c| !self.contains(Vec3::new(c.x, c.y, p.z))) {
+                    problems.push("The listening area extends outside of the space.".to_string());
+                }
+            }
+        }
+
+        for probe in &self.probes {
+            let p = probe.position;
+            if !self.contains(p) {
+                problems.push(format!("Probe '{}' at ({}, {}, {}) is outside of the space.", probe.name, p.x, p.y, p.z));
+            }
+        }
+
+        for (i, source) in self.sources.iter().enumerate() {
+            if !self.contains(source.position) {
+                let p = source.position;
+                problems.push(format!("Source {} at ({}, {}, {}) is outside of the space.", i, p.x, p.y, p.z));
+            }
+            if !(source.direction.norm() > 0.5) && source.directivity != Directivity::Omni {
+                problems.push(format!("Source {} is aimed at its own position.", i));
+            }
+            for (j, other) in self.sources.iter().enumerate().skip(i + 1) {
+                if (source.position - other.position).norm() < 0.05 {
+                    problems.push(format!("Sources {} and {} overlap.", i, j));
+                }
+            }
+        }
+
+        if self.simulation.solver == Solver::Modal && Shoebox::of(self).is_none() {
+            problems.push(
+                "The modal solver needs a single rectangular room along the axes, without openings or obstacles."
+                    .to_string()
+            );
+        }
+
+        if problems.is_empty() { Ok(()) } else { Err(problems) }
+    }
+
+    /// Return the height of the floor at `p`, taking raised floor levels into account.
+    pub fn floor_height_at(&self, p: Vec2) -> f32 {
+        self.floor_levels
+            .iter()
+            .rev()
+            .find(|level| polygon_contains(&level.corners[..], p))
+            .map_or(0.0, |level| level.height)
+    }
+
+    /// Return whether the point lies inside one of the rooms, or inside the mesh.
+    pub fn contains(&self, p: Vec3) -> bool {
+        if self.rooms.is_empty() {
+            // Count how many faces a ray from `p` passes through. The direction
+            // is arbitrary, but an odd one makes it unlikely to hit an edge.
+            let q = p + Vec3::new(1.0e4, 0.731, 0.377);
+            let n = self.faces.iter().filter(|face| face.blocks(p, q)).count();
+            return n % 2 == 1
+        }
+
+        let q = Vec2::new(p.x, p.y);
+        let in_obstacle = self.obstacles.iter().any(|o| {
+            p.z < o.height && o.corners.len() >= 3 && polygon_contains(&o.corners[..], q)
+        });
+        p.z > 0.0 && p.z < self.height && !in_obstacle
+            && self.rooms.iter().any(|r| polygon_contains(&r.corners[..], q))
+    }
+
+    /// Return where the walls cross the horizontal plane at height `z`, as
+    /// line segments seen from above.
+    ///
+    /// For floor plans these are the walls, without the openings, and the
+    /// sides of obstacles that reach above `z`. For a mesh, every polygon that
+    /// crosses the plane contributes the segment where it does.
+    pub fn outline(&self, z: f32) -> Vec<(Vec2, Vec2)> {
+        let mut segments = Vec::new();
+
+        if self.rooms.is_empty() {
+            for face in &self.faces {
+                let n = face.polygon.len();
+                let crossings: Vec<Vec2> = (0..n)
+                    .filter_map(|i| {
+                        let (a, b) = (face.polygon[i], face.polygon[(i + 1) % n]);
+                        if (a.z < z) == (b.z < z) {
+                            return None
+                        }
+                        let p = a + (b - a) * ((z - a.z) / (b.z - a.z));
+                        Some(Vec2::new(p.x, p.y))
+                    })
+                    .collect();
+                // A convex polygon crosses the plane at two edges.
+                if crossings.len() == 2 {
+                    segments.push((crossings[0], crossings[1]));
+                }
+            }
+            return segments
+        }
+
+        for room in &self.rooms {
+            let n = room.corners.len();
+            for i in (0..n).filter(|i| !room.openings.contains(i)) {
+                segments.push((room.corners[i], room.corners[(i + 1) % n]));
+            }
+        }
+        for obstacle in self.obstacles.iter().filter(|o| o.height > z) {
+            let n = obstacle.corners.len();
+            // A free-standing wall has one side, not two that coincide.
+            let n_sides = if n == 2 { 1 } else { n };
+            for i in 0..n_sides {
+                segments.push((obstacle.corners[i], obstacle.corners[(i + 1) % n]));
+            }
+        }
+
+        segments
+    }
+
+    /// Return the body of the listener, if there is one.
+    fn body(&self) -> Option<&Body> {
+        self.listener.as_ref().and_then(|listener| listener.body.as_ref())
+    }
+
+    /// Return when the direct sound of source `si` arrives at `position`, in ms.
+    ///
+    /// The time includes the delay of the source. If a wall that lets nothing
+    /// through blocks the direct path, the sound only arrives by reflection,
+    /// and this returns `None`.
+    pub fn arrival_ms(&self, si: usize, position: Vec3) -> Option<f32> {
+        let source = &self.sources[si];
+        if self.transmission(source.position, position) == 0.0 {
+            return None
+        }
+        let distance = (position - source.position).norm();
+        Some(distance / self.atmosphere.speed_of_sound() * 1000.0 + source.delay_ms)
+    }
+
+    /// Return whether the segment from `p` to `q` passes through a wall.
+    fn is_occluded(&self, p: Vec3, q: Vec3) -> bool {
+        self.occluders.iter().any(|&i| self.faces[i].blocks(p, q))
+    }
+
+    /// Return the factor that the amplitude along the segment from `p` to `q`
+    /// is multiplied by, for the walls that it passes through.
+    ///
+    /// This is 1.0 if the segment passes through no wall, and 0.0 if it
+    /// passes through a wall that lets nothing through.
+    fn transmission(&self, p: Vec3, q: Vec3) -> f32 {
+        let mut gain = 1.0;
+        for &i in &self.occluders {
+            let face = &self.faces[i];
+            if face.blocks(p, q) {
+                gain *= face.transmission;
+                if gain == 0.0 {
+                    return 0.0
+                }
+            }
+        }
+        gain
+    }
+
+    /// Return the factor that the absorbers on face `fi` multiply the
+    /// reflection at `p` by, relative to the reflection off the face.
+    ///
+    /// Absorbers reflect with -1 away from their resonance, like a hard wall,
+    /// so this factor is 1.0 there, and where there is no absorber.
+    fn absorber_factor(&self, fi: usize, frequency: f32, p: Vec3) -> Complex {
+        let mut factor = Complex::new(1.0, 0.0);
+        for absorber in &self.absorbers {
+            if absorber.face == fi && absorber.contains(&self.faces[fi], p) {
+                factor = factor * (Complex::zero() - absorber.reflectivity_at(frequency));
+            }
+        }
+        factor
+    }
+
+    /// Return the equivalent absorption area that the absorbers add at `frequency`, in square meters.
+    ///
+    /// Part of the energy that reaches an absorber is absorbed by the face
+    /// behind it too, that part is not counted again.
+    pub fn absorber_area(&self, frequency: f32) -> f32 {
+        self.absorbers
+            .iter()
+            .map(|a| {
+                let face = self.faces[a.face].reflectivity_at(frequency).norm_squared();
+                a.area() * a.absorption_at(frequency) * face
+            })
+            .sum()
+    }
+
+    /// Return the gain along a reflection path, or None if it is impossible.
+    ///
+    /// The path starts at `source`, and reflects off the faces `path[n - 1]`
+    /// down to `path[0]`, before reaching `images[0]`. Element `i + 1` of
+    /// `images` is `images[i]` reflected in face `path[i]`, so the straight
+    /// line from the source to `images[n]` is the unfolded path. For the path
+    /// to be valid, every reflection point must lie within the bounds of the
+    /// face that it reflects off; an image source that "sees" the listener
+    /// only through the extension of a wall beyond its end does not count.
+    /// Furthermore, no part of the path may pass through a wall, unless the
+    /// wall lets sound through. In a room that is not convex, or between
+    /// coupled rooms, this means that sound can otherwise only go around
+    /// corners and through openings by reflecting.
+    ///
+    /// The gain accounts for the walls that the path passes through, for the
+    /// absorbers that it reflects off, and for the body, if there is one, and
+    /// is 1.0 otherwise. Reflection coefficients of the faces and the distance
+    /// are not included.
+    pub fn path_gain(&self, frequency: f32, source: Vec3, images: &[Vec3], path: &[usize]) -> Option<Complex> {
+        let mut gain = [Complex::zero()];
+        if self.path_gains(&[frequency], source, images, path, &mut gain) {
+            Some(gain[0])
+        } else {
+            None
+        }
+    }
+
+    /// Like `path_gain()`, but at every frequency in `frequencies` at once.
+    ///
+    /// The reflection points only need to be found once. Stores the gains in
+    /// `gains` and returns true, or returns false if the path is impossible.
+    pub fn path_gains(
+        &self,
+        frequencies: &[f32],
+        source: Vec3,
+        images: &[Vec3],
+        path: &[usize],
+        gains: &mut [Complex],
+    ) -> bool {
+        assert_eq!(images.len(), path.len() + 1);
+        assert_eq!(frequencies.len(), gains.len());
+        for g in gains.iter_mut() {
+            *g = Complex::new(1.0, 0.0);
+        }
 
-            faces: vec![
-                // Walls.
-                Face::new(p0, p1, up),
-                Face::new(p1, p2, up),
-                Face::new(p2, p3, up),
-                Face::new(p3, p0, up),
+        // Walk the path back from the source. The segment from the previous
+        // reflection point to the next image crosses the plane of the next face
+        // at the next reflection point. The walls that the path passes through
+        // do not depend on the frequency, the body and the absorbers do.
+        let body = self.body();
+        let mut transmission = 1.0;
+        let mut from = source;
+        for i in (0..path.len()).rev() {
+            let face = &self.faces[path[i]];
+            match face.intersect(from, images[i + 1]) {
+                Some(q) if face.bounds_contain(q) => {
+                    transmission *= self.transmission(from, q);
+                    if transmission == 0.0 {
+                        return false
+                    }
+                    for (g, &frequency) in gains.iter_mut().zip(frequencies) {
+                        if let Some(body) = body {
+                            *g = *g * body.transmission(&self.atmosphere, frequency, from, q);
+                        }
+                        if !self.absorbers.is_empty() {
+                            *g = *g * self.absorber_factor(path[i], frequency, q);
+                        }
+                    }
+                    from = q;
+                }
+                _ => return false,
+            }
+        }
 
-                // Floor and ceiling.
-                Face::new(p0, p1, -side),
-                Face::new(p0 + ceil_off, p1 + ceil_off, side),
-            ],
+        transmission *= self.transmission(from, images[0]);
+        if transmission == 0.0 {
+            return false
         }
+
+        for (g, &frequency) in gains.iter_mut().zip(frequencies) {
+            if let Some(body) = body {
+                *g = *g * body.transmission(&self.atmosphere, frequency, from, images[0]);
+            }
+            *g = *g * transmission;
+        }
+        true
     }
 
     /// See `Source::sample_at()`.
     pub fn sample_at(&self, rng: &mut Rng, frequency: f32, position: Vec3) -> Complex {
-        // Sample first order reflections.
-        for face in &self.faces {
-            if !face.is_facing(position) {
-                return Complex::zero();
+        let si = rng.index(&self.sources[..]);
+        self.sample_source_at(rng, si, frequency, position)
+    }
+
+    /// Like `sample_at()`, at every frequency in `frequencies`, into `out`.
+    ///
+    /// The frequencies share the source and the paths, see
+    /// `sample_source_at_frequencies()`.
+    pub fn sample_at_frequencies(&self, rng: &mut Rng, frequencies: &[f32], position: Vec3, out: &mut [Complex]) {
+        let si = rng.index(&self.sources[..]);
+        self.sample_source_at_frequencies(rng, si, frequencies, position, out)
+    }
+
+    /// Return whether source `si` is audible, taking mute and solo into account.
+    ///
+    /// If any source is soloed, only soloed sources are audible.
+    pub fn is_audible(&self, si: usize) -> bool {
+        let any_solo = self.sources.iter().any(|s| s.solo);
+        let source = &self.sources[si];
+        !source.muted && (source.solo || !any_solo)
+    }
+
+    /// Sample the field produced by source `si` alone, including reflections.
+    ///
+    /// Reflections up to `exact_order` are computed exactly, by enumerating
+    /// all image sources, only the higher orders are sampled. With the modal
+    /// solver, the field of a shoebox room is computed exactly, and `rng` is
+    /// not used. A source that is not audible produces no field.
+    pub fn sample_source_at(&self, rng: &mut Rng, si: usize, frequency: f32, position: Vec3) -> Complex {
+        let mut z = [Complex::zero()];
+        self.sample_source_at_frequencies(rng, si, &[frequency], position, &mut z);
+        z[0]
+    }
+
+    /// Like `sample_source_at()`, at every frequency in `frequencies`, into `out`.
+    ///
+    /// The geometry of a path does not depend on the frequency, only the phase
+    /// and the reflection coefficients along it do. So rather than sampling a
+    /// path per frequency, we sample a path per group of up to
+    /// `FREQUENCIES_PER_PATH` frequencies, and evaluate it at all of them. This
+    /// is much cheaper for a band or a sweep. The samples of the frequencies in
+    /// a group are correlated, but every one of them is still unbiased.
+    pub fn sample_source_at_frequencies(
+        &self,
+        rng: &mut Rng,
+        si: usize,
+        frequencies: &[f32],
+        position: Vec3,
+        out: &mut [Complex],
+    ) {
+        assert_eq!(frequencies.len(), out.len());
+        let groups = frequencies.chunks(FREQUENCIES_PER_PATH).zip(out.chunks_mut(FREQUENCIES_PER_PATH));
+        for (group, z) in groups {
+            self.sample_path(rng, si, group, position, z);
+        }
+    }
+
+    /// Sample a single path for `sample_source_at_frequencies()`, for at most
+    /// `FREQUENCIES_PER_PATH` frequencies.
+    fn sample_path(&self, rng: &mut Rng, si: usize, frequencies: &[f32], position: Vec3, out: &mut [Complex]) {
+        for z in out.iter_mut() {
+            *z = Complex::zero();
+        }
+        if !self.contains(position) || !self.is_audible(si) {
+            return
+        }
+
+        if self.simulation.solver == Solver::Modal {
+            if let Some(shoebox) = Shoebox::of(self) {
+                for (z, &frequency) in out.iter_mut().zip(frequencies) {
+                    *z = shoebox.field_at(self, si, frequency, position);
+                }
+                return
             }
         }
 
-        let reflectivity = -0.95;
+        let source = &self.sources[si];
+        let exact_order = self.simulation.exact_order.min(MAX_EXACT_ORDER) as usize;
+
+        // The length of the unfolded path is the distance from the source to
+        // the image of the listener, so a time limit is a limit on the distance.
+        let (max_bounces, max_distance) = match self.simulation.max_time_ms {
+            Some(t) => (MAX_TIMED_BOUNCES, t * 1e-3 * self.atmosphere.speed_of_sound()),
+            None => (MAX_BOUNCES, std::f32::INFINITY),
+        };
+
+        // The listener position reflected in the faces of the path so far, and
+        // the faces themselves, to check the validity of the path.
+        let mut images = [Vec3::zero(); MAX_TIMED_BOUNCES + 1];
+        let mut path = [0; MAX_TIMED_BOUNCES];
+        images[0] = position;
+
+        let n = frequencies.len();
+        let mut amplitude = [Complex::new(1.0 / 4096.0, 0.0); FREQUENCIES_PER_PATH];
+        self.sum_image_sources(
+            source,
+            frequencies,
+            &mut images,
+            &mut path,
+            0,
+            exact_order,
+            max_distance,
+            &amplitude[..n],
+            out,
+        );
+
+        // The edges are divided into pieces per wavelength, so diffraction
+        // does not share the paths.
+        if self.simulation.diffraction {
+            for (z, &frequency) in out.iter_mut().zip(frequencies) {
+                *z = *z + self.sum_diffraction(source, frequency, position, max_distance) * amplitude[0];
+            }
+        }
 
         // The incoming energy is the sum over all paths that start at the
         // source and end at the listener. We can partition the set of all paths
@@ -159,35 +1585,55 @@ impl Scene {
         // n=0 we have a direct path, at n=1 we can bounce via any of the faces,
         // at n=1 we can bounce via any of the faces first, and then through a
         // different face, etc. The number of paths blows up as num_faces^n, so
-        // enumerating them quickly becomes infeasible; we need to sample. And
-        // while we're sampling for a given n, we need to compute the
-        // reflections for n-1 anyway, so we might as well sample n-1 at the
-        // same time. The set of paths with n+1 bounces is num_faces-1 times as
-        // large as the set of paths with n bounces, so for every path with n+1
-        // bounces, if we take its prefix of n bounces into account too, then
-        // the weight of the path with n+1 bounces should be num_faces-1 times
-        // as large.
-        let factor = reflectivity * (self.faces.len() - 1) as f32;
-
-        let si = rng.index(&self.sources[..]);
-        let source = &self.sources[si];
+        // enumerating them quickly becomes infeasible beyond `exact_order`; we
+        // need to sample. And while we're sampling for a given n, we need to
+        // compute the reflections for n-1 anyway, so we might as well sample
+        // n-1 at the same time. There are num_faces paths with one bounce, and
+        // the set of paths with n+1 bounces is num_faces-1 times as large as
+        // the set of paths with n bounces, so for every path with n+1 bounces,
+        // if we take its prefix of n bounces into account too, then the weight
+        // of the path with n+1 bounces should be num_faces-1 times as large.
+        // On top of that, every bounce multiplies by the (complex) reflection
+        // coefficient of the face. Openings do not reflect, so they do not
+        // count as faces here.
+        let n_faces = self.reflectors.len() as f32;
+        let factor = n_faces - 1.0;
 
-        let mut z = Complex::zero();
         let mut p = position;
-        let mut amplitude = 1.0 / 4096.0;
-        let mut fi = rng.index(&self.faces[..]);
+        let mut gains = [Complex::zero(); FREQUENCIES_PER_PATH];
+        let mut fi = self.reflectors[rng.index(&self.reflectors[..])];
+
+        // The later bounces are much weaker than the direct sound, so sum them
+        // with compensation, see `complex::kahan_add()`.
+        let mut compensation = [Complex::zero(); FREQUENCIES_PER_PATH];
 
         // We go for up to 56 bounces. With walls of 3m long, that amounts to
-        // about 500ms.
-        for bounce in 0..30 {
-            // Directly, from source to listener.
-            let m = source.sample_at(frequency, p);
-            z = z + m * amplitude;
+        // about 500ms. With a time limit, we stop at the first image that is
+        // too far away. Images further along the walk are nearly always
+        // further away still, because every reflection moves the image away.
+        for bounce in 0..max_bounces {
+            if (p - source.position).norm() > max_distance {
+                break
+            }
+
+            // Directly, from source to listener, via the faces so far. Not all
+            // sequences of faces form a path that sound can actually take.
+            // Paths up to the exact order have been summed already.
+            if bounce > exact_order {
+                let gains = &mut gains[..n];
+                if self.path_gains(frequencies, source.position, &images[..bounce + 1], &path[..bounce], gains) {
+                    for k in 0..n {
+                        let m = source.sample_at(&self.atmosphere, frequencies[k], p);
+                        complex::kahan_add(&mut out[k], &mut compensation[k], m * amplitude[k] * gains[k]);
+                    }
+                }
+            }
 
             // Pick a face to reflect from, which should not be the same face
-            // that we reflected from last time.
+            // that we reflected from last time. For the first bounce, this
+            // excludes a random face, so every face is equally likely.
             loop {
-                let next_fi = rng.index(&self.faces[..]);
+                let next_fi = self.reflectors[rng.index(&self.reflectors[..])];
                 if next_fi != fi {
                     fi = next_fi;
                     break;
@@ -196,9 +1642,103 @@ impl Scene {
 
             let face = &self.faces[fi];
             p = face.reflect(p);
-            amplitude *= factor;
+            path[bounce] = fi;
+            images[bounce + 1] = p;
+            let weight = if bounce == 0 { n_faces } else { factor };
+            for k in 0..n {
+                amplitude[k] = amplitude[k] * face.reflectivity_at(frequencies[k]) * weight;
+            }
         }
+    }
 
+    /// Sum the sound that reaches `position` from `source` by bending around
+    /// an edge once, along paths up to `max_distance`.
+    ///
+    /// Every piece of every edge acts as a secondary source, fed by the direct
+    /// sound, see `Edge::coefficient()`. The paths from the source to the
+    /// piece and on to `position` must not pass through a wall. Like the
+    /// paths of reflections, the path is unfolded into a straight line from
+    /// the source, towards the piece of the edge, so its level falls off in
+    /// the same way. Paths that reflect before or after the edge are left out.
+    fn sum_diffraction(&self, source: &Source, frequency: f32, position: Vec3, max_distance: f32) -> Complex {
+        let wavelength = self.atmosphere.speed_of_sound() / frequency;
+        let mut z = Complex::zero();
+        for edge in &self.edges {
+            let (points, dz) = edge.segments(wavelength);
+            for &point in &points {
+                let m = (point - source.position).norm();
+                let l = (position - point).norm();
+                if m + l > max_distance || self.is_occluded(source.position, point) || self.is_occluded(point, position) {
+                    continue
+                }
+                if let Some(d) = edge.coefficient(source.position, position, point, dz) {
+                    let unfolded = source.position + (point - source.position) * ((m + l) / m);
+                    z = z + source.sample_at(&self.atmosphere, frequency, unfolded) * d;
+                }
+            }
+        }
         z
     }
+
+    /// Add the contributions of all paths that extend the first `depth`
+    /// reflections in `path`, up to `max_order` reflections in total, to `out`.
+    /// Paths longer than `max_distance` are left out.
+    ///
+    /// This is the image source method: `images[depth]` is the listener
+    /// reflected in the faces of the path so far, and every sequence of faces
+    /// without repeats in a row is a candidate path. `amplitude` includes the
+    /// reflection coefficients of the path so far, for every frequency in
+    /// `frequencies`, at most `FREQUENCIES_PER_PATH` of them.
+    fn sum_image_sources(
+        &self,
+        source: &Source,
+        frequencies: &[f32],
+        images: &mut [Vec3],
+        path: &mut [usize],
+        depth: usize,
+        max_order: usize,
+        max_distance: f32,
+        amplitude: &[Complex],
+        out: &mut [Complex],
+    ) {
+        let n = frequencies.len();
+        if (images[depth] - source.position).norm() <= max_distance {
+            let mut gains = [Complex::zero(); FREQUENCIES_PER_PATH];
+            let gains = &mut gains[..n];
+            if self.path_gains(frequencies, source.position, &images[..depth + 1], &path[..depth], gains) {
+                for k in 0..n {
+                    let m = source.sample_at(&self.atmosphere, frequencies[k], images[depth]);
+                    out[k] = out[k] + m * amplitude[k] * gains[k];
+                }
+            }
+        }
+
+        if depth == max_order {
+            return
+        }
+
+        for &fi in &self.reflectors {
+            if depth > 0 && path[depth - 1] == fi {
+                continue
+            }
+            let face = &self.faces[fi];
+            path[depth] = fi;
+            images[depth + 1] = face.reflect(images[depth]);
+            let mut reflected = [Complex::zero(); FREQUENCIES_PER_PATH];
+            for k in 0..n {
+                reflected[k] = amplitude[k] * face.reflectivity_at(frequencies[k]);
+            }
+            self.sum_image_sources(
+                source,
+                frequencies,
+                images,
+                path,
+                depth + 1,
+                max_order,
+                max_distance,
+                &reflected[..n],
+                out,
+            );
+        }
+    }
 }
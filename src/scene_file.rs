@@ -0,0 +1,1309 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::absorber::{self, Absorber, AbsorberKind};
+use crate::array::{self, ArrayKind};
+use crate::atmosphere::Atmosphere;
+use crate::complex::Complex;
+use crate::eq::{Band, Biquad, Crossover, CrossoverKind, FilterKind, Rolloff};
+use crate::frd::FrequencyResponse;
+use crate::material::{self, Material};
+use crate::obj;
+use crate::scene::{
+    Body, Directivity, Face, FloorLevel, Listener, ListeningArea, Obstacle, Port, Probe, Room, Scene,
+    Simulation, Solver, Source, MAX_EXACT_ORDER,
+};
+use crate::shell;
+use crate::target::Target;
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// An error that occurred while loading a scene file.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io(io::Error),
+
+    /// The file is not valid TOML.
+    Toml(toml::de::Error),
+
+    /// The file is valid TOML, but it does not describe a valid scene.
+    Invalid(String),
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for LoadError {
+    fn from(err: toml::de::Error) -> LoadError {
+        LoadError::Toml(err)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref err) => write!(f, "{}", err),
+            LoadError::Toml(ref err) => write!(f, "{}", err),
+            LoadError::Invalid(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, LoadError>;
+
+pub fn invalid<T>(msg: String) -> Result<T> {
+    Err(LoadError::Invalid(msg))
+}
+
+/// Return the scene if it passes `Scene::validate()`, or all problems as one error.
+fn validated(scene: Scene) -> Result<Scene> {
+    match scene.validate() {
+        Ok(()) => Ok(scene),
+        Err(problems) => invalid(problems.join("\n")),
+    }
+}
+
+/// Look up `key` in `table`, where `path` is the path of `table` for use in errors.
+pub fn get<'a>(table: &'a Value, path: &str, key: &str) -> Result<&'a Value> {
+    match table.get(key) {
+        Some(value) => Ok(value),
+        None if path.is_empty() => invalid(format!("Missing '{}'.", key)),
+        None => invalid(format!("Missing '{}.{}'.", path, key)),
+    }
+}
+
+pub fn as_f32(value: &Value, path: &str) -> Result<f32> {
+    match *value {
+        Value::Float(x) => Ok(x as f32),
+        Value::Integer(x) => Ok(x as f32),
+        _ => invalid(format!("Expected '{}' to be a number.", path)),
+    }
+}
+
+/// Interpret `value` as an array of exactly `n` numbers.
+fn as_f32s(value: &Value, path: &str, n: usize) -> Result<Vec<f32>> {
+    match value.as_array() {
+        Some(xs) if xs.len() == n => xs.iter().map(|x| as_f32(x, path)).collect(),
+        _ => invalid(format!("Expected '{}' to be an array of {} numbers.", path, n)),
+    }
+}
+
+fn as_vec2(value: &Value, path: &str) -> Result<Vec2> {
+    let xs = as_f32s(value, path, 2)?;
+    Ok(Vec2::new(xs[0], xs[1]))
+}
+
+fn as_vec3(value: &Value, path: &str) -> Result<Vec3> {
+    let xs = as_f32s(value, path, 3)?;
+    Ok(Vec3::new(xs[0], xs[1], xs[2]))
+}
+
+/// Interpret `value` as a complex number, either a number or a `[re, im]` pair.
+fn as_complex(value: &Value, path: &str) -> Result<Complex> {
+    match value.as_array() {
+        Some(_) => {
+            let xs = as_f32s(value, path, 2)?;
+            Ok(Complex::new(xs[0], xs[1]))
+        }
+        None => Ok(Complex::new(as_f32(value, path)?, 0.0)),
+    }
+}
+
+pub fn as_usize(value: &Value, path: &str) -> Result<usize> {
+    match *value {
+        Value::Integer(x) if x >= 0 => Ok(x as usize),
+        _ => invalid(format!("Expected '{}' to be a non-negative integer.", path)),
+    }
+}
+
+pub fn as_array<'a>(value: &'a Value, path: &str) -> Result<&'a [Value]> {
+    match value.as_array() {
+        Some(xs) => Ok(&xs[..]),
+        None => invalid(format!("Expected '{}' to be an array.", path)),
+    }
+}
+
+pub fn as_bool(value: &Value, path: &str) -> Result<bool> {
+    match value.as_bool() {
+        Some(b) => Ok(b),
+        None => invalid(format!("Expected '{}' to be true or false.", path)),
+    }
+}
+
+pub fn as_str<'a>(value: &'a Value, path: &str) -> Result<&'a str> {
+    match value.as_str() {
+        Some(s) => Ok(s),
+        None => invalid(format!("Expected '{}' to be a string.", path)),
+    }
+}
+
+/// Parse the `[material.<name>]` tables.
+///
+/// A material has exactly one of these keys:
+///
+/// * `absorption`, with one absorption coefficient per octave band.
+/// * `reflectivity`, with a single reflection coefficient, either a number or
+///   a complex `[re, im]` pair.
+/// * `impedance`, with the normalized surface impedance as a complex pair, or
+///   one pair per octave band.
+pub fn parse_materials(root: &Value) -> Result<HashMap<String, Material>> {
+    let mut materials = HashMap::new();
+
+    let table = match root.get("material") {
+        None => return Ok(materials),
+        Some(t) => match t.as_table() {
+            Some(t) => t,
+            None => return invalid("Expected 'material' to be a table.".to_string()),
+        },
+    };
+
+    for (name, m) in table.iter() {
+        let path = format!("material.{}", name);
+        let material = match (m.get("absorption"), m.get("reflectivity"), m.get("impedance")) {
+            (Some(a), None, None) => {
+                let a = as_f32s(a, &format!("{}.absorption", path), material::N_BANDS)?;
+                let mut absorption = [0.0; material::N_BANDS];
+                absorption.copy_from_slice(&a[..]);
+                Material::from_absorption(name.clone(), absorption)
+            }
+            (None, Some(r), None) => {
+                let r = as_complex(r, &format!("{}.reflectivity", path))?;
+                Material::constant(name.clone(), r)
+            }
+            (None, None, Some(z)) => {
+                let z_path = format!("{}.impedance", path);
+                let mut impedance = [Complex::zero(); material::N_BANDS];
+                match z.as_array() {
+                    Some(zs) if zs.len() == material::N_BANDS => {
+                        for (dst, z) in impedance.iter_mut().zip(zs) {
+                            *dst = as_complex(z, &z_path)?;
+                        }
+                    }
+                    _ => {
+                        let z = as_complex(z, &z_path)?;
+                        for dst in impedance.iter_mut() {
+                            *dst = z;
+                        }
+                    }
+                }
+                Material::from_impedance(name.clone(), impedance)
+            }
+            _ => return invalid(format!(
+                "Expected '{}' to have one of 'absorption', 'reflectivity', or 'impedance'.", path
+            )),
+        };
+        materials.insert(name.clone(), material);
+    }
+
+    Ok(materials)
+}
+
+/// Resolve a material name, first in the scene file, then in the built-ins.
+pub fn lookup_material(materials: &HashMap<String, Material>, value: &Value, path: &str) -> Result<Material> {
+    let name = as_str(value, path)?;
+    match materials.get(name) {
+        Some(m) => Ok(m.clone()),
+        None => match Material::builtin(name) {
+            Some(m) => Ok(m),
+            None => invalid(format!(
+                "Unknown material '{}' in '{}'. Define it in a [material.{}] table, \
+                or use one of the built-in materials: {}.",
+                name, path, name, Material::builtin_names().join(", "),
+            )),
+        },
+    }
+}
+
+/// Apply the `<suffix>`, `wall_<suffix>`, `floor_<suffix>`, and
+/// `ceiling_<suffix>` keys of a room, for suffix "material", "reflectivity",
+/// or "transmission". The parsed values are applied to a face with `set`.
+///
+/// `walls` are the indices of the walls of the room. The rooms share the floor
+/// and the ceiling, the keys that do not name a face apply to them only for the
+/// first room.
+fn apply_face_keys<T: Clone>(
+    scene: &mut Scene,
+    room: &Value,
+    path: &str,
+    walls: Range<usize>,
+    is_first: bool,
+    suffix: &str,
+    parse: &dyn Fn(&Value, &str) -> Result<T>,
+    set: &dyn Fn(&mut Face, T),
+) -> Result<()> {
+    let floor = scene.n_walls();
+    let ceiling = floor + 1;
+
+    if let Some(m) = room.get(suffix) {
+        let m = parse(m, &format!("{}.{}", path, suffix))?;
+        for face in scene.faces[walls.clone()].iter_mut() {
+            set(face, m.clone());
+        }
+        if is_first {
+            set(&mut scene.faces[floor], m.clone());
+            set(&mut scene.faces[ceiling], m);
+        }
+    }
+
+    let key = format!("wall_{}", suffix);
+    if let Some(ms) = room.get(&key[..]) {
+        let key_path = format!("{}.{}", path, key);
+        let ms = as_array(ms, &key_path)?;
+        if ms.len() != walls.len() {
+            return invalid(format!("Expected '{}' to have {} elements, one per wall.", key_path, walls.len()))
+        }
+        for (face, m) in scene.faces[walls].iter_mut().zip(ms) {
+            set(face, parse(m, &key_path)?);
+        }
+    }
+
+    for &(name, fi) in &[("floor", floor), ("ceiling", ceiling)] {
+        let key = format!("{}_{}", name, suffix);
+        if let Some(m) = room.get(&key[..]) {
+            set(&mut scene.faces[fi], parse(m, &format!("{}.{}", path, key))?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse the `port` of `source`, and return the source that models the port.
+fn parse_port(value: &Value, source: &Source, path: &str) -> Result<Source> {
+    let tuning = as_f32(get(value, path, "tuning")?, &format!("{}.tuning", path))?;
+    if !(tuning > 0.0) {
+        return invalid(format!("Expected '{}.tuning' to be positive.", path))
+    }
+    let q_loss = match value.get("q_loss") {
+        Some(q) => as_f32(q, &format!("{}.q_loss", path))?,
+        None => 7.0,
+    };
+    if !(q_loss > 0.0) {
+        return invalid(format!("Expected '{}.q_loss' to be positive.", path))
+    }
+
+    // The port shares the signal chain of the cone, but it radiates from its
+    // own position, in all directions.
+    let mut port = source.clone();
+    port.position = as_vec3(get(value, path, "position")?, &format!("{}.position", path))?;
+    port.directivity = Directivity::Omni;
+    if let Some(g) = value.get("gain_db") {
+        port.gain_db += as_f32(g, &format!("{}.gain_db", path))?;
+    }
+    port.port = Some(Port {
+        tuning_hz: tuning,
+        q_loss: q_loss,
+    });
+    Ok(port)
+}
+
+/// Parse the `array` of source table `s`, and return its kind and spacing.
+fn parse_array(s: &Value, value: &Value, path: &str) -> Result<(ArrayKind, f32)> {
+    let kind = match as_str(value, &format!("{}.array", path))? {
+        "cardioid" => ArrayKind::Cardioid,
+        "end_fire" => {
+            let count = match s.get("array_count") {
+                Some(n) => as_usize(n, &format!("{}.array_count", path))?,
+                None => 4,
+            };
+            if count < 2 {
+                return invalid(format!("Expected '{}.array_count' to be at least 2.", path))
+            }
+            ArrayKind::EndFire { count: count }
+        }
+        _ => return invalid(format!("Expected '{}.array' to be \"cardioid\" or \"end_fire\".", path)),
+    };
+    let spacing = match s.get("array_spacing") {
+        Some(x) => as_f32(x, &format!("{}.array_spacing", path))?,
+        None => 1.0,
+    };
+    if !(spacing > 0.0) {
+        return invalid(format!("Expected '{}.array_spacing' to be positive.", path))
+    }
+    Ok((kind, spacing))
+}
+
+/// Parse the `[atmosphere]` table.
+fn parse_atmosphere(value: &Value) -> Result<Atmosphere> {
+    let get_or = |key: &str, default: f32| match value.get(key) {
+        Some(x) => as_f32(x, &format!("atmosphere.{}", key)),
+        None => Ok(default),
+    };
+    let temperature = get_or("temperature", 25.0)?;
+    let pressure = get_or("pressure", 101.325)?;
+    let humidity = get_or("humidity", 50.0)?;
+    if !(temperature > -273.15) {
+        return invalid("Expected 'atmosphere.temperature' to be above absolute zero.".to_string())
+    }
+    if !(pressure > 0.0) {
+        return invalid("Expected 'atmosphere.pressure' to be positive.".to_string())
+    }
+    if !(humidity >= 0.0 && humidity <= 100.0) {
+        return invalid("Expected 'atmosphere.humidity' to be between 0 and 100.".to_string())
+    }
+    Ok(Atmosphere::new(temperature, pressure, humidity))
+}
+
+/// Parse the `[simulation]` table.
+fn parse_simulation(value: &Value) -> Result<Simulation> {
+    let mut simulation = Simulation::standard();
+    if let Some(x) = value.get("exact_order") {
+        let exact_order = as_usize(x, "simulation.exact_order")?;
+        if exact_order > MAX_EXACT_ORDER as usize {
+            return invalid(format!("Expected 'simulation.exact_order' to be at most {}.", MAX_EXACT_ORDER))
+        }
+        simulation.exact_order = exact_order as u32;
+    }
+    if let Some(x) = value.get("solver") {
+        simulation.solver = match as_str(x, "simulation.solver")? {
+            "path_tracing" => Solver::PathTracing,
+            "modal" => Solver::Modal,
+            "fdtd" => Solver::Fdtd,
+            _ => return invalid(
+                "Expected 'simulation.solver' to be \"path_tracing\", \"modal\", or \"fdtd\".".to_string()
+            ),
+        };
+    }
+    if let Some(x) = value.get("max_time_ms") {
+        let max_time_ms = as_f32(x, "simulation.max_time_ms")?;
+        if !(max_time_ms > 0.0) {
+            return invalid("Expected 'simulation.max_time_ms' to be positive.".to_string())
+        }
+        simulation.max_time_ms = Some(max_time_ms);
+    }
+    if let Some(x) = value.get("fdtd_spacing") {
+        let spacing = as_f32(x, "simulation.fdtd_spacing")?;
+        if !(spacing >= 0.01) {
+            return invalid("Expected 'simulation.fdtd_spacing' to be at least 0.01 m.".to_string())
+        }
+        simulation.fdtd.spacing = spacing;
+    }
+    if let Some(x) = value.get("fdtd_courant") {
+        let courant = as_f32(x, "simulation.fdtd_courant")?;
+        if !(courant > 0.0 && courant <= 1.0) {
+            return invalid("Expected 'simulation.fdtd_courant' to be above 0, at most 1.".to_string())
+        }
+        simulation.fdtd.courant = courant;
+    }
+    if let Some(x) = value.get("diffraction") {
+        simulation.diffraction = as_bool(x, "simulation.diffraction")?;
+    }
+    Ok(simulation)
+}
+
+/// Parse the `[[probe]]` tables.
+fn parse_probes(root: &Value) -> Result<Vec<Probe>> {
+    let values = match root.get("probe") {
+        Some(p) => as_array(p, "probe")?,
+        None => return Ok(Vec::new()),
+    };
+    let mut probes = Vec::new();
+    for (i, p) in values.iter().enumerate() {
+        let path = format!("probe[{}]", i);
+        let name = match p.get("name") {
+            Some(n) => as_str(n, &format!("{}.name", path))?.to_string(),
+            None => format!("Probe {}", i + 1),
+        };
+        probes.push(Probe {
+            name: name,
+            position: as_vec3(get(p, &path, "position")?, &format!("{}.position", path))?,
+        });
+    }
+    Ok(probes)
+}
+
+/// Parse the `[[absorber]]` tables, and find the faces that the absorbers are on.
+fn parse_absorbers(root: &Value, scene: &Scene) -> Result<Vec<Absorber>> {
+    let values = match root.get("absorber") {
+        Some(a) => as_array(a, "absorber")?,
+        None => return Ok(Vec::new()),
+    };
+    let c = scene.atmosphere.speed_of_sound();
+    let mut absorbers = Vec::new();
+    for (i, a) in values.iter().enumerate() {
+        let path = format!("absorber[{}]", i);
+        let get_positive = |key: &str| {
+            let key_path = format!("{}.{}", path, key);
+            let x = as_f32(get(a, &path, key)?, &key_path)?;
+            if !(x > 0.0) {
+                return invalid(format!("Expected '{}' to be positive.", key_path))
+            }
+            Ok(x)
+        };
+
+        let type_path = format!("{}.type", path);
+        let kind = match as_str(get(a, &path, "type")?, &type_path)? {
+            "membrane" => AbsorberKind::Membrane,
+            "helmholtz" => AbsorberKind::Helmholtz,
+            _ => return invalid(format!("Expected '{}' to be \"membrane\" or \"helmholtz\".", type_path)),
+        };
+
+        let center = as_vec3(get(a, &path, "position")?, &format!("{}.position", path))?;
+        let face = match absorber::face_at(scene, center) {
+            Some(face) => face,
+            None => return invalid(format!("Expected '{}.position' to be on a face.", path)),
+        };
+        let size_path = format!("{}.size", path);
+        let size = as_vec2(get(a, &path, "size")?, &size_path)?;
+        if !(size.x > 0.0 && size.y > 0.0) {
+            return invalid(format!("Expected '{}' to be positive.", size_path))
+        }
+
+        let frequency = match (a.get("frequency"), kind) {
+            (Some(_), _) => get_positive("frequency")?,
+            (None, AbsorberKind::Membrane) => {
+                absorber::membrane_frequency(c, get_positive("mass")?, get_positive("depth")?)
+            }
+            (None, AbsorberKind::Helmholtz) => {
+                let open_area = get_positive("open_area")?;
+                if open_area > 1.0 {
+                    return invalid(format!("Expected '{}.open_area' to be at most 1.", path))
+                }
+                let thickness = get_positive("thickness")?;
+                let hole_radius = get_positive("hole_radius")?;
+                absorber::helmholtz_frequency(c, open_area, thickness, hole_radius, get_positive("depth")?)
+            }
+        };
+        let q = match (a.get("q"), kind) {
+            (Some(_), _) => get_positive("q")?,
+            (None, AbsorberKind::Membrane) => 4.0,
+            (None, AbsorberKind::Helmholtz) => 8.0,
+        };
+        let peak_absorption = match a.get("absorption") {
+            Some(_) => get_positive("absorption")?,
+            None => 0.9,
+        };
+        if peak_absorption > 1.0 {
+            return invalid(format!("Expected '{}.absorption' to be at most 1.", path))
+        }
+
+        absorbers.push(Absorber {
+            kind: kind,
+            face: face,
+            center: center,
+            width: size.x,
+            height: size.y,
+            frequency: frequency,
+            q: q,
+            peak_absorption: peak_absorption,
+        });
+    }
+    Ok(absorbers)
+}
+
+/// Parse the `[crossover]` table.
+fn parse_crossover(value: &Value) -> Result<Crossover> {
+    let kind = match as_str(get(value, "crossover", "type")?, "crossover.type")? {
+        "linkwitz_riley" => CrossoverKind::LinkwitzRiley,
+        "butterworth" => CrossoverKind::Butterworth,
+        _ => return invalid("Expected 'crossover.type' to be \"linkwitz_riley\" or \"butterworth\".".to_string()),
+    };
+    let frequency = as_f32(get(value, "crossover", "frequency")?, "crossover.frequency")?;
+    if !(frequency > 0.0) {
+        return invalid("Expected 'crossover.frequency' to be positive.".to_string())
+    }
+    let slope = match value.get("slope") {
+        Some(x) => as_usize(x, "crossover.slope")?,
+        None => 24,
+    };
+    let step = match kind {
+        CrossoverKind::LinkwitzRiley => 12,
+        CrossoverKind::Butterworth => 6,
+    };
+    if slope == 0 || slope > 48 || slope % step != 0 {
+        return invalid(format!("Expected 'crossover.slope' to be a multiple of {} dB, up to 48 dB.", step))
+    }
+    Ok(Crossover {
+        kind: kind,
+        frequency: frequency,
+        order: slope as u32 / 6,
+    })
+}
+
+/// Parse an EQ filter of a source.
+fn parse_biquad(value: &Value, path: &str) -> Result<Biquad> {
+    let kind = match as_str(get(value, path, "type")?, &format!("{}.type", path))? {
+        "peaking" => FilterKind::Peaking,
+        "low_shelf" => FilterKind::LowShelf,
+        "high_shelf" => FilterKind::HighShelf,
+        "high_pass" => FilterKind::HighPass,
+        "low_pass" => FilterKind::LowPass,
+        _ => return invalid(format!(
+            "Expected '{}.type' to be \"peaking\", \"low_shelf\", \"high_shelf\", \"high_pass\", or \"low_pass\".",
+            path
+        )),
+    };
+    let frequency = as_f32(get(value, path, "frequency")?, &format!("{}.frequency", path))?;
+    if !(frequency > 0.0 && frequency < 20_000.0) {
+        return invalid(format!("Expected '{}.frequency' to be between 0 and 20 kHz.", path))
+    }
+    let q = match value.get("q") {
+        Some(q) => as_f32(q, &format!("{}.q", path))?,
+        None => 0.707,
+    };
+    if !(q > 0.0) {
+        return invalid(format!("Expected '{}.q' to be positive.", path))
+    }
+    let gain_db = match (kind, value.get("gain_db")) {
+        (FilterKind::HighPass, _) | (FilterKind::LowPass, _) => 0.0,
+        (_, Some(g)) => as_f32(g, &format!("{}.gain_db", path))?,
+        (_, None) => return invalid(format!("Expected '{}' to have a 'gain_db'.", path)),
+    };
+    Ok(Biquad {
+        kind: kind,
+        frequency: frequency,
+        q: q,
+        gain_db: gain_db,
+    })
+}
+
+/// Parse the measured response of a source: a path to an FRD file, or points.
+pub fn parse_frd(value: &Value, dir: &Path, path: &str) -> Result<FrequencyResponse> {
+    let loaded = match value.as_str() {
+        Some(frd_path) => {
+            let frd_path = dir.join(frd_path);
+            FrequencyResponse::load(&frd_path).map_err(|err| (err, frd_path.display().to_string()))
+        }
+        None => {
+            let mut points = Vec::new();
+            for p in as_array(value, path)? {
+                let xs = as_array(p, path)?.iter().map(|x| as_f32(x, path)).collect::<Result<Vec<f32>>>()?;
+                match xs.len() {
+                    2 if xs[0] > 0.0 => points.push((xs[0], xs[1], 0.0)),
+                    3 if xs[0] > 0.0 => points.push((xs[0], xs[1], xs[2])),
+                    _ => return invalid(format!("Expected the points in '{}' to be [frequency, dB, phase].", path)),
+                }
+            }
+            FrequencyResponse::from_points(points).map_err(|err| (err, path.to_string()))
+        }
+    };
+    match loaded {
+        Ok(response) => Ok(response),
+        Err((err, name)) => invalid(format!("Failed to load frequency response '{}': {}", name, err)),
+    }
+}
+
+/// Build a scene from the `[mesh]` table of a scene file.
+///
+/// Relative paths are resolved against `dir`, the directory of the scene file.
+fn load_mesh(
+    mesh: &Value,
+    dir: &Path,
+    materials: &HashMap<String, Material>,
+    sources: Vec<Source>,
+) -> Result<Scene> {
+    let scale = match mesh.get("scale") {
+        Some(s) => as_f32(s, "mesh.scale")?,
+        None => 1.0,
+    };
+    let y_up = match mesh.get("up") {
+        None => true,
+        Some(up) => match as_str(up, "mesh.up")? {
+            "y" => true,
+            "z" => false,
+            _ => return invalid("Expected 'mesh.up' to be \"y\" or \"z\".".to_string()),
+        },
+    };
+
+    let default_material = match mesh.get("material") {
+        Some(m) => lookup_material(materials, m, "mesh.material")?,
+        None => Material::default_material(),
+    };
+
+    // Materials for parts of the model, by object or material name.
+    let mut part_materials = HashMap::new();
+    if let Some(t) = mesh.get("materials") {
+        let t = match t.as_table() {
+            Some(t) => t,
+            None => return invalid("Expected 'mesh.materials' to be a table.".to_string()),
+        };
+        for (name, m) in t.iter() {
+            let m = lookup_material(materials, m, &format!("mesh.materials.{}", name))?;
+            part_materials.insert(name.clone(), m);
+        }
+    }
+
+    // Bundles include the contents of the OBJ file, rather than its path.
+    let loaded = match mesh.get("obj") {
+        Some(obj) => obj::parse(as_str(obj, "mesh.obj")?, scale, y_up).map_err(|err| (err, "mesh.obj".to_string())),
+        None => {
+            let obj_path = dir.join(as_str(get(mesh, "mesh", "path")?, "mesh.path")?);
+            obj::load(&obj_path, scale, y_up).map_err(|err| (err, obj_path.display().to_string()))
+        }
+    };
+    let polygons = match loaded {
+        Ok(polygons) => polygons,
+        Err((err, name)) => return invalid(format!("Failed to load mesh '{}': {}", name, err)),
+    };
+
+    let faces = polygons
+        .into_iter()
+        .map(|polygon| {
+            let material = part_materials
+                .get(&polygon.object)
+                .or_else(|| part_materials.get(&polygon.material))
+                .unwrap_or(&default_material)
+                .clone();
+            let mut face = Face::new_polygon(polygon.vertices);
+            face.material = material;
+            face
+        })
+        .collect();
+
+    Ok(Scene::new_mesh(faces, sources))
+}
+
+impl Scene {
+    /// Load a scene from a TOML file.
+    ///
+    /// A scene file looks like this:
+    ///
+    /// ```toml
+    /// [room]
+    /// # Corners of the floor plan in meters, in order. The floor plan does
+    /// # not need to be convex.
+    /// corners = [[0.0, 0.0], [8.32, 0.0], [8.32, 3.35], [0.0, 3.35]]
+    /// height = 2.8
+    ///
+    /// # Optional materials. The room material applies to every face that has
+    /// # no material of its own. Wall i runs from corner i to corner i + 1.
+    /// material = "drywall"
+    /// wall_material = ["drywall", "drywall", "glass", "concrete"]
+    /// floor_material = "carpet"
+    /// ceiling_material = "drywall"
+    ///
+    /// # Alternatively, a frequency-independent reflection coefficient can be
+    /// # given directly. These take precedence over materials. A coefficient
+    /// # is a number, or a complex [re, im] pair.
+    /// reflectivity = -0.95
+    /// wall_reflectivity = [-0.95, -0.95, [-0.5, 0.3], -0.95]
+    /// floor_reflectivity = -0.8
+    /// ceiling_reflectivity = -0.9
+    ///
+    /// # Optional, the part of the amplitude that passes through a face, 0 by
+    /// # default. The energy that passes through is not reflected. In a scene
+    /// # with several rooms, it reaches the adjacent rooms, through the walls
+    /// # of both. With the same keys per face as the reflectivity.
+    /// transmission = 0.1
+    /// wall_transmission = [0.1, 0.1, 0.3, 0.0]
+    ///
+    /// # Custom materials, with absorption coefficients for the octave bands
+    /// # from 31.5 Hz up to 4 kHz.
+    /// [material.curtain]
+    /// absorption = [0.03, 0.05, 0.07, 0.31, 0.49, 0.75, 0.70, 0.60]
+    ///
+    /// # Or with a surface impedance, normalized to that of air, as a complex
+    /// # [re, im] pair, or one pair per octave band.
+    /// [material.panel]
+    /// impedance = [8.0, -12.0]
+    ///
+    /// # Instead of a single [room], a scene can have several coupled rooms,
+    /// # with the same keys. The rooms have the same height, and share the
+    /// # floor and ceiling. Where two rooms are connected, both have a wall
+    /// # that is an opening. To open up part of a wall, split it with an extra
+    /// # corner.
+    /// [[room]]
+    /// corners = [[0.0, 0.0], [5.0, 0.0], [5.0, 1.0], [5.0, 2.5], [5.0, 4.0], [0.0, 4.0]]
+    /// height = 2.6
+    /// openings = [2]
+    ///
+    /// [[room]]
+    /// corners = [[5.0, 1.0], [8.0, 1.0], [8.0, 4.0], [5.0, 4.0], [5.0, 2.5]]
+    /// height = 2.6
+    /// openings = [4]
+    ///
+    /// # Instead of rooms, the geometry can come from a closed mesh, a
+    /// # Wavefront OBJ file as exported by most modelling programs. Meshes are
+    /// # much slower to render than rooms built from floor plans.
+    /// [mesh]
+    /// # Relative to the directory of the scene file. Alternatively, `obj` is
+    /// # the contents of the file, as in bundles.
+    /// path = "studio.obj"
+    /// # Optional, multiplies the coordinates to get meters. Defaults to 1.0.
+    /// scale = 0.0254
+    /// # Optional, the up axis of the file, "y" (the default) or "z".
+    /// up = "y"
+    /// # Optional, the material of faces that have no material of their own.
+    /// material = "drywall"
+    ///
+    /// # Optional, materials for parts of the model, by object or group name,
+    /// # or by the name of the material in the file. Object names come first.
+    /// [mesh.materials]
+    /// Floor = "wood_floor"
+    /// Window = "glass"
+    ///
+    /// # Optional, for a room within a room: an outer shell around the walls
+    /// # and ceiling, behind an air gap of `gap` meters. The walls and ceiling
+    /// # are then a leaf of `inner_mass` kg/m², such as 25 for two layers of
+    /// # drywall, with a structural `loss_factor` that defaults to 0.05. The
+    /// # outer shell has a `material` or `reflectivity`, concrete by default.
+    /// # Set `coupled = false` to start with the walls as solid walls of their
+    /// # own material, the viewer can switch between the two. Only for rooms.
+    /// [shell]
+    /// gap = 0.15
+    /// inner_mass = 25.0
+    /// loss_factor = 0.05
+    /// material = "concrete"
+    ///
+    /// # Optional, objects that stand on the floor, but do not reach the
+    /// # ceiling. With two corners, the obstacle is a free-standing wall
+    /// # without thickness, such as a pony wall. With more corners, it is a
+    /// # block with a top, such as a kitchen island. Obstacles take a
+    /// # `material` or `reflectivity`, like faces of the room. Only for rooms.
+    /// [[obstacle]]
+    /// corners = [[2.0, 2.0], [3.2, 2.0], [3.2, 2.9], [2.0, 2.9]]
+    /// height = 0.9
+    /// material = "wood_floor"
+    ///
+    /// # Optional, raised parts of the floor, such as a riser. The viewer
+    /// # renders the field at ear height above the floor, so the slice steps
+    /// # up over a riser. Add an obstacle with the same corners to model the
+    /// # sound reflecting off the riser. Later levels take precedence. Only
+    /// # for rooms.
+    /// [[floor_level]]
+    /// corners = [[0.5, 2.4], [2.5, 2.4], [2.5, 3.35], [0.5, 3.35]]
+    /// height = 0.3
+    ///
+    /// # Optional, resonant bass traps on the faces: a "membrane" trap is a
+    /// # panel on a sealed air space, a "helmholtz" trap a perforated panel.
+    /// # The `position` is the center of the trap, within 5 cm of a face, and
+    /// # the `size` is its width along the face and its height. It absorbs
+    /// # around `frequency` Hz, over a band of about `frequency / q` Hz, with
+    /// # a peak `absorption` that defaults to 0.9. The quality factor defaults
+    /// # to 4 for membranes and 8 for perforated panels. Instead of the
+    /// # frequency, give the construction: the `mass` of the panel in kg/m²
+    /// # and the `depth` of the air space in meters for a membrane, and the
+    /// # `open_area` fraction, `thickness`, `hole_radius`, and `depth` in
+    /// # meters for a perforated panel.
+    /// [[absorber]]
+    /// type = "membrane"
+    /// position = [0.0, 1.5, 1.2]
+    /// size = [1.2, 0.6]
+    /// frequency = 60.0
+    /// q = 4.0
+    ///
+    /// [listener]
+    /// position = [1.40, 3.0, 1.0]
+    ///
+    /// # Optional, a sphere at the listener position that models the head
+    /// # and shoulders. It shadows the sound at high frequencies. Points inside
+    /// # it are not shadowed, so to compare against in-ear measurements, sample
+    /// # at the ears rather than at the listener position.
+    /// [listener.body]
+    /// radius = 0.15
+    /// # Attenuation directly behind the body at high frequencies. Defaults
+    /// # to -10 dB.
+    /// shadow_db = -10.0
+    ///
+    /// # Optional, a rectangle around the listener, such as a sofa, given by
+    /// # two opposite corners. The viewer reports the average level over it,
+    /// # and how much the level varies from seat to seat.
+    /// [listener.area]
+    /// corners = [[0.90, 2.70], [1.90, 3.20]]
+    ///
+    /// # Optional, more points to compare the response at the listener
+    /// # with, such as the other seats. The name defaults to "Probe 1",
+    /// # "Probe 2", and so on.
+    /// [[probe]]
+    /// name = "left seat"
+    /// position = [0.90, 3.0, 1.0]
+    ///
+    /// # Optional, a target or house curve for the response at the listener,
+    /// # as an FRD file relative to the directory of the scene file, or as a
+    /// # list of [frequency, dB] points. Only its shape matters, the viewer
+    /// # shifts it to the level of the response, and reports how far the
+    /// # response deviates from it.
+    /// [target]
+    /// curve = [[20.0, 6.0], [60.0, 3.0], [200.0, 0.0]]
+    ///
+    /// [[source]]
+    /// position = [0.60, 0.30, 1.0]
+    /// # Optional, sources aim at the listener by default.
+    /// aim = [1.40, 3.0, 1.0]
+    /// # Optional, "omni", "dipole", "cardioid", or "cosine" (the default).
+    /// # For a dipole, `dipole_spacing` is the path length in meters from the
+    /// # front to the back of the baffle, it defaults to 0.3.
+    /// directivity = "omni"
+    /// # Optional, the gain in dB, delay in milliseconds, and polarity of the
+    /// # signal that drives the source. They default to no change.
+    /// gain_db = -3.0
+    /// delay_ms = 2.5
+    /// invert = true
+    /// # Optional, the measured on-axis response of the speaker, as an FRD
+    /// # file relative to the directory of the scene file, or as a list of
+    /// # [frequency, dB, phase in degrees] points. The level is applied as it
+    /// # is, so compensate an absolute measurement with `gain_db`.
+    /// frd = "sub.frd"
+    ///
+    /// # Optional, EQ filters in the signal that drives the source, as in a
+    /// # DSP. The type is "peaking", "low_shelf", "high_shelf", "high_pass", or
+    /// # "low_pass". The Q defaults to 0.707, the gain is only needed for
+    /// # peaking and shelving filters.
+    /// [[source.eq]]
+    /// type = "peaking"
+    /// frequency = 42.0
+    /// q = 5.0
+    /// gain_db = -6.0
+    ///
+    /// # Optional, the air in the room, which determines the speed of sound
+    /// # and how much the air absorbs. The temperature is in degrees Celsius,
+    /// # 25 by default, the pressure in kPa, 101.325 by default, and the
+    /// # relative humidity in percent, 50 by default.
+    /// [atmosphere]
+    /// temperature = 20.0
+    /// humidity = 40.0
+    ///
+    /// # Optional, how to simulate. Reflections up to `exact_order` are
+    /// # computed exactly rather than sampled, which removes most of the noise,
+    /// # but every sample checks about faces^order paths. Defaults to 0, at
+    /// # most 6. For a rectangular room, 3 is a good choice. The solver is
+    /// # "path_tracing" (the default), "modal", or "fdtd". The modal solver sums
+    /// # the modes of a rectangular room, which is noise-free, but only works
+    /// # for a single [room] with 4 corners along the axes, and treats sources
+    /// # as omnidirectional. The fdtd solver shows a pulse from the sources
+    /// # propagating through the room over time, on a grid with
+    /// # `fdtd_spacing` in meters, which resolves frequencies up to about
+    /// # 34 / spacing Hz. The time step is `fdtd_courant` times the largest
+    /// # stable one, 1 by default. Reflections that arrive more than
+    /// # `max_time_ms` after the sound leaves the source are left out. Without
+    /// # a limit, paths are followed for 30 reflections, whatever the size of
+    /// # the room. With `diffraction`, false by default, sound also bends
+    /// # around the top and the corners of obstacles, such as a partial wall,
+    /// # once, which makes the shadow behind them less deep at low frequencies.
+    /// [simulation]
+    /// exact_order = 3
+    /// solver = "path_tracing"
+    /// max_time_ms = 300.0
+    /// fdtd_spacing = 0.1
+    /// fdtd_courant = 1.0
+    /// diffraction = true
+    ///
+    /// # Optional, a crossover between subwoofers and main speakers. The type
+    /// # is "linkwitz_riley" or "butterworth", the slope is in dB per octave,
+    /// # 24 by default, so LR4 is a Linkwitz-Riley with a 24 dB slope. Sources
+    /// # are placed on a side of it with `band = "sub"` or `band = "mains"`,
+    /// # sources without a band are full range. With an LR2, invert the mains.
+    /// [crossover]
+    /// type = "linkwitz_riley"
+    /// frequency = 80.0
+    /// slope = 24
+    ///
+    /// [[source]]
+    /// position = [0.30, 0.30, 1.0]
+    /// band = "mains"
+    /// # Optional, the natural roll-off of a full-range speaker, as the -3 dB
+    /// # frequency of a Butterworth high-pass. The order defaults to 2, like a
+    /// # sealed box, use 4 for a ported box.
+    /// rolloff = 45.0
+    /// rolloff_order = 2
+    ///
+    /// [[source]]
+    /// position = [4.16, 0.30, 0.3]
+    /// # Optional, make the source directional by adding sources behind it,
+    /// # along its aim direction. A "cardioid" array adds one delayed and
+    /// # inverted source, an "end_fire" array adds `array_count - 1` delayed
+    /// # sources, 4 in total by default. The spacing defaults to 1.0 meter.
+    /// # The added sources come after all sources in the file.
+    /// array = "cardioid"
+    /// array_spacing = 1.0
+    ///
+    /// # Optional, the port of a bass reflex box, a source of its own that
+    /// # plays the signal of the source through the port. It comes after all
+    /// # sources in the file, before the sources of arrays. The tuning is the
+    /// # resonance frequency of the box in Hz, `q_loss` defaults to 7, and
+    /// # `gain_db` adjusts the port output relative to the cone.
+    /// [source.port]
+    /// position = [4.40, 0.30, 0.1]
+    /// tuning = 28.0
+    /// ```
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Scene> {
+        let contents = fs::read_to_string(&path)?;
+        let root: Value = contents.parse()?;
+
+        let listener_table = get(&root, "", "listener")?;
+        let listener_position = as_vec3(get(listener_table, "listener", "position")?, "listener.position")?;
+
+        let mut listener = Listener::new(listener_position);
+        listener.body = match listener_table.get("body") {
+            None => None,
+            Some(b) => {
+                let radius = as_f32(get(b, "listener.body", "radius")?, "listener.body.radius")?;
+                if !(radius > 0.0) {
+                    return invalid("Expected 'listener.body.radius' to be positive.".to_string())
+                }
+                let shadow_db = match b.get("shadow_db") {
+                    Some(s) => as_f32(s, "listener.body.shadow_db")?,
+                    None => -10.0,
+                };
+                Some(Body {
+                    center: listener_position,
+                    radius: radius,
+                    shadow_gain: 10.0_f32.powf(shadow_db / 20.0),
+                })
+            }
+        };
+
+        if let Some(a) = listener_table.get("area") {
+            let corners_path = "listener.area.corners";
+            let corners = as_array(get(a, "listener.area", "corners")?, corners_path)?;
+            if corners.len() != 2 {
+                return invalid(format!("Expected '{}' to have 2 corners.", corners_path))
+            }
+            let a = as_vec2(&corners[0], corners_path)?;
+            let b = as_vec2(&corners[1], corners_path)?;
+            listener.area = Some(ListeningArea::new(a, b));
+        }
+
+        let probes = parse_probes(&root)?;
+
+        let dir = path.as_ref().parent().unwrap_or(Path::new("."));
+
+        let target = match root.get("target") {
+            Some(t) => Some(Target::new(parse_frd(get(t, "target", "curve")?, dir, "target.curve")?)),
+            None => None,
+        };
+
+        let atmosphere = match root.get("atmosphere") {
+            Some(a) => parse_atmosphere(a)?,
+            None => Atmosphere::standard(),
+        };
+
+        let simulation = match root.get("simulation") {
+            Some(sim) => parse_simulation(sim)?,
+            None => Simulation::standard(),
+        };
+
+        let crossover = match root.get("crossover") {
+            Some(c) => Some(parse_crossover(c)?),
+            None => None,
+        };
+
+        let mut sources = Vec::new();
+        let mut arrays = Vec::new();
+        let mut ports = Vec::new();
+        for (i, s) in as_array(get(&root, "", "source")?, "source")?.iter().enumerate() {
+            let path = format!("source[{}]", i);
+            let position = as_vec3(get(s, &path, "position")?, &format!("{}.position", path))?;
+            let aim = match s.get("aim") {
+                Some(aim) => as_vec3(aim, &format!("{}.aim", path))?,
+                None => listener_position,
+            };
+            let mut source = Source::new(position, aim);
+            if let Some(d) = s.get("directivity") {
+                source.directivity = match as_str(d, &format!("{}.directivity", path))? {
+                    "omni" => Directivity::Omni,
+                    "cardioid" => Directivity::Cardioid,
+                    "cosine" => Directivity::Cosine,
+                    "dipole" => {
+                        let spacing = match s.get("dipole_spacing") {
+                            Some(x) => as_f32(x, &format!("{}.dipole_spacing", path))?,
+                            None => 0.3,
+                        };
+                        if !(spacing > 0.0) {
+                            return invalid(format!("Expected '{}.dipole_spacing' to be positive.", path))
+                        }
+                        Directivity::Dipole { spacing: spacing }
+                    }
+                    _ => return invalid(format!(
+                        "Expected '{}.directivity' to be \"omni\", \"dipole\", \"cardioid\", or \"cosine\".", path
+                    )),
+                };
+            }
+            if let Some(g) = s.get("gain_db") {
+                source.gain_db = as_f32(g, &format!("{}.gain_db", path))?;
+            }
+            if let Some(d) = s.get("delay_ms") {
+                source.delay_ms = as_f32(d, &format!("{}.delay_ms", path))?;
+                if !(source.delay_ms >= 0.0) {
+                    return invalid(format!("Expected '{}.delay_ms' not to be negative.", path))
+                }
+            }
+            if let Some(inv) = s.get("invert") {
+                source.invert = as_bool(inv, &format!("{}.invert", path))?;
+            }
+            if let Some(eq) = s.get("eq") {
+                let eq_path = format!("{}.eq", path);
+                for (j, filter) in as_array(eq, &eq_path)?.iter().enumerate() {
+                    source.eq.push(parse_biquad(filter, &format!("{}[{}]", eq_path, j))?);
+                }
+            }
+            if let Some(b) = s.get("band") {
+                let band_path = format!("{}.band", path);
+                let band = match as_str(b, &band_path)? {
+                    "sub" => Band::Sub,
+                    "mains" => Band::Mains,
+                    _ => return invalid(format!("Expected '{}' to be \"sub\" or \"mains\".", band_path)),
+                };
+                match crossover {
+                    Some(c) => source.crossover = Some((c, band)),
+                    None => return invalid(format!("'{}' needs a [crossover] table.", band_path)),
+                }
+            }
+            if let Some(r) = s.get("rolloff") {
+                let frequency = as_f32(r, &format!("{}.rolloff", path))?;
+                let order = match s.get("rolloff_order") {
+                    Some(n) => as_usize(n, &format!("{}.rolloff_order", path))? as u32,
+                    None => 2,
+                };
+                if !(frequency > 0.0) {
+                    return invalid(format!("Expected '{}.rolloff' to be positive.", path))
+                }
+                if order < 1 || order > 8 {
+                    return invalid(format!("Expected '{}.rolloff_order' to be between 1 and 8.", path))
+                }
+                source.rolloff = Some(Rolloff {
+                    frequency: frequency,
+                    order: order,
+                });
+            }
+            if let Some(frd) = s.get("frd") {
+                source.response = Some(parse_frd(frd, dir, &format!("{}.frd", path))?);
+            }
+            if let Some(a) = s.get("array") {
+                arrays.push((i, parse_array(s, a, &path)?));
+            }
+            if let Some(p) = s.get("port") {
+                ports.push(parse_port(p, &source, &format!("{}.port", path))?);
+            }
+            sources.push(source);
+        }
+
+        if sources.is_empty() {
+            return invalid("The scene must contain at least one source.".to_string())
+        }
+        sources.extend(ports);
+
+        let materials = parse_materials(&root)?;
+
+        if let Some(mesh) = root.get("mesh") {
+            for key in &["shell", "obstacle", "floor_level"] {
+                if root.get(*key).is_some() {
+                    return invalid(format!("'{}' is only supported for rooms, not with a mesh.", key))
+                }
+            }
+            let mut scene = load_mesh(mesh, dir, &materials, sources)?;
+            scene.listener = Some(listener);
+            scene.probes = probes;
+            scene.target = target;
+            scene.atmosphere = atmosphere;
+            scene.simulation = simulation;
+            scene.absorbers = parse_absorbers(&root, &scene)?;
+            for &(si, (kind, spacing)) in &arrays {
+                array::add_array(&mut scene, si, kind, spacing);
+            }
+            return validated(scene)
+        }
+
+        // The room is either a single [room] table, or an array of [[room]]
+        // tables, with their path for use in errors.
+        let room_values: Vec<(&Value, String)> = match get(&root, "", "room")? {
+            Value::Array(rs) => rs.iter().enumerate().map(|(i, r)| (r, format!("room[{}]", i))).collect(),
+            r => vec![(r, "room".to_string())],
+        };
+        if room_values.is_empty() {
+            return invalid("The scene must contain at least one room.".to_string())
+        }
+
+        let mut rooms = Vec::new();
+        let mut height = 0.0;
+        for (i, &(room, ref path)) in room_values.iter().enumerate() {
+            let corners_path = format!("{}.corners", path);
+            let corners: Vec<Vec2> = as_array(get(room, path, "corners")?, &corners_path)?
+                .iter()
+                .map(|c| as_vec2(c, &corners_path))
+                .collect::<Result<_>>()?;
+            if corners.len() < 3 {
+                return invalid(format!("Expected '{}' to have at least 3 corners.", corners_path))
+            }
+
+            let height_path = format!("{}.height", path);
+            let room_height = as_f32(get(room, path, "height")?, &height_path)?;
+            if !(room_height > 0.0) {
+                return invalid(format!("Expected '{}' to be positive.", height_path))
+            }
+            if i > 0 && (room_height - height).abs() > 1e-6 {
+                return invalid("All rooms must have the same height.".to_string())
+            }
+            height = room_height;
+
+            let mut openings = Vec::new();
+            if let Some(os) = room.get("openings") {
+                let openings_path = format!("{}.openings", path);
+                for o in as_array(os, &openings_path)? {
+                    let o = as_usize(o, &openings_path)?;
+                    if o >= corners.len() {
+                        return invalid(format!(
+                            "Wall {} in '{}' does not exist, the room has {} walls.", o, openings_path, corners.len()
+                        ))
+                    }
+                    openings.push(o);
+                }
+            }
+
+            rooms.push(Room {
+                corners: corners,
+                openings: openings,
+            });
+        }
+
+        let mut scene = Scene::new_rooms(rooms, height, sources);
+        scene.listener = Some(listener);
+        scene.probes = probes;
+        scene.target = target;
+        scene.atmosphere = atmosphere;
+        scene.simulation = simulation;
+
+        // Materials first, then explicit reflection coefficients, and within
+        // both, from general to specific. Transmission is independent of both.
+        let set_material = |face: &mut Face, m: Material| face.material = m;
+        let mut first_wall = 0;
+        for (i, &(room, ref path)) in room_values.iter().enumerate() {
+            let walls = first_wall..first_wall + scene.rooms[i].corners.len();
+            let parse_material = |m: &Value, p: &str| lookup_material(&materials, m, p);
+            apply_face_keys(&mut scene, room, path, walls.clone(), i == 0, "material", &parse_material, &set_material)?;
+            first_wall = walls.end;
+        }
+
+        let mut first_wall = 0;
+        for (i, &(room, ref path)) in room_values.iter().enumerate() {
+            let walls = first_wall..first_wall + scene.rooms[i].corners.len();
+            let parse_reflectivity = |r: &Value, p: &str| {
+                let r = as_complex(r, p)?;
+                Ok(Material::constant(format!("reflectivity {} + {}i", r.real(), r.imag()), r))
+            };
+            apply_face_keys(&mut scene, room, path, walls.clone(), i == 0, "reflectivity", &parse_reflectivity, &set_material)?;
+            first_wall = walls.end;
+        }
+
+        let mut first_wall = 0;
+        for (i, &(room, ref path)) in room_values.iter().enumerate() {
+            let walls = first_wall..first_wall + scene.rooms[i].corners.len();
+            let parse_transmission = |t: &Value, p: &str| {
+                let t = as_f32(t, p)?;
+                if !(t >= 0.0 && t <= 1.0) {
+                    return invalid(format!("Expected '{}' to be between 0 and 1.", p))
+                }
+                Ok(t)
+            };
+            let set_transmission = |face: &mut Face, t: f32| face.transmission = t;
+            apply_face_keys(&mut scene, room, path, walls.clone(), i == 0, "transmission", &parse_transmission, &set_transmission)?;
+            first_wall = walls.end;
+        }
+
+        if let Some(value) = root.get("shell") {
+            let get_positive = |key: &str| {
+                let path = format!("shell.{}", key);
+                let x = as_f32(get(value, "shell", key)?, &path)?;
+                if !(x > 0.0) {
+                    return invalid(format!("Expected '{}' to be positive.", path))
+                }
+                Ok(x)
+            };
+            let gap = get_positive("gap")?;
+            let inner_mass = get_positive("inner_mass")?;
+            let loss_factor = match value.get("loss_factor") {
+                Some(x) => as_f32(x, "shell.loss_factor")?,
+                None => 0.05,
+            };
+            if !(loss_factor >= 0.0) {
+                return invalid("Expected 'shell.loss_factor' to be at least 0.".to_string())
+            }
+            let outer = match (value.get("material"), value.get("reflectivity")) {
+                (_, Some(r)) => {
+                    let r = as_complex(r, "shell.reflectivity")?;
+                    Material::constant(format!("reflectivity {} + {}i", r.real(), r.imag()), r)
+                }
+                (Some(m), None) => lookup_material(&materials, m, "shell.material")?,
+                (None, None) => Material::builtin("concrete").unwrap(),
+            };
+            shell::add_shell(&mut scene, gap, inner_mass, loss_factor, outer);
+            if let Some(x) = value.get("coupled") {
+                shell::set_coupled(&mut scene, as_bool(x, "shell.coupled")?);
+            }
+        }
+
+        if let Some(obstacles) = root.get("obstacle") {
+            for (i, o) in as_array(obstacles, "obstacle")?.iter().enumerate() {
+                let path = format!("obstacle[{}]", i);
+                let corners_path = format!("{}.corners", path);
+                let corners: Vec<Vec2> = as_array(get(o, &path, "corners")?, &corners_path)?
+                    .iter()
+                    .map(|c| as_vec2(c, &corners_path))
+                    .collect::<Result<_>>()?;
+                if corners.len() < 2 {
+                    return invalid(format!("Expected '{}' to have at least 2 corners.", corners_path))
+                }
+
+                let height_path = format!("{}.height", path);
+                let obstacle_height = as_f32(get(o, &path, "height")?, &height_path)?;
+                if !(obstacle_height > 0.0 && obstacle_height < height) {
+                    return invalid(format!("Expected '{}' to be positive and less than the room height.", height_path))
+                }
+
+                let material = match (o.get("material"), o.get("reflectivity")) {
+                    (_, Some(r)) => {
+                        let r = as_complex(r, &format!("{}.reflectivity", path))?;
+                        Material::constant(format!("reflectivity {} + {}i", r.real(), r.imag()), r)
+                    }
+                    (Some(m), None) => lookup_material(&materials, m, &format!("{}.material", path))?,
+                    (None, None) => Material::default_material(),
+                };
+
+                let obstacle = Obstacle {
+                    corners: corners,
+                    height: obstacle_height,
+                };
+                scene.add_obstacle(obstacle, material);
+            }
+        }
+
+        if let Some(levels) = root.get("floor_level") {
+            for (i, l) in as_array(levels, "floor_level")?.iter().enumerate() {
+                let path = format!("floor_level[{}]", i);
+                let corners_path = format!("{}.corners", path);
+                let corners: Vec<Vec2> = as_array(get(l, &path, "corners")?, &corners_path)?
+                    .iter()
+                    .map(|c| as_vec2(c, &corners_path))
+                    .collect::<Result<_>>()?;
+                if corners.len() < 3 {
+                    return invalid(format!("Expected '{}' to have at least 3 corners.", corners_path))
+                }
+
+                let height_path = format!("{}.height", path);
+                let level_height = as_f32(get(l, &path, "height")?, &height_path)?;
+                if !(level_height >= 0.0 && level_height < height) {
+                    return invalid(format!("Expected '{}' to be at least 0 and less than the room height.", height_path))
+                }
+
+                scene.floor_levels.push(FloorLevel {
+                    corners: corners,
+                    height: level_height,
+                });
+            }
+        }
+
+        scene.absorbers = parse_absorbers(&root, &scene)?;
+
+        for &(si, (kind, spacing)) in &arrays {
+            array::add_array(&mut scene, si, kind, spacing);
+        }
+
+        validated(scene)
+    }
+}
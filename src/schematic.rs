@@ -0,0 +1,175 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::io;
+use std::io::Write;
+
+use crate::scene::{Face, Scene};
+use crate::vec3::Vec3;
+
+/// Scale of the drawing, in SVG units (pixels) per meter.
+const PX_PER_M: f32 = 100.0;
+
+/// Margin around the room, in meters, to leave space for the dimensions.
+const MARGIN_M: f32 = 0.8;
+
+/// Distance between a wall and its dimension line, in meters.
+const DIMENSION_OFFSET_M: f32 = 0.3;
+
+/// Return whether the face is a wall, as opposed to a floor or ceiling.
+fn is_wall(normal: Vec3) -> bool {
+    normal.z.abs() < 0.5
+}
+
+/// Return the ends of the wall as seen from above.
+///
+/// For a polygon face, such as a triangle of a mesh, these are its extreme
+/// points along the wall.
+fn wall_ends(face: &Face) -> (Vec3, Vec3) {
+    let polygon = face.polygon();
+    if polygon.is_empty() {
+        return (face.start(), face.end())
+    }
+
+    let along = face.normal().cross(Vec3::new(0.0, 0.0, 1.0));
+    let key = |p: &&Vec3| along.dot(**p);
+    let p0 = polygon.iter().min_by(|a, b| key(a).partial_cmp(&key(b)).unwrap()).unwrap();
+    let p1 = polygon.iter().max_by(|a, b| key(a).partial_cmp(&key(b)).unwrap()).unwrap();
+    (*p0, *p1)
+}
+
+/// Maps world coordinates in meters (with y up) to SVG coordinates (y down).
+struct Transform {
+    x_min: f32,
+    y_max: f32,
+}
+
+impl Transform {
+    fn x(&self, p: Vec3) -> f32 {
+        (p.x - self.x_min) * PX_PER_M
+    }
+
+    fn y(&self, p: Vec3) -> f32 {
+        (self.y_max - p.y) * PX_PER_M
+    }
+}
+
+/// Write a top-down schematic of the scene, with wall dimensions, as SVG.
+///
+/// The schematic contains the walls, the sources with their aim direction, and
+/// the listener if one is given.
+pub fn write_svg<W: Write>(scene: &Scene, listener: Option<Vec3>, out: &mut W) -> io::Result<()> {
+    let walls: Vec<_> = scene.faces.iter().filter(|f| is_wall(f.normal())).collect();
+
+    let mut points: Vec<Vec3> = Vec::new();
+    points.extend(walls.iter().flat_map(|f| {
+        let (p0, p1) = wall_ends(f);
+        vec![p0, p1]
+    }));
+    points.extend(scene.sources.iter().map(|s| s.position));
+    points.extend(listener);
+
+    let x_min = points.iter().map(|p| p.x).fold(std::f32::INFINITY, f32::min) - MARGIN_M;
+    let x_max = points.iter().map(|p| p.x).fold(std::f32::NEG_INFINITY, f32::max) + MARGIN_M;
+    let y_min = points.iter().map(|p| p.y).fold(std::f32::INFINITY, f32::min) - MARGIN_M;
+    let y_max = points.iter().map(|p| p.y).fold(std::f32::NEG_INFINITY, f32::max) + MARGIN_M;
+    let t = Transform { x_min: x_min, y_max: y_max };
+
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}" font-family="sans-serif" font-size="14">"#,
+        (x_max - x_min) * PX_PER_M,
+        (y_max - y_min) * PX_PER_M,
+    )?;
+    writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+
+    for wall in &walls {
+        let (p0, p1) = wall_ends(wall);
+        let style = if wall.opening {
+            r#"stroke="gray" stroke-width="1" stroke-dasharray="6 4""#
+        } else if wall.height() != std::f32::INFINITY {
+            // Obstacles, which do not reach the ceiling.
+            r#"stroke="dimgray" stroke-width="2""#
+        } else {
+            r#"stroke="black" stroke-width="4" stroke-linecap="square""#
+        };
+        writeln!(
+            out,
+            r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" {}/>"#,
+            t.x(p0), t.y(p0), t.x(p1), t.y(p1), style,
+        )?;
+
+        // Walls of a mesh are often split into triangles, so dimensions of
+        // their parts would only be clutter. Obstacles have two sides, which
+        // would get two labels.
+        if !wall.polygon().is_empty() || wall.height() != std::f32::INFINITY {
+            continue
+        }
+
+        // The dimension line goes on the outside of the wall, parallel to it.
+        let offset = -wall.normal() * DIMENSION_OFFSET_M;
+        let (d0, d1) = (p0 + offset, p1 + offset);
+        writeln!(
+            out,
+            r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="gray" stroke-width="1"/>"#,
+            t.x(d0), t.y(d0), t.x(d1), t.y(d1),
+        )?;
+
+        // Rotate the label along the wall, but keep the text upright.
+        let mid = (d0 + d1) * 0.5 + offset * 0.3;
+        let mut angle = (t.y(d1) - t.y(d0)).atan2(t.x(d1) - t.x(d0)).to_degrees();
+        if angle >= 90.0 {
+            angle -= 180.0;
+        }
+        if angle < -90.0 {
+            angle += 180.0;
+        }
+        writeln!(
+            out,
+            r#"<text x="{x:.1}" y="{y:.1}" text-anchor="middle" dominant-baseline="middle" transform="rotate({a:.1} {x:.1} {y:.1})">{l:.2} m</text>"#,
+            x = t.x(mid),
+            y = t.y(mid),
+            a = angle,
+            l = (p1 - p0).norm(),
+        )?;
+    }
+
+    for (i, source) in scene.sources.iter().enumerate() {
+        let p = source.position;
+        let q = p + source.direction * 0.4;
+        writeln!(
+            out,
+            r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="firebrick" stroke-width="2"/>"#,
+            t.x(p), t.y(p), t.x(q), t.y(q),
+        )?;
+        writeln!(
+            out,
+            r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="firebrick"/>"#,
+            t.x(p), t.y(p), 0.12 * PX_PER_M,
+        )?;
+        writeln!(
+            out,
+            r#"<text x="{:.1}" y="{:.1}" fill="firebrick">S{}</text>"#,
+            t.x(p) + 0.15 * PX_PER_M, t.y(p) - 0.15 * PX_PER_M, i,
+        )?;
+    }
+
+    if let Some(p) = listener {
+        writeln!(
+            out,
+            r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="none" stroke="navy" stroke-width="3"/>"#,
+            t.x(p), t.y(p), 0.12 * PX_PER_M,
+        )?;
+        writeln!(
+            out,
+            r#"<text x="{:.1}" y="{:.1}" fill="navy">L</text>"#,
+            t.x(p) + 0.15 * PX_PER_M, t.y(p) - 0.15 * PX_PER_M,
+        )?;
+    }
+
+    writeln!(out, "</svg>")
+}
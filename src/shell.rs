@@ -0,0 +1,124 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::f32::consts::PI;
+
+use crate::atmosphere::Atmosphere;
+use crate::complex::Complex;
+use crate::material::{Material, BAND_CENTERS_HZ, N_BANDS};
+use crate::scene::Scene;
+
+/// The density of air, in kg/m³.
+pub const AIR_DENSITY: f32 = 1.2;
+
+/// An outer shell around the room, separated from it by an air gap.
+///
+/// This is how isolated studios are built: the walls and ceiling of the
+/// inner room are a light leaf, such as a few layers of drywall on a frame of
+/// their own, and behind them is an air gap and the heavy outer shell of the
+/// building. At low frequencies the inner leaf is far from rigid: it moves
+/// with the pressure in the room, and the air in the gap acts as a spring
+/// behind it. Together they resonate at the mass-air-mass frequency, where
+/// the inner leaf absorbs strongly, and below it the inner room is coupled to
+/// the gap and the outer shell.
+#[derive(Clone, Debug)]
+pub struct Shell {
+    /// The depth of the air gap between the inner leaf and the outer shell, in meters.
+    pub gap: f32,
+
+    /// The mass of the inner leaf per area, in kg/m².
+    pub inner_mass: f32,
+
+    /// The structural loss factor of the inner leaf, the part of its energy
+    /// that it loses per radian of vibration.
+    pub loss_factor: f32,
+
+    /// The material of the outer shell, as seen from the gap.
+    pub outer: Material,
+
+    /// The faces that are the inner leaf, and their materials without the shell.
+    pub faces: Vec<(usize, Material)>,
+
+    /// Whether the faces are the inner leaf, or a plain wall of their own material.
+    pub coupled: bool,
+}
+
+impl Shell {
+    /// Return the frequency at which the inner leaf resonates on the air in the gap, in Hz.
+    pub fn resonance_hz(&self, atmosphere: &Atmosphere) -> f32 {
+        let c = atmosphere.speed_of_sound();
+        (AIR_DENSITY * c * c / (self.inner_mass * self.gap)).sqrt() / (2.0 * PI)
+    }
+
+    /// Return the material of the inner leaf, backed by the gap and the outer shell.
+    ///
+    /// At normal incidence, the surface impedance of the leaf is that of a
+    /// limp mass with losses, in series with the impedance of the gap, which
+    /// is a layer of air closed off by the outer shell.
+    pub fn material(&self, atmosphere: &Atmosphere) -> Material {
+        let c = atmosphere.speed_of_sound();
+        let z_air = AIR_DENSITY * c;
+        let one = Complex::new(1.0, 0.0);
+
+        let mut impedance = [Complex::zero(); N_BANDS];
+        for (z, &f) in impedance.iter_mut().zip(BAND_CENTERS_HZ.iter()) {
+            let omega = 2.0 * PI * f;
+            let reactance = omega * self.inner_mass / z_air;
+            let leaf = Complex::new(self.loss_factor * reactance, -reactance);
+
+            // The reflection coefficient of the outer shell, in the convention
+            // where a rigid wall reflects with +1, delayed by the round trip
+            // through the gap.
+            let round_trip = Complex::exp_i(2.0 * omega * self.gap / c);
+            let r = round_trip * (Complex::zero() - self.outer.reflectivity_at(f));
+            let gap = (one + r) / (one - r);
+
+            *z = leaf + gap;
+        }
+
+        Material::from_impedance("inner leaf".to_string(), impedance)
+    }
+}
+
+/// Put the walls and ceiling of the rooms in a shell.
+///
+/// Walls that are openings stay open. The faces are coupled to the shell.
+pub fn add_shell(scene: &mut Scene, gap: f32, inner_mass: f32, loss_factor: f32, outer: Material) {
+    let n_walls = scene.n_walls();
+    let ceiling = n_walls + 1;
+    let faces = (0..n_walls)
+        .filter(|&i| !scene.faces[i].opening)
+        .chain(Some(ceiling))
+        .map(|i| (i, scene.faces[i].material.clone()))
+        .collect();
+
+    scene.shell = Some(Shell {
+        gap: gap,
+        inner_mass: inner_mass,
+        loss_factor: loss_factor,
+        outer: outer,
+        faces: faces,
+        coupled: false,
+    });
+    set_coupled(scene, true);
+}
+
+/// Couple or decouple the inner leaf and the shell, to compare the room with and without it.
+///
+/// Without the shell, the faces have their own materials, as if they were
+/// solid walls.
+pub fn set_coupled(scene: &mut Scene, coupled: bool) {
+    let shell = match scene.shell.as_mut() {
+        Some(shell) => shell,
+        None => return,
+    };
+    shell.coupled = coupled;
+    let leaf = shell.material(&scene.atmosphere);
+    for &(i, ref material) in &shell.faces {
+        scene.faces[i].material = if coupled { leaf.clone() } else { material.clone() };
+    }
+}
@@ -0,0 +1,122 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A program spectrum, used to weight frequencies when averaging over a band.
+pub enum Spectrum {
+    /// White noise, equal power per Hz.
+    White,
+
+    /// Pink noise, equal power per octave.
+    Pink,
+
+    /// An approximation of the long-term spectrum of movie LFE content.
+    ///
+    /// Modelled as pink noise that tilts down by 3 dB per octave above 30 Hz,
+    /// with a fourth-order roll-off above 120 Hz, the upper limit of the LFE
+    /// channel.
+    Lfe,
+
+    /// A user-supplied curve of (frequency in Hz, level in dB) points.
+    ///
+    /// The level is the power per octave, and it is interpolated linearly in
+    /// log-frequency between the points. The points are sorted by frequency.
+    Curve(Vec<(f32, f32)>),
+}
+
+impl Spectrum {
+    /// Parse a spectrum name, or load a curve from a file if it is not a name.
+    pub fn from_arg(arg: &str) -> io::Result<Spectrum> {
+        match arg {
+            "white" => Ok(Spectrum::White),
+            "pink" => Ok(Spectrum::Pink),
+            "lfe" => Ok(Spectrum::Lfe),
+            path => Spectrum::load(path),
+        }
+    }
+
+    /// Load a curve from a text file with a frequency and level on every line.
+    ///
+    /// This is the format used by FRD files and REW exports. Further columns
+    /// (such as phase) are ignored, as are lines that start with `*` or `#`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Spectrum> {
+        let contents = fs::read_to_string(path)?;
+        let mut points = Vec::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('*') || line.starts_with('#') {
+                continue
+            }
+
+            let mut parts = line.split(|c: char| c.is_whitespace() || c == ',').filter(|p| !p.is_empty());
+            let mut next = || parts.next().and_then(|p| p.parse::<f32>().ok());
+            match (next(), next()) {
+                (Some(f), Some(db)) if f > 0.0 => points.push((f, db)),
+                _ => {
+                    let msg = format!("Expected frequency and level on line {}.", i + 1);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+                }
+            }
+        }
+
+        if points.is_empty() {
+            let msg = "The spectrum file does not contain any points.";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+        }
+
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Ok(Spectrum::Curve(points))
+    }
+
+    /// Return the power per octave at `frequency`, in dB, up to a constant.
+    fn level_per_octave_db(&self, frequency: f32) -> f32 {
+        match *self {
+            Spectrum::White => 10.0 * frequency.log10(),
+            Spectrum::Pink => 0.0,
+            Spectrum::Lfe => {
+                let tilt = -3.0 * (frequency / 30.0).log2().max(0.0);
+                let rolloff = -10.0 * (1.0 + (frequency / 120.0).powi(8)).log10();
+                tilt + rolloff
+            }
+            Spectrum::Curve(ref points) => {
+                let n = points.len();
+                if frequency <= points[0].0 {
+                    return points[0].1
+                }
+                if frequency >= points[n - 1].0 {
+                    return points[n - 1].1
+                }
+                let i = points.iter().position(|&(f, _)| f > frequency).unwrap();
+                let (f0, db0) = points[i - 1];
+                let (f1, db1) = points[i];
+                let t = (frequency / f0).ln() / (f1 / f0).ln();
+                db0 + (db1 - db0) * t
+            }
+        }
+    }
+
+    /// Return normalized power weights for logarithmically spaced frequencies.
+    ///
+    /// Because the frequencies are spaced logarithmically, every frequency
+    /// represents an equal fraction of an octave, so the weight is the power
+    /// per octave. The weights sum to one.
+    pub fn weights(&self, frequencies: &[f32]) -> Vec<f32> {
+        let mut weights: Vec<f32> = frequencies
+            .iter()
+            .map(|&f| 10_f32.powf(self.level_per_octave_db(f) / 10.0))
+            .collect();
+        let total: f32 = weights.iter().sum();
+        for w in weights.iter_mut() {
+            *w /= total;
+        }
+        weights
+    }
+}
@@ -0,0 +1,60 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use crate::frd::FrequencyResponse;
+use crate::response;
+
+/// A target or house curve, the shape that the response at the listener should have.
+///
+/// Only the shape matters: a response is compared with the curve shifted to
+/// the level of the response, so how loud the response is does not count
+/// towards its deviation.
+#[derive(Clone)]
+pub struct Target {
+    curve: FrequencyResponse,
+}
+
+impl Target {
+    pub fn new(curve: FrequencyResponse) -> Target {
+        Target {
+            curve: curve,
+        }
+    }
+
+    /// Return a curve that is equally loud at every frequency.
+    pub fn flat() -> Target {
+        let curve = FrequencyResponse::from_points(vec![(1000.0, 0.0, 0.0)]).expect("A single point is a valid curve.");
+        Target::new(curve)
+    }
+
+    /// Return the level of the curve at `frequency`, in dB.
+    ///
+    /// Between the points of the curve, the level is interpolated linearly
+    /// in log-frequency, and outside of them it is that of the nearest point.
+    pub fn level_db(&self, frequency: f32) -> f32 {
+        response::to_db(self.curve.at(frequency))
+    }
+
+    /// Return the curve at `frequencies`, shifted to the mean level of `levels_db`.
+    pub fn aligned_db(&self, frequencies: &[f32], levels_db: &[f32]) -> Vec<f32> {
+        let curve: Vec<f32> = frequencies.iter().map(|&f| self.level_db(f)).collect();
+        let n = frequencies.len() as f32;
+        let offset = levels_db.iter().zip(&curve).map(|(db, target)| db - target).sum::<f32>() / n;
+        curve.iter().map(|target| target + offset).collect()
+    }
+
+    /// Return the root mean square deviation of `levels_db` from the aligned curve, in dB.
+    ///
+    /// The frequencies are usually spaced logarithmically, then every octave
+    /// counts equally. Lower is closer to the target.
+    pub fn deviation_db(&self, frequencies: &[f32], levels_db: &[f32]) -> f32 {
+        let aligned = self.aligned_db(frequencies, levels_db);
+        let n = frequencies.len() as f32;
+        let squares: f32 = levels_db.iter().zip(&aligned).map(|(db, target)| (db - target) * (db - target)).sum();
+        (squares / n).sqrt()
+    }
+}
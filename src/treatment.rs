@@ -0,0 +1,272 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::fs;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::complex::Complex;
+use crate::material::Material;
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+use crate::scene_file::{self, Result, invalid};
+use crate::vec3::Vec3;
+
+/// The number of Monte Carlo samples per frequency, per source.
+const N_SAMPLES: u32 = 4096;
+
+/// A part of the room that a material can be applied to.
+#[derive(Copy, Clone, Debug)]
+enum Target {
+    Room,
+    Wall(usize),
+    Floor,
+    Ceiling,
+}
+
+/// One change to the base scene.
+enum Change {
+    /// Apply a material to some faces.
+    Material(Target, Material),
+
+    /// Cut off a corner with a new wall, see `Scene::chamfer_corner()`.
+    CornerTrap { corner: usize, size: f32, material: Material },
+}
+
+/// A named set of changes to the base scene, e.g. "rear wall absorber".
+pub struct Scenario {
+    pub name: String,
+    changes: Vec<Change>,
+}
+
+impl Scenario {
+    /// Apply the changes to `base`, and return the treated scene.
+    ///
+    /// Materials are applied before corner traps, because a corner trap adds a
+    /// wall, which shifts the indices of later walls. Corner traps are applied
+    /// from the highest corner down, so corner indices refer to the base scene.
+    pub fn apply(&self, base: &Scene) -> Scene {
+        let mut scene = base.clone();
+        let n_walls = scene.n_walls();
+
+        for change in &self.changes {
+            if let Change::Material(target, ref material) = *change {
+                let faces = match target {
+                    Target::Room => 0..scene.faces.len(),
+                    Target::Wall(i) => i..i + 1,
+                    Target::Floor => n_walls..n_walls + 1,
+                    Target::Ceiling => n_walls + 1..n_walls + 2,
+                };
+                for face in &mut scene.faces[faces] {
+                    face.material = material.clone();
+                }
+            }
+        }
+
+        let mut traps: Vec<_> = self.changes.iter().filter_map(|change| match *change {
+            Change::CornerTrap { corner, size, ref material } => Some((corner, size, material)),
+            _ => None,
+        }).collect();
+        traps.sort_by_key(|&(corner, _, _)| corner);
+
+        for &(corner, size, material) in traps.iter().rev() {
+            scene = scene.chamfer_corner(corner, size, material.clone());
+        }
+
+        scene
+    }
+}
+
+/// Load treatment scenarios from a TOML file.
+///
+/// `base` is the scene that the scenarios apply to, it is used to validate wall
+/// and corner indices. A scenario file looks like this:
+///
+/// ```toml
+/// [[scenario]]
+/// name = "Ceiling cloud"
+/// ceiling_material = "absorber"
+///
+/// [[scenario]]
+/// name = "Rear wall absorber"
+/// # Change the material of individual walls, by index.
+/// wall = [{ index = 2, material = "absorber" }]
+///
+/// [[scenario]]
+/// name = "4 corner traps"
+/// # Cut off corners. The size is the distance from the corner along both
+/// # walls, the material defaults to "absorber".
+/// corner_trap = [
+///   { corner = 0, size = 0.4 },
+///   { corner = 1, size = 0.4 },
+///   { corner = 2, size = 0.4 },
+///   { corner = 3, size = 0.4, material = "curtain" },
+/// ]
+///
+/// # Custom materials, like in the scene file.
+/// [material.curtain]
+/// absorption = [0.03, 0.05, 0.07, 0.31, 0.49, 0.75, 0.70, 0.60]
+/// ```
+///
+/// A scenario can also set `material` and `floor_material`, as in the `[room]`
+/// section of a scene file.
+pub fn load_scenarios<P: AsRef<Path>>(path: P, base: &Scene) -> Result<Vec<Scenario>> {
+    let contents = fs::read_to_string(path)?;
+    let root: Value = contents.parse()?;
+    let materials = scene_file::parse_materials(&root)?;
+    if base.rooms.is_empty() {
+        return invalid("Treatment scenarios need a scene built from a floor plan.".to_string())
+    }
+    let n_walls = base.n_walls();
+
+    let mut scenarios = Vec::new();
+    let scenario_values = scene_file::as_array(scene_file::get(&root, "", "scenario")?, "scenario")?;
+
+    for (i, s) in scenario_values.iter().enumerate() {
+        let path = format!("scenario[{}]", i);
+        let name = scene_file::as_str(scene_file::get(s, &path, "name")?, &format!("{}.name", path))?;
+        let mut changes = Vec::new();
+
+        let targets = [
+            ("material", Target::Room),
+            ("floor_material", Target::Floor),
+            ("ceiling_material", Target::Ceiling),
+        ];
+        for &(key, target) in targets.iter() {
+            if let Some(m) = s.get(key) {
+                let m = scene_file::lookup_material(&materials, m, &format!("{}.{}", path, key))?;
+                changes.push(Change::Material(target, m));
+            }
+        }
+
+        if let Some(ws) = s.get("wall") {
+            let wall_path = format!("{}.wall", path);
+            for w in scene_file::as_array(ws, &wall_path)? {
+                let index_path = format!("{}.index", wall_path);
+                let index = scene_file::as_usize(scene_file::get(w, &wall_path, "index")?, &index_path)?;
+                if index >= n_walls {
+                    return invalid(format!(
+                        "Wall {} in '{}' does not exist, the room has {} walls.", index, wall_path, n_walls
+                    ))
+                }
+                let m = scene_file::get(w, &wall_path, "material")?;
+                let m = scene_file::lookup_material(&materials, m, &format!("{}.material", wall_path))?;
+                changes.push(Change::Material(Target::Wall(index), m));
+            }
+        }
+
+        if let Some(ts) = s.get("corner_trap") {
+            let trap_path = format!("{}.corner_trap", path);
+            for t in scene_file::as_array(ts, &trap_path)? {
+                let corner_path = format!("{}.corner", trap_path);
+                let corner = scene_file::as_usize(scene_file::get(t, &trap_path, "corner")?, &corner_path)?;
+                if corner >= n_walls {
+                    return invalid(format!(
+                        "Corner {} in '{}' does not exist, the room has {} corners.", corner, trap_path, n_walls
+                    ))
+                }
+                let size_path = format!("{}.size", trap_path);
+                let size = scene_file::as_f32(scene_file::get(t, &trap_path, "size")?, &size_path)?;
+                if !(size > 0.0) {
+                    return invalid(format!("Expected '{}' to be positive.", size_path))
+                }
+                let material = match t.get("material") {
+                    Some(m) => scene_file::lookup_material(&materials, m, &format!("{}.material", trap_path))?,
+                    None => Material::builtin("absorber").unwrap(),
+                };
+                changes.push(Change::CornerTrap { corner: corner, size: size, material: material });
+            }
+        }
+
+        scenarios.push(Scenario {
+            name: name.to_string(),
+            changes: changes,
+        });
+    }
+
+    Ok(scenarios)
+}
+
+/// Estimate the response of all sources together at `listener`.
+fn sample_response(scene: &Scene, listener: Vec3, frequencies: &[f32], seed: u64) -> Vec<Complex> {
+    // Use the same seed for every scenario, so the differences between them
+    // are not drowned in sampling noise.
+    let mut rng = Rng::new(seed);
+    response::sample_response(scene, &mut rng, listener, frequencies, N_SAMPLES)
+}
+
+/// Return the mean and standard deviation of the levels, in dB.
+///
+/// The standard deviation is a measure of how uneven the response is.
+fn mean_std(levels: &[f32]) -> (f32, f32) {
+    let n = levels.len() as f32;
+    let mean = levels.iter().sum::<f32>() / n;
+    let var = levels.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+    (mean, var.sqrt())
+}
+
+/// Print the response at the listener for the base scene and every scenario.
+pub fn print_comparison(
+    base: &Scene,
+    scenarios: &[Scenario],
+    listener: Vec3,
+    f_min: f32,
+    f_max: f32,
+    seed: u64,
+) {
+    let frequencies = response::log_frequencies(f_min, f_max, 12);
+
+    let mut names = vec!["untreated".to_string()];
+    let mut responses = vec![sample_response(base, listener, &frequencies[..], seed)];
+    for scenario in scenarios {
+        names.push(scenario.name.clone());
+        responses.push(sample_response(&scenario.apply(base), listener, &frequencies[..], seed));
+    }
+
+    // The scale of the sampled field is arbitrary, so report levels relative to
+    // the peak of the untreated response.
+    let reference = responses[0].iter().map(|&z| response::to_db(z)).fold(std::f32::NEG_INFINITY, f32::max);
+    let levels: Vec<Vec<f32>> = responses
+        .iter()
+        .map(|r| r.iter().map(|&z| response::to_db(z) - reference).collect())
+        .collect();
+
+    println!("Seed: {}", seed);
+    for (i, name) in names.iter().enumerate() {
+        println!("{:>2}: {}", i, name);
+    }
+    println!();
+
+    print!("{:>9}", "Hz");
+    for i in 0..names.len() {
+        print!("  {:>6}", i);
+    }
+    println!();
+
+    for (k, f) in frequencies.iter().enumerate() {
+        print!("{:>9.1}", f);
+        for ls in &levels {
+            print!("  {:>6.1}", ls[k]);
+        }
+        println!();
+    }
+    println!();
+
+    let stats: Vec<(f32, f32)> = levels.iter().map(|ls| mean_std(&ls[..])).collect();
+    print!("{:>9}", "mean dB");
+    for &(mean, _) in &stats {
+        print!("  {:>6.1}", mean);
+    }
+    println!();
+    print!("{:>9}", "std dB");
+    for &(_, std) in &stats {
+        print!("  {:>6.1}", std);
+    }
+    println!();
+}
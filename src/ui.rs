@@ -0,0 +1,2341 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use gdk;
+use gdk_pixbuf;
+use glib;
+use gtk::prelude::*;
+
+use crate::absorber;
+use crate::array::{self, ArrayKind};
+use crate::arrival;
+use crate::colormap::Colormap;
+use crate::complex::Complex;
+use crate::crawl;
+use crate::impulse::ImpulseResponse;
+use crate::integration::{self, Integration};
+use crate::material::Material;
+use crate::modal::Shoebox;
+use crate::plot;
+use crate::pool::{AnalysisPool, Priority};
+use crate::probe::{self, Overlay};
+use crate::project::{Annotation, Measurement};
+use crate::rand::Rng;
+use crate::renderer::{self, Averaging, Quantity, Renderer, Tile, View};
+use crate::residual;
+use crate::response;
+use crate::reverb;
+use crate::sbir;
+use crate::scene::{Scene, Solver};
+use crate::shell;
+use crate::vec2::Vec2;
+use crate::vec3::Vec3;
+
+/// Width of the color bar next to the image, in pixels.
+const LEGEND_WIDTH: u32 = 16;
+
+/// The closest that the slice gets to the floor and the ceiling, in meters.
+const SLICE_MARGIN: f64 = 0.05;
+
+/// How long the size of the image area must stay the same before we render at
+/// the new size, so dragging the edge of the window does not restart the
+/// render for every pixel that it moves.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How often the viewer shows the new samples, unless set with `--refresh`.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The shortest refresh interval, the viewer checks for a refresh this often.
+pub const MIN_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Width and height of the directivity plot, in pixels.
+const POLAR_SIZE: u32 = 240;
+
+/// Height of the frequency response plot, in pixels. It is as wide as the
+/// directivity plot.
+const RESPONSE_HEIGHT: u32 = 160;
+
+/// Monte Carlo samples per frequency for the surface comparison. Fewer than
+/// for the command-line analyses, so the comparison shows up quickly.
+const SURFACE_SAMPLES: u32 = 1024;
+
+/// The length of the impulse response in the panel, in seconds, and the Monte
+/// Carlo samples per frequency to synthesize it from.
+const IMPULSE_DURATION_S: f32 = 0.5;
+const IMPULSE_SAMPLES: u32 = 256;
+
+/// Monte Carlo samples per frequency for the response next to the boundary
+/// interference, and the frequencies per octave to sample it at.
+const SBIR_SAMPLES: u32 = 512;
+const SBIR_STEPS_PER_OCTAVE: u32 = 24;
+
+/// Monte Carlo samples per frequency for the responses at the listener and the probes.
+const OVERLAY_SAMPLES: u32 = 1024;
+
+/// The band of the sub crawl, in Hz, and the number of spots to list.
+const CRAWL_F_MIN: f32 = 20.0;
+const CRAWL_F_MAX: f32 = 120.0;
+const CRAWL_SPOTS: usize = 5;
+
+/// The highest frequency in the table of room modes, in Hz.
+const MODE_F_MAX: f32 = 300.0;
+
+/// Wrap an RGB image, as produced by `Renderer::paint()`, in a pixbuf.
+pub fn build_pixbuf(rgb: Vec<u8>, width: u32, height: u32) -> gdk_pixbuf::Pixbuf {
+    let has_alpha = false;
+    let bits_per_sample = 8;
+    let row_stride = width as i32 * 3;
+    gdk_pixbuf::Pixbuf::new_from_mut_slice(
+        rgb,
+        gdk_pixbuf::Colorspace::Rgb,
+        has_alpha,
+        bits_per_sample,
+        width as i32,
+        height as i32,
+        row_stride,
+    )
+}
+
+/// Copy `tiles` of an RGB image, as produced by `Renderer::paint()`, into
+/// `pixbuf`, which holds the image as it was before.
+///
+/// Only the tiles that were repainted change, so there is no need to copy
+/// the whole image on every paint.
+fn update_pixbuf(pixbuf: &gdk_pixbuf::Pixbuf, rgb: &[u8], width: u32, tiles: &[Tile]) {
+    for tile in tiles {
+        let row_len = tile.width as usize * 3;
+        let mut tile_rgb = Vec::with_capacity(row_len * tile.height as usize);
+        for y in tile.y..tile.y + tile.height {
+            let start = (y * width + tile.x) as usize * 3;
+            tile_rgb.extend_from_slice(&rgb[start..start + row_len]);
+        }
+        let tile_pixbuf = build_pixbuf(tile_rgb, tile.width, tile.height);
+        tile_pixbuf.copy_area(0, 0, tile.width as i32, tile.height as i32, pixbuf, tile.x as i32, tile.y as i32);
+    }
+}
+
+/// Plot the horizontal directivity of source `si` at the current frequency.
+fn paint_directivity(renderer: &Renderer, si: usize) -> gdk_pixbuf::Pixbuf {
+    let scene = renderer.scene();
+    let mut canvas = plot::Canvas::new(POLAR_SIZE, POLAR_SIZE, plot::WHITE);
+
+    // The scene may have been reloaded with fewer sources, then we draw only
+    // the grid.
+    let levels = match scene.sources.get(si) {
+        Some(source) => source.horizontal_directivity_db(&scene.atmosphere, renderer.frequency(), 180),
+        None => Vec::new(),
+    };
+    plot::plot_polar(&mut canvas, &levels[..], 40.0, plot::BLUE);
+
+    build_pixbuf(canvas.rgb, canvas.width, canvas.height)
+}
+
+/// A panel that shows the modelled directivity of a source.
+#[derive(Clone)]
+struct DirectivityPanel {
+    frame: gtk::Frame,
+    sources: gtk::ComboBoxText,
+    plot: gtk::Image,
+}
+
+impl DirectivityPanel {
+    fn new(renderer: &Arc<Renderer>) -> DirectivityPanel {
+        let frame = gtk::Frame::new(Some("Directivity"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let sources = gtk::ComboBoxText::new();
+        for i in 0..renderer.scene().sources.len() {
+            sources.append_text(&format!("Source {}", i));
+        }
+        sources.set_active(Some(0));
+        let sources_label = gtk::Label::new_with_mnemonic(Some("_Source"));
+        sources_label.set_mnemonic_widget(Some(&sources));
+
+        let plot = gtk::Image::new_from_pixbuf(Some(&paint_directivity(renderer, 0)));
+        plot.set_tooltip_text(Some("Polar plot of the horizontal directivity of the selected source."));
+        let caption = gtk::Label::new(Some(
+            "Seen from above, on-axis points up.\nRings every 10 dB, outer ring 0 dB."
+        ));
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&sources_label, expand, fill, padding);
+        vbox.pack_start(&sources, expand, fill, padding);
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+
+        let panel = DirectivityPanel {
+            frame: frame,
+            sources: sources,
+            plot: plot,
+        };
+
+        let p_ref = panel.clone();
+        let r_ref = renderer.clone();
+        panel.sources.connect_changed(move |_| p_ref.update(&r_ref));
+
+        panel
+    }
+
+    /// Redraw the plot, for the selected source at the current frequency.
+    fn update(&self, renderer: &Renderer) {
+        let si = self.sources.get_active().unwrap_or(0) as usize;
+        self.plot.set_from_pixbuf(Some(&paint_directivity(renderer, si)));
+    }
+}
+
+/// Levels in dB at the listener, before and after changing a surface.
+struct SurfaceDelta {
+    frequencies: Vec<f32>,
+    before: Vec<f32>,
+    after: Vec<f32>,
+}
+
+impl SurfaceDelta {
+    /// Sample the response at the listener in `before` and `after`.
+    ///
+    /// Both scenes are sampled with the same seed, so the difference between
+    /// the two is due to the change, not to sampling noise. Levels are relative
+    /// to the peak of the `before` response.
+    fn compute(before: &Scene, after: &Scene, listener: Vec3, seed: u64) -> SurfaceDelta {
+        let frequencies = response::log_frequencies(20.0, 320.0, 12);
+        let sample = |scene: &Scene| {
+            let mut rng = Rng::new(seed);
+            response::sample_response(scene, &mut rng, listener, &frequencies[..], SURFACE_SAMPLES)
+        };
+        let r_before = sample(before);
+        let r_after = sample(after);
+
+        let reference = r_before.iter().map(|&z| response::to_db(z)).fold(std::f32::NEG_INFINITY, f32::max);
+        let to_levels = |r: Vec<Complex>| -> Vec<f32> {
+            r.into_iter().map(|z| response::to_db(z) - reference).collect()
+        };
+
+        SurfaceDelta {
+            before: to_levels(r_before),
+            after: to_levels(r_after),
+            frequencies: frequencies,
+        }
+    }
+
+    /// Summarize the change as the mean difference, and the largest one.
+    fn describe(&self) -> String {
+        let deltas: Vec<f32> = self.after.iter().zip(&self.before).map(|(a, b)| a - b).collect();
+        let mean = deltas.iter().sum::<f32>() / deltas.len() as f32;
+        let (k, max) = deltas
+            .iter()
+            .enumerate()
+            .fold((0, 0.0_f32), |(k, m), (i, &d)| if d.abs() > m.abs() { (i, d) } else { (k, m) });
+        format!(
+            "Mean change {:+.1} dB.\nLargest change {:+.1} dB at {:.0} Hz.",
+            mean, max, self.frequencies[k],
+        )
+    }
+
+    fn paint(&self) -> gdk_pixbuf::Pixbuf {
+        let mut canvas = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let curves = [
+            (&self.before[..], plot::BLUE),
+            (&self.after[..], plot::ORANGE),
+        ];
+        plot::plot_response(&mut canvas, &self.frequencies[..], &curves, 10.0, 50.0);
+        build_pixbuf(canvas.rgb, canvas.width, canvas.height)
+    }
+}
+
+/// A panel that shows how one surface contributes to the response at the listener.
+///
+/// Select a face, then remove it (make it fully absorbing) or give it a
+/// different material, to see the frequency response at the listener before
+/// and after. This only previews the change, the rendered scene is unaffected.
+#[derive(Clone)]
+struct SurfacePanel {
+    frame: gtk::Frame,
+    faces: gtk::ComboBoxText,
+    remove: gtk::CheckButton,
+    materials: gtk::ComboBoxText,
+    plot: gtk::Image,
+    summary: gtk::Label,
+
+    /// Incremented on every update, so results of outdated updates that are
+    /// still computing when the selection changes can be discarded.
+    generation: Arc<AtomicUsize>,
+}
+
+impl SurfacePanel {
+    fn new(renderer: &Arc<Renderer>, pool: &Arc<AnalysisPool>) -> SurfacePanel {
+        let frame = gtk::Frame::new(Some("Surface"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let scene = renderer.scene();
+        let faces = gtk::ComboBoxText::new();
+        for (i, face) in scene.faces.iter().enumerate() {
+            faces.append_text(&format!("{} ({})", scene.face_name(i), face.material.name));
+        }
+        faces.set_active(Some(0));
+        let faces_label = gtk::Label::new_with_mnemonic(Some("Fa_ce"));
+        faces_label.set_mnemonic_widget(Some(&faces));
+
+        let remove = gtk::CheckButton::new_with_mnemonic("_Remove (fully absorbing)");
+
+        let materials = gtk::ComboBoxText::new();
+        materials.append_text("Unchanged material");
+        for name in Material::builtin_names() {
+            materials.append_text(name);
+        }
+        materials.set_active(Some(0));
+        let materials_label = gtk::Label::new_with_mnemonic(Some("_Material"));
+        materials_label.set_mnemonic_widget(Some(&materials));
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let plot = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        let caption = gtk::Label::new(Some(
+            "Response at the listener, 20 to 320 Hz.\nBlue before, orange after the change."
+        ));
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&faces_label, expand, fill, padding);
+        vbox.pack_start(&faces, expand, fill, padding);
+        vbox.pack_start(&remove, expand, fill, padding);
+        vbox.pack_start(&materials_label, expand, fill, padding);
+        vbox.pack_start(&materials, expand, fill, padding);
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+        vbox.pack_start(&summary, expand, fill, padding);
+
+        let panel = SurfacePanel {
+            frame: frame,
+            faces: faces,
+            remove: remove,
+            materials: materials,
+            plot: plot,
+            summary: summary,
+            generation: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let p_ref = panel.clone();
+        let r_ref = renderer.clone();
+        let pool_ref = pool.clone();
+        panel.faces.connect_changed(move |_| p_ref.update(&r_ref, &pool_ref));
+        let p_ref = panel.clone();
+        let r_ref = renderer.clone();
+        let pool_ref = pool.clone();
+        panel.remove.connect_toggled(move |_| p_ref.update(&r_ref, &pool_ref));
+        let p_ref = panel.clone();
+        let r_ref = renderer.clone();
+        let pool_ref = pool.clone();
+        panel.materials.connect_changed(move |_| p_ref.update(&r_ref, &pool_ref));
+
+        panel
+    }
+
+    /// Start computing the responses for the current selection.
+    ///
+    /// The computation runs on the analysis pool, the plot is updated when it
+    /// completes. If the selection changes before it starts, it is skipped.
+    fn update(&self, renderer: &Renderer, pool: &AnalysisPool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let before = renderer.scene();
+        let fi = self.faces.get_active().unwrap_or(0) as usize;
+        let mi = self.materials.get_active().unwrap_or(0) as usize;
+        let remove = self.remove.get_active();
+
+        // The scene may have been reloaded with fewer faces, or without a
+        // listener, then there is nothing to compare.
+        let listener = match before.listener {
+            Some(ref listener) if fi < before.faces.len() => listener.position,
+            _ => {
+                self.summary.set_text("Nothing to compare.");
+                return
+            }
+        };
+        if !remove && mi == 0 {
+            self.summary.set_text("Remove the surface or pick a material.");
+            return
+        }
+
+        let mut after = (*before).clone();
+        after.faces[fi].material = if remove {
+            Material::constant("removed".to_string(), Complex::zero())
+        } else {
+            Material::builtin(Material::builtin_names()[mi - 1]).unwrap()
+        };
+
+        self.summary.set_text("Computing ...");
+        let seed = renderer.seed();
+        let (sender, receiver) = mpsc::channel();
+        let current = self.generation.clone();
+        pool.submit(Priority::Interactive, move || {
+            if current.load(Ordering::SeqCst) != generation {
+                return
+            }
+            let delta = SurfaceDelta::compute(&before, &after, listener, seed);
+            // The receiver is gone if the panel was closed, that is fine.
+            let _ = sender.send(delta);
+        });
+
+        let p_ref = self.clone();
+        glib::source::timeout_add_local(100, move || {
+            match receiver.try_recv() {
+                Ok(delta) => {
+                    if p_ref.generation.load(Ordering::SeqCst) == generation {
+                        p_ref.plot.set_from_pixbuf(Some(&delta.paint()));
+                        p_ref.summary.set_text(&delta.describe());
+                    }
+                    glib::source::Continue(false)
+                }
+                Err(mpsc::TryRecvError::Empty) => glib::source::Continue(true),
+                Err(mpsc::TryRecvError::Disconnected) => glib::source::Continue(false),
+            }
+        });
+    }
+}
+
+/// A panel that maps where the simulation deviates from measurements.
+///
+/// At the current frequency, the difference between the measured and
+/// simulated level at every measurement position is interpolated over the
+/// floor plan. Red means the room is louder than the model says, blue quieter.
+#[derive(Clone)]
+struct MeasurementsPanel {
+    frame: gtk::Frame,
+    plot: gtk::Image,
+    summary: gtk::Label,
+    measurements: Arc<Vec<Measurement>>,
+
+    /// Incremented on every update, see `SurfacePanel::generation`.
+    generation: Arc<AtomicUsize>,
+}
+
+impl MeasurementsPanel {
+    fn new(measurements: &[Measurement]) -> MeasurementsPanel {
+        let frame = gtk::Frame::new(Some("Measured minus simulated"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let plot = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        plot.set_tooltip_text(Some("Map of the measured minus simulated level, the summary below lists the values."));
+        let caption = gtk::Label::new(Some("Red is louder than simulated,\nblue quieter, full color at 10 dB."));
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+        vbox.pack_start(&summary, expand, fill, padding);
+
+        MeasurementsPanel {
+            frame: frame,
+            plot: plot,
+            summary: summary,
+            measurements: Arc::new(measurements.to_vec()),
+            generation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Start computing the residuals at the current frequency on the analysis pool.
+    fn update(&self, renderer: &Renderer, pool: &AnalysisPool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.measurements.len() < 2 {
+            self.summary.set_text("At least two measurements are needed.");
+            return
+        }
+
+        self.summary.set_text("Computing ...");
+        let scene = renderer.scene();
+        let frequency = renderer.frequency();
+        let seed = renderer.seed();
+        let measurements = self.measurements.clone();
+        let (sender, receiver) = mpsc::channel();
+        let current = self.generation.clone();
+        pool.submit(Priority::Interactive, move || {
+            if current.load(Ordering::SeqCst) != generation {
+                return
+            }
+            let residuals = residual::compute(&scene, &measurements[..], frequency, seed);
+            let map = residual::paint_map(&scene, &measurements[..], &residuals[..], POLAR_SIZE, RESPONSE_HEIGHT);
+            let _ = sender.send((residuals, map));
+        });
+
+        let p_ref = self.clone();
+        glib::source::timeout_add_local(100, move || {
+            match receiver.try_recv() {
+                Ok((residuals, map)) => {
+                    if p_ref.generation.load(Ordering::SeqCst) == generation {
+                        p_ref.plot.set_from_pixbuf(Some(&build_pixbuf(map.rgb, map.width, map.height)));
+                        let lines: Vec<String> = p_ref.measurements
+                            .iter()
+                            .zip(residuals)
+                            .map(|(m, r)| format!("{}: {:+.1} dB", m.name, r))
+                            .collect();
+                        p_ref.summary.set_text(&lines.join("\n"));
+                    }
+                    glib::source::Continue(false)
+                }
+                Err(mpsc::TryRecvError::Empty) => glib::source::Continue(true),
+                Err(mpsc::TryRecvError::Disconnected) => glib::source::Continue(false),
+            }
+        });
+    }
+}
+
+/// A panel that maps when the direct sound of a source arrives.
+///
+/// With a reference source selected, it maps the difference in arrival time
+/// between the two instead, which shows where their direct sound sums in
+/// phase, before any reflections are considered.
+#[derive(Clone)]
+struct ArrivalPanel {
+    frame: gtk::Frame,
+    sources: gtk::ComboBoxText,
+    references: gtk::ComboBoxText,
+    plot: gtk::Image,
+    caption: gtk::Label,
+    summary: gtk::Label,
+}
+
+impl ArrivalPanel {
+    fn new(renderer: &Arc<Renderer>) -> ArrivalPanel {
+        let frame = gtk::Frame::new(Some("Arrival time"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let n_sources = renderer.scene().sources.len();
+        let sources = gtk::ComboBoxText::new();
+        let references = gtk::ComboBoxText::new();
+        references.append_text("None");
+        for i in 0..n_sources {
+            sources.append_text(&format!("Source {}", i));
+            references.append_text(&format!("Source {}", i));
+        }
+        sources.set_active(Some(0));
+        references.set_active(Some(0));
+        let sources_label = gtk::Label::new_with_mnemonic(Some("S_ource"));
+        sources_label.set_mnemonic_widget(Some(&sources));
+        let references_label = gtk::Label::new_with_mnemonic(Some("Relative _to"));
+        references_label.set_mnemonic_widget(Some(&references));
+        references.set_tooltip_text(Some("Map the arrival time minus that of this source."));
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let plot = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        plot.set_tooltip_text(Some(
+            "Map of the arrival time of the direct sound, the summary below lists the times at the listener."
+        ));
+        let caption = gtk::Label::new(None);
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&sources_label, expand, fill, padding);
+        vbox.pack_start(&sources, expand, fill, padding);
+        vbox.pack_start(&references_label, expand, fill, padding);
+        vbox.pack_start(&references, expand, fill, padding);
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+        vbox.pack_start(&summary, expand, fill, padding);
+
+        let panel = ArrivalPanel {
+            frame: frame,
+            sources: sources,
+            references: references,
+            plot: plot,
+            caption: caption,
+            summary: summary,
+        };
+
+        let p_ref = panel.clone();
+        let r_ref = renderer.clone();
+        panel.sources.connect_changed(move |_| p_ref.update(&r_ref.scene()));
+        let p_ref = panel.clone();
+        let r_ref = renderer.clone();
+        panel.references.connect_changed(move |_| p_ref.update(&r_ref.scene()));
+
+        panel.update(&renderer.scene());
+        panel
+    }
+
+    /// Redraw the map for the selected sources.
+    ///
+    /// Arrival times do not depend on the frequency, only on the scene.
+    fn update(&self, scene: &Scene) {
+        let si = self.sources.get_active().unwrap_or(0) as usize;
+        let reference = match self.references.get_active() {
+            Some(i) if i > 0 => Some(i as usize - 1),
+            _ => None,
+        };
+
+        // The scene may have been reloaded with fewer sources.
+        if si >= scene.sources.len() || reference.map_or(false, |ri| ri >= scene.sources.len()) {
+            return
+        }
+
+        let map = arrival::paint_map(scene, si, reference, POLAR_SIZE, RESPONSE_HEIGHT);
+        self.plot.set_from_pixbuf(Some(&build_pixbuf(map.canvas.rgb, map.canvas.width, map.canvas.height)));
+
+        let caption = match reference {
+            Some(ri) => format!(
+                "Red is later than source {}, blue earlier,\nfull color at 5 ms. Lines every 1 ms.",
+                ri,
+            ),
+            None => format!(
+                "White at {:.1} ms, blue at {:.1} ms.\nLines every 1 ms.",
+                map.min_ms, map.max_ms,
+            ),
+        };
+        self.caption.set_text(&caption);
+
+        let listener = match scene.listener {
+            Some(ref listener) => listener.position,
+            None => {
+                self.summary.set_text("No listener in the scene.");
+                return
+            }
+        };
+        let lines: Vec<String> = (0..scene.sources.len())
+            .map(|i| match scene.arrival_ms(i, listener) {
+                Some(t) => format!("Source {} at the listener: {:.2} ms", i, t),
+                None => format!("Source {} at the listener: blocked", i),
+            })
+            .collect();
+        self.summary.set_text(&lines.join("\n"));
+    }
+}
+
+/// A panel that overlays the frequency responses at the listener and the probes.
+///
+/// The probes are points in the scene file, such as the other seats, so the
+/// response at the listener can be compared against them at a glance. Their
+/// markers on the map have the colors of their curves. Below them are the
+/// phase and the group delay at the listener.
+#[derive(Clone)]
+struct OverlayPanel {
+    frame: gtk::Frame,
+    smoothing: gtk::ComboBoxText,
+    plot: gtk::Image,
+    phase: gtk::Image,
+    group_delay: gtk::Image,
+    summary: gtk::Label,
+
+    /// The responses of the last update, to plot again when the smoothing changes.
+    overlay: Rc<RefCell<Option<Overlay>>>,
+
+    /// Incremented on every update, see `SurfacePanel::generation`.
+    generation: Arc<AtomicUsize>,
+}
+
+impl OverlayPanel {
+    fn new() -> OverlayPanel {
+        let frame = gtk::Frame::new(Some("Frequency response"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let smoothing = gtk::ComboBoxText::new();
+        smoothing.append_text("None");
+        smoothing.append_text("1/12 octave");
+        smoothing.append_text("1/3 octave");
+        smoothing.set_active(Some(0));
+        smoothing.set_tooltip_text(Some("Smooth the responses over a fraction of an octave, like measurement software."));
+        let smoothing_label = gtk::Label::new_with_mnemonic(Some("Smoothin_g"));
+        smoothing_label.set_mnemonic_widget(Some(&smoothing));
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let plot = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        plot.set_tooltip_text(Some(
+            "Responses at the listener and the probes, the summary below names the curves."
+        ));
+        let caption = gtk::Label::new(Some(&format!(
+            "{:.0} to {:.0} Hz, relative to the loudest\npoint, with a line every 10 dB.",
+            probe::F_MIN, probe::F_MAX,
+        )[..]));
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let phase = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        phase.set_tooltip_text(Some("Unwrapped phase at the listener. A delay makes the phase drop with frequency."));
+        let phase_caption = gtk::Label::new(Some("Phase at the listener, unsmoothed."));
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let group_delay = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        group_delay.set_tooltip_text(Some(
+            "Group delay at the listener, the delay of every frequency. Modes that ring show as peaks."
+        ));
+        let group_delay_caption = gtk::Label::new(Some(
+            "Group delay at the listener, from the\nphase, with the grid lines in ms."
+        ));
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&smoothing_label, expand, fill, padding);
+        vbox.pack_start(&smoothing, expand, fill, padding);
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+        vbox.pack_start(&phase, expand, fill, padding);
+        vbox.pack_start(&phase_caption, expand, fill, padding);
+        vbox.pack_start(&group_delay, expand, fill, padding);
+        vbox.pack_start(&group_delay_caption, expand, fill, padding);
+        vbox.pack_start(&summary, expand, fill, padding);
+
+        let panel = OverlayPanel {
+            frame: frame,
+            smoothing: smoothing,
+            plot: plot,
+            phase: phase,
+            group_delay: group_delay,
+            summary: summary,
+            overlay: Rc::new(RefCell::new(None)),
+            generation: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let p_ref = panel.clone();
+        panel.smoothing.connect_changed(move |_| p_ref.repaint());
+
+        panel
+    }
+
+    /// Plot the responses of the last update with the selected smoothing.
+    fn repaint(&self) {
+        let smoothing = match self.smoothing.get_active() {
+            Some(1) => response::Smoothing::Fraction(12),
+            Some(2) => response::Smoothing::Fraction(3),
+            _ => response::Smoothing::Off,
+        };
+        if let Some(ref overlay) = *self.overlay.borrow() {
+            let canvas = overlay.paint(smoothing, POLAR_SIZE, RESPONSE_HEIGHT);
+            self.plot.set_from_pixbuf(Some(&build_pixbuf(canvas.rgb, canvas.width, canvas.height)));
+        }
+    }
+
+    /// Start sampling the responses on the analysis pool.
+    fn update(&self, renderer: &Renderer, pool: &AnalysisPool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.summary.set_text("Computing ...");
+        let scene = renderer.scene();
+        let seed = renderer.seed();
+        let (sender, receiver) = mpsc::channel();
+        let current = self.generation.clone();
+        pool.submit(Priority::Interactive, move || {
+            if current.load(Ordering::SeqCst) != generation {
+                return
+            }
+            let _ = sender.send(Overlay::compute(&scene, seed, OVERLAY_SAMPLES));
+        });
+
+        let p_ref = self.clone();
+        glib::source::timeout_add_local(100, move || {
+            match receiver.try_recv() {
+                Ok(overlay) => {
+                    if p_ref.generation.load(Ordering::SeqCst) == generation {
+                        p_ref.summary.set_text(&overlay.describe());
+                        let empty = || plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+                        let canvas = overlay.paint_phase(POLAR_SIZE, RESPONSE_HEIGHT).unwrap_or_else(empty);
+                        p_ref.phase.set_from_pixbuf(Some(&build_pixbuf(canvas.rgb, canvas.width, canvas.height)));
+                        let canvas = overlay.paint_group_delay(POLAR_SIZE, RESPONSE_HEIGHT).unwrap_or_else(empty);
+                        p_ref.group_delay.set_from_pixbuf(Some(&build_pixbuf(canvas.rgb, canvas.width, canvas.height)));
+                        *p_ref.overlay.borrow_mut() = Some(overlay);
+                        p_ref.repaint();
+                    }
+                    glib::source::Continue(false)
+                }
+                Err(mpsc::TryRecvError::Empty) => glib::source::Continue(true),
+                Err(mpsc::TryRecvError::Disconnected) => glib::source::Continue(false),
+            }
+        });
+    }
+}
+
+/// A panel that lists the speaker-boundary interference dips of a source.
+///
+/// The dips are predicted from the distances of the source to the faces near
+/// it, and shown next to the simulated response of the source at the
+/// listener, to tell them apart from the dips that the modes of the room cause.
+#[derive(Clone)]
+struct SbirPanel {
+    frame: gtk::Frame,
+    sources: gtk::ComboBoxText,
+    plot: gtk::Image,
+    summary: gtk::Label,
+
+    /// Incremented on every update, see `SurfacePanel::generation`.
+    generation: Arc<AtomicUsize>,
+}
+
+impl SbirPanel {
+    fn new(renderer: &Arc<Renderer>, pool: &Arc<AnalysisPool>) -> SbirPanel {
+        let frame = gtk::Frame::new(Some("Speaker-boundary interference"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let sources = gtk::ComboBoxText::new();
+        for i in 0..renderer.scene().sources.len() {
+            sources.append_text(&format!("Source {}", i));
+        }
+        sources.set_active(Some(0));
+        let sources_label = gtk::Label::new_with_mnemonic(Some("So_urce"));
+        sources_label.set_mnemonic_widget(Some(&sources));
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let plot = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        plot.set_tooltip_text(Some(
+            "Response of the source at the listener, with the dips that the faces near the source cause marked."
+        ));
+        let caption = gtk::Label::new(Some(
+            "Black is the simulated response, blue the\nresponse of the near faces alone, red their dips."
+        ));
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&sources_label, expand, fill, padding);
+        vbox.pack_start(&sources, expand, fill, padding);
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+        vbox.pack_start(&summary, expand, fill, padding);
+
+        let panel = SbirPanel {
+            frame: frame,
+            sources: sources,
+            plot: plot,
+            summary: summary,
+            generation: Arc::new(AtomicUsize::new(0)),
+        };
+
+        let p_ref = panel.clone();
+        let r_ref = renderer.clone();
+        let pool_ref = pool.clone();
+        panel.sources.connect_changed(move |_| p_ref.update(&r_ref, &pool_ref));
+
+        panel.update(renderer, pool);
+        panel
+    }
+
+    /// List the dips of the selected source, and start sampling its response on the analysis pool.
+    fn update(&self, renderer: &Renderer, pool: &AnalysisPool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let scene = renderer.scene();
+        let si = self.sources.get_active().unwrap_or(0) as usize;
+        let listener = match scene.listener {
+            Some(ref listener) => listener.position,
+            None => {
+                self.summary.set_text("No listener in the scene.");
+                return
+            }
+        };
+
+        // The scene may have been reloaded with fewer sources.
+        if si >= scene.sources.len() {
+            return
+        }
+
+        // The dips are cheap to find, the response takes a while.
+        let boundaries = sbir::boundaries(&scene, si, listener);
+        let description = sbir::describe(&scene, &boundaries[..]);
+        self.summary.set_text(&format!("{}\nComputing the response ...", description));
+
+        let seed = renderer.seed();
+        let (sender, receiver) = mpsc::channel();
+        let current = self.generation.clone();
+        pool.submit(Priority::Interactive, move || {
+            if current.load(Ordering::SeqCst) != generation {
+                return
+            }
+            let frequencies = response::log_frequencies(sbir::F_MIN, sbir::F_MAX, SBIR_STEPS_PER_OCTAVE);
+            let mut rng = Rng::new(seed);
+            let r = response::sample_source_response(&scene, &mut rng, si, listener, &frequencies[..], SBIR_SAMPLES);
+            let levels: Vec<f32> = r.into_iter().map(response::to_db).collect();
+            let predicted = sbir::predicted_db(&scene, si, listener, &frequencies[..]);
+            let canvas = sbir::paint(&boundaries[..], &frequencies[..], &levels[..], &predicted[..], POLAR_SIZE, RESPONSE_HEIGHT);
+            let _ = sender.send(canvas);
+        });
+
+        let p_ref = self.clone();
+        glib::source::timeout_add_local(100, move || {
+            match receiver.try_recv() {
+                Ok(canvas) => {
+                    if p_ref.generation.load(Ordering::SeqCst) == generation {
+                        p_ref.plot.set_from_pixbuf(Some(&build_pixbuf(canvas.rgb, canvas.width, canvas.height)));
+                        p_ref.summary.set_text(&description);
+                    }
+                    glib::source::Continue(false)
+                }
+                Err(mpsc::TryRecvError::Empty) => glib::source::Continue(true),
+                Err(mpsc::TryRecvError::Disconnected) => glib::source::Continue(false),
+            }
+        });
+    }
+}
+
+/// A panel that lists the reverberation time of the scene per octave band,
+/// and the Schroeder frequency that follows from it.
+///
+/// The times follow from the areas and the materials of the faces alone, so
+/// they are cheap to compute, and they are a sanity check for the decay of
+/// the impulse response.
+#[derive(Clone)]
+struct ReverbPanel {
+    frame: gtk::Frame,
+    summary: gtk::Label,
+}
+
+impl ReverbPanel {
+    fn new(scene: &Scene) -> ReverbPanel {
+        let frame = gtk::Frame::new(Some("Reverberation time"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+        summary.set_tooltip_text(Some(
+            "RT60 by the formulas of Sabine and Eyring, which assume a diffuse field. In small rooms at low frequencies, the modes dominate instead."
+        ));
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&summary, expand, fill, padding);
+
+        let panel = ReverbPanel {
+            frame: frame,
+            summary: summary,
+        };
+        panel.update(scene);
+        panel
+    }
+
+    fn update(&self, scene: &Scene) {
+        let times = reverb::reverb_times(scene);
+        let schroeder_hz = reverb::schroeder_frequency(scene);
+        let mut text = format!(
+            "Volume {:.1} m³, Schroeder frequency {:.0} Hz,\n{:.2} modes per Hz there.\n{}",
+            reverb::volume(scene),
+            schroeder_hz,
+            reverb::modal_density(scene, schroeder_hz),
+            reverb::describe(&times[..]),
+        );
+        if !scene.absorbers.is_empty() {
+            text.push_str("\n");
+            text.push_str(&absorber::describe(scene));
+        }
+        self.summary.set_text(&text);
+    }
+}
+
+/// A table of the modes of a shoebox room, up to `MODE_F_MAX`.
+///
+/// Clicking a column header sorts by it, and selecting a mode jumps the
+/// frequency to it, see `connect_select()`.
+#[derive(Clone)]
+struct ModePanel {
+    frame: gtk::Frame,
+    tree: gtk::TreeView,
+    store: gtk::ListStore,
+
+    /// The room and the speed of sound that the table lists the modes for.
+    shown: Rc<Cell<Option<(Shoebox, f32)>>>,
+}
+
+impl ModePanel {
+    fn new(scene: &Scene) -> ModePanel {
+        let frame = gtk::Frame::new(Some("Room modes"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        // The columns are the order, the kind, the frequency as displayed,
+        // and the frequency as a number to sort by.
+        let store = gtk::ListStore::new(&[glib::Type::String, glib::Type::String, glib::Type::String, glib::Type::F32]);
+        let tree = gtk::TreeView::new_with_model(&store);
+        tree.set_headers_clickable(true);
+        tree.set_tooltip_text(Some("Select a mode to render its frequency."));
+        for &(title, column, sort_column) in &[("Mode", 0, 0), ("Kind", 1, 1), ("Frequency (Hz)", 2, 3)] {
+            let cell = gtk::CellRendererText::new();
+            let tree_column = gtk::TreeViewColumn::new();
+            tree_column.set_title(title);
+            tree_column.pack_start(&cell, true);
+            tree_column.add_attribute(&cell, "text", column);
+            tree_column.set_sort_column_id(sort_column);
+            tree.append_column(&tree_column);
+        }
+
+        let scroll = gtk::ScrolledWindow::new(None, None);
+        scroll.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+        scroll.set_min_content_height(RESPONSE_HEIGHT as i32);
+        scroll.add(&tree);
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&scroll, expand, fill, padding);
+
+        let panel = ModePanel {
+            frame: frame,
+            tree: tree,
+            store: store,
+            shown: Rc::new(Cell::new(None)),
+        };
+        panel.update(scene);
+        panel
+    }
+
+    /// Call `f` with the frequency of the mode that the user selects.
+    fn connect_select<F: Fn(f32) + 'static>(&self, f: F) {
+        self.tree.get_selection().connect_changed(move |selection| {
+            if let Some((model, iter)) = selection.get_selected() {
+                if let Some(frequency_hz) = model.get_value(&iter, 3).get::<f32>() {
+                    f(frequency_hz);
+                }
+            }
+        });
+    }
+
+    /// Fill the table with the modes of the scene, if its room or the speed of sound changed.
+    fn update(&self, scene: &Scene) {
+        let c = scene.atmosphere.speed_of_sound();
+        let shoebox = Shoebox::of(scene);
+        let shown = shoebox.map(|b| (b, c));
+        if shown == self.shown.get() {
+            return
+        }
+        self.shown.set(shown);
+        self.store.clear();
+
+        let modes = match shoebox {
+            Some(b) => b.modes(c, MODE_F_MAX),
+            None => return,
+        };
+        for mode in &modes {
+            let order = format!("{} {} {}", mode.order[0], mode.order[1], mode.order[2]);
+            let frequency = format!("{:.1}", mode.frequency);
+            self.store.insert_with_values(
+                None,
+                &[0, 1, 2, 3],
+                &[&order, &mode.kind.name(), &frequency, &mode.frequency],
+            );
+        }
+    }
+}
+
+/// A panel that shows how the subs and the mains sum around the crossover.
+///
+/// The subs and the mains are simulated together, each with their side of the
+/// crossover and their roll-off, and their responses at the listener are
+/// plotted separately and summed. To see the field at the crossover
+/// frequency itself, jump the frequency slider there.
+#[derive(Clone)]
+struct IntegrationPanel {
+    frame: gtk::Frame,
+    jump: gtk::Button,
+    plot: gtk::Image,
+    summary: gtk::Label,
+
+    /// Incremented on every update, see `SurfacePanel::generation`.
+    generation: Arc<AtomicUsize>,
+}
+
+impl IntegrationPanel {
+    fn new() -> IntegrationPanel {
+        let frame = gtk::Frame::new(Some("Subs and mains"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let plot = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        plot.set_tooltip_text(Some(
+            "Response at the listener around the crossover, the summary below describes the sum at the crossover."
+        ));
+        let caption = gtk::Label::new(Some(
+            "Response at the listener, a quarter to\n4 times the crossover frequency. Blue subs,\norange mains, black the sum."
+        ));
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+        let jump = gtk::Button::new_with_mnemonic("_Jump to the crossover frequency");
+        jump.set_tooltip_text(Some("Set the frequency slider to the crossover frequency, to see the field there."));
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+        vbox.pack_start(&summary, expand, fill, padding);
+        vbox.pack_start(&jump, expand, fill, padding);
+
+        IntegrationPanel {
+            frame: frame,
+            jump: jump,
+            plot: plot,
+            summary: summary,
+            generation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Start computing the responses around the crossover on the analysis pool.
+    fn update(&self, renderer: &Renderer, pool: &AnalysisPool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let scene = renderer.scene();
+        let listener = match scene.listener {
+            Some(ref listener) => listener.position,
+            None => {
+                self.summary.set_text("No listener in the scene.");
+                return
+            }
+        };
+
+        self.summary.set_text("Computing ...");
+        let seed = renderer.seed();
+        let (sender, receiver) = mpsc::channel();
+        let current = self.generation.clone();
+        pool.submit(Priority::Interactive, move || {
+            if current.load(Ordering::SeqCst) != generation {
+                return
+            }
+            let result = Integration::compute(&scene, listener, seed);
+            let _ = sender.send(result);
+        });
+
+        let p_ref = self.clone();
+        glib::source::timeout_add_local(100, move || {
+            match receiver.try_recv() {
+                Ok(result) => {
+                    if p_ref.generation.load(Ordering::SeqCst) == generation {
+                        match result {
+                            Some(integration) => {
+                                let canvas = integration.paint(POLAR_SIZE, RESPONSE_HEIGHT);
+                                p_ref.plot.set_from_pixbuf(Some(&build_pixbuf(canvas.rgb, canvas.width, canvas.height)));
+                                p_ref.summary.set_text(&integration.describe());
+                            }
+                            None => p_ref.summary.set_text("No crossover in the scene."),
+                        }
+                    }
+                    glib::source::Continue(false)
+                }
+                Err(mpsc::TryRecvError::Empty) => glib::source::Continue(true),
+                Err(mpsc::TryRecvError::Disconnected) => glib::source::Continue(false),
+            }
+        });
+    }
+}
+
+/// A panel that shows the impulse response at the listener, as an energy-time
+/// curve, and as a waterfall of how the modes ring out.
+///
+/// The response is synthesized from the field at the listener, see
+/// `ImpulseResponse::synthesize()`. The `impulse` command exports it.
+#[derive(Clone)]
+struct ImpulsePanel {
+    frame: gtk::Frame,
+    plot: gtk::Image,
+    waterfall: gtk::Image,
+    summary: gtk::Label,
+
+    /// Incremented on every update, see `SurfacePanel::generation`.
+    generation: Arc<AtomicUsize>,
+}
+
+impl ImpulsePanel {
+    fn new() -> ImpulsePanel {
+        let frame = gtk::Frame::new(Some("Impulse response"));
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+        vbox.set_border_width(6);
+        frame.add(&vbox);
+
+        let empty = plot::Canvas::new(POLAR_SIZE, RESPONSE_HEIGHT, plot::WHITE);
+        let plot = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        plot.set_tooltip_text(Some(
+            "Energy-time curve at the listener, with a line every 50 ms and every 10 dB."
+        ));
+        let caption = gtk::Label::new(Some(&format!(
+            "Energy at the listener over the first\n{:.0} ms, up to 400 Hz, relative to the peak.",
+            IMPULSE_DURATION_S * 1e3,
+        )[..]));
+        let empty = plot::Canvas::new(POLAR_SIZE, POLAR_SIZE, plot::WHITE);
+        let waterfall = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(empty.rgb, empty.width, empty.height)));
+        waterfall.set_tooltip_text(Some(
+            "Cumulative spectral decay at the listener, from 20 to 200 Hz, with a slice every 10 ms."
+        ));
+        let waterfall_caption = gtk::Label::new(Some(
+            "What still rings after 0 ms (blue) to 200 ms (red),\nover 40 dB. Ridges that reach the back are boomy."
+        ));
+        let summary = gtk::Label::new(None);
+        summary.set_selectable(true);
+
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&plot, expand, fill, padding);
+        vbox.pack_start(&caption, expand, fill, padding);
+        vbox.pack_start(&waterfall, expand, fill, padding);
+        vbox.pack_start(&waterfall_caption, expand, fill, padding);
+        vbox.pack_start(&summary, expand, fill, padding);
+
+        ImpulsePanel {
+            frame: frame,
+            plot: plot,
+            waterfall: waterfall,
+            summary: summary,
+            generation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Start synthesizing the impulse response on the analysis pool.
+    fn update(&self, renderer: &Renderer, pool: &AnalysisPool) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let scene = renderer.scene();
+        let listener = match scene.listener {
+            Some(ref listener) => listener.position,
+            None => {
+                self.summary.set_text("No listener in the scene.");
+                return
+            }
+        };
+
+        self.summary.set_text("Computing ...");
+        let seed = renderer.seed();
+        let (sender, receiver) = mpsc::channel();
+        let current = self.generation.clone();
+        pool.submit(Priority::Interactive, move || {
+            if current.load(Ordering::SeqCst) != generation {
+                return
+            }
+            let ir = ImpulseResponse::synthesize(&scene, listener, IMPULSE_DURATION_S, IMPULSE_SAMPLES, seed);
+            let waterfall = ir.waterfall();
+            let _ = sender.send((ir, waterfall));
+        });
+
+        let p_ref = self.clone();
+        glib::source::timeout_add_local(100, move || {
+            match receiver.try_recv() {
+                Ok((ir, waterfall)) => {
+                    if p_ref.generation.load(Ordering::SeqCst) == generation {
+                        let canvas = ir.paint(POLAR_SIZE, RESPONSE_HEIGHT);
+                        p_ref.plot.set_from_pixbuf(Some(&build_pixbuf(canvas.rgb, canvas.width, canvas.height)));
+                        let canvas = waterfall.paint(POLAR_SIZE, POLAR_SIZE);
+                        p_ref.waterfall.set_from_pixbuf(Some(&build_pixbuf(canvas.rgb, canvas.width, canvas.height)));
+                        let peak = ir.samples
+                            .iter()
+                            .enumerate()
+                            .fold((0, 0.0), |(k, m), (i, x)| if x.abs() > m { (i, x.abs()) } else { (k, m) });
+                        let ringing = match waterfall.longest_ringing() {
+                            Some((f, t60)) => format!("\nRings longest at {:.0} Hz, decay time {:.2} s.", f, t60),
+                            None => String::new(),
+                        };
+                        p_ref.summary.set_text(&format!(
+                            "Peak at {:.0} ms.{}", peak.0 as f32 * 1e3 / ir.sample_rate as f32, ringing,
+                        ));
+                    }
+                    glib::source::Continue(false)
+                }
+                Err(mpsc::TryRecvError::Empty) => glib::source::Continue(true),
+                Err(mpsc::TryRecvError::Disconnected) => glib::source::Continue(false),
+            }
+        });
+    }
+}
+
+/// Describe the quarter and half wavelength at `frequency`, for display in a label.
+fn describe_wavelength(scene: &Scene, frequency: f32) -> String {
+    let wavelength = scene.atmosphere.speed_of_sound() / frequency;
+    let region = if frequency < reverb::schroeder_frequency(scene) {
+        "below the Schroeder frequency, the modes dominate"
+    } else {
+        "above the Schroeder frequency, the field is close to diffuse"
+    };
+    format!(
+        "Quarter wavelength {:.2} m, half wavelength {:.2} m.\n{:.2} modes per Hz, {}.",
+        0.25 * wavelength, 0.5 * wavelength, reverb::modal_density(scene, frequency), region,
+    )
+}
+
+/// Return the frequencies of the modes of the scene, if it is a shoebox, up to `MODE_F_MAX`.
+fn mode_frequencies(scene: &Scene) -> Vec<f32> {
+    match Shoebox::of(scene) {
+        Some(b) => b.modes(scene.atmosphere.speed_of_sound(), MODE_F_MAX).iter().map(|m| m.frequency).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Return the mode frequency closest to `frequency`, if the scene has modes
+/// and the frequency is in the range of the mode table.
+fn closest_mode(scene: &Scene, frequency: f32) -> Option<f32> {
+    if frequency > MODE_F_MAX {
+        return None
+    }
+    let distance = |f: f32| (f / frequency).ln().abs();
+    mode_frequencies(scene)
+        .into_iter()
+        .min_by(|&a, &b| distance(a).partial_cmp(&distance(b)).unwrap())
+}
+
+/// Return the mode frequencies right below and above `frequency`, the ones
+/// that snapping moves to next.
+fn adjacent_modes(scene: &Scene, frequency: f32) -> Vec<f32> {
+    let mut below: Option<f32> = None;
+    let mut above: Option<f32> = None;
+    for f in mode_frequencies(scene) {
+        if f < frequency * 0.999 && below.map_or(true, |b| f > b) {
+            below = Some(f);
+        }
+        if f > frequency * 1.001 && above.map_or(true, |a| f < a) {
+            above = Some(f);
+        }
+    }
+    below.into_iter().chain(above).collect()
+}
+
+/// Mark the Schroeder frequency of the scene on the frequency slider, and
+/// the modes of the room above it.
+///
+/// Below the Schroeder frequency, the steady-state field that the renderer
+/// shows is what a listener hears. Above it, the field is closer to diffuse.
+fn mark_frequency_scale(scale: &gtk::Scale, scene: &Scene) {
+    scale.clear_marks();
+    let schroeder_hz = reverb::schroeder_frequency(scene);
+    scale.add_mark((schroeder_hz as f64 / 2.0).log10(), gtk::PositionType::Bottom, Some("Schroeder"));
+    for mode_hz in mode_frequencies(scene) {
+        scale.add_mark((mode_hz as f64 / 2.0).log10(), gtk::PositionType::Top, None);
+    }
+}
+
+/// Describe the level over the listening area, for display in a label.
+///
+/// With the time-domain solver, there are no levels, describe the time instead.
+fn describe_area(renderer: &Renderer) -> String {
+    if let Some(t) = renderer.pressure_time_ms() {
+        return format!("Simulated time {:.0} ms.", t)
+    }
+    match renderer.area_stats() {
+        Some(stats) => format!(
+            "Average {:+.1} dB, seat to seat {:.1} dB.\nRange {:+.1} to {:+.1} dB.",
+            stats.mean_db, stats.std_db, stats.min_db, stats.max_db,
+        ),
+        None => "No listening area in view.".to_string(),
+    }
+}
+
+/// Describe how far the render has converged, for the status bar.
+fn describe_convergence(renderer: &Renderer) -> String {
+    match renderer.convergence() {
+        Some(c) if renderer.is_converged() => {
+            format!("{:.0} samples per pixel, ~{:.2} dB uncertainty, stopped.", c.passes, c.uncertainty_db)
+        }
+        Some(c) => format!("{:.0} samples per pixel, ~{:.2} dB uncertainty.", c.passes, c.uncertainty_db),
+        None => String::new(),
+    }
+}
+
+/// Return the height of the ceiling, or of the top of a mesh, in meters.
+fn ceiling_height(scene: &Scene) -> f64 {
+    scene.bounding_box().1.z as f64
+}
+
+/// Describe the point under the cursor and the pinned point, for the status bar.
+fn describe_readout(renderer: &Renderer, cursor: Option<Vec2>) -> String {
+    let describe = |p: Vec2| {
+        let position = match renderer.position_at(p) {
+            Some(q) => format!("x {:.2} m, y {:.2} m, z {:.2} m", q.x, q.y, q.z),
+            None => "outside the image".to_string(),
+        };
+        match renderer.level_at(p) {
+            Some(level) => format!("{}, {:+.1} dB", position, level),
+            None => position,
+        }
+    };
+    let mut parts = Vec::new();
+    if let Some(p) = cursor {
+        parts.push(describe(p));
+    }
+    if let Some(p) = renderer.get_pin() {
+        parts.push(format!("Pinned: {}", describe(p)));
+    }
+    if parts.is_empty() {
+        return "Point at the image to read the level, click to pin a marker.".to_string()
+    }
+    // Levels are relative to the listener, see `Renderer::level_at()`.
+    format!("{}. Levels relative to the listener.", parts.join(". "))
+}
+
+/// Controls for the position, gain, delay, polarity, mute, and solo of every source.
+///
+/// The position controls move a source without the mouse: the arrow keys
+/// nudge it by 5 cm. The source keeps its direction.
+fn build_sources_panel(renderer: &Arc<Renderer>) -> gtk::Frame {
+    let frame = gtk::Frame::new(Some("Sources"));
+    let grid = gtk::Grid::new();
+    grid.set_border_width(6);
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(6);
+    frame.add(&grid);
+
+    grid.attach(&gtk::Label::new(Some("X m")), 1, 0, 1, 1);
+    grid.attach(&gtk::Label::new(Some("Y m")), 2, 0, 1, 1);
+    grid.attach(&gtk::Label::new(Some("Gain dB")), 3, 0, 1, 1);
+    grid.attach(&gtk::Label::new(Some("Delay ms")), 4, 0, 1, 1);
+    grid.attach(&gtk::Label::new(Some("Mute")), 6, 0, 1, 1);
+    grid.attach(&gtk::Label::new(Some("Solo")), 7, 0, 1, 1);
+
+    let scene = renderer.scene();
+    let (min, max) = scene.bounding_box();
+    for (i, source) in scene.sources.iter().enumerate() {
+        let row = i as i32 + 1;
+        let x = gtk::SpinButton::new_with_range(min.x as f64, max.x as f64, 0.05);
+        x.set_digits(2);
+        x.set_value(source.position.x as f64);
+        x.set_tooltip_text(Some(&format!("Source {} x position in meters", i)[..]));
+        let y = gtk::SpinButton::new_with_range(min.y as f64, max.y as f64, 0.05);
+        y.set_digits(2);
+        y.set_value(source.position.y as f64);
+        y.set_tooltip_text(Some(&format!("Source {} y position in meters", i)[..]));
+        let gain = gtk::SpinButton::new_with_range(-40.0, 20.0, 0.5);
+        gain.set_digits(1);
+        gain.set_value(source.gain_db as f64);
+        gain.set_tooltip_text(Some(&format!("Source {} gain in dB", i)[..]));
+        let delay = gtk::SpinButton::new_with_range(0.0, 100.0, 0.1);
+        delay.set_digits(2);
+        delay.set_value(source.delay_ms as f64);
+        delay.set_tooltip_text(Some(&format!("Source {} delay in milliseconds", i)[..]));
+        let invert = gtk::CheckButton::new_with_label("Invert");
+        invert.set_active(source.invert);
+        invert.set_tooltip_text(Some(&format!("Invert the polarity of source {}", i)[..]));
+        let mute = gtk::CheckButton::new();
+        mute.set_active(source.muted);
+        mute.set_tooltip_text(Some(&format!("Mute source {}", i)[..]));
+        let solo = gtk::CheckButton::new();
+        solo.set_active(source.solo);
+        solo.set_tooltip_text(Some(&format!("Solo source {}", i)[..]));
+
+        grid.attach(&gtk::Label::new(Some(&format!("Source {}", i)[..])), 0, row, 1, 1);
+        grid.attach(&x, 1, row, 1, 1);
+        grid.attach(&y, 2, row, 1, 1);
+        grid.attach(&gain, 3, row, 1, 1);
+        grid.attach(&delay, 4, row, 1, 1);
+        grid.attach(&invert, 5, row, 1, 1);
+        grid.attach(&mute, 6, row, 1, 1);
+        grid.attach(&solo, 7, row, 1, 1);
+
+        let r_ref = renderer.clone();
+        mute.connect_toggled(move |mute_ref| r_ref.set_mute(i, mute_ref.get_active()));
+        let r_ref = renderer.clone();
+        solo.connect_toggled(move |solo_ref| r_ref.set_solo(i, solo_ref.get_active()));
+
+        let r_ref = renderer.clone();
+        x.connect_value_changed(move |x_ref| {
+            let v = x_ref.get_value() as f32;
+            r_ref.update_source(i, |s| s.position.x = v);
+        });
+        let r_ref = renderer.clone();
+        y.connect_value_changed(move |y_ref| {
+            let v = y_ref.get_value() as f32;
+            r_ref.update_source(i, |s| s.position.y = v);
+        });
+
+        let r_ref = renderer.clone();
+        gain.connect_value_changed(move |gain_ref| {
+            let g = gain_ref.get_value() as f32;
+            r_ref.update_source(i, |s| s.gain_db = g);
+        });
+        let r_ref = renderer.clone();
+        delay.connect_value_changed(move |delay_ref| {
+            let d = delay_ref.get_value() as f32;
+            r_ref.update_source(i, |s| s.delay_ms = d);
+        });
+        let r_ref = renderer.clone();
+        invert.connect_toggled(move |invert_ref| {
+            let inv = invert_ref.get_active();
+            r_ref.update_source(i, |s| s.invert = inv);
+        });
+    }
+
+    frame
+}
+
+/// A check button for every source array, to compare a source with and without it.
+fn build_arrays_panel(renderer: &Arc<Renderer>) -> gtk::Frame {
+    let frame = gtk::Frame::new(Some("Arrays"));
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    vbox.set_border_width(6);
+    frame.add(&vbox);
+
+    for (ai, a) in renderer.scene().arrays.iter().enumerate() {
+        let kind = match a.kind {
+            ArrayKind::Cardioid => "Cardioid".to_string(),
+            ArrayKind::EndFire { count } => format!("End-fire ({} sources)", count),
+        };
+        let toggle = gtk::CheckButton::new_with_label(&format!("{} array on source {}", kind, a.front));
+        toggle.set_active(a.enabled);
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&toggle, expand, fill, padding);
+
+        let r_ref = renderer.clone();
+        toggle.connect_toggled(move |toggle_ref| {
+            // The scene may have been reloaded without the array.
+            let mut scene = (*r_ref.scene()).clone();
+            if ai < scene.arrays.len() {
+                array::set_enabled(&mut scene, ai, toggle_ref.get_active());
+                r_ref.set_scene(scene);
+            }
+        });
+    }
+
+    frame
+}
+
+/// A check button to compare the room with and without its outer shell.
+fn build_shell_panel(renderer: &Arc<Renderer>) -> gtk::Frame {
+    let frame = gtk::Frame::new(Some("Room within a room"));
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    vbox.set_border_width(6);
+    frame.add(&vbox);
+
+    let scene = renderer.scene();
+    let shell = scene.shell.as_ref().unwrap();
+    let toggle = gtk::CheckButton::new_with_mnemonic("Couple to the outer s_hell");
+    toggle.set_active(shell.coupled);
+    toggle.set_tooltip_text(Some(
+        "When off, the walls and ceiling are solid walls of their own material."
+    ));
+    let label = gtk::Label::new(Some(&format!(
+        "Gap {:.0} cm, inner leaf {:.0} kg/m²,\nmass-air-mass resonance at {:.0} Hz.",
+        shell.gap * 100.0, shell.inner_mass, shell.resonance_hz(&scene.atmosphere),
+    )[..]));
+    let expand = false;
+    let fill = false;
+    let padding = 0;
+    vbox.pack_start(&toggle, expand, fill, padding);
+    vbox.pack_start(&label, expand, fill, padding);
+
+    let r_ref = renderer.clone();
+    toggle.connect_toggled(move |toggle_ref| {
+        let mut scene = (*r_ref.scene()).clone();
+        shell::set_coupled(&mut scene, toggle_ref.get_active());
+        r_ref.set_scene(scene);
+    });
+
+    frame
+}
+
+/// A toggle to do a virtual sub crawl, see `crawl::crawl()`.
+///
+/// While the toggle is on, the viewer shows the field of a sub at the
+/// listener instead of the sources of the scene, and the panel lists the
+/// spots along the walls where it is loudest. Turning it off restores the
+/// sources.
+fn build_crawl_panel(renderer: &Arc<Renderer>, pool: &Arc<AnalysisPool>) -> gtk::Frame {
+    let frame = gtk::Frame::new(Some("Sub crawl"));
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    vbox.set_border_width(6);
+    frame.add(&vbox);
+
+    let explanation = gtk::Label::new(Some(crawl::EXPLANATION));
+    let toggle = gtk::ToggleButton::new_with_mnemonic("_Place a sub at the listener");
+    toggle.set_tooltip_text(Some(&format!(
+        "Show the field of a sub at the listener, and rank the spots along the walls from {:.0} to {:.0} Hz.",
+        CRAWL_F_MIN, CRAWL_F_MAX,
+    )[..]));
+    let results = gtk::Label::new(None);
+    results.set_selectable(true);
+    let expand = false;
+    let fill = false;
+    let padding = 0;
+    vbox.pack_start(&explanation, expand, fill, padding);
+    vbox.pack_start(&toggle, expand, fill, padding);
+    vbox.pack_start(&results, expand, fill, padding);
+
+    // The scene with its own sources, while the crawl replaces them.
+    let original: Rc<RefCell<Option<Scene>>> = Rc::new(RefCell::new(None));
+    let counter = Arc::new(AtomicUsize::new(0));
+    let r_ref = renderer.clone();
+    let pool_ref = pool.clone();
+    toggle.connect_toggled(move |toggle_ref| {
+        let generation = counter.fetch_add(1, Ordering::SeqCst) + 1;
+        if !toggle_ref.get_active() {
+            if let Some(scene) = original.borrow_mut().take() {
+                r_ref.set_scene(scene);
+            }
+            results.set_text("");
+            return
+        }
+
+        let scene = (*r_ref.scene()).clone();
+        let listener = match scene.listener {
+            Some(ref listener) => listener.position,
+            None => {
+                results.set_text("No listener in the scene.");
+                return
+            }
+        };
+        r_ref.set_scene(crawl::reciprocal_scene(&scene, listener));
+        *original.borrow_mut() = Some(scene.clone());
+
+        results.set_text("Crawling ...");
+        let seed = r_ref.seed();
+        let (sender, receiver) = mpsc::channel();
+        let current = counter.clone();
+        pool_ref.submit(Priority::Background, move || {
+            if current.load(Ordering::SeqCst) != generation {
+                return
+            }
+            let spots = crawl::crawl(&scene, listener, CRAWL_F_MIN, CRAWL_F_MAX, seed);
+            let _ = sender.send(crawl::describe(&spots[..], CRAWL_SPOTS));
+        });
+
+        let results_ref = results.clone();
+        let current = counter.clone();
+        glib::source::timeout_add_local(100, move || {
+            match receiver.try_recv() {
+                Ok(description) => {
+                    if current.load(Ordering::SeqCst) == generation {
+                        results_ref.set_text(&description);
+                    }
+                    glib::source::Continue(false)
+                }
+                Err(mpsc::TryRecvError::Empty) => glib::source::Continue(true),
+                Err(mpsc::TryRecvError::Disconnected) => glib::source::Continue(false),
+            }
+        });
+    });
+
+    frame
+}
+
+/// A spin button to change the seed of the random sampling.
+///
+/// Rendering with a different seed shows which patterns in the image are
+/// sampling noise, and the same seed reproduces a render.
+fn build_seed_panel(renderer: &Arc<Renderer>) -> gtk::Frame {
+    let frame = gtk::Frame::new(Some("Seed"));
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    vbox.set_border_width(6);
+    frame.add(&vbox);
+
+    let seed = gtk::SpinButton::new_with_range(0.0, std::u32::MAX as f64, 1.0);
+    seed.set_digits(0);
+    seed.set_value(renderer.seed() as f64);
+    seed.set_tooltip_text(Some("Seed of the random sampling"));
+    let expand = false;
+    let fill = false;
+    let padding = 0;
+    vbox.pack_start(&seed, expand, fill, padding);
+
+    let r_ref = renderer.clone();
+    seed.connect_value_changed(move |seed_ref| {
+        r_ref.set_seed(seed_ref.get_value() as u64);
+    });
+
+    frame
+}
+
+/// A list of the notes in the project.
+fn build_notes_panel(annotations: &[Annotation]) -> gtk::Frame {
+    let frame = gtk::Frame::new(Some("Notes"));
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    vbox.set_border_width(6);
+    frame.add(&vbox);
+
+    for a in annotations {
+        let text = format!("({:.2}, {:.2}): {}", a.position.x, a.position.y, a.text);
+        let label = gtk::Label::new(Some(&text[..]));
+        label.set_line_wrap(true);
+        label.set_selectable(true);
+        let expand = false;
+        let fill = false;
+        let padding = 0;
+        vbox.pack_start(&label, expand, fill, padding);
+    }
+
+    frame
+}
+
+pub fn build_ui(
+    application: &gtk::Application,
+    renderer: &Arc<Renderer>,
+    pool: &Arc<AnalysisPool>,
+    annotations: &[Annotation],
+    measurements: &[Measurement],
+    refresh_interval: Duration,
+) {
+    let window = gtk::ApplicationWindow::new(application);
+
+    window.set_title("Basstrace");
+    window.set_border_width(10);
+    window.set_position(gtk::WindowPosition::Center);
+    window.set_default_size(1280, 720);
+
+    let vbox = gtk::Box::new(
+        gtk::Orientation::Vertical,
+        10,
+    );
+    window.add(&vbox);
+
+    let hbox = gtk::Box::new(
+        gtk::Orientation::Horizontal,
+        10,
+    );
+
+    // The image takes the space that the window has left, see below.
+    let expand = true;
+    let fill = true;
+    let padding = 0;
+    vbox.pack_start(&hbox, expand, fill, padding);
+
+    let width = renderer.width();
+    let height = renderer.height();
+    let mut rgb = vec![0_u8; width as usize * height as usize * 3];
+    let mut canvas = build_pixbuf(rgb.clone(), width, height);
+    let image = gtk::Image::new_from_pixbuf(Some(&canvas));
+    image.set_tooltip_text(Some(
+        "Sound pressure level in a horizontal slice, at ear height unless moved with the z slider, seen from above. \
+         White lines are the walls, blue markers the sources with an arrow where they aim, and the red marker is \
+         the listener. Hatched areas are outside the room. The panels on the right describe it in numbers."
+    ));
+
+    // The image has no window of its own to receive pointer events, so it
+    // goes in an event box. The image sits in the top left corner of the box,
+    // so event coordinates are pixel coordinates.
+    image.set_halign(gtk::Align::Start);
+    image.set_valign(gtk::Align::Start);
+    let event_box = gtk::EventBox::new();
+    event_box.add(&image);
+    event_box.add_events(
+        gdk::EventMask::POINTER_MOTION_MASK | gdk::EventMask::BUTTON_PRESS_MASK | gdk::EventMask::LEAVE_NOTIFY_MASK
+    );
+
+    // An image cannot be smaller than its pixbuf, so it goes in a scrolled
+    // window, which can. It opens at the size of the image, after that the
+    // window decides, and we render at the size that the area gets, once it
+    // stops changing. Until then, the old image scrolls or has a border.
+    let canvas_area = gtk::ScrolledWindow::new(None, None);
+    canvas_area.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Automatic);
+    canvas_area.set_size_request(width as i32, height as i32);
+    canvas_area.add(&event_box);
+    let resize: Rc<Cell<Option<(u32, u32, Instant)>>> = Rc::new(Cell::new(None));
+    let r_ref = renderer.clone();
+    let rs_ref = resize.clone();
+    canvas_area.connect_size_allocate(move |_, allocation| {
+        let (width, height) = (allocation.width.max(1) as u32, allocation.height.max(1) as u32);
+        let current = match rs_ref.get() {
+            Some((w, h, _)) => (w, h),
+            None => (r_ref.width(), r_ref.height()),
+        };
+        if (width, height) != current {
+            rs_ref.set(Some((width, height, Instant::now())));
+        }
+    });
+
+    let expand = true;
+    let fill = true;
+    let padding = 0;
+    hbox.pack_start(&canvas_area, expand, fill, padding);
+
+    let expand = false;
+    let fill = false;
+
+    // The labels of the color bar are spread evenly over its height, see
+    // `Renderer::legend()`.
+    let (bar_rgb, _) = renderer.legend(LEGEND_WIDTH, height);
+    let legend_bar = gtk::Image::new_from_pixbuf(Some(&build_pixbuf(bar_rgb, LEGEND_WIDTH, height)));
+    let legend_labels_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    legend_labels_box.set_homogeneous(true);
+    let legend_labels: Vec<gtk::Label> = (0..renderer::LEGEND_LABELS).map(|_| gtk::Label::new(None)).collect();
+    for label in &legend_labels {
+        label.set_halign(gtk::Align::Start);
+        legend_labels_box.pack_start(label, true, true, padding);
+    }
+    let legend = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    legend.set_tooltip_text(Some("Levels relative to the listener, or the signed pressure relative to the peak in view."));
+    legend.pack_start(&legend_bar, expand, fill, padding);
+    legend.pack_start(&legend_labels_box, expand, true, padding);
+    hbox.pack_start(&legend, expand, fill, padding);
+
+    // The height of the slice, with the ceiling at the top. The ends stay
+    // clear of the floor and ceiling, where points are outside the room.
+    let z_scale = gtk::Scale::new_with_range(
+        gtk::Orientation::Vertical,
+        SLICE_MARGIN, ceiling_height(&renderer.scene()) - SLICE_MARGIN, 0.05,
+    );
+    z_scale.set_inverted(true);
+    z_scale.set_digits(2);
+    z_scale.set_value(renderer.slice_height() as f64);
+    z_scale.set_tooltip_text(Some(
+        "Height of the slice above the floor. Vertical modes have their pressure maxima at the floor and the ceiling."
+    ));
+    let r_ref = renderer.clone();
+    z_scale.connect_value_changed(move |scale| r_ref.set_slice_height(scale.get_value() as f32));
+    let z_label = gtk::Label::new_with_mnemonic(Some("_z (m)"));
+    z_label.set_mnemonic_widget(Some(&z_scale));
+    let z_box = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    z_box.pack_start(&z_label, expand, fill, padding);
+    z_box.pack_start(&z_scale, true, true, padding);
+    hbox.pack_start(&z_box, expand, fill, padding);
+
+    let side_panel = gtk::Box::new(
+        gtk::Orientation::Vertical,
+        10,
+    );
+    hbox.pack_start(&side_panel, expand, fill, padding);
+
+    let directivity = DirectivityPanel::new(renderer);
+    side_panel.pack_start(&directivity.frame, expand, fill, padding);
+    side_panel.pack_start(&build_sources_panel(renderer), expand, fill, padding);
+    if !renderer.scene().arrays.is_empty() {
+        side_panel.pack_start(&build_arrays_panel(renderer), expand, fill, padding);
+    }
+    if renderer.scene().shell.is_some() {
+        side_panel.pack_start(&build_shell_panel(renderer), expand, fill, padding);
+    }
+    if !renderer.scene().rooms.is_empty() {
+        side_panel.pack_start(&build_crawl_panel(renderer, pool), expand, fill, padding);
+    }
+    side_panel.pack_start(&SurfacePanel::new(renderer, pool).frame, expand, fill, padding);
+
+    let area_frame = gtk::Frame::new(Some("Listening area"));
+    let area_label = gtk::Label::new(Some(&describe_area(renderer)[..]));
+    area_label.set_selectable(true);
+    let area_box = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    area_box.set_border_width(6);
+    area_box.pack_start(&area_label, expand, fill, padding);
+    area_frame.add(&area_box);
+    side_panel.pack_start(&area_frame, expand, fill, padding);
+    let overlay = OverlayPanel::new();
+    side_panel.pack_start(&overlay.frame, expand, fill, padding);
+    overlay.update(renderer, pool);
+    side_panel.pack_start(&build_seed_panel(renderer), expand, fill, padding);
+    if !annotations.is_empty() {
+        side_panel.pack_start(&build_notes_panel(annotations), expand, fill, padding);
+    }
+    let arrival = ArrivalPanel::new(renderer);
+    side_panel.pack_start(&arrival.frame, expand, fill, padding);
+    let sbir = SbirPanel::new(renderer, pool);
+    side_panel.pack_start(&sbir.frame, expand, fill, padding);
+    let reverb = ReverbPanel::new(&renderer.scene());
+    side_panel.pack_start(&reverb.frame, expand, fill, padding);
+    let modes = if Shoebox::of(&renderer.scene()).is_none() {
+        None
+    } else {
+        let panel = ModePanel::new(&renderer.scene());
+        side_panel.pack_start(&panel.frame, expand, fill, padding);
+        Some(panel)
+    };
+    let residuals = if measurements.is_empty() {
+        None
+    } else {
+        let panel = MeasurementsPanel::new(measurements);
+        side_panel.pack_start(&panel.frame, expand, fill, padding);
+        panel.update(renderer, pool);
+        Some(panel)
+    };
+    let impulse = ImpulsePanel::new();
+    side_panel.pack_start(&impulse.frame, expand, fill, padding);
+    impulse.update(renderer, pool);
+    let integration = if integration::crossover_frequency(&renderer.scene()).is_none() {
+        None
+    } else {
+        let panel = IntegrationPanel::new();
+        side_panel.pack_start(&panel.frame, expand, fill, padding);
+        panel.update(renderer, pool);
+        Some(panel)
+    };
+
+    let min = 1.0;
+    let max = 4.0;
+    let step = 0.01;
+    let scale = gtk::Scale::new_with_range(
+        gtk::Orientation::Horizontal,
+        min, max, step,
+    );
+    let wavelength_label = gtk::Label::new(Some(&describe_wavelength(&renderer.scene(), renderer.frequency())[..]));
+    wavelength_label.set_selectable(true);
+    let snap = gtk::CheckButton::new_with_mnemonic("S_nap to room modes");
+    snap.set_tooltip_text(Some("Move the frequency slider to the closest mode of the room, marked above the slider."));
+    snap.set_sensitive(Shoebox::of(&renderer.scene()).is_some());
+    let r_ref = renderer.clone();
+    let pool_ref = pool.clone();
+    let w_ref = wavelength_label.clone();
+    let snap_ref = snap.clone();
+    scale.connect_value_changed(move |scale_ref| {
+        // Frequency = 10^slider_value.
+        let log10_frequency = scale_ref.get_value() as f32;
+        let mut frequency_hz = 2.0 * 10_f32.powf(log10_frequency);
+
+        // Moving the slider to the mode calls us again, and the slider may
+        // round the value to its step, so render the mode frequency itself.
+        if snap_ref.get_active() {
+            if let Some(mode_hz) = closest_mode(&r_ref.scene(), frequency_hz) {
+                let log10_mode = (mode_hz / 2.0).log10();
+                if (log10_mode - log10_frequency).abs() > 0.5 * step as f32 {
+                    scale_ref.set_value(log10_mode as f64);
+                    return
+                }
+                frequency_hz = mode_hz;
+            }
+        }
+
+        r_ref.set_frequency(frequency_hz);
+
+        // Once the render converges, sample where the slider goes next.
+        let neighbors: Vec<f32> = if snap_ref.get_active() {
+            adjacent_modes(&r_ref.scene(), frequency_hz)
+        } else {
+            [log10_frequency as f64 - step, log10_frequency as f64 + step]
+                .iter()
+                .filter(|&&x| min <= x && x <= max)
+                .map(|&x| 2.0 * 10_f32.powf(x as f32))
+                .collect()
+        };
+        r_ref.set_neighbors(&neighbors[..]);
+        w_ref.set_text(&describe_wavelength(&r_ref.scene(), frequency_hz));
+        directivity.update(&r_ref);
+        if let Some(ref panel) = residuals {
+            panel.update(&r_ref, &pool_ref);
+        }
+    });
+    scale.connect_format_value(move |_self, log10_frequency| {
+        let frequency_hz = 2.0 * 10_f32.powf(log10_frequency as f32);
+        format!("{:.1}", frequency_hz)
+    });
+    scale.set_value((renderer.frequency() as f64 / 2.0).log10());
+    if let Some(ref panel) = integration {
+        let r_ref = renderer.clone();
+        let s_ref = scale.clone();
+        panel.jump.connect_clicked(move |_| {
+            if let Some(frequency_hz) = integration::crossover_frequency(&r_ref.scene()) {
+                s_ref.set_value((frequency_hz as f64 / 2.0).log10());
+            }
+        });
+    }
+    if let Some(ref panel) = modes {
+        let s_ref = scale.clone();
+        panel.connect_select(move |frequency_hz| s_ref.set_value((frequency_hz as f64 / 2.0).log10()));
+    }
+    mark_frequency_scale(&scale, &renderer.scene());
+    let r_ref = renderer.clone();
+    let s_ref = scale.clone();
+    snap.connect_toggled(move |check| {
+        if check.get_active() {
+            if let Some(mode_hz) = closest_mode(&r_ref.scene(), r_ref.frequency()) {
+                s_ref.set_value((mode_hz as f64 / 2.0).log10());
+            }
+        }
+    });
+    let scale_label = gtk::Label::new_with_mnemonic(Some("_Frequency (Hz)"));
+    scale_label.set_mnemonic_widget(Some(&scale));
+
+    let expand = false;
+    let fill = false;
+    let padding = 0;
+    vbox.pack_start(&scale_label, expand, fill, padding);
+
+    let expand = true;
+    vbox.pack_start(&scale, expand, fill, padding);
+
+    let wavelengths = gtk::CheckButton::new_with_mnemonic("Show _wavelength arcs");
+    wavelengths.set_tooltip_text(Some(
+        "Circles at a quarter (solid) and half (dashed) wavelength around the sources (blue) and the listener (red)."
+    ));
+    let r_ref = renderer.clone();
+    wavelengths.connect_toggled(move |check| r_ref.set_show_wavelengths(check.get_active()));
+    let quantities = gtk::ComboBoxText::new();
+    quantities.append_text("Pressure");
+    quantities.append_text("Particle velocity");
+    quantities.append_text("Phase");
+    quantities.append_text("Pressure, animated");
+    quantities.set_active(Some(0));
+    quantities.set_tooltip_text(Some(
+        "Particle velocity is high where porous absorbers work best, such as a quarter wavelength from a wall. \
+         Phase colors the pressure by its phase, where opposite colors meet, the sound cancels. \
+         Animated shows the pressure oscillating in slow motion, the nodes stay gray."
+    ));
+    let views = gtk::ComboBoxText::new();
+    views.append_text("Plan");
+    views.append_text("Section along x");
+    views.append_text("Section along y");
+    views.append_text("Four heights");
+    views.set_active(Some(0));
+    views.set_tooltip_text(Some(
+        "A vertical section through the pinned marker, or through the listener if there is no marker. \
+         Floor-to-ceiling modes show as horizontal bands. \
+         Four heights shows plans from near the ceiling at the top left to near the floor at the bottom right, \
+         with the same exposure."
+    ));
+    let views_label = gtk::Label::new_with_mnemonic(Some("_View"));
+    views_label.set_mnemonic_widget(Some(&views));
+    let r_ref = renderer.clone();
+    let z_ref = z_scale.clone();
+    views.connect_changed(move |combo| {
+        let scene = r_ref.scene();
+        let (min, max) = scene.bounding_box();
+        let center = Vec2::new(0.5 * (min.x + max.x), 0.5 * (min.y + max.y));
+        let through = r_ref
+            .get_pin()
+            .and_then(|p| r_ref.position_at(p))
+            .or_else(|| scene.listener.as_ref().map(|l| l.position))
+            .map(|p| Vec2::new(p.x, p.y))
+            .unwrap_or(center);
+        let view = match combo.get_active() {
+            Some(1) => View::section_through(&scene, through, true),
+            Some(2) => View::section_through(&scene, through, false),
+            Some(3) => View::heights(&scene, r_ref.width(), r_ref.height()),
+            _ => View::Plan,
+        };
+        z_ref.set_sensitive(view == View::Plan);
+        r_ref.set_view(view);
+    });
+    // The scale from the command line may not be one of the choices.
+    let mut scales = vec![
+        ("Fit".to_string(), None),
+        ("5 mm/px".to_string(), Some(0.005)),
+        ("1 cm/px".to_string(), Some(0.01)),
+        ("2 cm/px".to_string(), Some(0.02)),
+        ("5 cm/px".to_string(), Some(0.05)),
+    ];
+    let meters_per_pixel = renderer.meters_per_pixel();
+    if let Some(s) = meters_per_pixel {
+        if scales.iter().all(|x| x.1 != meters_per_pixel) {
+            scales.push((format!("{:.1} mm/px", s * 1e3), meters_per_pixel));
+        }
+    }
+    let scale_combo = gtk::ComboBoxText::new();
+    for &(ref name, _) in &scales {
+        scale_combo.append_text(name);
+    }
+    scale_combo.set_active(scales.iter().position(|x| x.1 == meters_per_pixel).map(|i| i as u32));
+    scale_combo.set_tooltip_text(Some(
+        "The size of a pixel. Fit shows the whole scene, a fixed scale keeps distances comparable between scenes."
+    ));
+    let scale_label = gtk::Label::new_with_mnemonic(Some("Sc_ale"));
+    scale_label.set_mnemonic_widget(Some(&scale_combo));
+    let r_ref = renderer.clone();
+    scale_combo.connect_changed(move |combo| {
+        if let Some(i) = combo.get_active() {
+            r_ref.set_meters_per_pixel(scales[i as usize].1);
+        }
+    });
+    let colormaps = gtk::ComboBoxText::new();
+    colormaps.append_text("Gray");
+    colormaps.append_text("Viridis");
+    colormaps.append_text("Inferno");
+    colormaps.append_text("Diverging");
+    colormaps.set_active(Some(0));
+    colormaps.set_tooltip_text(Some(
+        "Viridis and inferno show equal steps in level as equal steps in color. \
+         Diverging is white at the level of the listener, or at zero pressure."
+    ));
+    let colormaps_label = gtk::Label::new_with_mnemonic(Some("Co_lors"));
+    colormaps_label.set_mnemonic_widget(Some(&colormaps));
+    let r_ref = renderer.clone();
+    colormaps.connect_changed(move |combo| {
+        let colormap = match combo.get_active() {
+            Some(1) => Colormap::Viridis,
+            Some(2) => Colormap::Inferno,
+            Some(3) => Colormap::Diverging,
+            _ => Colormap::Gray,
+        };
+        r_ref.set_colormap(colormap);
+    });
+    let quantities_label = gtk::Label::new_with_mnemonic(Some("_Display"));
+    quantities_label.set_mnemonic_widget(Some(&quantities));
+    let r_ref = renderer.clone();
+    quantities.connect_changed(move |combo| {
+        let quantity = match combo.get_active() {
+            Some(1) => Quantity::Velocity,
+            Some(2) => Quantity::Phase,
+            Some(3) => Quantity::Instantaneous,
+            _ => Quantity::Pressure,
+        };
+        r_ref.set_quantity(quantity);
+    });
+    // The band from the command line may not be one of the choices.
+    let mut bands = vec![
+        ("Single frequency".to_string(), Averaging::Centered(0.0)),
+        ("⅓ octave".to_string(), Averaging::Centered(1.0 / 3.0)),
+        ("1 octave".to_string(), Averaging::Centered(1.0)),
+        ("20–80 Hz".to_string(), Averaging::Fixed(20.0, 80.0)),
+        ("20–200 Hz".to_string(), Averaging::Fixed(20.0, 200.0)),
+    ];
+    let averaging = renderer.averaging();
+    if let Averaging::Centered(octaves) = averaging {
+        if bands.iter().all(|b| b.1 != averaging) {
+            bands.push((format!("{:.2} octaves", octaves), averaging));
+        }
+    }
+    let band_combo = gtk::ComboBoxText::new();
+    for &(ref name, _) in &bands {
+        band_combo.append_text(name);
+    }
+    band_combo.set_active(bands.iter().position(|b| b.1 == averaging).map(|i| i as u32));
+    band_combo.set_tooltip_text(Some(
+        "Average the power over a band, weighted by the program spectrum, like a measurement with pink noise."
+    ));
+    let band_label = gtk::Label::new_with_mnemonic(Some("_Band"));
+    band_label.set_mnemonic_widget(Some(&band_combo));
+    let r_ref = renderer.clone();
+    band_combo.connect_changed(move |combo| {
+        if let Some(i) = combo.get_active() {
+            r_ref.set_averaging(bands[i as usize].1);
+        }
+    });
+    let contours = gtk::CheckButton::new_with_mnemonic("Show contour lin_es");
+    contours.set_tooltip_text(Some(
+        "Lines of equal level every 3 dB. The red line is at the level of the listener."
+    ));
+    let r_ref = renderer.clone();
+    contours.connect_toggled(move |check| r_ref.set_show_contours(check.get_active()));
+    let intensity = gtk::CheckButton::new_with_mnemonic("Show _intensity arrows");
+    intensity.set_tooltip_text(Some(
+        "Arrows in the direction of the net flow of energy. They are short where standing waves dominate."
+    ));
+    let r_ref = renderer.clone();
+    intensity.connect_toggled(move |check| r_ref.set_show_intensity(check.get_active()));
+    let wavelengths_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    let expand = false;
+    wavelengths_box.pack_start(&views_label, expand, fill, padding);
+    wavelengths_box.pack_start(&views, expand, fill, padding);
+    wavelengths_box.pack_start(&scale_label, expand, fill, padding);
+    wavelengths_box.pack_start(&scale_combo, expand, fill, padding);
+    wavelengths_box.pack_start(&quantities_label, expand, fill, padding);
+    wavelengths_box.pack_start(&quantities, expand, fill, padding);
+    wavelengths_box.pack_start(&colormaps_label, expand, fill, padding);
+    wavelengths_box.pack_start(&colormaps, expand, fill, padding);
+    wavelengths_box.pack_start(&band_label, expand, fill, padding);
+    wavelengths_box.pack_start(&band_combo, expand, fill, padding);
+    wavelengths_box.pack_start(&wavelengths, expand, fill, padding);
+    wavelengths_box.pack_start(&contours, expand, fill, padding);
+    wavelengths_box.pack_start(&intensity, expand, fill, padding);
+    wavelengths_box.pack_start(&snap, expand, fill, padding);
+    wavelengths_box.pack_start(&wavelength_label, expand, fill, padding);
+    vbox.pack_start(&wavelengths_box, expand, fill, padding);
+
+    // The status bar shows the position and level under the cursor, and at
+    // the pinned marker. Left click pins the marker, right click removes it.
+    let cursor: Rc<Cell<Option<Vec2>>> = Rc::new(Cell::new(None));
+    let status = gtk::Label::new(Some(&describe_readout(renderer, None)[..]));
+    status.set_halign(gtk::Align::Start);
+    status.set_selectable(true);
+
+    // How far the render has converged, and when to stop sampling.
+    let convergence = gtk::Label::new(None);
+    convergence.set_tooltip_text(Some(
+        "The median over the image of the standard error of the level at the middle frequency of the band. \
+         It shrinks with the square root of the number of samples."
+    ));
+    let targets = [("Never", None), ("1 dB", Some(1.0)), ("0.5 dB", Some(0.5)), ("0.2 dB", Some(0.2)), ("0.1 dB", Some(0.1))];
+    let target_combo = gtk::ComboBoxText::new();
+    for &(name, _) in &targets {
+        target_combo.append_text(name);
+    }
+    target_combo.set_active(Some(0));
+    target_combo.set_tooltip_text(Some(
+        "Stop sampling once the uncertainty is below this, to leave the CPU idle. Any change starts over."
+    ));
+    let target_label = gtk::Label::new_with_mnemonic(Some("Stop at uncertaint_y"));
+    target_label.set_mnemonic_widget(Some(&target_combo));
+    let r_ref = renderer.clone();
+    target_combo.connect_changed(move |combo| {
+        if let Some(i) = combo.get_active() {
+            r_ref.set_target_uncertainty(targets[i as usize].1);
+        }
+    });
+
+    // Sampling keeps every core busy, which on battery may not be worth it.
+    let pause = gtk::ToggleButton::new_with_label("Pause");
+    pause.set_tooltip_text(Some("Stop sampling, until resumed. Changes still start the render over."));
+    let r_ref = renderer.clone();
+    pause.connect_toggled(move |toggle| r_ref.set_paused(toggle.get_active()));
+    let cpu_limits = [("100%", 1.0), ("75%", 0.75), ("50%", 0.5), ("25%", 0.25)];
+    let cpu_combo = gtk::ComboBoxText::new();
+    for &(name, _) in &cpu_limits {
+        cpu_combo.append_text(name);
+    }
+    cpu_combo.set_active(Some(0));
+    cpu_combo.set_tooltip_text(Some(
+        "The share of the time that the render threads sample, they sleep for the rest. Lower is slower, but cooler."
+    ));
+    let cpu_label = gtk::Label::new_with_mnemonic(Some("Ma_x CPU"));
+    cpu_label.set_mnemonic_widget(Some(&cpu_combo));
+    let r_ref = renderer.clone();
+    cpu_combo.connect_changed(move |combo| {
+        if let Some(i) = combo.get_active() {
+            r_ref.set_max_cpu(cpu_limits[i as usize].1);
+        }
+    });
+
+    // Between refreshes, the image can lag behind the samples.
+    let refresh = gtk::Button::new_with_label("Refresh now");
+    refresh.set_tooltip_text(Some("Show the samples so far, rather than waiting for the next refresh."));
+    let refresh_requested = Rc::new(Cell::new(false));
+    let rr_ref = refresh_requested.clone();
+    refresh.connect_clicked(move |_| rr_ref.set(true));
+
+    let status_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    status_box.pack_start(&status, expand, fill, padding);
+    status_box.pack_end(&refresh, expand, fill, padding);
+    status_box.pack_end(&pause, expand, fill, padding);
+    status_box.pack_end(&cpu_combo, expand, fill, padding);
+    status_box.pack_end(&cpu_label, expand, fill, padding);
+    status_box.pack_end(&target_combo, expand, fill, padding);
+    status_box.pack_end(&target_label, expand, fill, padding);
+    status_box.pack_end(&convergence, expand, fill, padding);
+    vbox.pack_start(&status_box, expand, fill, padding);
+    let to_pixel = |renderer: &Renderer, (x, y): (f64, f64)| {
+        if x < 0.0 || y < 0.0 || x >= renderer.width() as f64 || y >= renderer.height() as f64 {
+            None
+        } else {
+            Some((x as u32, y as u32))
+        }
+    };
+    let r_ref = renderer.clone();
+    let c_ref = cursor.clone();
+    let st_ref = status.clone();
+    event_box.connect_motion_notify_event(move |_, event| {
+        c_ref.set(to_pixel(&r_ref, event.get_position()).map(|(x, y)| r_ref.to_world(x, y)));
+        st_ref.set_text(&describe_readout(&r_ref, c_ref.get()));
+        gtk::Inhibit(false)
+    });
+    let r_ref = renderer.clone();
+    let c_ref = cursor.clone();
+    let st_ref = status.clone();
+    event_box.connect_leave_notify_event(move |_, _| {
+        c_ref.set(None);
+        st_ref.set_text(&describe_readout(&r_ref, None));
+        gtk::Inhibit(false)
+    });
+    let r_ref = renderer.clone();
+    let c_ref = cursor.clone();
+    let st_ref = status.clone();
+    event_box.connect_button_press_event(move |_, event| {
+        match event.get_button() {
+            1 => r_ref.set_pin(to_pixel(&r_ref, event.get_position()).map(|(x, y)| r_ref.to_world(x, y))),
+            3 => r_ref.set_pin(None),
+            _ => return gtk::Inhibit(false),
+        }
+        st_ref.set_text(&describe_readout(&r_ref, c_ref.get()));
+        gtk::Inhibit(true)
+    });
+
+    // Update the image every `refresh_interval`, and as soon as the first pass
+    // after a change completes, or when asked to. With the time-domain solver
+    // or the animated pressure, update every frame, so the wavefronts move
+    // smoothly.
+    let r_ref = renderer.clone();
+    let pool_ref = pool.clone();
+    let s_ref = scale.clone();
+    let w_ref = wavelength_label.clone();
+    let snap_ref = snap.clone();
+    let z_ref = z_scale.clone();
+    let mut arrival_scene = renderer.scene();
+    let mut ticks = 0_u32;
+    let mut last_refresh = Instant::now();
+    let mut first_pass_complete = false;
+    glib::source::timeout_add_local(MIN_REFRESH_INTERVAL.as_millis() as u32, move || {
+        // The window has opened at the size of the image, from now on it may
+        // also shrink.
+        if ticks == 0 {
+            canvas_area.set_size_request(-1, -1);
+        }
+
+        // Render at the new size once the image area stopped changing, and
+        // paint right away, the image was cleared.
+        let resized = match resize.get() {
+            Some((width, height, since)) if since.elapsed() >= RESIZE_DEBOUNCE => {
+                resize.set(None);
+                if (width, height) != (r_ref.width(), r_ref.height()) {
+                    r_ref.set_size(width, height);
+                    rgb = vec![0_u8; width as usize * height as usize * 3];
+                    canvas = build_pixbuf(rgb.clone(), width, height);
+                }
+                true
+            }
+            _ => false,
+        };
+
+        ticks += 1;
+        let complete = r_ref.is_first_pass_complete();
+        let first_pass_completed = complete && !first_pass_complete;
+        first_pass_complete = complete;
+        let due = last_refresh.elapsed() >= refresh_interval || refresh_requested.replace(false) || first_pass_completed;
+        if !resized && !due && r_ref.scene().simulation.solver != Solver::Fdtd && !r_ref.is_animated() {
+            return glib::source::Continue(true)
+        }
+        last_refresh = Instant::now();
+
+        // Arrival times change only when the sources do, and they and the
+        // reverberation times are cheap to compute, so there is no need for
+        // the analysis pool. The impulse response, the boundary interference,
+        // and the sum of the subs and mains do not depend on the frequency
+        // either.
+        let scene = r_ref.scene();
+        if !Arc::ptr_eq(&scene, &arrival_scene) {
+            arrival.update(&scene);
+            reverb.update(&scene);
+            if let Some(ref panel) = modes {
+                panel.update(&scene);
+            }
+            mark_frequency_scale(&s_ref, &scene);
+            z_ref.set_range(SLICE_MARGIN, ceiling_height(&scene) - SLICE_MARGIN);
+            snap_ref.set_sensitive(Shoebox::of(&scene).is_some());
+            w_ref.set_text(&describe_wavelength(&scene, r_ref.frequency()));
+            impulse.update(&r_ref, &pool_ref);
+            overlay.update(&r_ref, &pool_ref);
+            sbir.update(&r_ref, &pool_ref);
+            if let Some(ref panel) = integration {
+                panel.update(&r_ref, &pool_ref);
+            }
+            arrival_scene = scene;
+        }
+
+        // Paint into our own buffer, and copy the tiles that changed to the
+        // pixbuf, a tile at a time. This is much cheaper than setting pixels
+        // on the pixbuf one by one, and than copying the whole image when
+        // only a few tiles received samples. Setting the pixbuf again makes
+        // the image show the new pixels.
+        let (width, height) = (r_ref.width(), r_ref.height());
+        let painted = r_ref.paint(&mut rgb[..]);
+        if !painted.is_empty() {
+            update_pixbuf(&canvas, &rgb[..], width, &painted[..]);
+            image.set_from_pixbuf(Some(&canvas));
+            area_label.set_text(&describe_area(&r_ref));
+            let (bar_rgb, labels) = r_ref.legend(LEGEND_WIDTH, height);
+            legend_bar.set_from_pixbuf(Some(&build_pixbuf(bar_rgb, LEGEND_WIDTH, height)));
+            for (label, text) in legend_labels.iter().zip(labels) {
+                label.set_text(&text);
+            }
+            status.set_text(&describe_readout(&r_ref, cursor.get()));
+        }
+        convergence.set_text(&describe_convergence(&r_ref));
+        glib::source::Continue(true)
+    });
+
+    window.show_all();
+}
@@ -0,0 +1,172 @@
+// Basstrace -- Visualize room acoustics
+// Copyright 2019 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use std::thread;
+
+use crate::complex::Complex;
+use crate::pool;
+use crate::rand::Rng;
+use crate::response;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+/// A regular 3D grid over the bounding box of the scene.
+pub struct Grid {
+    pub origin: Vec3,
+    pub spacing: f32,
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+}
+
+impl Grid {
+    /// Cover the bounding box of the scene with points `spacing` meters apart.
+    pub fn new(scene: &Scene, spacing: f32) -> Grid {
+        let (min, max) = scene.bounding_box();
+        let n = |extent: f32| (extent / spacing).floor() as usize + 1;
+        Grid {
+            origin: min,
+            spacing: spacing,
+            nx: n(max.x - min.x),
+            ny: n(max.y - min.y),
+            nz: n(max.z - min.z),
+        }
+    }
+
+    pub fn n_points(&self) -> usize {
+        self.nx * self.ny * self.nz
+    }
+
+    fn point(&self, i: usize, j: usize, k: usize) -> Vec3 {
+        self.origin + Vec3::new(i as f32, j as f32, k as f32) * self.spacing
+    }
+
+    /// Return the index of point (`i`, `j`), in slice `k`, in the order of
+    /// `sample()` and the VTK file: by slice, then by row.
+    pub fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        (k * self.ny + j) * self.nx + i
+    }
+
+    /// Return the position of the point with the given index, see `index()`.
+    pub fn position(&self, index: usize) -> Vec3 {
+        let i = index % self.nx;
+        let j = index / self.nx % self.ny;
+        let k = index / (self.nx * self.ny);
+        self.point(i, j, k)
+    }
+}
+
+/// Sample row `j` of slice `k`, return the level of every point in dB.
+///
+/// Every row has its own random stream, so the result does not depend on
+/// which thread samples it. Points outside of the space are NaN, which
+/// visualization programs treat as missing.
+fn sample_row(scene: &Scene, grid: &Grid, frequency: f32, n_samples: u32, seed: u64, j: usize, k: usize) -> Vec<f32> {
+    let mut rng = Rng::new((seed << 32).wrapping_add((k * grid.ny + j) as u64));
+    let weight = (n_samples as f32).recip();
+    (0..grid.nx)
+        .map(|i| {
+            let p = grid.point(i, j, k);
+            if !scene.contains(p) {
+                return std::f32::NAN
+            }
+            let mut z = Complex::zero();
+            for _ in 0..n_samples {
+                z = z + scene.sample_at(&mut rng, frequency, p);
+            }
+            response::to_db(z * weight)
+        })
+        .collect()
+}
+
+/// Sample horizontal slice `k`, with the rows spread over threads.
+fn sample_slice(scene: &Arc<Scene>, grid: &Arc<Grid>, frequency: f32, n_samples: u32, seed: u64, k: usize) -> Vec<f32> {
+    let n_threads = pool::cpu_count();
+    let threads: Vec<_> = (0..n_threads)
+        .map(|t| {
+            let scene = scene.clone();
+            let grid = grid.clone();
+            thread::spawn(move || {
+                (t..grid.ny)
+                    .step_by(n_threads)
+                    .map(|j| (j, sample_row(&scene, &grid, frequency, n_samples, seed, j, k)))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+
+    let mut slice = vec![0.0; grid.nx * grid.ny];
+    for handle in threads {
+        for (j, row) in handle.join().unwrap() {
+            slice[j * grid.nx..(j + 1) * grid.nx].copy_from_slice(&row[..]);
+        }
+    }
+    slice
+}
+
+/// Return the level in dB at every point of `grid` at `frequency`, see `Grid::index()`.
+///
+/// Like `write_vtk()`, but with the whole grid in memory, for grids that are
+/// coarse enough. Progress is reported on stderr.
+pub fn sample(scene: Arc<Scene>, grid: Arc<Grid>, frequency: f32, n_samples: u32, seed: u64) -> Vec<f32> {
+    let mut levels = Vec::with_capacity(grid.n_points());
+    for k in 0..grid.nz {
+        eprint!("\rSampling slice {} of {} ...", k + 1, grid.nz);
+        levels.extend(sample_slice(&scene, &grid, frequency, n_samples, seed, k));
+    }
+    eprintln!();
+    levels
+}
+
+/// Write the level at every point of `grid` at `frequency` as a VTK file.
+///
+/// The file is a legacy VTK structured points dataset, which ParaView and
+/// VisIt open, with one scalar `level_db` per point. The header only depends on
+/// the grid, so slices are written to `out` as soon as they are sampled, and
+/// only one slice is ever in memory. This makes it feasible to export grids
+/// that are much larger than memory. Every point is estimated by averaging
+/// `n_samples` Monte Carlo samples, progress is reported on stderr.
+pub fn write_vtk<W: Write>(
+    scene: Arc<Scene>,
+    grid: Grid,
+    frequency: f32,
+    n_samples: u32,
+    seed: u64,
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "# vtk DataFile Version 3.0")?;
+    writeln!(out, "Basstrace sound pressure level at {} Hz", frequency)?;
+    writeln!(out, "BINARY")?;
+    writeln!(out, "DATASET STRUCTURED_POINTS")?;
+    writeln!(out, "DIMENSIONS {} {} {}", grid.nx, grid.ny, grid.nz)?;
+    writeln!(out, "ORIGIN {} {} {}", grid.origin.x, grid.origin.y, grid.origin.z)?;
+    writeln!(out, "SPACING {} {} {}", grid.spacing, grid.spacing, grid.spacing)?;
+    writeln!(out, "POINT_DATA {}", grid.n_points())?;
+    writeln!(out, "SCALARS level_db float 1")?;
+    writeln!(out, "LOOKUP_TABLE default")?;
+
+    let grid = Arc::new(grid);
+    let mut bytes = Vec::with_capacity(grid.nx * grid.ny * 4);
+    for k in 0..grid.nz {
+        eprint!("\rSampling slice {} of {} ...", k + 1, grid.nz);
+        let slice = sample_slice(&scene, &grid, frequency, n_samples, seed, k);
+
+        // Legacy VTK files are big-endian.
+        bytes.clear();
+        for x in slice {
+            bytes.extend_from_slice(&x.to_bits().to_be_bytes());
+        }
+        out.write_all(&bytes[..])?;
+    }
+    eprintln!();
+
+    writeln!(out)?;
+    out.flush()
+}